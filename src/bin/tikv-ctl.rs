@@ -260,6 +260,9 @@ fn dump_mvcc_lock(db: &DB, key: &str, encoded: bool) {
         println!("Primary: {:?}", escape(lock.primary.as_slice()));
         println!("Type: {:?}", lock.lock_type);
         println!("Start_ts: {:?}", lock.ts);
+        if let Some(ref v) = lock.short_value {
+            println!("Short_value: {:?}", escape(v));
+        }
         println!("");
     }
 }
@@ -274,6 +277,9 @@ fn dump_mvcc_write(db: &DB, key: &str, encoded: bool) {
         println!("Type: {:?}", write.write_type);
         println!("Start_ts: {:?}", write.start_ts);
         println!("Commit_ts: {:?}", commit_ts);
+        if let Some(ref v) = write.short_value {
+            println!("Short_value: {:?}", escape(v));
+        }
         println!("");
     }
 }
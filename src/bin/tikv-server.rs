@@ -52,9 +52,10 @@ use tikv::server::{DEFAULT_LISTENING_ADDR, DEFAULT_CLUSTER_ID, Server, Node, Con
 use tikv::server::{ServerTransport, ServerRaftStoreRouter};
 use tikv::server::transport::RaftStoreRouter;
 use tikv::server::{PdStoreAddrResolver, StoreAddrResolver};
+use tikv::server::deadlock::Detector;
+use tikv::server::gc_worker::GcWorker;
 use tikv::raftstore::store::{self, SnapManager};
 use tikv::pd::RpcClient;
-use tikv::util::time_monitor::TimeMonitor;
 
 const ROCKSDB_STATS_KEY: &'static str = "rocksdb.stats";
 
@@ -333,6 +334,11 @@ fn get_rocksdb_raftlog_cf_option(config: &toml::Value) -> RocksdbOptions {
     get_rocksdb_cf_option(config, "raftcf", 256 * 1024 * 1024, false)
 }
 
+fn get_rocksdb_raw_cf_option(config: &toml::Value) -> RocksdbOptions {
+    // Raw keys are read by point gets mostly, like the default column family.
+    get_rocksdb_cf_option(config, "rawcf", 256 * 1024 * 1024, true)
+}
+
 fn get_rocksdb_lock_cf_option() -> RocksdbOptions {
     let mut opts = RocksdbOptions::new();
     let mut block_base_opts = BlockBasedOptions::new();
@@ -418,6 +424,10 @@ fn build_cfg(matches: &Matches, config: &toml::Value, cluster_id: u64, addr: &st
         get_toml_int(config, "raftstore.max-peer-down-duration", Some(300_000)) as u64;
     cfg.raft_store.max_peer_down_duration = Duration::from_millis(max_peer_down_millis);
 
+    let max_clock_drift_millis =
+        get_toml_int(config, "raftstore.max-clock-drift", Some(500)) as u64;
+    cfg.raft_store.raft_max_clock_drift = Duration::from_millis(max_clock_drift_millis);
+
     cfg.raft_store.pd_heartbeat_tick_interval =
         get_toml_int(config, "raftstore.pd-heartbeat-tick-interval", Some(60_000)) as u64;
 
@@ -434,6 +444,23 @@ fn build_cfg(matches: &Matches, config: &toml::Value, cluster_id: u64, addr: &st
         get_toml_int(config, "storage.scheduler-concurrency", Some(102400)) as usize;
     cfg.storage.sched_worker_pool_size =
         get_toml_int(config, "storage.scheduler-worker-pool-size", Some(4)) as usize;
+    cfg.storage.sched_high_priority_pool_size =
+        get_toml_int(config, "storage.scheduler-high-priority-pool-size", Some(2)) as usize;
+    cfg.storage.sched_low_priority_pool_size =
+        get_toml_int(config, "storage.scheduler-low-priority-pool-size", Some(1)) as usize;
+    cfg.storage.sched_pending_write_threshold =
+        get_toml_int(config,
+                     "storage.scheduler-pending-write-threshold",
+                     Some(100 * 1024 * 1024)) as usize;
+    cfg.storage.sched_lock_wait_timeout =
+        get_toml_int(config, "storage.scheduler-lock-wait-timeout", Some(0)) as u64;
+    cfg.storage.read_pool_size =
+        get_toml_int(config, "storage.read-pool-size", Some(4)) as usize;
+    cfg.storage.read_pool_too_busy_threshold =
+        get_toml_int(config, "storage.read-pool-too-busy-threshold", Some(1000)) as usize;
+    cfg.storage.gc_interval = get_toml_int(config, "storage.gc-interval", Some(0)) as u64;
+    cfg.storage.gc_max_regions_per_sec =
+        get_toml_int(config, "storage.gc-max-regions-per-sec", Some(10)) as u64;
 
     cfg
 }
@@ -449,7 +476,8 @@ fn build_raftkv(config: &toml::Value,
     let cfs_opts = vec![get_rocksdb_default_cf_option(config),
                         get_rocksdb_lock_cf_option(),
                         get_rocksdb_write_cf_option(config),
-                        get_rocksdb_raftlog_cf_option(config)];
+                        get_rocksdb_raftlog_cf_option(config),
+                        get_rocksdb_raw_cf_option(config)];
     let mut db_path = path.clone();
     db_path.push("db");
     let engine =
@@ -566,24 +594,37 @@ fn run_raft_server(listener: TcpListener,
     }
 
     let (mut node, mut store, raft_router, snap_mgr, engine) =
-        build_raftkv(config, ch.clone(), pd_client, cfg);
+        build_raftkv(config, ch.clone(), pd_client.clone(), cfg);
     info!("tikv server config: {:?}", cfg);
 
     initial_metric(config, Some(node.id()));
 
+    let mut detector = Detector::new();
+    if let Err(e) = detector.start(node.id(), pd_client.clone(), ch.clone()) {
+        panic!("failed to start deadlock detector, error = {:?}", e);
+    }
+    store.set_deadlock_detector(detector.scheduler());
+    store.set_pd_client(pd_client.clone());
+
     info!("start storage");
     if let Err(e) = store.start(&cfg.storage) {
         panic!("failed to start storage, error = {:?}", e);
     }
 
-    let svr = Server::new(&mut event_loop,
-                          cfg,
-                          listener,
-                          store,
-                          raft_router,
-                          resolver,
-                          snap_mgr)
+    let mut gc_worker = GcWorker::new();
+    if let Err(e) = gc_worker.start(pd_client, raft_router.clone(), store.clone(), &cfg.storage) {
+        panic!("failed to start gc worker, error = {:?}", e);
+    }
+
+    let mut svr = Server::new(&mut event_loop,
+                              cfg,
+                              listener,
+                              store,
+                              raft_router,
+                              resolver,
+                              snap_mgr)
         .unwrap();
+    svr.set_deadlock_detector(detector.scheduler());
     start_server(svr, event_loop, engine);
     node.stop().unwrap();
 }
@@ -681,6 +722,5 @@ fn main() {
     if cluster_id == DEFAULT_CLUSTER_ID {
         panic!("in raftkv, cluster_id must greater than 0");
     }
-    let _m = TimeMonitor::default();
     run_raft_server(listener, pd_client, &matches, &config, &cfg);
 }
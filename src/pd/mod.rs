@@ -23,6 +23,7 @@ pub use self::client::RpcClient;
 
 use kvproto::metapb;
 use kvproto::pdpb;
+use storage::mvcc::MvccStats;
 
 pub type Key = Vec<u8>;
 
@@ -84,11 +85,17 @@ pub trait PdClient: Send + Sync {
     // Get region by region id.
     fn get_region_by_id(&self, region_id: u64) -> Result<Option<metapb::Region>>;
 
-    // Leader for a region will use this to heartbeat Pd.
+    // Get the leader of the region which the key belongs to, return None if pd
+    // doesn't know the leader yet.
+    fn get_region_leader(&self, key: &[u8]) -> Result<Option<metapb::Peer>>;
+
+    // Leader for a region will use this to heartbeat Pd, `mvcc_stats` helps pd
+    // find the regions worth collecting garbage.
     fn region_heartbeat(&self,
                         region: metapb::Region,
                         leader: metapb::Peer,
-                        down_peers: Vec<pdpb::PeerStats>)
+                        down_peers: Vec<pdpb::PeerStats>,
+                        mvcc_stats: MvccStats)
                         -> Result<pdpb::RegionHeartbeatResponse>;
 
     // Ask pd for split, pd will returns the new split region id.
@@ -99,4 +106,8 @@ pub trait PdClient: Send + Sync {
 
     // Report pd the split region.
     fn report_split(&self, left: metapb::Region, right: metapb::Region) -> Result<()>;
+
+    // Get the GC safe point of the cluster, the versions before it are not read by any
+    // transaction and can be collected.
+    fn get_gc_safe_point(&self) -> Result<u64>;
 }
@@ -14,6 +14,7 @@
 use uuid::Uuid;
 use kvproto::{metapb, pdpb};
 use protobuf::RepeatedField;
+use storage::mvcc::MvccStats;
 use super::{Error, Result, RpcClient};
 
 impl super::PdClient for RpcClient {
@@ -104,6 +105,22 @@ impl super::PdClient for RpcClient {
         Ok(resp.take_get_region().take_region())
     }
 
+    fn get_region_leader(&self, key: &[u8]) -> Result<Option<metapb::Peer>> {
+        let mut get_region = pdpb::GetRegionRequest::new();
+        get_region.set_region_key(key.to_vec());
+
+        let mut req = self.new_request(pdpb::CommandType::GetRegion);
+        req.set_get_region(get_region);
+
+        let mut resp = try!(self.send(&req));
+        try!(check_resp(&resp));
+        if resp.get_get_region().has_leader() {
+            Ok(Some(resp.take_get_region().take_leader()))
+        } else {
+            Ok(None)
+        }
+    }
+
     fn get_region_by_id(&self, region_id: u64) -> Result<Option<metapb::Region>> {
         let mut get_region_by_id = pdpb::GetRegionByIDRequest::new();
         get_region_by_id.set_region_id(region_id);
@@ -123,12 +140,15 @@ impl super::PdClient for RpcClient {
     fn region_heartbeat(&self,
                         region: metapb::Region,
                         leader: metapb::Peer,
-                        down_peers: Vec<pdpb::PeerStats>)
+                        down_peers: Vec<pdpb::PeerStats>,
+                        mvcc_stats: MvccStats)
                         -> Result<pdpb::RegionHeartbeatResponse> {
         let mut heartbeat = pdpb::RegionHeartbeatRequest::new();
         heartbeat.set_region(region);
         heartbeat.set_leader(leader);
         heartbeat.set_down_peers(RepeatedField::from_vec(down_peers));
+        heartbeat.set_mvcc_versions(mvcc_stats.versions);
+        heartbeat.set_mvcc_garbage(mvcc_stats.garbage);
 
         let mut req = self.new_request(pdpb::CommandType::RegionHeartbeat);
         req.set_region_heartbeat(heartbeat);
@@ -172,6 +192,15 @@ impl super::PdClient for RpcClient {
         let resp = try!(self.send(&req));
         check_resp(&resp)
     }
+
+    fn get_gc_safe_point(&self) -> Result<u64> {
+        let mut req = self.new_request(pdpb::CommandType::GetGCSafePoint);
+        req.set_get_gc_safe_point(pdpb::GetGCSafePointRequest::new());
+
+        let resp = try!(self.send(&req));
+        try!(check_resp(&resp));
+        Ok(resp.get_get_gc_safe_point().get_safe_point())
+    }
 }
 
 impl RpcClient {
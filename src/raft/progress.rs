@@ -79,6 +79,14 @@ pub struct Progress {
     // When a leader receives a reply, the previous inflights should
     // be freed by calling inflights.freeTo.
     pub ins: Inflights,
+
+    // is_learner is true if the peer is a learner, which receives the log but never
+    // votes or counts toward quorum.
+    pub is_learner: bool,
+
+    // is_outgoing_only is true if the peer is a voter of the outgoing configuration but not
+    // of the incoming one during joint consensus. It's removed when the joint consensus is left.
+    pub is_outgoing_only: bool,
 }
 
 
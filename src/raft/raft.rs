@@ -30,14 +30,16 @@ use std::cmp;
 use std::boxed::Box;
 use raft::storage::Storage;
 use rand::{self, Rng};
-use kvproto::eraftpb::{HardState, Entry, EntryType, Message, Snapshot, MessageType};
-use protobuf::repeated::RepeatedField;
+use kvproto::eraftpb::{HardState, Entry, EntryType, Message, Snapshot, MessageType, ConfChange,
+                       ConfChangeType, ConfState};
+use protobuf::{self, RepeatedField};
 use raft::progress::{Progress, Inflights, ProgressState};
 use raft::errors::{Result, Error, StorageError};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use raft::raft_log::{self, RaftLog};
 use raft::read_only::{ReadOnlyOption, ReadState, ReadOnly};
 
+const CAMPAIGN_PRE_ELECTION: &'static [u8] = b"CampaignPreElection";
 const CAMPAIGN_ELECTION: &'static [u8] = b"CampaignElection";
 const CAMPAIGN_TRANSFER: &'static [u8] = b"CampaignTransfer";
 
@@ -46,6 +48,7 @@ pub enum StateRole {
     Follower,
     Candidate,
     Leader,
+    PreCandidate,
 }
 
 impl Default for StateRole {
@@ -73,6 +76,12 @@ pub struct Config {
     /// peer is private and only used for testing right now.
     pub peers: Vec<u64>,
 
+    /// learners contains the IDs of all learner nodes (including self if the local
+    /// node is a learner) in the raft cluster. Learners only receive entries from
+    /// the leader node. They do not vote or promote themselves.
+    /// Like peers, it should only be set when starting a new raft cluster.
+    pub learners: Vec<u64>,
+
     /// ElectionTick is the number of node.tick invocations that must pass between
     /// elections. That is, if a follower does not receive any message from the
     /// leader of current term before ElectionTick has elapsed, it will become
@@ -106,6 +115,11 @@ pub struct Config {
     /// quorum is not active for an electionTimeout.
     pub check_quorum: bool,
 
+    /// pre_vote enables the Pre-Vote algorithm described in raft thesis section
+    /// 9.6. This prevents disruption when a node that has been partitioned away
+    /// rejoins the cluster.
+    pub pre_vote: bool,
+
     /// read_only_option specifies how the read only request is processed.
     pub read_only_option: ReadOnlyOption,
 
@@ -160,6 +174,8 @@ pub struct Raft<T: Storage> {
     pub max_inflight: usize,
     pub max_msg_size: u64,
     pub prs: HashMap<u64, Progress>,
+    /// The voters of the outgoing configuration during joint consensus, empty otherwise.
+    pub outgoing_voters: HashSet<u64>,
 
     pub state: StateRole,
 
@@ -190,6 +206,7 @@ pub struct Raft<T: Storage> {
     heartbeat_elapsed: usize,
 
     pub check_quorum: bool,
+    pub pre_vote: bool,
 
     heartbeat_timeout: usize,
     election_timeout: usize,
@@ -215,6 +232,10 @@ fn new_progress(next_idx: u64, ins_size: usize) -> Progress {
     }
 }
 
+fn is_pre_vote_msg(t: MessageType) -> bool {
+    t == MessageType::MsgRequestPreVote || t == MessageType::MsgRequestPreVoteResponse
+}
+
 fn new_message(to: u64, field_type: MessageType, from: Option<u64>) -> Message {
     let mut m = Message::new();
     m.set_to(to);
@@ -231,15 +252,19 @@ impl<T: Storage> Raft<T> {
         let rs = store.initial_state().expect("");
         let raft_log = RaftLog::new(store, c.tag.clone());
         let mut peers: &[u64] = &c.peers;
-        if !rs.conf_state.get_nodes().is_empty() {
-            if !peers.is_empty() {
+        let mut learners: &[u64] = &c.learners;
+        let mut outgoing: &[u64] = &[];
+        if !rs.conf_state.get_nodes().is_empty() || !rs.conf_state.get_learners().is_empty() {
+            if !peers.is_empty() || !learners.is_empty() {
                 // TODO: the peers argument is always nil except in
                 // tests; the argument should be removed and these tests should be
                 // updated to specify their nodes through a snap
-                panic!("{} cannot specify both new(peers) and ConfState.Nodes",
+                panic!("{} cannot specify both new(peers/learners) and ConfState.Nodes",
                        c.tag)
             }
             peers = rs.conf_state.get_nodes();
+            learners = rs.conf_state.get_learners();
+            outgoing = rs.conf_state.get_outgoing_nodes();
         }
         let mut r = Raft {
             id: c.id,
@@ -247,9 +272,11 @@ impl<T: Storage> Raft<T> {
             raft_log: raft_log,
             max_inflight: c.max_inflight_msgs,
             max_msg_size: c.max_size_per_msg,
-            prs: HashMap::with_capacity(peers.len()),
+            prs: HashMap::with_capacity(peers.len() + learners.len()),
+            outgoing_voters: outgoing.iter().cloned().collect(),
             state: StateRole::Follower,
             check_quorum: c.check_quorum,
+            pre_vote: c.pre_vote,
             read_only: ReadOnly::new(c.read_only_option),
             heartbeat_timeout: c.heartbeat_tick,
             election_timeout: c.election_tick,
@@ -269,6 +296,21 @@ impl<T: Storage> Raft<T> {
         for p in peers {
             r.prs.insert(*p, new_progress(1, r.max_inflight));
         }
+        for p in learners {
+            if r.prs.contains_key(p) {
+                panic!("{} node {} is in both learner and peer list", r.tag, p);
+            }
+            let mut pr = new_progress(1, r.max_inflight);
+            pr.is_learner = true;
+            r.prs.insert(*p, pr);
+        }
+        for p in outgoing {
+            if !r.prs.contains_key(p) {
+                let mut pr = new_progress(1, r.max_inflight);
+                pr.is_outgoing_only = true;
+                r.prs.insert(*p, pr);
+            }
+        }
         if rs.hard_state != HardState::new() {
             r.load_state(rs.hard_state);
         }
@@ -277,10 +319,12 @@ impl<T: Storage> Raft<T> {
         }
         let term = r.term;
         r.become_follower(term, INVALID_ID);
-        info!("{} newRaft [peers: {:?}, term: {:?}, commit: {}, applied: {}, last_index: {}, \
-               last_term: {}]",
+        info!("{} newRaft [peers: {:?}, learners: {:?}, outgoing: {:?}, term: {:?}, commit: {}, \
+               applied: {}, last_index: {}, last_term: {}]",
               r.tag,
               r.nodes(),
+              r.learner_nodes(),
+              r.outgoing_nodes(),
               r.term,
               r.raft_log.committed,
               r.raft_log.get_applied(),
@@ -323,8 +367,47 @@ impl<T: Storage> Raft<T> {
         self.state == StateRole::Leader && self.check_quorum
     }
 
+    // Learners don't count toward quorum. In joint consensus it's the quorum of the incoming
+    // voters, use `has_quorum` or `vote_result` to take the outgoing voters into account.
     fn quorum(&self) -> usize {
-        self.prs.len() / 2 + 1
+        self.incoming_voters().len() / 2 + 1
+    }
+
+    /// Returns true if the group is in joint consensus, where decisions need majorities of
+    /// both the incoming and the outgoing voters.
+    pub fn in_joint(&self) -> bool {
+        !self.outgoing_voters.is_empty()
+    }
+
+    // Returns the IDs of the voters of the incoming configuration.
+    fn incoming_voters(&self) -> Vec<u64> {
+        self.prs
+            .iter()
+            .filter(|&(_, p)| !p.is_learner && !p.is_outgoing_only)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    // Returns true if `id` is a voter of either the incoming or the outgoing configuration.
+    fn is_voter(&self, id: u64) -> bool {
+        self.outgoing_voters.contains(&id) ||
+        self.prs.get(&id).map_or(false, |p| !p.is_learner && !p.is_outgoing_only)
+    }
+
+    // Returns the groups of voters that a decision needs a majority of each.
+    fn voter_groups(&self) -> Vec<Vec<u64>> {
+        let mut groups = vec![self.incoming_voters()];
+        if self.in_joint() {
+            groups.push(self.outgoing_voters.iter().cloned().collect());
+        }
+        groups
+    }
+
+    // Returns true if the nodes accepted by `f` make up a majority of each voter group.
+    fn has_quorum<F: Fn(u64) -> bool>(&self, f: F) -> bool {
+        self.voter_groups()
+            .iter()
+            .all(|ids| ids.iter().filter(|id| f(**id)).count() >= ids.len() / 2 + 1)
     }
 
     // for testing leader lease
@@ -340,20 +423,50 @@ impl<T: Storage> Raft<T> {
         self.heartbeat_timeout
     }
 
+    /// Returns the sorted IDs of the voters, learners are not included. In joint consensus
+    /// they are the voters of the incoming configuration.
     pub fn nodes(&self) -> Vec<u64> {
-        let mut nodes = Vec::with_capacity(self.prs.len());
-        nodes.extend(self.prs.keys());
+        let mut nodes = self.incoming_voters();
+        nodes.sort();
+        nodes
+    }
+
+    /// Returns the sorted IDs of the voters of the outgoing configuration, which is empty if
+    /// not in joint consensus.
+    pub fn outgoing_nodes(&self) -> Vec<u64> {
+        let mut nodes: Vec<_> = self.outgoing_voters.iter().cloned().collect();
+        nodes.sort();
+        nodes
+    }
+
+    /// Returns the sorted IDs of the learners.
+    pub fn learner_nodes(&self) -> Vec<u64> {
+        let mut nodes: Vec<_> =
+            self.prs.iter().filter(|&(_, p)| p.is_learner).map(|(id, _)| *id).collect();
         nodes.sort();
         nodes
     }
 
+    /// Returns true if the local node is a learner.
+    pub fn is_learner(&self) -> bool {
+        self.prs.get(&self.id).map_or(false, |p| p.is_learner)
+    }
+
     // send persists state to stable storage and then sends to its mailbox.
     fn send(&mut self, mut m: Message) {
         m.set_from(self.id);
-        // do not attach term to MsgPropose
-        // proposals are a way to forward to the leader and
-        // should be treated as local message.
-        if m.get_msg_type() != MessageType::MsgPropose {
+        if is_pre_vote_msg(m.get_msg_type()) {
+            // pre-vote messages carry the term they are sent for, which may be
+            // different from the current one.
+            if m.get_term() == 0 {
+                panic!("{} term should be set when sending {:?}",
+                       self.tag,
+                       m.get_msg_type());
+            }
+        } else if m.get_msg_type() != MessageType::MsgPropose {
+            // do not attach term to MsgPropose
+            // proposals are a way to forward to the leader and
+            // should be treated as local message.
             m.set_term(self.term);
         }
         self.msgs.push(m);
@@ -506,13 +619,18 @@ impl<T: Storage> Raft<T> {
     // r.bcast_append).
     pub fn maybe_commit(&mut self) -> bool {
         // TODO: optimize
-        let mut mis = Vec::with_capacity(self.prs.len());
-        for p in self.prs.values() {
-            mis.push(p.matched);
-        }
-        // reverse sort
-        mis.sort_by(|a, b| b.cmp(a));
-        let mci = mis[self.quorum() - 1];
+        // In joint consensus, an index is committed only if it's committed in both the
+        // incoming and the outgoing configurations.
+        let mci = self.voter_groups()
+            .iter()
+            .map(|ids| {
+                let mut mis: Vec<_> = ids.iter().map(|id| self.prs[id].matched).collect();
+                // reverse sort
+                mis.sort_by(|a, b| b.cmp(a));
+                mis[ids.len() / 2]
+            })
+            .min()
+            .unwrap();
         let term = self.term;
         self.raft_log.maybe_commit(mci, term)
     }
@@ -533,7 +651,10 @@ impl<T: Storage> Raft<T> {
         let (last_index, max_inflight) = (self.raft_log.last_index(), self.max_inflight);
         let self_id = self.id;
         for (id, p) in &mut self.prs {
+            let (is_learner, is_outgoing_only) = (p.is_learner, p.is_outgoing_only);
             *p = new_progress(last_index + 1, max_inflight);
+            p.is_learner = is_learner;
+            p.is_outgoing_only = is_outgoing_only;
             if id == &self_id {
                 p.matched = last_index;
             }
@@ -556,7 +677,9 @@ impl<T: Storage> Raft<T> {
 
     pub fn tick(&mut self) {
         match self.state {
-            StateRole::Candidate | StateRole::Follower => self.tick_election(),
+            StateRole::Candidate | StateRole::PreCandidate | StateRole::Follower => {
+                self.tick_election()
+            }
             StateRole::Leader => self.tick_heartbeat(),
         }
     }
@@ -618,6 +741,18 @@ impl<T: Storage> Raft<T> {
         info!("{} became candidate at term {}", self.tag, self.term);
     }
 
+    // TODO: revoke pub when there is a better way to test.
+    pub fn become_pre_candidate(&mut self) {
+        assert!(self.state != StateRole::Leader,
+                "invalid transition [leader -> pre-candidate]");
+        // Becoming a pre-candidate changes our state,
+        // but doesn't change anything else. In particular it does not increase
+        // self.term or change self.vote.
+        self.state = StateRole::PreCandidate;
+        self.votes = HashMap::new();
+        info!("{} became pre-candidate at term {}", self.tag, self.term);
+    }
+
     // TODO: revoke pub when there is a better way to test.
     pub fn become_leader(&mut self) {
         assert!(self.state != StateRole::Follower,
@@ -647,25 +782,42 @@ impl<T: Storage> Raft<T> {
     }
 
     fn campaign(&mut self, campaign_type: &[u8]) {
-        self.become_candidate();
+        let (vote_msg, term) = if campaign_type == CAMPAIGN_PRE_ELECTION {
+            self.become_pre_candidate();
+            // Pre-vote RPCs are sent for next term before we've incremented self.term.
+            (MessageType::MsgRequestPreVote, self.term + 1)
+        } else {
+            self.become_candidate();
+            (MessageType::MsgRequestVote, self.term)
+        };
         let id = self.id;
-        let poll_res = self.poll(id, true);
-        if self.quorum() == poll_res {
-            self.become_leader();
+        self.poll(id, true);
+        if self.vote_result() == Some(true) {
+            // We won the election after voting for ourselves (which must mean that
+            // this is a single-node cluster). Advance to the next state.
+            if campaign_type == CAMPAIGN_PRE_ELECTION {
+                self.campaign(CAMPAIGN_ELECTION);
+            } else {
+                self.become_leader();
+            }
             return;
         }
-        let ids: Vec<_> = self.prs.keys().cloned().collect();
+        // Learners never vote, while the outgoing voters do in joint consensus.
+        let mut ids: Vec<_> = self.prs.keys().cloned().filter(|id| self.is_voter(*id)).collect();
+        ids.sort();
         for id in ids {
             if id == self.id {
                 continue;
             }
-            info!("{} [logterm: {}, index: {}] sent vote request to {} at term {}",
+            info!("{} [logterm: {}, index: {}] sent {:?} request to {} at term {}",
                   self.tag,
                   self.raft_log.last_term(),
                   self.raft_log.last_index(),
+                  vote_msg,
                   id,
                   self.term);
-            let mut m = new_message(id, MessageType::MsgRequestVote, None);
+            let mut m = new_message(id, vote_msg, None);
+            m.set_term(term);
             m.set_index(self.raft_log.last_index());
             m.set_log_term(self.raft_log.last_term());
             if campaign_type == CAMPAIGN_TRANSFER {
@@ -691,10 +843,31 @@ impl<T: Storage> Raft<T> {
         self.votes.values().filter(|x| **x).count()
     }
 
+    // Returns Some(true) if the election is won with the votes received, Some(false) if it's
+    // lost, or None if it's still undecided.
+    fn vote_result(&self) -> Option<bool> {
+        let mut res = Some(true);
+        for ids in self.voter_groups() {
+            let quorum = ids.len() / 2 + 1;
+            let granted = ids.iter().filter(|id| self.votes.get(*id) == Some(&true)).count();
+            let rejected = ids.iter().filter(|id| self.votes.get(*id) == Some(&false)).count();
+            if rejected >= quorum {
+                return Some(false);
+            }
+            if granted < quorum {
+                res = None;
+            }
+        }
+        res
+    }
+
     pub fn step(&mut self, m: Message) -> Result<()> {
         if m.get_msg_type() == MessageType::MsgHup {
             if self.state == StateRole::Leader {
                 debug!("{} ignoring MsgHup because already leader", self.tag);
+            } else if !self.promotable() {
+                warn!("{} is unpromotable and can not campaign; ignoring MsgHup",
+                      self.tag);
             } else {
                 let ents = self.raft_log
                     .slice(self.raft_log.applied + 1,
@@ -713,7 +886,11 @@ impl<T: Storage> Raft<T> {
                 info!("{} is starting a new election at term {}",
                       self.tag,
                       self.term);
-                self.campaign(CAMPAIGN_ELECTION);
+                if self.pre_vote {
+                    self.campaign(CAMPAIGN_PRE_ELECTION);
+                } else {
+                    self.campaign(CAMPAIGN_ELECTION);
+                }
             }
             return Ok(());
         }
@@ -721,7 +898,8 @@ impl<T: Storage> Raft<T> {
         if m.get_term() == 0 {
             // local message
         } else if m.get_term() > self.term {
-            let leader_id = if m.get_msg_type() == MessageType::MsgRequestVote {
+            let leader_id = if m.get_msg_type() == MessageType::MsgRequestVote ||
+                               m.get_msg_type() == MessageType::MsgRequestPreVote {
                 let force = m.get_context() == CAMPAIGN_TRANSFER;
                 let in_lease = self.check_quorum && self.leader_id != INVALID_ID &&
                                self.election_elapsed < self.election_timeout;
@@ -749,13 +927,24 @@ impl<T: Storage> Raft<T> {
             } else {
                 m.get_from()
             };
-            info!("{} [term: {}] received a {:?} message with higher term from {} [term: {}]",
-                  self.tag,
-                  self.term,
-                  m.get_msg_type(),
-                  m.get_from(),
-                  m.get_term());
-            self.become_follower(m.get_term(), leader_id);
+            if m.get_msg_type() == MessageType::MsgRequestPreVote ||
+               (m.get_msg_type() == MessageType::MsgRequestPreVoteResponse && !m.get_reject()) {
+                // Never change our term in response to a pre-vote request.
+                // We send pre-vote requests with a term in our future. If the
+                // pre-vote is granted, we will increment our term when we get a
+                // quorum. If it is not, the term comes from the node that
+                // rejected our vote so we should become a follower at the new
+                // term.
+            } else {
+                info!("{} [term: {}] received a {:?} message with higher term from {} \
+                       [term: {}]",
+                      self.tag,
+                      self.term,
+                      m.get_msg_type(),
+                      m.get_from(),
+                      m.get_term());
+                self.become_follower(m.get_term(), leader_id);
+            }
         } else if m.get_term() < self.term {
             if self.check_quorum &&
                (m.get_msg_type() == MessageType::MsgHeartbeat ||
@@ -774,6 +963,26 @@ impl<T: Storage> Raft<T> {
                 // will not create disruptive term increases
                 let to_send = new_message(m.get_from(), MessageType::MsgAppendResponse, None);
                 self.send(to_send);
+            } else if m.get_msg_type() == MessageType::MsgRequestPreVote {
+                // Before pre-vote is enabled, there may be a candidate with a higher term
+                // but a shorter log. After pre-vote is enabled, the cluster may deadlock
+                // if we drop pre-vote requests with a lower term, so reject them instead.
+                info!("{} [logterm: {}, index: {}, vote: {}] rejected {:?} from {} \
+                       [logterm: {}, index: {}] at term {}",
+                      self.tag,
+                      self.raft_log.last_term(),
+                      self.raft_log.last_index(),
+                      self.vote,
+                      m.get_msg_type(),
+                      m.get_from(),
+                      m.get_log_term(),
+                      m.get_index(),
+                      self.term);
+                let mut to_send =
+                    new_message(m.get_from(), MessageType::MsgRequestPreVoteResponse, None);
+                to_send.set_term(self.term);
+                to_send.set_reject(true);
+                self.send(to_send);
             } else {
                 // ignore other cases
                 info!("{} [term: {}] ignored a {:?} message with lower term from {} [term: {}]",
@@ -792,8 +1001,28 @@ impl<T: Storage> Raft<T> {
                 return Ok(());
             }
         }
+        if (m.get_msg_type() == MessageType::MsgRequestVote ||
+            m.get_msg_type() == MessageType::MsgRequestPreVote) && self.is_learner() {
+            info!("{} [logterm: {}, index: {}, vote: {}] ignored {:?} from {} [logterm: {}, \
+                   index: {}] at term {}: learner can not vote",
+                  self.tag,
+                  self.raft_log.last_term(),
+                  self.raft_log.last_index(),
+                  self.vote,
+                  m.get_msg_type(),
+                  m.get_from(),
+                  m.get_log_term(),
+                  m.get_index(),
+                  self.term);
+            return Ok(());
+        }
+        if m.get_msg_type() == MessageType::MsgRequestPreVote {
+            // Pre-votes are handled the same way in all states.
+            self.handle_pre_vote(&m);
+            return Ok(());
+        }
         match self.state {
-            StateRole::Candidate => self.step_candidate(m),
+            StateRole::Candidate | StateRole::PreCandidate => self.step_candidate(m),
             StateRole::Follower => self.step_follower(m),
             StateRole::Leader => self.step_leader(m),
         }
@@ -888,6 +1117,12 @@ impl<T: Storage> Raft<T> {
                    self.tag);
             return;
         }
+        if self.prs[&lead_transferee].is_learner {
+            debug!("{} ignored transferring leadership to learner {}",
+                   self.tag,
+                   lead_transferee);
+            return;
+        }
         // Transfer leadership to third party.
         info!("{} [term {}] starts to transfer leadership to {}",
               self.tag,
@@ -959,12 +1194,17 @@ impl<T: Storage> Raft<T> {
                     }
                 }
 
-                if self.read_only.option != ReadOnlyOption::Safe || m.get_context().is_empty() {
+                if self.read_only.option != ReadOnlyOption::Safe || m.get_context().is_empty() ||
+                   self.prs[&m.get_from()].is_learner {
                     return;
                 }
 
-                let ack_count = self.read_only.recv_ack(m);
-                if ack_count < self.quorum() {
+                let acks = match self.read_only.recv_ack(m) {
+                    Some(acks) => acks,
+                    None => return,
+                };
+                let self_id = self.id;
+                if !self.has_quorum(|id| id == self_id || acks.contains(&id)) {
                     return;
                 }
 
@@ -1039,6 +1279,26 @@ impl<T: Storage> Raft<T> {
               self.term);
     }
 
+    fn handle_pre_vote(&mut self, m: &Message) {
+        let t = MessageType::MsgRequestPreVoteResponse;
+        // m.term > self.term means the pre-candidate asks for a vote in its next term,
+        // where we haven't voted for anyone yet.
+        if (self.vote == INVALID_ID || m.get_term() > self.term || self.vote == m.get_from()) &&
+           self.raft_log.is_up_to_date(m.get_index(), m.get_log_term()) {
+            self.log_vote_approve(m);
+            let mut to_send = new_message(m.get_from(), t, None);
+            to_send.set_term(m.get_term());
+            to_send.set_reject(false);
+            self.send(to_send);
+        } else {
+            self.log_vote_reject(m);
+            let mut to_send = new_message(m.get_from(), t, None);
+            to_send.set_term(self.term);
+            to_send.set_reject(true);
+            self.send(to_send);
+        }
+    }
+
     fn step_leader(&mut self, mut m: Message) {
         // These message types do not require any progress for m.From.
         match m.get_msg_type() {
@@ -1079,6 +1339,10 @@ impl<T: Storage> Raft<T> {
                         if self.pending_conf {
                             *e = Entry::new();
                             e.set_entry_type(EntryType::EntryNormal);
+                        } else if !self.is_conf_change_allowed(e) {
+                            *e = Entry::new();
+                            e.set_entry_type(EntryType::EntryNormal);
+                            continue;
                         }
                         self.pending_conf = true;
                     }
@@ -1097,7 +1361,8 @@ impl<T: Storage> Raft<T> {
                 return;
             }
             MessageType::MsgReadIndex => {
-                if self.quorum() > 1 {
+                let self_id = self.id;
+                if !self.has_quorum(|id| id == self_id) {
                     // thinking: use an interally defined context instead of the user given context.
                     // We can express this in terms of the term and index instead of
                     // a user-supplied value.
@@ -1171,6 +1436,13 @@ impl<T: Storage> Raft<T> {
 
     fn step_candidate(&mut self, m: Message) {
         let term = self.term;
+        // Only handle vote responses corresponding to our candidacy (while in
+        // candidate state, we may get stale pre-vote responses, and vice versa).
+        let vote_resp_type = if self.state == StateRole::PreCandidate {
+            MessageType::MsgRequestPreVoteResponse
+        } else {
+            MessageType::MsgRequestVoteResponse
+        };
         match m.get_msg_type() {
             MessageType::MsgPropose => {
                 info!("{} no leader at term {}; dropping proposal", self.tag, term);
@@ -1195,19 +1467,26 @@ impl<T: Storage> Raft<T> {
                 to_send.set_reject(true);
                 self.send(to_send);
             }
-            MessageType::MsgRequestVoteResponse => {
+            t if t == vote_resp_type => {
                 let gr = self.poll(m.get_from(), !m.get_reject());
                 let quorum = self.quorum();
-                info!("{} [quorum:{}] has received {} votes and {} vote rejections",
+                info!("{} [quorum:{}] has received {} {:?} votes and {} vote rejections",
                       self.tag,
                       quorum,
                       gr,
+                      t,
                       self.votes.len() - gr);
-                if quorum == gr {
-                    self.become_leader();
-                    self.bcast_append();
-                } else if quorum == self.votes.len() - gr {
-                    self.become_follower(term, INVALID_ID);
+                match self.vote_result() {
+                    Some(true) => {
+                        if self.state == StateRole::PreCandidate {
+                            self.campaign(CAMPAIGN_ELECTION);
+                        } else {
+                            self.become_leader();
+                            self.bcast_append();
+                        }
+                    }
+                    Some(false) => self.become_follower(term, INVALID_ID),
+                    None => {}
                 }
             }
             MessageType::MsgTimeoutNow => {
@@ -1276,6 +1555,13 @@ impl<T: Storage> Raft<T> {
                 self.send(m);
             }
             MessageType::MsgTimeoutNow => {
+                if !self.promotable() {
+                    info!("{} [term {}] received MsgTimeoutNow from {} but is not promotable",
+                          self.tag,
+                          self.term,
+                          m.get_from());
+                    return;
+                }
                 info!("{} [term {}] received MsgTimeoutNow from {} and starts an election to \
                        get leadership.",
                       self.tag,
@@ -1410,11 +1696,26 @@ impl<T: Storage> Raft<T> {
               self.raft_log.last_term(),
               meta.get_index(),
               meta.get_term());
-        self.prs = HashMap::with_capacity(meta.get_conf_state().get_nodes().len());
-        for &n in meta.get_conf_state().get_nodes() {
+        let conf_state = meta.get_conf_state();
+        self.prs = HashMap::with_capacity(conf_state.get_nodes().len() +
+                                          conf_state.get_learners().len());
+        self.outgoing_voters = conf_state.get_outgoing_nodes().iter().cloned().collect();
+        let nodes = conf_state.get_nodes().iter().map(|n| (n, false, false));
+        let learners = conf_state.get_learners().iter().map(|n| (n, true, false));
+        // The voters being removed in joint consensus.
+        let outgoing_only = conf_state.get_outgoing_nodes()
+            .iter()
+            .filter(|n| !conf_state.get_nodes().contains(n))
+            .map(|n| (n, false, true));
+        for (&n, is_learner, is_outgoing_only) in nodes.chain(learners).chain(outgoing_only) {
             let next_idx = self.raft_log.last_index() + 1;
             let matched = if n == self.id { next_idx - 1 } else { 0 };
             self.set_progress(n, matched, next_idx);
+            {
+                let pr = self.prs.get_mut(&n).unwrap();
+                pr.is_learner = is_learner;
+                pr.is_outgoing_only = is_outgoing_only;
+            }
             info!("{} restored progress of {} [{:?}]",
                   self.tag,
                   n,
@@ -1437,19 +1738,44 @@ impl<T: Storage> Raft<T> {
     }
 
     // promotable indicates whether state machine can be promoted to leader,
-    // which is true when its own id is in progress list.
+    // which is true when it's a voter of either the incoming or the outgoing configuration.
     pub fn promotable(&self) -> bool {
-        self.prs.contains_key(&self.id)
+        self.is_voter(self.id)
     }
 
+    /// Adds a voter, or promotes the learner `id` to a voter.
     pub fn add_node(&mut self, id: u64) {
-        if self.prs.contains_key(&id) {
+        self.add_node_or_learner(id, false)
+    }
+
+    /// Adds a learner, which receives the log but never votes.
+    pub fn add_learner(&mut self, id: u64) {
+        self.add_node_or_learner(id, true)
+    }
+
+    fn add_node_or_learner(&mut self, id: u64, is_learner: bool) {
+        if let Some(pr) = self.prs.get_mut(&id) {
+            if is_learner && !pr.is_learner {
+                // can only change learner to voter
+                info!("{} ignored add_learner: do not support changing {} from voter to \
+                       learner",
+                      self.tag,
+                      id);
+                return;
+            }
             // Ignore any redundant addNode calls (which can happen because the
             // initial bootstrapping entries are applied twice).
-            return;
+            if is_learner == pr.is_learner {
+                return;
+            }
+            // change learner to voter, the progress is kept.
+            info!("{} promoted learner {} to voter", self.tag, id);
+            pr.is_learner = false;
+        } else {
+            let last_index = self.raft_log.last_index();
+            self.set_progress(id, 0, last_index + 1);
+            self.prs.get_mut(&id).unwrap().is_learner = is_learner;
         }
-        let last_index = self.raft_log.last_index();
-        self.set_progress(id, 0, last_index + 1);
         self.pending_conf = false;
     }
 
@@ -1473,6 +1799,120 @@ impl<T: Storage> Raft<T> {
         }
     }
 
+    /// Enters joint consensus with the nodes and learners of `conf_state` as the incoming
+    /// configuration, the current voters become the outgoing configuration. The voters being
+    /// removed keep receiving the log until `leave_joint` is called, while the learners being
+    /// removed are removed at once as they never count toward quorum.
+    pub fn enter_joint(&mut self, conf_state: &ConfState) {
+        self.pending_conf = false;
+        if self.in_joint() {
+            info!("{} ignored entering joint consensus: already in joint consensus",
+                  self.tag);
+            return;
+        }
+        let (nodes, learners) = (conf_state.get_nodes(), conf_state.get_learners());
+        if nodes.is_empty() {
+            info!("{} ignored entering joint consensus: no voters in {:?}",
+                  self.tag,
+                  conf_state);
+            return;
+        }
+        if let Some(id) = learners.iter().find(|&&id| nodes.contains(&id) || self.is_voter(id)) {
+            // can only change learner to voter
+            info!("{} ignored entering joint consensus: can't make {} a learner",
+                  self.tag,
+                  id);
+            return;
+        }
+
+        self.outgoing_voters = self.incoming_voters().into_iter().collect();
+        for (id, pr) in &mut self.prs {
+            pr.is_outgoing_only = !pr.is_learner && !nodes.contains(id);
+        }
+        let removed_learners: Vec<_> = self.learner_nodes()
+            .into_iter()
+            .filter(|id| !nodes.contains(id) && !learners.contains(id))
+            .collect();
+        for id in removed_learners {
+            self.del_progress(id);
+        }
+        let last_index = self.raft_log.last_index();
+        let incoming = nodes.iter().map(|id| (id, false));
+        let incoming_learners = learners.iter().map(|id| (id, true));
+        for (&id, is_learner) in incoming.chain(incoming_learners) {
+            if !self.prs.contains_key(&id) {
+                self.set_progress(id, 0, last_index + 1);
+            }
+            // A learner becoming a voter keeps its progress.
+            self.prs.get_mut(&id).unwrap().is_learner = is_learner;
+        }
+        info!("{} entered joint consensus [incoming: {:?}, outgoing: {:?}, learners: {:?}]",
+              self.tag,
+              self.nodes(),
+              self.outgoing_nodes(),
+              self.learner_nodes());
+    }
+
+    /// Leaves joint consensus, the voters which are only in the outgoing configuration are
+    /// removed.
+    pub fn leave_joint(&mut self) {
+        self.pending_conf = false;
+        if !self.in_joint() {
+            info!("{} ignored leaving joint consensus: not in joint consensus",
+                  self.tag);
+            return;
+        }
+        let removed: Vec<_> = self.prs
+            .iter()
+            .filter(|&(_, p)| p.is_outgoing_only)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &removed {
+            self.del_progress(*id);
+        }
+        self.outgoing_voters.clear();
+        info!("{} left joint consensus [voters: {:?}, learners: {:?}, removed: {:?}]",
+              self.tag,
+              self.nodes(),
+              self.learner_nodes(),
+              removed);
+
+        // do not try to commit or abort transferring if there is no nodes in the cluster.
+        if self.prs.is_empty() {
+            return;
+        }
+
+        // The outgoing voters aren't needed any more, so see if any pending entries can
+        // be committed.
+        if self.maybe_commit() {
+            self.bcast_append();
+        }
+        // If the removed node is the lead_transferee, then abort the leadership transferring.
+        if self.state == StateRole::Leader &&
+           self.lead_transferee.map_or(false, |id| removed.contains(&id)) {
+            self.abort_leader_transfer()
+        }
+    }
+
+    // In joint consensus, the only configuration change allowed is leaving it, which is
+    // not allowed otherwise.
+    fn is_conf_change_allowed(&self, e: &Entry) -> bool {
+        let cc: ConfChange = match protobuf::parse_from_bytes(e.get_data()) {
+            Ok(cc) => cc,
+            // Leave it to the application.
+            Err(_) => return true,
+        };
+        let leaving = cc.get_change_type() == ConfChangeType::FinalizeMembershipChange;
+        if leaving != self.in_joint() {
+            info!("{} [in joint: {}] ignored conf change {:?}",
+                  self.tag,
+                  self.in_joint(),
+                  cc.get_change_type());
+            return false;
+        }
+        true
+    }
+
     pub fn reset_pending_conf(&mut self) {
         self.pending_conf = false;
     }
@@ -1526,22 +1966,23 @@ impl<T: Storage> Raft<T> {
     // false.
     // check_quorum_active also resets all recent_active to false.
     fn check_quorum_active(&mut self) -> bool {
-        let mut act = 0;
+        let mut active = HashSet::new();
         let self_id = self.id;
         for (id, p) in &mut self.prs {
             if id == &self_id {
                 // self is always active
-                act += 1;
+                active.insert(*id);
                 continue;
             }
 
             if p.recent_active {
-                act += 1;
+                active.insert(*id);
             }
 
             p.recent_active = false;
         }
-        act >= self.quorum()
+        // Learners are never counted as they aren't voters.
+        self.has_quorum(|id| active.contains(&id))
     }
 
     pub fn send_timeout_now(&mut self, to: u64) {
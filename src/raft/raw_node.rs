@@ -62,6 +62,7 @@ fn is_response_msg(t: MessageType) -> bool {
     match t {
         MessageType::MsgAppendResponse |
         MessageType::MsgRequestVoteResponse |
+        MessageType::MsgRequestPreVoteResponse |
         MessageType::MsgHeartbeatResponse |
         MessageType::MsgUnreachable => true,
         _ => false,
@@ -261,20 +262,21 @@ impl<T: Storage> RawNode<T> {
     }
 
     pub fn apply_conf_change(&mut self, cc: ConfChange) -> ConfState {
-        if cc.get_node_id() == INVALID_ID {
-            self.raft.reset_pending_conf();
-            let mut cs = ConfState::new();
-            cs.set_nodes(self.raft.nodes());
-            return cs;
-        }
         let nid = cc.get_node_id();
-        assert!(cc.has_change_type(), "unexpected conf type");
+        assert!(nid == INVALID_ID || cc.has_change_type(), "unexpected conf type");
         match cc.get_change_type() {
+            // Membership changes carry the whole configuration instead of a node id.
+            ConfChangeType::BeginMembershipChange => self.raft.enter_joint(cc.get_configuration()),
+            ConfChangeType::FinalizeMembershipChange => self.raft.leave_joint(),
+            _ if nid == INVALID_ID => self.raft.reset_pending_conf(),
             ConfChangeType::AddNode => self.raft.add_node(nid),
+            ConfChangeType::AddLearnerNode => self.raft.add_learner(nid),
             ConfChangeType::RemoveNode => self.raft.remove_node(nid),
         }
         let mut cs = ConfState::new();
         cs.set_nodes(self.raft.nodes());
+        cs.set_learners(self.raft.learner_nodes());
+        cs.set_outgoing_nodes(self.raft.outgoing_nodes());
         cs
     }
 
@@ -412,6 +414,8 @@ mod test {
             (MessageType::MsgTimeoutNow, false),
             (MessageType::MsgReadIndex, false),
             (MessageType::MsgReadIndexResp, false),
+            (MessageType::MsgRequestPreVote, false),
+            (MessageType::MsgRequestPreVoteResponse, false),
         ];
         for (msg_type, result) in tests {
             assert_eq!(is_local_msg(msg_type), result);
@@ -101,14 +101,14 @@ impl ReadOnly {
 
     /// rev_ack notifies the ReadOnly struct that the raft state machine received
     /// an acknowledgment of the heartbeat that attached with the read only request
-    /// context.
-    pub fn recv_ack(&mut self, m: &Message) -> usize {
+    /// context, and returns the nodes which have acknowledged it, the local node is
+    /// not included.
+    pub fn recv_ack(&mut self, m: &Message) -> Option<HashSet<u64>> {
         match self.pending_read_index.get_mut(m.get_context()) {
-            None => 0,
+            None => None,
             Some(rs) => {
                 rs.acks.insert(m.get_from());
-                // add one to include an ack from local node
-                rs.acks.len() + 1
+                Some(rs.acks.clone())
             }
         }
     }
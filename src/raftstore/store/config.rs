@@ -43,6 +43,7 @@ const DEFAULT_LOCK_CF_COMPACT_INTERVAL_SECS: u64 = 60 * 10; // 10 min
 // a peer should consider itself as a stale peer that is out of region.
 const DEFAULT_MAX_LEADER_MISSING_SECS: u64 = 2 * 60 * 60;
 const DEFAULT_SNAPSHOT_APPLY_BATCH_SIZE: usize = 1024 * 1024 * 10; // 10m
+const DEFAULT_MAX_CLOCK_DRIFT_MS: u64 = 500;
 
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -99,6 +100,10 @@ pub struct Config {
     pub max_leader_missing_duration: Duration,
 
     pub snap_apply_batch_size: usize,
+
+    /// The leader lease is the election timeout minus raft_max_clock_drift, within which
+    /// the leader serves reads locally.
+    pub raft_max_clock_drift: Duration,
 }
 
 impl Default for Config {
@@ -129,6 +134,7 @@ impl Default for Config {
             max_leader_missing_duration: Duration::from_secs(DEFAULT_MAX_LEADER_MISSING_SECS),
             snap_apply_batch_size: DEFAULT_SNAPSHOT_APPLY_BATCH_SIZE,
             lock_cf_compact_interval_secs: DEFAULT_LOCK_CF_COMPACT_INTERVAL_SECS,
+            raft_max_clock_drift: Duration::from_millis(DEFAULT_MAX_CLOCK_DRIFT_MS),
         }
     }
 }
@@ -150,6 +156,24 @@ impl Config {
                                 self.region_split_size));
         }
 
+        if self.max_leader_lease() == Duration::new(0, 0) {
+            return Err(box_err!("max clock drift {:?} must < election timeout {} ticks",
+                                self.raft_max_clock_drift,
+                                self.raft_election_timeout_ticks));
+        }
+
         Ok(())
     }
+
+    /// Returns how long the leader lease lasts after a quorum acknowledges the leader.
+    /// Followers reject votes until an election timeout after hearing from the leader, but
+    /// the first tick of the timeout may come at once, so it's one tick shorter.
+    pub fn max_leader_lease(&self) -> Duration {
+        let ticks = self.raft_election_timeout_ticks.saturating_sub(1) as u64;
+        let election_timeout = Duration::from_millis(self.raft_base_tick_interval * ticks);
+        if election_timeout <= self.raft_max_clock_drift {
+            return Duration::new(0, 0);
+        }
+        election_timeout - self.raft_max_clock_drift
+    }
 }
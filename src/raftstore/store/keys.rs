@@ -53,6 +53,7 @@ pub const REGION_META_MAX_KEY: &'static [u8] = &[LOCAL_PREFIX, REGION_META_PREFI
 pub const RAFT_LOG_SUFFIX: u8 = 0x01;
 pub const RAFT_STATE_SUFFIX: u8 = 0x02;
 pub const APPLY_STATE_SUFFIX: u8 = 0x03;
+pub const MVCC_STATS_SUFFIX: u8 = 0x04;
 
 // For region meta
 pub const REGION_STATE_SUFFIX: u8 = 0x01;
@@ -95,6 +96,10 @@ pub fn apply_state_key(region_id: u64) -> Vec<u8> {
     make_region_id_key(region_id, APPLY_STATE_SUFFIX, 0)
 }
 
+pub fn mvcc_stats_key(region_id: u64) -> Vec<u8> {
+    make_region_id_key(region_id, MVCC_STATS_SUFFIX, 0)
+}
+
 /// Get the log index from raft log key generated by `raft_log_key`.
 pub fn raft_log_index(key: &[u8]) -> Result<u64> {
     let expect_key_len = REGION_RAFT_PREFIX_KEY.len() + mem::size_of::<u64>() +
@@ -218,6 +223,7 @@ mod tests {
             assert!(raft_log_key(region_id, 1).starts_with(&prefix));
             assert!(raft_state_key(region_id).starts_with(&prefix));
             assert!(apply_state_key(region_id).starts_with(&prefix));
+            assert!(mvcc_stats_key(region_id).starts_with(&prefix));
         }
 
         // test sort.
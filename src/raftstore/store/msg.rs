@@ -17,10 +17,12 @@ use std::fmt;
 use kvproto::eraftpb::Snapshot;
 use kvproto::raft_serverpb::RaftMessage;
 use kvproto::raft_cmdpb::{RaftCmdRequest, RaftCmdResponse};
-use kvproto::metapb::RegionEpoch;
+use kvproto::metapb::{Region, RegionEpoch, Peer};
 use raft::SnapshotStatus;
+use storage::mvcc::MvccStats;
 
 pub type Callback = Box<FnBox(RaftCmdResponse) + Send>;
+pub type LeaderRegionsCallback = Box<FnBox(Vec<(Region, Peer, MvccStats)>) + Send>;
 
 #[derive(Debug)]
 pub enum Tick {
@@ -65,6 +67,12 @@ pub enum Msg {
         region_id: u64,
         snap: Option<Snapshot>,
     },
+
+    // The system time jumped back, the leader leases can't be trusted any more.
+    ClockJumped,
+
+    // Gets the regions led by the store along with the leader peers and their mvcc stats.
+    LeaderRegions { callback: LeaderRegionsCallback },
 }
 
 impl fmt::Debug for Msg {
@@ -88,6 +96,8 @@ impl fmt::Debug for Msg {
                        region_id)
             }
             Msg::SnapshotStats => write!(fmt, "Snapshot stats"),
+            Msg::ClockJumped => write!(fmt, "Clock Jumped"),
+            Msg::LeaderRegions { .. } => write!(fmt, "Leader Regions"),
             Msg::SnapGenRes { region_id, ref snap } => {
                 write!(fmt,
                        "SnapGenRes [region_id: {}, is_success: {}]",
@@ -27,7 +27,7 @@ use kvproto::metapb;
 use kvproto::eraftpb::{self, ConfChangeType, MessageType};
 use kvproto::raft_cmdpb::{RaftCmdRequest, RaftCmdResponse, ChangePeerRequest, CmdType,
                           AdminCmdType, Request, Response, AdminRequest, AdminResponse,
-                          TransferLeaderRequest, TransferLeaderResponse};
+                          TransferLeaderRequest, TransferLeaderResponse, ChangePeersRequest};
 use kvproto::raft_serverpb::{RaftMessage, RaftApplyState, RaftTruncatedState, PeerState};
 use kvproto::pdpb::PeerStats;
 use raft::{self, RawNode, StateRole, SnapshotStatus, Ready, ProgressState, INVALID_INDEX};
@@ -36,7 +36,8 @@ use raftstore::coprocessor::CoprocessorHost;
 use raftstore::coprocessor::split_observer::SplitObserver;
 use util::{escape, SlowTimer, rocksdb};
 use pd::{PdClient, INVALID_ID};
-use storage::{CF_LOCK, CF_RAFT};
+use storage::{CF_DEFAULT, CF_LOCK, CF_WRITE, CF_RAFT};
+use storage::mvcc::{MvccStats, Write};
 use super::store::{Store, RaftReadyMetrics, RaftMessageMetrics, RaftMetrics};
 use super::peer_storage::{PeerStorage, ApplySnapResult, write_initial_state, write_peer_state};
 use super::util;
@@ -44,7 +45,7 @@ use super::msg::Callback;
 use super::cmd_resp;
 use super::transport::Transport;
 use super::keys;
-use super::engine::{Snapshot, Peekable, Mutable};
+use super::engine::{self, Snapshot, Peekable, Mutable};
 use super::metrics::*;
 
 const TRANSFER_LEADER_ALLOW_LOG_LAG: u64 = 10;
@@ -84,6 +85,10 @@ pub enum ExecResult {
         peer: metapb::Peer,
         region: metapb::Region,
     },
+    ChangePeers {
+        region: metapb::Region,
+        removed: Vec<metapb::Peer>,
+    },
     CompactLog { state: RaftTruncatedState },
     SplitRegion {
         left: metapb::Region,
@@ -153,6 +158,56 @@ impl PendingCmdQueue {
     }
 }
 
+/// The reads batched under a single read index context.
+struct ReadIndexRequest {
+    id: Uuid,
+    term: u64,
+    cmds: Vec<(PendingCmd, RaftCmdRequest)>,
+    // The time the read index is sent, None if it's not sent yet.
+    send_time: Option<Instant>,
+    read_index: Option<u64>,
+}
+
+#[derive(Default)]
+struct ReadIndexQueue {
+    reads: VecDeque<ReadIndexRequest>,
+    uuids: HashSet<Uuid>,
+}
+
+impl ReadIndexQueue {
+    fn contains(&self, uuid: &Uuid) -> bool {
+        self.uuids.contains(uuid)
+    }
+
+    // Adds the read to the last batch unless its read index has been sent.
+    fn push(&mut self, term: u64, cmd: PendingCmd, req: RaftCmdRequest) {
+        self.uuids.insert(cmd.uuid);
+        if let Some(read) = self.reads.back_mut() {
+            if read.send_time.is_none() && read.term == term {
+                read.cmds.push((cmd, req));
+                return;
+            }
+        }
+        self.reads.push_back(ReadIndexRequest {
+            id: Uuid::new_v4(),
+            term: term,
+            cmds: vec![(cmd, req)],
+            send_time: None,
+            read_index: None,
+        });
+    }
+
+    fn pop_front(&mut self) -> Option<ReadIndexRequest> {
+        let read = self.reads.pop_front();
+        if let Some(ref read) = read {
+            for &(ref cmd, _) in &read.cmds {
+                self.uuids.remove(&cmd.uuid);
+            }
+        }
+        read
+    }
+}
+
 /// Call the callback of `cmd` that the region is removed.
 fn notify_region_removed(region_id: u64, peer_id: u64, mut cmd: PendingCmd) {
     let region_not_found = Error::RegionNotFound(region_id);
@@ -175,6 +230,8 @@ pub struct Peer {
     region_id: u64,
     pub raft_group: RawNode<PeerStorage>,
     pending_cmds: PendingCmdQueue,
+    // The reads waiting for the read index to be confirmed and applied.
+    pending_reads: ReadIndexQueue,
     // Record the last instant of each peer's heartbeat response.
     pub peer_heartbeats: HashMap<u64, Instant>,
     coprocessor_host: CoprocessorHost,
@@ -185,6 +242,16 @@ pub struct Peer {
 
     leader_missing_time: Option<Instant>,
 
+    // The term and the expired time of the leader lease, within which the reads are served
+    // locally. See `renew_leader_lease` for details.
+    leader_lease: Option<(u64, Instant)>,
+    max_leader_lease: Duration,
+    // The term, index and propose time of the proposals not committed yet, which renew the
+    // leader lease once committed.
+    proposal_times: VecDeque<(u64, u64, Instant)>,
+    // The term, context and send time of the read index used to renew the leader lease.
+    lease_read_index: Option<(u64, Vec<u8>, Instant)>,
+
     pub tag: String,
 
     pub last_compacted_idx: u64,
@@ -265,6 +332,7 @@ impl Peer {
             region_id: region.get_id(),
             raft_group: raft_group,
             pending_cmds: Default::default(),
+            pending_reads: Default::default(),
             peer_cache: store.peer_cache(),
             peer_heartbeats: HashMap::new(),
             coprocessor_host: CoprocessorHost::new(),
@@ -272,6 +340,10 @@ impl Peer {
             delete_keys_hint: 0,
             pending_remove: false,
             leader_missing_time: Some(Instant::now()),
+            leader_lease: None,
+            max_leader_lease: cfg.max_leader_lease(),
+            proposal_times: VecDeque::new(),
+            lease_read_index: None,
             tag: tag,
             last_compacted_idx: 0,
         };
@@ -314,6 +386,11 @@ impl Peer {
         if let Some(cmd) = self.pending_cmds.conf_change.take() {
             notify_region_removed(self.region_id, peer_id, cmd);
         }
+        while let Some(read) = self.pending_reads.pop_front() {
+            for (cmd, _) in read.cmds {
+                notify_region_removed(self.region_id, peer_id, cmd);
+            }
+        }
 
         if self.get_store().is_initialized() {
             // If we meet panic when deleting data and raft log, the dirty data
@@ -498,6 +575,8 @@ impl Peer {
             return Ok(None);
         }
 
+        self.send_read_index();
+
         if !self.raft_group.has_ready() {
             return Ok(None);
         }
@@ -578,6 +657,12 @@ impl Peer {
             try!(self.handle_raft_commit_entries(&ready.committed_entries))
         };
 
+        self.renew_lease_by_proposals();
+        self.renew_lease_by_read_states(&ready.read_states);
+        if !self.is_applying() {
+            self.apply_reads(&ready.read_states);
+        }
+
         self.raft_group.advance(ready);
         Ok(())
     }
@@ -590,7 +675,7 @@ impl Peer {
                    req: RaftCmdRequest,
                    mut err_resp: RaftCmdResponse)
                    -> bool {
-        if self.pending_cmds.contains(&cmd.uuid) {
+        if self.pending_cmds.contains(&cmd.uuid) || self.pending_reads.contains(&cmd.uuid) {
             cmd_resp::bind_error(&mut err_resp, box_err!("duplicated uuid {:?}", cmd.uuid));
             cmd.call(err_resp);
             return false;
@@ -605,16 +690,15 @@ impl Peer {
 
             // for read-only, if we don't care stale read, we can
             // execute these commands immediately in leader.
-            let mut ctx = ExecContext::new(self, 0, 0, &req);
-            let (mut resp, _) = self.exec_raft_cmd(&mut ctx).unwrap_or_else(|e| {
-                error!("{} execute raft command err: {:?}", self.tag, e);
-                (cmd_resp::new_error(e), None)
-            });
-
-            cmd_resp::bind_uuid(&mut resp, cmd.uuid);
-            cmd_resp::bind_term(&mut resp, self.term());
-            cmd.call(resp);
+            self.exec_read(cmd, &req);
             return false;
+        } else if self.is_read_index(&req) {
+            PEER_PROPOSAL_COUNTER_VEC.with_label_values(&["read_index"]).inc();
+
+            // The reads in the same tick share a read index, which is sent when handling
+            // the raft ready, and nothing is appended to the log.
+            let term = self.term();
+            self.pending_reads.push(term, cmd, req);
         } else if get_transfer_leader_cmd(&req).is_some() {
             let transfer_leader = get_transfer_leader_cmd(&req).unwrap();
             let peer = transfer_leader.get_peer();
@@ -631,7 +715,7 @@ impl Peer {
             // return immediately. Note that this command may fail, we can view it just as an advice
             cmd.call(make_transfer_leader_response());
             return false;
-        } else if get_change_peer_cmd(&req).is_some() {
+        } else if is_conf_change_cmd(&req) {
             if self.raft_group.raft.pending_conf {
                 info!("{} there is a pending conf change, try later", self.tag);
                 cmd_resp::bind_error(&mut err_resp,
@@ -640,6 +724,21 @@ impl Peer {
                 cmd.call(err_resp);
                 return false;
             }
+            // Only leaving is allowed in joint consensus.
+            let in_joint = self.raft_group.raft.in_joint();
+            let leaving = get_change_peers_cmd(&req).map_or(false, |r| r.get_changes().is_empty());
+            if leaving != in_joint {
+                info!("{} [in joint: {}] conf change is not allowed, try later",
+                      self.tag,
+                      in_joint);
+                cmd_resp::bind_error(&mut err_resp,
+                                     box_err!("{} [in joint: {}] conf change is not allowed, \
+                                               try later",
+                                              self.tag,
+                                              in_joint));
+                cmd.call(err_resp);
+                return false;
+            }
             if let Some(cmd) = self.pending_cmds.take_conf_change() {
                 // if it loses leadership before conf change is replicated, there may be
                 // a stale pending conf change before next conf change is applied. If it
@@ -668,7 +767,7 @@ impl Peer {
 
     fn is_local_read(&self, req: &RaftCmdRequest) -> bool {
         if (req.has_header() && req.get_header().get_read_quorum()) ||
-           !self.raft_group.raft.in_lease() || req.get_requests().len() == 0 {
+           !self.is_in_leader_lease() || req.get_requests().len() == 0 {
             return false;
         }
 
@@ -678,13 +777,186 @@ impl Peer {
             return false;
         }
 
-        for cmd_req in req.get_requests() {
-            if cmd_req.get_cmd_type() != CmdType::Snap && cmd_req.get_cmd_type() != CmdType::Get {
-                return false;
+        is_read_cmd(req)
+    }
+
+    fn is_read_index(&self, req: &RaftCmdRequest) -> bool {
+        // The read index is only known to be committed after the leader has applied an
+        // entry of its term, the reads are proposed to the log before that.
+        self.is_leader() && self.get_store().applied_index_term == self.term() &&
+        is_read_cmd(req)
+    }
+
+    // Executes the read command `req` on the applied state.
+    fn exec_read(&mut self, mut cmd: PendingCmd, req: &RaftCmdRequest) {
+        let mut ctx = ExecContext::new(self, 0, 0, req);
+        let (mut resp, _) = self.exec_raft_cmd(&mut ctx).unwrap_or_else(|e| {
+            error!("{} execute raft command err: {:?}", self.tag, e);
+            (cmd_resp::new_error(e), None)
+        });
+
+        cmd_resp::bind_uuid(&mut resp, cmd.uuid);
+        cmd_resp::bind_term(&mut resp, self.term());
+        cmd.call(resp);
+    }
+
+    // Sends the read index for the reads batched since the last one.
+    fn send_read_index(&mut self) {
+        let id = match self.pending_reads.reads.back_mut() {
+            Some(read) => {
+                if read.send_time.is_some() {
+                    return;
+                }
+                read.send_time = Some(Instant::now());
+                read.id
+            }
+            None => return,
+        };
+        self.raft_group.read_index(id.as_bytes().to_vec());
+    }
+
+    // Records the read indexes confirmed in `read_states`, and executes the reads whose read
+    // index has been applied. The reads are in the same order as their read indexes.
+    fn apply_reads(&mut self, read_states: &[raft::ReadState]) {
+        for rs in read_states {
+            let mut renew_time = None;
+            for read in &mut self.pending_reads.reads {
+                if read.id.as_bytes()[..] == rs.request_ctx[..] {
+                    read.read_index = Some(rs.index);
+                    renew_time = read.send_time.map(|t| (read.term, t));
+                    break;
+                }
+            }
+            // A quorum has acknowledged the heartbeats sent with the read index.
+            if let Some((term, ts)) = renew_time {
+                self.renew_leader_lease(term, ts);
             }
         }
 
-        true
+        let (is_leader, term) = (self.is_leader(), self.term());
+        let applied_index = self.get_store().applied_index();
+        loop {
+            let done = match self.pending_reads.reads.front() {
+                Some(read) => {
+                    match read.read_index {
+                        Some(index) => index <= applied_index,
+                        // The read index is dropped when the leader steps down, while a
+                        // confirmed one is still valid.
+                        None => !is_leader || read.term != term,
+                    }
+                }
+                None => break,
+            };
+            if !done {
+                break;
+            }
+            let read = self.pending_reads.pop_front().unwrap();
+            if read.read_index.is_some() {
+                for (cmd, req) in read.cmds {
+                    self.exec_read(cmd, &req);
+                }
+            } else {
+                for (cmd, _) in read.cmds {
+                    self.notify_not_leader(cmd);
+                }
+            }
+        }
+    }
+
+    /// Returns true if the peer is the leader and its lease is valid, so no other peer can
+    /// be elected and the reads can be served locally.
+    pub fn is_in_leader_lease(&self) -> bool {
+        if !self.is_leader() || self.raft_group.raft.lead_transferee.is_some() {
+            return false;
+        }
+        match self.leader_lease {
+            Some((term, expired_time)) => term == self.term() && Instant::now() < expired_time,
+            None => false,
+        }
+    }
+
+    /// Renews the leader lease of `term` with the time `ts` at which a message was sent to
+    /// the followers, after a quorum has acknowledged the message. The followers reject
+    /// votes within an election timeout after hearing from the leader, so no other peer can
+    /// be elected before the lease expires.
+    fn renew_leader_lease(&mut self, term: u64, ts: Instant) {
+        if !self.is_leader() || term != self.term() {
+            return;
+        }
+        let expired_time = ts + self.max_leader_lease;
+        if let Some((t, old)) = self.leader_lease {
+            if t == term && old >= expired_time {
+                return;
+            }
+        }
+        self.leader_lease = Some((term, expired_time));
+    }
+
+    /// Drops the leader lease, the reads go through raft until the lease is renewed by the
+    /// messages sent after now.
+    pub fn expire_leader_lease(&mut self) {
+        self.proposal_times.clear();
+        self.lease_read_index = None;
+        if self.leader_lease.take().is_some() {
+            info!("{} leader lease expired", self.tag);
+        }
+    }
+
+    // Renews the leader lease with the committed proposals, the followers have received
+    // them after they were proposed.
+    fn renew_lease_by_proposals(&mut self) {
+        let (term, committed) = (self.term(), self.raft_group.raft.raft_log.committed);
+        let mut renew_time = None;
+        while let Some(&(t, index, ts)) = self.proposal_times.front() {
+            if t == term && index > committed {
+                break;
+            }
+            self.proposal_times.pop_front();
+            if t == term {
+                renew_time = Some(ts);
+            }
+        }
+        if let Some(ts) = renew_time {
+            self.renew_leader_lease(term, ts);
+        }
+    }
+
+    // Renews the leader lease if the read index sent for it has been acknowledged by the
+    // heartbeat responses of a quorum.
+    fn renew_lease_by_read_states(&mut self, read_states: &[raft::ReadState]) {
+        let acked = match self.lease_read_index {
+            Some((_, ref ctx, _)) => read_states.iter().any(|rs| rs.request_ctx == *ctx),
+            None => false,
+        };
+        if acked {
+            let (term, _, ts) = self.lease_read_index.take().unwrap();
+            self.renew_leader_lease(term, ts);
+        }
+    }
+
+    /// Sends a read index to renew the leader lease with the heartbeat responses if the lease
+    /// is going to expire, so the reads can keep being served locally without writes.
+    pub fn maybe_renew_leader_lease(&mut self) {
+        if !self.is_leader() {
+            self.lease_read_index = None;
+            self.proposal_times.clear();
+            return;
+        }
+        let (term, now) = (self.term(), Instant::now());
+        if let Some((t, _, ts)) = self.lease_read_index {
+            // Wait for the pending one unless it's stale.
+            if t == term && now.duration_since(ts) < self.max_leader_lease {
+                return;
+            }
+        }
+        if let Some((t, expired_time)) = self.leader_lease {
+            if t == term && expired_time > now + self.max_leader_lease / 2 {
+                return;
+            }
+        }
+        let ctx = Uuid::new_v4().as_bytes().to_vec();
+        self.raft_group.read_index(ctx.clone());
+        self.lease_read_index = Some((term, ctx, now));
     }
 
     /// Call the callback of `cmd` that leadership may have been changed.
@@ -709,12 +981,14 @@ impl Peer {
         PEER_PROPOSE_LOG_SIZE_HISTOGRAM.observe(data.len() as f64);
 
         let propose_index = self.next_proposal_index();
+        let propose_time = Instant::now();
         try!(self.raft_group.propose(data));
         if self.next_proposal_index() == propose_index {
             // The message is dropped silently, this usually due to leader absence
             // or transferring leader. Both cases can be considered as NotLeader error.
             return Err(Error::NotLeader(self.region_id, None));
         }
+        self.proposal_times.push_back((self.term(), propose_index, propose_time));
 
         Ok(())
     }
@@ -724,6 +998,8 @@ impl Peer {
 
         info!("{} transfer leader to {:?}", self.tag, peer);
 
+        // The transferee campaigns at once regardless of the lease.
+        self.expire_leader_lease();
         self.raft_group.transfer_leader(peer.get_id());
     }
 
@@ -752,11 +1028,17 @@ impl Peer {
 
         PEER_PROPOSE_LOG_SIZE_HISTOGRAM.observe(data.len() as f64);
 
-        let change_peer = get_change_peer_cmd(&cmd).unwrap();
-
         let mut cc = eraftpb::ConfChange::new();
-        cc.set_change_type(change_peer.get_change_type());
-        cc.set_node_id(change_peer.get_peer().get_id());
+        if let Some(change_peer) = get_change_peer_cmd(&cmd) {
+            cc.set_change_type(change_peer.get_change_type());
+            cc.set_node_id(change_peer.get_peer().get_id());
+        } else if get_change_peers_cmd(&cmd).unwrap().get_changes().is_empty() {
+            cc.set_change_type(ConfChangeType::FinalizeMembershipChange);
+        } else {
+            // The new configuration is filled when the command is applied, as it's decided
+            // by the region at that time.
+            cc.set_change_type(ConfChangeType::BeginMembershipChange);
+        }
         cc.set_context(data);
 
         info!("{} propose conf change {:?} peer {:?}",
@@ -765,12 +1047,14 @@ impl Peer {
               cc.get_node_id());
 
         let propose_index = self.next_proposal_index();
+        let propose_time = Instant::now();
         try!(self.raft_group.propose_conf_change(cc));
         if self.next_proposal_index() == propose_index {
             // The message is dropped silently, this usually due to leader absence
             // or transferring leader. Both cases can be considered as NotLeader error.
             return Err(Error::NotLeader(self.region_id, None));
         }
+        self.proposal_times.push_back((self.term(), propose_index, propose_time));
 
         Ok(())
     }
@@ -782,7 +1066,8 @@ impl Peer {
                 AdminCmdType::CompactLog |
                 AdminCmdType::InvalidAdmin => {}
                 AdminCmdType::Split => check_ver = true,
-                AdminCmdType::ChangePeer => check_conf_ver = true,
+                AdminCmdType::ChangePeer |
+                AdminCmdType::ChangePeers => check_conf_ver = true,
                 AdminCmdType::TransferLeader => {
                     check_ver = true;
                     check_conf_ver = true;
@@ -829,7 +1114,8 @@ impl Peer {
         }
 
         // Try to find in region, if found, set in cache.
-        for peer in self.get_store().get_region().get_peers() {
+        let region = self.get_store().get_region();
+        for peer in region.get_peers().iter().chain(region.get_outgoing_peers()) {
             if peer.get_id() == peer_id {
                 self.peer_cache.borrow_mut().insert(peer_id, peer.clone());
                 return Some(peer.clone());
@@ -989,18 +1275,21 @@ impl Peer {
         let term = entry.get_term();
         let conf_change = try!(protobuf::parse_from_bytes::<eraftpb::ConfChange>(entry.get_data()));
         let cmd = try!(protobuf::parse_from_bytes::<RaftCmdRequest>(conf_change.get_context()));
-        let (res, cc) = match self.process_raft_cmd(index, term, cmd) {
+        let (res, mut cc) = match self.process_raft_cmd(index, term, cmd) {
             res @ Some(_) => (res, conf_change),
             // If failed, tell raft that the config change was aborted.
             None => (None, eraftpb::ConfChange::new()),
         };
+        if cc.get_change_type() == ConfChangeType::BeginMembershipChange {
+            cc.set_configuration(util::conf_state_from_region(self.region()));
+        }
         self.raft_group.apply_conf_change(cc);
 
         Ok(res)
     }
 
     fn find_cb(&mut self, uuid: Uuid, term: u64, cmd: &RaftCmdRequest) -> Option<Callback> {
-        if get_change_peer_cmd(cmd).is_some() {
+        if is_conf_change_cmd(cmd) {
             if let Some(mut cmd) = self.pending_cmds.take_conf_change() {
                 if cmd.uuid == uuid {
                     return Some(cmd.cb.take().unwrap());
@@ -1097,11 +1386,13 @@ impl Peer {
 
         let mut storage = self.mut_store();
         storage.apply_state = ctx.apply_state;
+        storage.mvcc_stats = ctx.mvcc_stats;
         storage.applied_index_term = term;
 
         if let Some(ref exec_result) = exec_result {
             match *exec_result {
-                ExecResult::ChangePeer { ref region, .. } => {
+                ExecResult::ChangePeer { ref region, .. } |
+                ExecResult::ChangePeers { ref region, .. } => {
                     storage.region = region.clone();
                 }
                 ExecResult::CompactLog { .. } => {}
@@ -1131,9 +1422,22 @@ impl Peer {
             info!("{} clear pending conf change", self.tag);
             cmd.cb.take();
         }
+        while let Some(read) = self.pending_reads.pop_front() {
+            info!("{} clear {} reads", self.tag, read.cmds.len());
+            for (mut cmd, _) in read.cmds {
+                cmd.cb.take();
+            }
+        }
     }
 }
 
+fn is_read_cmd(msg: &RaftCmdRequest) -> bool {
+    !msg.get_requests().is_empty() &&
+    msg.get_requests().iter().all(|req| {
+        req.get_cmd_type() == CmdType::Snap || req.get_cmd_type() == CmdType::Get
+    })
+}
+
 fn get_transfer_leader_cmd(msg: &RaftCmdRequest) -> Option<&TransferLeaderRequest> {
     if !msg.has_admin_request() {
         return None;
@@ -1158,9 +1462,97 @@ fn get_change_peer_cmd(msg: &RaftCmdRequest) -> Option<&ChangePeerRequest> {
     Some(req.get_change_peer())
 }
 
+fn get_change_peers_cmd(msg: &RaftCmdRequest) -> Option<&ChangePeersRequest> {
+    if !msg.has_admin_request() {
+        return None;
+    }
+    let req = msg.get_admin_request();
+    if !req.has_change_peers() {
+        return None;
+    }
+
+    Some(req.get_change_peers())
+}
+
+fn is_conf_change_cmd(msg: &RaftCmdRequest) -> bool {
+    get_change_peer_cmd(msg).is_some() || get_change_peers_cmd(msg).is_some()
+}
+
+/// Applies `changes` to `region` and enters joint consensus, the current voters are kept as
+/// the outgoing peers. Returns the learners removed, which leave at once.
+fn enter_joint(region: &mut metapb::Region,
+               changes: &[ChangePeerRequest])
+               -> Result<Vec<metapb::Peer>> {
+    if !region.get_outgoing_peers().is_empty() {
+        return Err(box_err!("region {:?} is already in joint consensus", region));
+    }
+    let voters: Vec<_> =
+        region.get_peers().iter().filter(|p| !p.get_is_learner()).cloned().collect();
+    let mut removed = vec![];
+    let mut changed_stores = HashSet::new();
+    for change in changes {
+        let change_type = change.get_change_type();
+        let peer = change.get_peer();
+        let store_id = peer.get_store_id();
+        if !changed_stores.insert(store_id) {
+            return Err(box_err!("store {} is changed more than once in {:?}", store_id, changes));
+        }
+        let exist = util::find_peer(region, store_id).cloned();
+        match (change_type, exist) {
+            (ConfChangeType::AddNode, Some(ref p)) if p.get_is_learner() &&
+                                                      p.get_id() == peer.get_id() => {
+                // Promotes the learner to a voter, the log it received is kept.
+                for p in region.mut_peers().iter_mut() {
+                    if p.get_store_id() == store_id {
+                        p.set_is_learner(false);
+                    }
+                }
+            }
+            (ConfChangeType::AddNode, None) |
+            (ConfChangeType::AddLearnerNode, None) => {
+                let mut peer = peer.clone();
+                peer.set_is_learner(change_type == ConfChangeType::AddLearnerNode);
+                region.mut_peers().push(peer);
+            }
+            (ConfChangeType::RemoveNode, Some(ref p)) if p.get_id() == peer.get_id() => {
+                util::remove_peer(region, store_id).unwrap();
+                // The voters are removed when leaving joint consensus.
+                if p.get_is_learner() {
+                    removed.push(p.clone());
+                }
+            }
+            (_, exist) => {
+                return Err(box_err!("can't {} peer {:?} with {:?} in region {:?}",
+                                    util::conf_change_type_str(&change_type),
+                                    peer,
+                                    exist,
+                                    region))
+            }
+        }
+    }
+    if region.get_peers().iter().all(|p| p.get_is_learner()) {
+        return Err(box_err!("no voters are left in region {:?} after {:?}", region, changes));
+    }
+    region.set_outgoing_peers(protobuf::RepeatedField::from_vec(voters));
+    Ok(removed)
+}
+
+/// Leaves joint consensus. Returns the outgoing peers which aren't in `region` any more.
+fn leave_joint(region: &mut metapb::Region) -> Result<Vec<metapb::Peer>> {
+    if region.get_outgoing_peers().is_empty() {
+        return Err(box_err!("region {:?} is not in joint consensus", region));
+    }
+    let removed = region.take_outgoing_peers()
+        .into_iter()
+        .filter(|p| region.get_peers().iter().all(|x| x.get_id() != p.get_id()))
+        .collect();
+    Ok(removed)
+}
+
 struct ExecContext<'a> {
     pub snap: Snapshot,
     pub apply_state: RaftApplyState,
+    pub mvcc_stats: MvccStats,
     pub wb: WriteBatch,
     pub req: &'a RaftCmdRequest,
     pub index: u64,
@@ -1172,6 +1564,7 @@ impl<'a> ExecContext<'a> {
         ExecContext {
             snap: Snapshot::new(peer.engine.clone()),
             apply_state: peer.get_store().apply_state.clone(),
+            mvcc_stats: peer.get_store().mvcc_stats.clone(),
             wb: WriteBatch::new(),
             req: req,
             index: index,
@@ -1184,6 +1577,9 @@ impl<'a> ExecContext<'a> {
         try!(self.wb.put_msg_cf(raft_cf,
                                 &keys::apply_state_key(region_id),
                                 &self.apply_state));
+        try!(self.wb.put_cf(raft_cf,
+                            &keys::mvcc_stats_key(region_id),
+                            &self.mvcc_stats.to_bytes()));
         Ok(())
     }
 }
@@ -1216,6 +1612,7 @@ impl Peer {
 
         let (mut response, exec_result) = try!(match cmd_type {
             AdminCmdType::ChangePeer => self.exec_change_peer(ctx, request),
+            AdminCmdType::ChangePeers => self.exec_change_peers(ctx, request),
             AdminCmdType::Split => self.exec_split(ctx, request),
             AdminCmdType::CompactLog => self.exec_compact_log(ctx, request),
             AdminCmdType::TransferLeader => Err(box_err!("transfer leader won't exec")),
@@ -1245,13 +1642,51 @@ impl Peer {
 
         // TODO: we should need more check, like peer validation, duplicated id, etc.
         let exists = util::find_peer(&region, store_id).is_some();
+        let is_learner = util::find_peer(&region, store_id).map_or(false, |p| p.get_is_learner());
         let conf_ver = region.get_region_epoch().get_conf_ver() + 1;
 
         region.mut_region_epoch().set_conf_ver(conf_ver);
 
         match change_type {
-            eraftpb::ConfChangeType::AddNode => {
-                PEER_ADMIN_CMD_COUNTER_VEC.with_label_values(&["add_peer", "all"]).inc();
+            eraftpb::ConfChangeType::AddNode if is_learner => {
+                PEER_ADMIN_CMD_COUNTER_VEC.with_label_values(&["promote_learner", "all"]).inc();
+
+                // Promotes the learner to a voter, the log it received is kept.
+                for p in region.mut_peers().iter_mut() {
+                    if p.get_store_id() == store_id {
+                        if p.get_id() != peer.get_id() {
+                            error!("{} can't promote learner {:?} with peer {:?} in region {:?}",
+                                   self.tag,
+                                   p,
+                                   peer,
+                                   self.region());
+                            return Err(box_err!("can't promote learner {:?} with peer {:?}",
+                                                p,
+                                                peer));
+                        }
+                        p.set_is_learner(false);
+                    }
+                }
+                let mut voter = peer.clone();
+                voter.set_is_learner(false);
+                self.peer_cache.borrow_mut().insert(peer.get_id(), voter);
+
+                PEER_ADMIN_CMD_COUNTER_VEC.with_label_values(&["promote_learner", "success"])
+                    .inc();
+
+                info!("{} promote learner {:?} in region {:?}",
+                      self.tag,
+                      peer,
+                      self.region());
+            }
+            eraftpb::ConfChangeType::AddNode |
+            eraftpb::ConfChangeType::AddLearnerNode => {
+                let tag = if change_type == eraftpb::ConfChangeType::AddNode {
+                    "add_peer"
+                } else {
+                    "add_learner"
+                };
+                PEER_ADMIN_CMD_COUNTER_VEC.with_label_values(&[tag, "all"]).inc();
 
                 if exists {
                     error!("{} can't add duplicated peer {:?} to region {:?}",
@@ -1264,12 +1699,14 @@ impl Peer {
                 }
                 // TODO: Do we allow adding peer in same node?
 
+                let mut peer = peer.clone();
+                peer.set_is_learner(change_type == eraftpb::ConfChangeType::AddLearnerNode);
                 // Add this peer to cache.
                 self.peer_cache.borrow_mut().insert(peer.get_id(), peer.clone());
                 self.peer_heartbeats.insert(peer.get_id(), Instant::now());
                 region.mut_peers().push(peer.clone());
 
-                PEER_ADMIN_CMD_COUNTER_VEC.with_label_values(&["add_peer", "success"]).inc();
+                PEER_ADMIN_CMD_COUNTER_VEC.with_label_values(&[tag, "success"]).inc();
 
                 info!("{} add peer {:?} to region {:?}",
                       self.tag,
@@ -1330,8 +1767,80 @@ impl Peer {
         })))
     }
 
+    fn exec_change_peers(&mut self,
+                         ctx: &ExecContext,
+                         request: &AdminRequest)
+                         -> Result<(AdminResponse, Option<ExecResult>)> {
+        let changes = request.get_change_peers().get_changes();
+        let mut region = self.region().clone();
+        let tag = if changes.is_empty() {
+            "leave_joint"
+        } else {
+            "enter_joint"
+        };
+        PEER_ADMIN_CMD_COUNTER_VEC.with_label_values(&[tag, "all"]).inc();
+
+        info!("{} exec ChangePeers {:?}, epoch: {:?}",
+              self.tag,
+              changes,
+              region.get_region_epoch());
+
+        let removed = if changes.is_empty() {
+            try!(leave_joint(&mut region))
+        } else {
+            try!(enter_joint(&mut region, changes))
+        };
+        let conf_ver = region.get_region_epoch().get_conf_ver() + 1;
+        region.mut_region_epoch().set_conf_ver(conf_ver);
+
+        for change in changes {
+            if let Some(peer) = util::find_peer(&region, change.get_peer().get_store_id()) {
+                // Add the new or promoted peers to cache.
+                self.peer_cache.borrow_mut().insert(peer.get_id(), peer.clone());
+                self.peer_heartbeats.entry(peer.get_id()).or_insert_with(Instant::now);
+            }
+        }
+        for peer in &removed {
+            if self.peer_id() == peer.get_id() {
+                // Remove ourself, we will destroy all region data later.
+                // So we need not to apply following logs.
+                self.pending_remove = true;
+            }
+            // Remove this peer from cache.
+            self.peer_cache.borrow_mut().remove(&peer.get_id());
+            self.peer_heartbeats.remove(&peer.get_id());
+        }
+
+        if self.pending_remove {
+            self.get_store()
+                .clear_meta(&ctx.wb)
+                .and_then(|_| write_peer_state(&ctx.wb, &region, PeerState::Tombstone))
+                .unwrap_or_else(|e| panic!("{} failed to remove self: {:?}", self.tag, e));
+        } else {
+            write_peer_state(&ctx.wb, &region, PeerState::Normal)
+                .unwrap_or_else(|e| panic!("{} failed to update region state: {:?}", self.tag, e));
+        }
+
+        PEER_ADMIN_CMD_COUNTER_VEC.with_label_values(&[tag, "success"]).inc();
+
+        info!("{} {} with removed {:?}, region: {:?}",
+              self.tag,
+              tag,
+              removed,
+              region);
+
+        let mut resp = AdminResponse::new();
+        resp.mut_change_peers().set_region(region.clone());
+
+        Ok((resp,
+            Some(ExecResult::ChangePeers {
+            region: region,
+            removed: removed,
+        })))
+    }
+
     fn exec_split(&mut self,
-                  ctx: &ExecContext,
+                  ctx: &mut ExecContext,
                   req: &AdminRequest)
                   -> Result<(AdminResponse, Option<ExecResult>)> {
         PEER_ADMIN_CMD_COUNTER_VEC.with_label_values(&["split", "all"]).inc();
@@ -1401,6 +1910,9 @@ impl Peer {
 
         self.size_diff_hint = 0;
         self.delete_keys_hint = 0;
+        // Which half the counted versions belong to is unknown without scanning them, so
+        // the stats are reset, and the new region starts without any.
+        ctx.mvcc_stats.reset();
 
         PEER_ADMIN_CMD_COUNTER_VEC.with_label_values(&["split", "success"]).inc();
 
@@ -1438,8 +1950,13 @@ impl Peer {
             Some(ExecResult::CompactLog { state: ctx.apply_state.get_truncated_state().clone() })))
     }
 
-    fn exec_write_cmd(&mut self, ctx: &ExecContext) -> Result<RaftCmdResponse> {
+    fn exec_write_cmd(&mut self, ctx: &mut ExecContext) -> Result<RaftCmdResponse> {
         let requests = ctx.req.get_requests();
+        // DeleteRange is applied to the engine directly instead of the write batch, so it can't
+        // be ordered with other requests of the same command.
+        if requests.len() > 1 && requests.iter().any(|r| r.get_cmd_type() == CmdType::DeleteRange) {
+            return Err(box_err!("delete range can't be batched with other requests"));
+        }
         let mut responses = Vec::with_capacity(requests.len());
 
         for req in requests {
@@ -1448,6 +1965,7 @@ impl Peer {
                 CmdType::Get => self.do_get(ctx, req),
                 CmdType::Put => self.do_put(ctx, req),
                 CmdType::Delete => self.do_delete(ctx, req),
+                CmdType::DeleteRange => self.do_delete_range(ctx, req),
                 CmdType::Snap => self.do_snap(ctx, req),
                 CmdType::Invalid => Err(box_err!("invalid cmd type, message maybe currupted")),
             });
@@ -1497,7 +2015,7 @@ impl Peer {
         Ok(resp)
     }
 
-    fn do_put(&mut self, ctx: &ExecContext, req: &Request) -> Result<Response> {
+    fn do_put(&mut self, ctx: &mut ExecContext, req: &Request) -> Result<Response> {
         let (key, value) = (req.get_put().get_key(), req.get_put().get_value());
         try!(self.check_data_key(key));
 
@@ -1513,6 +2031,11 @@ impl Peer {
         self.size_diff_hint += value.len() as u64;
         if req.get_put().has_cf() {
             let cf = req.get_put().get_cf();
+            if cf == CF_WRITE {
+                if let Ok(write) = Write::parse(value) {
+                    ctx.mvcc_stats.add(&write);
+                }
+            }
             // TODO: check whether cf exists or not.
             rocksdb::get_cf_handle(&self.engine, cf)
                 .and_then(|handle| ctx.wb.put_cf(handle, &key, value))
@@ -1536,7 +2059,7 @@ impl Peer {
         Ok(resp)
     }
 
-    fn do_delete(&mut self, ctx: &ExecContext, req: &Request) -> Result<Response> {
+    fn do_delete(&mut self, ctx: &mut ExecContext, req: &Request) -> Result<Response> {
         let key = req.get_delete().get_key();
         try!(self.check_data_key(key));
 
@@ -1551,6 +2074,9 @@ impl Peer {
         let resp = Response::new();
         if req.get_delete().has_cf() {
             let cf = req.get_delete().get_cf();
+            if cf == CF_WRITE {
+                ctx.mvcc_stats.sub();
+            }
             // TODO: check whether cf exists or not.
             rocksdb::get_cf_handle(&self.engine, cf)
                 .and_then(|handle| ctx.wb.delete_cf(handle, &key))
@@ -1571,6 +2097,36 @@ impl Peer {
         Ok(resp)
     }
 
+    fn do_delete_range(&mut self, ctx: &mut ExecContext, req: &Request) -> Result<Response> {
+        let (start_key, end_key) = (req.get_delete_range().get_start_key(),
+                                    req.get_delete_range().get_end_key());
+        try!(self.check_data_key(start_key));
+        let end_key = if end_key.is_empty() {
+            keys::enc_end_key(self.get_store().get_region())
+        } else {
+            try!(util::check_key_in_region_inclusive(end_key, self.get_store().get_region()));
+            keys::data_key(end_key)
+        };
+        let start_key = keys::data_key(start_key);
+        // Every version of the keys in the range is dropped, the caller must make sure it's
+        // safe to do so.
+        for cf in &[CF_DEFAULT, CF_LOCK, CF_WRITE] {
+            engine::delete_in_range_cf(&self.engine, cf, &start_key, &end_key).unwrap_or_else(|e| {
+                panic!("{} failed to delete range [{}, {}) in cf {}: {:?}",
+                       self.tag,
+                       escape(&start_key),
+                       escape(&end_key),
+                       cf,
+                       e)
+            });
+        }
+        self.size_diff_hint = 0;
+        // Versions dropped from the range can't be told apart from the others.
+        ctx.mvcc_stats.reset();
+
+        Ok(Response::new())
+    }
+
     fn do_snap(&mut self, _: &ExecContext, _: &Request) -> Result<Response> {
         let mut resp = Response::new();
         resp.mut_snap().set_region(self.get_store().get_region().clone());
@@ -32,10 +32,12 @@ use util::rocksdb;
 use raft::{self, Storage, RaftState, StorageError, Error as RaftError, Ready};
 use raftstore::{Result, Error};
 use super::worker::RegionTask;
+use super::util::conf_state_from_region;
 use super::keys::{self, enc_start_key, enc_end_key};
 use super::engine::{Snapshot as DbSnapshot, Peekable, Iterable, Mutable};
 use super::{SnapFile, SnapKey, SnapEntry, SnapManager};
 use storage::CF_RAFT;
+use storage::mvcc::MvccStats;
 
 // When we create a region peer, we should initialize its log term/index > 0,
 // so that we can force the follower peer to sync the snapshot first.
@@ -86,6 +88,8 @@ pub struct PeerStorage {
     pub apply_state: RaftApplyState,
     pub applied_index_term: u64,
     pub last_term: u64,
+    // Persisted next to the apply state, updated when applying the raft log.
+    pub mvcc_stats: MvccStats,
 
     snap_state: RefCell<SnapState>,
     region_sched: Scheduler<RegionTask>,
@@ -181,6 +185,13 @@ fn init_apply_state(engine: &DB, region: &Region) -> Result<RaftApplyState> {
     })
 }
 
+fn init_mvcc_stats(engine: &DB, region: &Region) -> Result<MvccStats> {
+    match try!(engine.get_value_cf(CF_RAFT, &keys::mvcc_stats_key(region.get_id()))) {
+        Some(v) => MvccStats::parse(&v).map_err(|e| box_err!("bad format mvcc stats: {:?}", e)),
+        None => Ok(MvccStats::new()),
+    }
+}
+
 fn init_last_term(engine: &DB,
                   region: &Region,
                   raft_state: &RaftLocalState,
@@ -213,6 +224,7 @@ impl PeerStorage {
         let raft_state = try!(init_raft_state(&engine, region));
         let apply_state = try!(init_apply_state(&engine, region));
         let last_term = try!(init_last_term(&engine, region, &raft_state, &apply_state));
+        let mvcc_stats = try!(init_mvcc_stats(&engine, region));
 
         Ok(PeerStorage {
             engine: engine,
@@ -225,6 +237,7 @@ impl PeerStorage {
             tag: tag,
             applied_index_term: RAFT_INIT_LOG_TERM,
             last_term: last_term,
+            mvcc_stats: mvcc_stats,
         })
     }
 
@@ -234,7 +247,6 @@ impl PeerStorage {
 
     pub fn initial_state(&self) -> raft::Result<RaftState> {
         let hard_state = self.raft_state.get_hard_state().clone();
-        let mut conf_state = ConfState::new();
         if hard_state == HardState::new() {
             assert!(!self.is_initialized(),
                     "peer for region {:?} is initialized but local state {:?} has empty hard \
@@ -244,17 +256,13 @@ impl PeerStorage {
 
             return Ok(RaftState {
                 hard_state: hard_state,
-                conf_state: conf_state,
+                conf_state: ConfState::new(),
             });
         }
 
-        for p in self.region.get_peers() {
-            conf_state.mut_nodes().push(p.get_id());
-        }
-
         Ok(RaftState {
             hard_state: hard_state,
-            conf_state: conf_state,
+            conf_state: conf_state_from_region(&self.region),
         })
     }
 
@@ -753,6 +761,8 @@ impl PeerStorage {
                 }
             }
 
+            // The stats were cleared with the meta, the data of the snapshot is not counted.
+            self.mvcc_stats.reset();
             self.schedule_applying_snapshot();
 
             self.region = res.region.clone();
@@ -842,11 +852,7 @@ pub fn do_snapshot(mgr: SnapManager, snap: &DbSnapshot, region_id: u64) -> raft:
     snapshot.mut_metadata().set_index(key.idx);
     snapshot.mut_metadata().set_term(key.term);
 
-    let mut conf_state = ConfState::new();
-    for p in state.get_region().get_peers() {
-        conf_state.mut_nodes().push(p.get_id());
-    }
-
+    let conf_state = conf_state_from_region(state.get_region());
     snapshot.mut_metadata().set_conf_state(conf_state);
 
     let mut snap_file = try!(mgr.rl().get_snap_file(&key, true));
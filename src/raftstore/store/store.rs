@@ -18,7 +18,7 @@ use std::option::Option;
 use std::collections::{HashMap, HashSet, BTreeMap};
 use std::boxed::Box;
 use std::collections::Bound::{Excluded, Unbounded};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 use std::{cmp, u64};
 
 use rocksdb::DB;
@@ -35,7 +35,7 @@ use kvproto::pdpb::StoreStats;
 use util::{HandyRwLock, SlowTimer, duration_to_nanos};
 use pd::PdClient;
 use kvproto::raft_cmdpb::{AdminCmdType, AdminRequest, StatusCmdType, StatusResponse,
-                          RaftCmdRequest, RaftCmdResponse};
+                          RaftCmdRequest, RaftCmdResponse, ChangePeersRequest};
 use protobuf::Message;
 use raft::{SnapshotStatus, INVALID_INDEX};
 use raftstore::{Result, Error};
@@ -43,6 +43,7 @@ use kvproto::metapb;
 use util::worker::{Worker, Scheduler};
 use util::transport::SendCh;
 use util::rocksdb;
+use util::time_monitor::TimeMonitor;
 use storage::{ALL_CFS, CF_DEFAULT, CF_LOCK, CF_WRITE};
 use super::worker::{SplitCheckRunner, SplitCheckTask, RegionTask, RegionRunner, CompactTask,
                     CompactRunner, RaftlogGcTask, RaftlogGcRunner, PdRunner, PdTask};
@@ -52,7 +53,7 @@ use super::engine::{Iterable, Peekable, delete_all_in_range};
 use super::config::Config;
 use super::peer::{Peer, PendingCmd, ReadyResult, ExecResult, StaleState};
 use super::peer_storage::{ApplySnapResult, SnapState};
-use super::msg::Callback;
+use super::msg::{Callback, LeaderRegionsCallback};
 use super::cmd_resp::{bind_uuid, bind_term, bind_error};
 use super::transport::Transport;
 use super::metrics::*;
@@ -390,6 +391,16 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         let pd_runner = PdRunner::new(self.pd_client.clone(), self.sendch.clone());
         box_try!(self.pd_worker.start(pd_runner));
 
+        // The leader leases are measured by the local clock, so they are dropped when the
+        // system time jumps back.
+        let ch = self.sendch.clone();
+        let on_jumped = move || {
+            if let Err(e) = ch.try_send(Msg::ClockJumped) {
+                error!("failed to notify clock jumped: {:?}", e);
+            }
+        };
+        let _monitor = TimeMonitor::new(on_jumped, SystemTime::now);
+
         try!(event_loop.run(self));
         Ok(())
     }
@@ -436,6 +447,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         for (&region_id, peer) in &mut self.region_peers {
             if !peer.get_store().is_applying() {
                 peer.raft_group.tick();
+                peer.maybe_renew_leader_lease();
 
                 // If this peer detects the leader is missing for a long long time,
                 // it should consider itself as a stale peer which is removed from
@@ -652,7 +664,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             let epoch = region.get_region_epoch();
 
             if util::is_epoch_stale(from_epoch, epoch) &&
-               util::find_peer(region, from_store_id).is_none() {
+               !util::has_peer_on_store(region, from_store_id) {
                 // The message is stale and not in current region.
                 self.handle_stale_msg(msg, epoch, is_vote_msg);
                 return Ok(true);
@@ -756,7 +768,10 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         try!(snap_data.merge_from_bytes(snap.get_data()));
         let snap_region = snap_data.take_region();
         let peer_id = msg.get_to_peer().get_id();
-        if snap_region.get_peers().into_iter().all(|p| p.get_id() != peer_id) {
+        if snap_region.get_peers()
+            .iter()
+            .chain(snap_region.get_outgoing_peers())
+            .all(|p| p.get_id() != peer_id) {
             info!("region {:?} doesn't contain peer {:?}, skip.",
                   snap_region,
                   msg.get_to_peer());
@@ -785,6 +800,22 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         Ok(true)
     }
 
+    fn on_clock_jumped(&mut self) {
+        warn!("{} system time jumped, expire all the leader leases", self.tag);
+        for peer in self.region_peers.values_mut() {
+            peer.expire_leader_lease();
+        }
+    }
+
+    fn on_leader_regions(&self, callback: LeaderRegionsCallback) {
+        let regions = self.region_peers
+            .values()
+            .filter(|p| p.is_leader())
+            .map(|p| (p.region().clone(), p.peer.clone(), p.get_store().mvcc_stats.clone()))
+            .collect();
+        callback(regions);
+    }
+
     fn insert_peer_cache(&mut self, peer: metapb::Peer) {
         self.peer_cache.borrow_mut().insert(peer.get_id(), peer);
     }
@@ -885,6 +916,45 @@ impl<T: Transport, C: PdClient> Store<T, C> {
         }
     }
 
+    fn on_ready_change_peers(&mut self, region_id: u64, removed: Vec<metapb::Peer>) {
+        let mut peer_id = 0;
+        if let Some(p) = self.region_peers.get(&region_id) {
+            if p.is_leader() {
+                // Notify pd immediately.
+                info!("{} notify pd with change peers region {:?}",
+                      p.tag,
+                      p.region());
+                self.heartbeat_pd(p);
+                if !p.region().get_outgoing_peers().is_empty() {
+                    self.propose_leave_joint(p);
+                }
+            }
+            peer_id = p.peer_id();
+        }
+
+        // We only care remove itself now.
+        let store_id = self.store_id();
+        if let Some(peer) = removed.into_iter().find(|p| p.get_store_id() == store_id) {
+            if peer_id == peer.get_id() {
+                self.destroy_peer(region_id, peer)
+            } else {
+                panic!("trying to remove unknown peer {:?}", peer);
+            }
+        }
+    }
+
+    // All the peers have been changed once the region enters joint consensus, so the leader
+    // proposes to leave it at once.
+    fn propose_leave_joint(&self, peer: &Peer) {
+        let request = new_leave_joint_request(peer.region(), peer.peer.clone());
+        if let Err(e) = self.sendch.try_send(Msg::RaftCmd {
+            request: request,
+            callback: Box::new(|_| {}),
+        }) {
+            error!("{} send leave joint err {:?}", peer.tag, e);
+        }
+    }
+
     fn on_ready_compact_log(&mut self, region_id: u64, state: RaftTruncatedState) {
         let mut peer = self.region_peers.get_mut(&region_id).unwrap();
         let task = RaftlogGcTask {
@@ -1019,6 +1089,9 @@ impl<T: Transport, C: PdClient> Store<T, C> {
                 ExecResult::ChangePeer { change_type, peer, .. } => {
                     self.on_ready_change_peer(region_id, change_type, peer)
                 }
+                ExecResult::ChangePeers { removed, .. } => {
+                    self.on_ready_change_peers(region_id, removed)
+                }
                 ExecResult::CompactLog { state } => self.on_ready_compact_log(region_id, state),
                 ExecResult::SplitRegion { left, right } => {
                     self.on_ready_split_region(region_id, left, right)
@@ -1297,6 +1370,7 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             region: peer.region().clone(),
             peer: peer.peer.clone(),
             down_peers: peer.collect_down_peers(self.cfg.max_peer_down_duration),
+            mvcc_stats: peer.get_store().mvcc_stats.clone(),
         };
         if let Err(e) = self.pd_worker.schedule(task) {
             error!("{} failed to notify pd: {}", peer.tag, e);
@@ -1313,6 +1387,10 @@ impl<T: Transport, C: PdClient> Store<T, C> {
             if peer.is_leader() {
                 leader_count += 1;
                 self.heartbeat_pd(peer);
+                // The leadership may change before the former leader leaves joint consensus.
+                if peer.raft_group.raft.in_joint() && !peer.raft_group.raft.pending_conf {
+                    self.propose_leave_joint(peer);
+                }
             }
         }
 
@@ -1595,6 +1673,21 @@ fn new_compact_log_request(region_id: u64,
     request
 }
 
+fn new_leave_joint_request(region: &metapb::Region, peer: metapb::Peer) -> RaftCmdRequest {
+    let mut request = RaftCmdRequest::new();
+    request.mut_header().set_region_id(region.get_id());
+    request.mut_header().set_region_epoch(region.get_region_epoch().clone());
+    request.mut_header().set_peer(peer);
+    request.mut_header().set_uuid(Uuid::new_v4().as_bytes().to_vec());
+
+    // No changes means leaving joint consensus.
+    let mut admin = AdminRequest::new();
+    admin.set_cmd_type(AdminCmdType::ChangePeers);
+    admin.set_change_peers(ChangePeersRequest::new());
+    request.set_admin_request(admin);
+    request
+}
+
 impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
     type Timeout = Tick;
     type Message = Msg;
@@ -1624,6 +1717,8 @@ impl<T: Transport, C: PdClient> mio::Handler for Store<T, C> {
                 self.on_unreachable(region_id, to_peer_id);
             }
             Msg::SnapshotStats => self.store_heartbeat_pd(),
+            Msg::ClockJumped => self.on_clock_jumped(),
+            Msg::LeaderRegions { callback } => self.on_leader_regions(callback),
             Msg::SnapGenRes { region_id, snap } => {
                 self.on_snap_gen_res(region_id, snap);
             }
@@ -16,7 +16,7 @@ use std::option::Option;
 use uuid::Uuid;
 
 use kvproto::metapb;
-use kvproto::eraftpb::{self, ConfChangeType};
+use kvproto::eraftpb::{self, ConfChangeType, ConfState};
 use kvproto::raft_cmdpb::RaftCmdRequest;
 use raftstore::{Result, Error};
 
@@ -30,6 +30,15 @@ pub fn find_peer(region: &metapb::Region, store_id: u64) -> Option<&metapb::Peer
     None
 }
 
+/// Returns true if the region has a peer on `store_id`, the voters being removed in joint
+/// consensus are included.
+pub fn has_peer_on_store(region: &metapb::Region, store_id: u64) -> bool {
+    region.get_peers()
+        .iter()
+        .chain(region.get_outgoing_peers())
+        .any(|p| p.get_store_id() == store_id)
+}
+
 pub fn remove_peer(region: &mut metapb::Region, store_id: u64) -> Option<metapb::Peer> {
     region.get_peers()
         .iter()
@@ -73,12 +82,34 @@ pub fn check_key_in_region(key: &[u8], region: &metapb::Region) -> Result<()> {
 
 const STR_CONF_CHANGE_ADD_NODE: &'static str = "AddNode";
 const STR_CONF_CHANGE_REMOVE_NODE: &'static str = "RemoveNode";
+const STR_CONF_CHANGE_ADD_LEARNER_NODE: &'static str = "AddLearnerNode";
+const STR_CONF_CHANGE_BEGIN_MEMBERSHIP_CHANGE: &'static str = "BeginMembershipChange";
+const STR_CONF_CHANGE_FINALIZE_MEMBERSHIP_CHANGE: &'static str = "FinalizeMembershipChange";
 
 pub fn conf_change_type_str(conf_type: &eraftpb::ConfChangeType) -> &'static str {
     match *conf_type {
         ConfChangeType::AddNode => STR_CONF_CHANGE_ADD_NODE,
         ConfChangeType::RemoveNode => STR_CONF_CHANGE_REMOVE_NODE,
+        ConfChangeType::AddLearnerNode => STR_CONF_CHANGE_ADD_LEARNER_NODE,
+        ConfChangeType::BeginMembershipChange => STR_CONF_CHANGE_BEGIN_MEMBERSHIP_CHANGE,
+        ConfChangeType::FinalizeMembershipChange => STR_CONF_CHANGE_FINALIZE_MEMBERSHIP_CHANGE,
+    }
+}
+
+/// Returns the raft configuration of the region.
+pub fn conf_state_from_region(region: &metapb::Region) -> ConfState {
+    let mut conf_state = ConfState::new();
+    for p in region.get_peers() {
+        if p.get_is_learner() {
+            conf_state.mut_learners().push(p.get_id());
+        } else {
+            conf_state.mut_nodes().push(p.get_id());
+        }
+    }
+    for p in region.get_outgoing_peers() {
+        conf_state.mut_outgoing_nodes().push(p.get_id());
     }
+    conf_state
 }
 
 // check whether epoch is staler than check_epoch.
@@ -28,6 +28,7 @@ use util::transport::SendCh;
 use pd::PdClient;
 use raftstore::store::Msg;
 use raftstore::store::util::is_epoch_stale;
+use storage::mvcc::MvccStats;
 
 use super::metrics::*;
 
@@ -42,6 +43,7 @@ pub enum Task {
         region: metapb::Region,
         peer: metapb::Peer,
         down_peers: Vec<pdpb::PeerStats>,
+        mvcc_stats: MvccStats,
     },
     StoreHeartbeat { stats: pdpb::StoreStats },
     ReportSplit {
@@ -143,11 +145,13 @@ impl<T: PdClient> Runner<T> {
     fn handle_heartbeat(&self,
                         region: metapb::Region,
                         peer: metapb::Peer,
-                        down_peers: Vec<pdpb::PeerStats>) {
+                        down_peers: Vec<pdpb::PeerStats>,
+                        mvcc_stats: MvccStats) {
         PD_REQ_COUNTER_VEC.with_label_values(&["heartbeat", "all"]).inc();
 
         // Now we use put region protocol for heartbeat.
-        match self.pd_client.region_heartbeat(region.clone(), peer.clone(), down_peers) {
+        match self.pd_client
+            .region_heartbeat(region.clone(), peer.clone(), down_peers, mvcc_stats) {
             Ok(mut resp) => {
                 PD_REQ_COUNTER_VEC.with_label_values(&["heartbeat", "success"]).inc();
 
@@ -272,8 +276,8 @@ impl<T: PdClient> Runnable<Task> for Runner<T> {
             Task::AskSplit { region, split_key, peer } => {
                 self.handle_ask_split(region, split_key, peer)
             }
-            Task::Heartbeat { region, peer, down_peers } => {
-                self.handle_heartbeat(region, peer, down_peers)
+            Task::Heartbeat { region, peer, down_peers, mvcc_stats } => {
+                self.handle_heartbeat(region, peer, down_peers, mvcc_stats)
             }
             Task::StoreHeartbeat { stats } => self.handle_store_heartbeat(stats),
             Task::ReportSplit { left, right } => self.handle_report_split(left, right),
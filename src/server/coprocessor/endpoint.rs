@@ -25,10 +25,11 @@ use protobuf::{Message as PbMsg, RepeatedField};
 use byteorder::{BigEndian, ReadBytesExt};
 use threadpool::ThreadPool;
 
-use storage::{Engine, SnapshotStore};
+use storage::{Engine, SnapshotStore, ConcurrencyManager};
 use kvproto::msgpb::{MessageType, Message};
 use kvproto::coprocessor::{Request, Response, KeyRange};
-use storage::{engine, Snapshot, Key, ScanMode};
+use kvproto::kvrpcpb::IsolationLevel;
+use storage::{engine, txn, Snapshot, Key, ScanMode};
 use util::codec::table::TableDecoder;
 use util::codec::number::NumberDecoder;
 use util::codec::datum::DatumDecoder;
@@ -63,20 +64,56 @@ pub struct Host {
     reqs: HashMap<u64, Vec<RequestTask>>,
     last_req_id: u64,
     pool: ThreadPool,
+    concurrency_manager: ConcurrencyManager,
 }
 
 impl Host {
-    pub fn new(engine: Box<Engine>, scheduler: Scheduler<Task>, concurrency: usize) -> Host {
+    pub fn new(engine: Box<Engine>,
+               scheduler: Scheduler<Task>,
+               concurrency: usize,
+               concurrency_manager: ConcurrencyManager)
+               -> Host {
         Host {
             engine: engine,
             sched: scheduler,
             reqs: HashMap::new(),
             last_req_id: 0,
             pool: ThreadPool::new_with_name(thd_name!("endpoint-pool"), concurrency),
+            concurrency_manager: concurrency_manager,
         }
     }
 }
 
+/// Updates `max_read_ts` with the ranges of a select request and checks their memory locks,
+/// see `ConcurrencyManager`. It must be done before taking the snapshot, like the reads of
+/// storage do. Reads at read committed ignore locks, so they are skipped.
+fn read_memory_locks(cm: &ConcurrencyManager, req: &Request) -> Result<()> {
+    match req.get_tp() {
+        REQ_TYPE_SELECT | REQ_TYPE_INDEX => {}
+        _ => return Ok(()),
+    }
+    let ctx = req.get_context();
+    if ctx.get_isolation_level() == IsolationLevel::RC {
+        return Ok(());
+    }
+    let mut sel = SelectRequest::new();
+    box_try!(sel.merge_from_bytes(req.get_data()));
+    for range in req.get_ranges() {
+        let start = Key::from_raw(range.get_start());
+        let end = if range.get_end().is_empty() {
+            None
+        } else {
+            Some(Key::from_raw(range.get_end()))
+        };
+        try!(cm.read_range(Some(&start),
+                        end.as_ref(),
+                        sel.get_start_ts(),
+                        ctx.get_resolved_locks())
+            .map_err(txn::Error::from));
+    }
+    Ok(())
+}
+
 pub enum Task {
     Request(RequestTask),
     SnapRes(u64, engine::Result<Box<Snapshot>>),
@@ -133,6 +170,10 @@ impl BatchRunnable<Task> for Host {
                                  req.on_resp);
                         continue;
                     }
+                    if let Err(e) = read_memory_locks(&self.concurrency_manager, &req.req) {
+                        on_error(e, req.on_resp);
+                        continue;
+                    }
                     let key = {
                         let ctx = req.req.get_context();
                         (ctx.get_region_id(),
@@ -268,7 +309,13 @@ impl TiDbEndPoint {
                          deadline: Instant,
                          sel: SelectRequest)
                          -> Result<Response> {
-        let snap = SnapshotStore::new(self.snap.as_ref(), sel.get_start_ts());
+        let snap = {
+            let ctx = req.get_context();
+            SnapshotStore::new(self.snap.as_ref(),
+                               sel.get_start_ts(),
+                               ctx.get_isolation_level(),
+                               ctx.get_resolved_locks().to_vec())
+        };
         let mut ctx = try!(SelectContext::new(sel, snap));
         let mut range = req.take_ranges().into_vec();
         let desc = ctx.core.sel.get_order_by().first().map_or(false, |o| o.get_desc());
@@ -832,9 +879,14 @@ mod tests {
 
     use util::worker::Worker;
     use storage::engine::{self, TEMP_DIR};
+    use storage::make_key;
+    use storage::txn::MemoryLock;
 
-    use kvproto::coprocessor::Request;
+    use kvproto::coprocessor::{Request, KeyRange};
+    use kvproto::kvrpcpb::IsolationLevel;
     use kvproto::msgpb::MessageType;
+    use protobuf::{Message, RepeatedField};
+    use tipb::select::SelectRequest;
 
     use std::sync::*;
     use std::time::Duration;
@@ -850,7 +902,7 @@ mod tests {
     fn test_req_outdated() {
         let mut worker = Worker::new("test-endpoint");
         let engine = engine::new_local_engine(TEMP_DIR, &[]).unwrap();
-        let end_point = Host::new(engine, worker.scheduler(), 1);
+        let end_point = Host::new(engine, worker.scheduler(), 1, ConcurrencyManager::new());
         worker.start_batch(end_point, 30).unwrap();
         let (tx, rx) = mpsc::channel();
         let mut task = RequestTask::new(Request::new(),
@@ -865,4 +917,51 @@ mod tests {
         assert!(copr_resp.has_other_error());
         assert_eq!(copr_resp.get_other_error(), super::OUTDATED_ERROR_MSG);
     }
+
+    #[test]
+    fn test_memory_locked() {
+        let mut worker = Worker::new("test-endpoint");
+        let engine = engine::new_local_engine(TEMP_DIR, &[]).unwrap();
+        let cm = ConcurrencyManager::new();
+        let lock = MemoryLock {
+            primary: b"k".to_vec(),
+            ts: 10,
+            ttl: 100,
+        };
+        cm.lock_keys(&[make_key(b"k")], lock);
+        let end_point = Host::new(engine, worker.scheduler(), 1, cm.clone());
+        worker.start_batch(end_point, 30).unwrap();
+
+        let new_req = |isolation_level| {
+            let mut sel = SelectRequest::new();
+            sel.set_start_ts(20);
+            let mut range = KeyRange::new();
+            range.set_start(b"a".to_vec());
+            range.set_end(b"z".to_vec());
+            let mut req = Request::new();
+            req.set_tp(REQ_TYPE_SELECT);
+            req.set_data(sel.write_to_bytes().unwrap());
+            req.set_ranges(RepeatedField::from_vec(vec![range]));
+            req.mut_context().set_isolation_level(isolation_level);
+            req
+        };
+        let (tx, rx) = mpsc::channel();
+        for &(isolation_level, locked) in &[(IsolationLevel::SI, true),
+                                            (IsolationLevel::RC, false)] {
+            let tx = tx.clone();
+            let task = RequestTask::new(new_req(isolation_level),
+                                        box move |msg| {
+                                            tx.send(msg).unwrap();
+                                        });
+            worker.schedule(Task::Request(task)).unwrap();
+            let resp = rx.recv_timeout(Duration::from_secs(3)).unwrap();
+            let copr_resp = resp.get_cop_resp();
+            assert_eq!(copr_resp.has_locked(), locked);
+            if locked {
+                assert_eq!(copr_resp.get_locked().get_lock_version(), 10);
+            }
+        }
+        // The read is counted even though it's locked, so the transaction commits after it.
+        assert_eq!(cm.max_read_ts(), 20);
+    }
 }
@@ -0,0 +1,643 @@
+// Copyright 2016 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deadlock detector for the transactions waiting for locks.
+//!
+//! The waiting transactions form a wait-for graph keyed by `start_ts`. Only one store in the
+//! cluster keeps the graph: the store holding the leader of the region which contains the empty
+//! key. Detectors on the other stores forward the wait-for edges to it, and it tells them back
+//! when an edge causes a deadlock.
+//!
+//! The leader is looked up from pd by a ticker thread every `LEADER_REFRESH_SECONDS`, so the
+//! detector itself never waits for pd.
+
+use std::sync::Arc;
+use std::sync::mpsc::{self, Sender};
+use std::boxed::{Box, FnBox};
+use std::fmt::{self, Formatter, Display};
+use std::collections::{HashMap, HashSet};
+use std::thread::{JoinHandle, Builder};
+use std::time::Duration;
+
+use kvproto::msgpb::{Message, MessageType};
+use kvproto::deadlock::{DeadlockRequest, DeadlockRequestType, DeadlockResponse};
+use pd::PdClient;
+use util::transport::SendCh;
+use util::worker::{Runnable, Worker, Scheduler};
+use super::{Msg, ConnData, Result};
+use super::metrics::*;
+
+// The detector leader is the store holding the leader of the region which contains this key.
+const LEADER_REGION_KEY: &'static [u8] = b"";
+const LEADER_REFRESH_SECONDS: u64 = 10;
+
+/// Called when waiting for a lock causes a deadlock.
+pub type Callback = Box<FnBox() + Send>;
+
+pub enum Task {
+    /// Command `cid` of txn `txn_ts` begins to wait for the lock of txn `lock_ts`.
+    Detect {
+        cid: u64,
+        txn_ts: u64,
+        lock_ts: u64,
+        cb: Callback,
+    },
+    /// Command `cid` of txn `txn_ts` stops waiting for the lock of txn `lock_ts`.
+    CleanUpWaitFor { cid: u64, txn_ts: u64, lock_ts: u64 },
+    /// Request from the detector of another store.
+    Request(DeadlockRequest),
+    /// Response from the detector leader.
+    Response(DeadlockResponse),
+    /// The detector leader is refreshed from pd.
+    ChangeLeader { store_id: Option<u64> },
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Task::Detect { cid, txn_ts, lock_ts, .. } => {
+                write!(f,
+                       "detect cid={} of txn {} waiting for txn {}",
+                       cid,
+                       txn_ts,
+                       lock_ts)
+            }
+            Task::CleanUpWaitFor { cid, txn_ts, lock_ts } => {
+                write!(f,
+                       "clean up cid={} of txn {} waiting for txn {}",
+                       cid,
+                       txn_ts,
+                       lock_ts)
+            }
+            Task::Request(ref req) => {
+                write!(f,
+                       "{:?} request from store {}",
+                       req.get_tp(),
+                       req.get_from_store_id())
+            }
+            Task::Response(ref resp) => {
+                write!(f,
+                       "deadlock response of txn {} waiting for txn {}",
+                       resp.get_txn_ts(),
+                       resp.get_wait_for_txn_ts())
+            }
+            Task::ChangeLeader { store_id } => {
+                write!(f, "change deadlock detector leader to {:?}", store_id)
+            }
+        }
+    }
+}
+
+/// Wait-for graph of transactions.
+#[derive(Default)]
+pub struct DetectTable {
+    // txn_ts -> start_ts of the txns it waits for
+    wait_for_map: HashMap<u64, HashSet<u64>>,
+}
+
+impl DetectTable {
+    pub fn new() -> DetectTable {
+        DetectTable::default()
+    }
+
+    /// Returns true if txn `txn_ts` waiting for txn `lock_ts` causes a deadlock, otherwise
+    /// adds the edge to the graph.
+    pub fn detect(&mut self, txn_ts: u64, lock_ts: u64) -> bool {
+        if self.reachable(lock_ts, txn_ts) {
+            return true;
+        }
+        self.wait_for_map.entry(txn_ts).or_insert_with(HashSet::new).insert(lock_ts);
+        false
+    }
+
+    fn reachable(&self, from: u64, to: u64) -> bool {
+        let mut visited = HashSet::new();
+        let mut stack = vec![from];
+        while let Some(ts) = stack.pop() {
+            if ts == to {
+                return true;
+            }
+            if !visited.insert(ts) {
+                continue;
+            }
+            if let Some(wait_for) = self.wait_for_map.get(&ts) {
+                stack.extend(wait_for.iter().cloned());
+            }
+        }
+        false
+    }
+
+    /// Removes the edge of txn `txn_ts` waiting for txn `lock_ts`.
+    pub fn clean_up_wait_for(&mut self, txn_ts: u64, lock_ts: u64) {
+        let empty = match self.wait_for_map.get_mut(&txn_ts) {
+            Some(wait_for) => {
+                wait_for.remove(&lock_ts);
+                wait_for.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            self.wait_for_map.remove(&txn_ts);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.wait_for_map.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.wait_for_map.is_empty()
+    }
+}
+
+pub struct Runner {
+    store_id: u64,
+    ch: SendCh<Msg>,
+    // store id of the detector leader
+    leader: Option<u64>,
+    // only used when this store is the leader
+    detect_table: DetectTable,
+    // (txn_ts, lock_ts) -> (cid, callback) of the commands waiting on the edge, an edge is
+    // only removed from the graph when its last waiter stops waiting
+    waiters: HashMap<(u64, u64), Vec<(u64, Callback)>>,
+    msg_id: u64,
+}
+
+impl Runner {
+    pub fn new(store_id: u64, ch: SendCh<Msg>) -> Runner {
+        Runner {
+            store_id: store_id,
+            ch: ch,
+            leader: None,
+            detect_table: DetectTable::new(),
+            waiters: HashMap::new(),
+            msg_id: 0,
+        }
+    }
+
+    fn change_leader(&mut self, store_id: Option<u64>) {
+        if self.leader != store_id {
+            info!("deadlock detector leader changes from {:?} to {:?}",
+                  self.leader,
+                  store_id);
+            // The graph is rebuilt by the edges detected afterwards, waiters whose edges are
+            // lost rely on the lock wait timeout.
+            self.detect_table.clear();
+            self.waiters.clear();
+        }
+        self.leader = store_id;
+    }
+
+    fn handle_detect(&mut self, cid: u64, txn_ts: u64, lock_ts: u64, cb: Callback) {
+        match self.leader {
+            Some(id) if id == self.store_id => {
+                DEADLOCK_DETECT_COUNTER.with_label_values(&["local"]).inc();
+                if self.detect_table.detect(txn_ts, lock_ts) {
+                    DEADLOCK_DETECT_COUNTER.with_label_values(&["deadlock"]).inc();
+                    cb.call_box(());
+                    return;
+                }
+                self.add_waiter(cid, txn_ts, lock_ts, cb);
+            }
+            Some(id) => {
+                DEADLOCK_DETECT_COUNTER.with_label_values(&["forward"]).inc();
+                self.add_waiter(cid, txn_ts, lock_ts, cb);
+                self.send_request(id, DeadlockRequestType::Detect, txn_ts, lock_ts);
+            }
+            None => {
+                DEADLOCK_DETECT_COUNTER.with_label_values(&["no_leader"]).inc();
+                warn!("no deadlock detector leader, skip detecting txn {} waiting for txn {}",
+                      txn_ts,
+                      lock_ts);
+            }
+        }
+    }
+
+    fn add_waiter(&mut self, cid: u64, txn_ts: u64, lock_ts: u64, cb: Callback) {
+        self.waiters.entry((txn_ts, lock_ts)).or_insert_with(Vec::new).push((cid, cb));
+    }
+
+    // Returns true if the edge has no waiters left after removing command `cid`.
+    fn remove_waiter(&mut self, cid: u64, txn_ts: u64, lock_ts: u64) -> bool {
+        let edge = (txn_ts, lock_ts);
+        let empty = match self.waiters.get_mut(&edge) {
+            Some(waiters) => {
+                match waiters.iter().position(|&(id, _)| id == cid) {
+                    Some(pos) => {
+                        waiters.swap_remove(pos);
+                    }
+                    None => return false,
+                }
+                waiters.is_empty()
+            }
+            None => return false,
+        };
+        if empty {
+            self.waiters.remove(&edge);
+        }
+        empty
+    }
+
+    fn handle_clean_up_wait_for(&mut self, cid: u64, txn_ts: u64, lock_ts: u64) {
+        // Other commands of the txn may still wait on the edge.
+        if !self.remove_waiter(cid, txn_ts, lock_ts) {
+            return;
+        }
+        match self.leader {
+            Some(id) if id == self.store_id => self.detect_table.clean_up_wait_for(txn_ts, lock_ts),
+            Some(id) => {
+                self.send_request(id, DeadlockRequestType::CleanUpWaitFor, txn_ts, lock_ts)
+            }
+            None => {}
+        }
+    }
+
+    fn handle_request(&mut self, req: DeadlockRequest) {
+        if self.leader != Some(self.store_id) {
+            debug!("store {} is not deadlock detector leader, drop request from store {}",
+                   self.store_id,
+                   req.get_from_store_id());
+            return;
+        }
+
+        let (txn_ts, lock_ts) = (req.get_txn_ts(), req.get_wait_for_txn_ts());
+        match req.get_tp() {
+            DeadlockRequestType::Detect => {
+                DEADLOCK_DETECT_COUNTER.with_label_values(&["remote"]).inc();
+                if self.detect_table.detect(txn_ts, lock_ts) {
+                    DEADLOCK_DETECT_COUNTER.with_label_values(&["deadlock"]).inc();
+                    let mut resp = DeadlockResponse::new();
+                    resp.set_txn_ts(txn_ts);
+                    resp.set_wait_for_txn_ts(lock_ts);
+                    let mut msg = Message::new();
+                    msg.set_msg_type(MessageType::DeadlockResp);
+                    msg.set_deadlock_resp(resp);
+                    self.send(req.get_from_store_id(), msg);
+                }
+            }
+            DeadlockRequestType::CleanUpWaitFor => {
+                self.detect_table.clean_up_wait_for(txn_ts, lock_ts)
+            }
+        }
+    }
+
+    fn handle_response(&mut self, resp: DeadlockResponse) {
+        // Every command waiting on the edge is in the deadlock.
+        let edge = (resp.get_txn_ts(), resp.get_wait_for_txn_ts());
+        if let Some(waiters) = self.waiters.remove(&edge) {
+            for (_, cb) in waiters {
+                cb.call_box(());
+            }
+        }
+    }
+
+    fn send_request(&mut self,
+                    store_id: u64,
+                    tp: DeadlockRequestType,
+                    txn_ts: u64,
+                    lock_ts: u64) {
+        let mut req = DeadlockRequest::new();
+        req.set_tp(tp);
+        req.set_from_store_id(self.store_id);
+        req.set_txn_ts(txn_ts);
+        req.set_wait_for_txn_ts(lock_ts);
+        let mut msg = Message::new();
+        msg.set_msg_type(MessageType::DeadlockReq);
+        msg.set_deadlock_req(req);
+        self.send(store_id, msg);
+    }
+
+    fn send(&mut self, store_id: u64, msg: Message) {
+        self.msg_id += 1;
+        let data = ConnData::new(self.msg_id, msg);
+        if let Err(e) = self.ch.try_send(Msg::SendStore {
+            store_id: store_id,
+            data: data,
+        }) {
+            error!("failed to send deadlock message to store {}: {:?}",
+                   store_id,
+                   e);
+        }
+    }
+}
+
+impl Runnable<Task> for Runner {
+    fn run(&mut self, task: Task) {
+        match task {
+            Task::Detect { cid, txn_ts, lock_ts, cb } => {
+                self.handle_detect(cid, txn_ts, lock_ts, cb)
+            }
+            Task::CleanUpWaitFor { cid, txn_ts, lock_ts } => {
+                self.handle_clean_up_wait_for(cid, txn_ts, lock_ts)
+            }
+            Task::Request(req) => self.handle_request(req),
+            Task::Response(resp) => self.handle_response(resp),
+            Task::ChangeLeader { store_id } => self.change_leader(store_id),
+        }
+    }
+}
+
+/// Deadlock detector service of a store.
+///
+/// The scheduler can be handed out before the service is started, tasks are queued until then.
+pub struct Detector {
+    worker: Worker<Task>,
+    ticker: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+/// Looks up the store id of the detector leader from pd.
+fn get_leader<T: PdClient>(pd_client: &T) -> Option<u64> {
+    match pd_client.get_region_leader(LEADER_REGION_KEY) {
+        Ok(leader) => leader.map(|peer| peer.get_store_id()),
+        Err(e) => {
+            error!("failed to get deadlock detector leader: {:?}", e);
+            None
+        }
+    }
+}
+
+impl Detector {
+    pub fn new() -> Detector {
+        Detector {
+            worker: Worker::new("deadlock-detector"),
+            ticker: None,
+        }
+    }
+
+    pub fn scheduler(&self) -> Scheduler<Task> {
+        self.worker.scheduler()
+    }
+
+    pub fn start<T>(&mut self, store_id: u64, pd_client: Arc<T>, ch: SendCh<Msg>) -> Result<()>
+        where T: PdClient + 'static
+    {
+        let runner = Runner::new(store_id, ch);
+        box_try!(self.worker.start(runner));
+
+        let interval = Duration::from_secs(LEADER_REFRESH_SECONDS);
+        let scheduler = self.worker.scheduler();
+        let (tx, rx) = mpsc::channel();
+        let h = try!(Builder::new()
+            .name(thd_name!("deadlock-leader-ticker"))
+            .spawn(move || {
+                loop {
+                    let task = Task::ChangeLeader { store_id: get_leader(pd_client.as_ref()) };
+                    if let Err(e) = scheduler.schedule(task) {
+                        error!("failed to schedule deadlock detector leader change: {:?}", e);
+                    }
+                    // Stops when the sender is dropped.
+                    if let Err(mpsc::RecvTimeoutError::Disconnected) = rx.recv_timeout(interval) {
+                        return;
+                    }
+                }
+            }));
+        self.ticker = Some((tx, h));
+        Ok(())
+    }
+}
+
+impl Drop for Detector {
+    fn drop(&mut self) {
+        if let Some((tx, h)) = self.ticker.take() {
+            drop(tx);
+            if let Err(e) = h.join() {
+                error!("failed to stop deadlock leader ticker thread: {:?}!!!", e);
+            }
+        }
+        if let Some(Err(e)) = self.worker.stop().map(|h| h.join()) {
+            error!("failed to stop deadlock detector thread: {:?}!!!", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Sender, Receiver};
+    use std::thread;
+    use std::time::Duration;
+
+    use mio::{EventLoop, Handler};
+    use kvproto::msgpb::{Message, MessageType};
+    use kvproto::deadlock::{DeadlockRequest, DeadlockRequestType, DeadlockResponse};
+    use util::transport::SendCh;
+    use util::worker::Runnable;
+    use super::super::Msg;
+    use super::*;
+
+    struct CollectHandler {
+        tx: Sender<(u64, Message)>,
+    }
+
+    impl Handler for CollectHandler {
+        type Timeout = ();
+        type Message = Msg;
+
+        fn notify(&mut self, event_loop: &mut EventLoop<CollectHandler>, msg: Msg) {
+            match msg {
+                Msg::Quit => event_loop.shutdown(),
+                Msg::SendStore { store_id, data } => self.tx.send((store_id, data.msg)).unwrap(),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    fn recv_request(rx: &Receiver<(u64, Message)>) -> (u64, DeadlockRequest) {
+        let (store_id, mut msg) = rx.recv_timeout(Duration::from_secs(3)).unwrap();
+        assert_eq!(msg.get_msg_type(), MessageType::DeadlockReq);
+        (store_id, msg.take_deadlock_req())
+    }
+
+    fn recv_response(rx: &Receiver<(u64, Message)>) -> (u64, DeadlockResponse) {
+        let (store_id, mut msg) = rx.recv_timeout(Duration::from_secs(3)).unwrap();
+        assert_eq!(msg.get_msg_type(), MessageType::DeadlockResp);
+        (store_id, msg.take_deadlock_resp())
+    }
+
+    fn new_request(tp: DeadlockRequestType, from: u64, txn_ts: u64, lock_ts: u64) -> Task {
+        let mut req = DeadlockRequest::new();
+        req.set_tp(tp);
+        req.set_from_store_id(from);
+        req.set_txn_ts(txn_ts);
+        req.set_wait_for_txn_ts(lock_ts);
+        Task::Request(req)
+    }
+
+    fn new_response(txn_ts: u64, lock_ts: u64) -> Task {
+        let mut resp = DeadlockResponse::new();
+        resp.set_txn_ts(txn_ts);
+        resp.set_wait_for_txn_ts(lock_ts);
+        Task::Response(resp)
+    }
+
+    fn new_detect(cid: u64, txn_ts: u64, lock_ts: u64, tx: &Sender<u64>) -> Task {
+        let tx = tx.clone();
+        Task::Detect {
+            cid: cid,
+            txn_ts: txn_ts,
+            lock_ts: lock_ts,
+            cb: box move || tx.send(cid).unwrap(),
+        }
+    }
+
+    fn new_clean_up(cid: u64, txn_ts: u64, lock_ts: u64) -> Task {
+        Task::CleanUpWaitFor {
+            cid: cid,
+            txn_ts: txn_ts,
+            lock_ts: lock_ts,
+        }
+    }
+
+    fn start_collect() -> (SendCh<Msg>, Receiver<(u64, Message)>, thread::JoinHandle<()>) {
+        let mut event_loop = EventLoop::new().unwrap();
+        let ch = SendCh::new(event_loop.channel(), "test-deadlock");
+        let (msg_tx, msg_rx) = mpsc::channel();
+        let h = thread::spawn(move || {
+            let mut handler = CollectHandler { tx: msg_tx };
+            event_loop.run(&mut handler).unwrap();
+        });
+        (ch, msg_rx, h)
+    }
+
+    #[test]
+    fn test_runner_forward() {
+        let (ch, msg_rx, h) = start_collect();
+        let (cb_tx, cb_rx) = mpsc::channel();
+        let mut runner = Runner::new(1, ch.clone());
+
+        // Edges are forwarded to the remote leader.
+        runner.run(Task::ChangeLeader { store_id: Some(2) });
+        runner.run(new_detect(1, 10, 20, &cb_tx));
+        let (store_id, req) = recv_request(&msg_rx);
+        assert_eq!(store_id, 2);
+        assert_eq!(req.get_tp(), DeadlockRequestType::Detect);
+        assert_eq!(req.get_from_store_id(), 1);
+        assert_eq!((req.get_txn_ts(), req.get_wait_for_txn_ts()), (10, 20));
+        // A follower drops the requests from other stores.
+        runner.run(new_request(DeadlockRequestType::Detect, 3, 20, 10));
+        // The response of the leader fires the callback of the forwarded edge, the edge is not
+        // in the graph of the leader so it isn't cleaned up afterwards.
+        runner.run(new_response(10, 20));
+        assert_eq!(cb_rx.recv_timeout(Duration::from_secs(3)).unwrap(), 1);
+        runner.run(new_clean_up(1, 10, 20));
+        runner.run(new_detect(2, 10, 30, &cb_tx));
+        assert_eq!(recv_request(&msg_rx).0, 2);
+        runner.run(new_clean_up(2, 10, 30));
+        let (store_id, req) = recv_request(&msg_rx);
+        assert_eq!(store_id, 2);
+        assert_eq!(req.get_tp(), DeadlockRequestType::CleanUpWaitFor);
+        assert_eq!((req.get_txn_ts(), req.get_wait_for_txn_ts()), (10, 30));
+
+        // The pending edges are dropped when the leader changes.
+        runner.run(new_detect(3, 30, 40, &cb_tx));
+        assert_eq!(recv_request(&msg_rx).0, 2);
+        runner.run(Task::ChangeLeader { store_id: Some(3) });
+        runner.run(new_response(30, 40));
+
+        // The local detector detects both the local and the remote edges.
+        runner.run(Task::ChangeLeader { store_id: Some(1) });
+        runner.run(new_request(DeadlockRequestType::Detect, 2, 50, 60));
+        runner.run(new_detect(4, 60, 50, &cb_tx));
+        assert_eq!(cb_rx.recv_timeout(Duration::from_secs(3)).unwrap(), 4);
+        runner.run(new_detect(5, 60, 70, &cb_tx));
+        runner.run(new_request(DeadlockRequestType::Detect, 3, 70, 50));
+        let (store_id, resp) = recv_response(&msg_rx);
+        assert_eq!(store_id, 3);
+        assert_eq!((resp.get_txn_ts(), resp.get_wait_for_txn_ts()), (70, 50));
+        runner.run(new_request(DeadlockRequestType::CleanUpWaitFor, 2, 50, 60));
+        runner.run(new_request(DeadlockRequestType::Detect, 3, 70, 50));
+
+        // No leader, nothing is detected.
+        runner.run(Task::ChangeLeader { store_id: None });
+        runner.run(new_detect(6, 80, 90, &cb_tx));
+
+        ch.send(Msg::Quit).unwrap();
+        h.join().unwrap();
+        assert!(msg_rx.try_recv().is_err());
+        assert!(cb_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_runner_waiters() {
+        let (ch, msg_rx, h) = start_collect();
+        let (cb_tx, cb_rx) = mpsc::channel();
+        let mut runner = Runner::new(1, ch.clone());
+
+        // Two commands of txn 10 wait for txn 20 through the remote leader.
+        runner.run(Task::ChangeLeader { store_id: Some(2) });
+        runner.run(new_detect(1, 10, 20, &cb_tx));
+        runner.run(new_detect(2, 10, 20, &cb_tx));
+        assert_eq!(recv_request(&msg_rx).1.get_tp(), DeadlockRequestType::Detect);
+        assert_eq!(recv_request(&msg_rx).1.get_tp(), DeadlockRequestType::Detect);
+        // The edge stays in the graph until both of them stop waiting.
+        runner.run(new_clean_up(1, 10, 20));
+        runner.run(new_clean_up(1, 10, 20));
+        assert!(msg_rx.recv_timeout(Duration::from_millis(100)).is_err());
+        runner.run(new_clean_up(2, 10, 20));
+        let req = recv_request(&msg_rx).1;
+        assert_eq!(req.get_tp(), DeadlockRequestType::CleanUpWaitFor);
+        assert_eq!((req.get_txn_ts(), req.get_wait_for_txn_ts()), (10, 20));
+
+        // The deadlock fails every command still waiting on the edge.
+        runner.run(new_detect(3, 10, 20, &cb_tx));
+        runner.run(new_detect(4, 10, 20, &cb_tx));
+        runner.run(new_detect(5, 10, 20, &cb_tx));
+        for _ in 0..3 {
+            recv_request(&msg_rx);
+        }
+        runner.run(new_clean_up(4, 10, 20));
+        runner.run(new_response(10, 20));
+        let mut cids = vec![cb_rx.recv_timeout(Duration::from_secs(3)).unwrap(),
+                            cb_rx.recv_timeout(Duration::from_secs(3)).unwrap()];
+        cids.sort();
+        assert_eq!(cids, vec![3, 5]);
+
+        // The same holds for the local detector.
+        runner.run(Task::ChangeLeader { store_id: Some(1) });
+        runner.run(new_detect(6, 10, 20, &cb_tx));
+        runner.run(new_detect(7, 10, 20, &cb_tx));
+        runner.run(new_clean_up(6, 10, 20));
+        runner.run(new_detect(8, 20, 10, &cb_tx));
+        assert_eq!(cb_rx.recv_timeout(Duration::from_secs(3)).unwrap(), 8);
+        runner.run(new_clean_up(7, 10, 20));
+        runner.run(new_detect(9, 20, 10, &cb_tx));
+
+        ch.send(Msg::Quit).unwrap();
+        h.join().unwrap();
+        assert!(msg_rx.try_recv().is_err());
+        assert!(cb_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_detect_table() {
+        let mut table = DetectTable::new();
+
+        // 1 -> 2 -> 3
+        assert!(!table.detect(1, 2));
+        assert!(!table.detect(2, 3));
+        // 3 -> 1 forms a cycle and is not added.
+        assert!(table.detect(3, 1));
+        // 1 -> 3 doesn't.
+        assert!(!table.detect(1, 3));
+
+        // After 2 stops waiting for 3, 3 -> 1 is fine.
+        table.clean_up_wait_for(2, 3);
+        assert!(!table.detect(3, 1));
+        // 2 -> 3 -> 1 -> 2
+        assert!(table.detect(2, 3));
+
+        table.clean_up_wait_for(1, 2);
+        table.clean_up_wait_for(1, 3);
+        table.clean_up_wait_for(3, 1);
+        assert!(table.is_empty());
+    }
+}
@@ -0,0 +1,544 @@
+// Copyright 2016 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Background GC of the old MVCC versions.
+//!
+//! Every `gc-interval`, the GC safe point of the cluster is fetched from pd. If it has moved
+//! forward, a GC round collects the versions before it in the regions led by this store, so
+//! every region is handled by exactly one store.
+//!
+//! A region is skipped if nothing has been written to it since it was collected with no
+//! versions left after the safe point, as the later safe points find nothing more in it.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Sender};
+use std::fmt::{self, Formatter, Display};
+use std::thread::{self, JoinHandle, Builder};
+use std::time::{Duration, Instant};
+
+use kvproto::kvrpcpb::{Context, CommandPri};
+use kvproto::metapb;
+use pd::PdClient;
+use raftstore::store::Msg as StoreMsg;
+use storage::{self, Storage};
+use storage::config::Config as StorageConfig;
+use storage::mvcc::MvccStats;
+use util::worker::{Runnable, Worker};
+use super::transport::RaftStoreRouter;
+use super::Result;
+use super::metrics::*;
+
+const LEADER_REGIONS_TIMEOUT_SECS: u64 = 10;
+
+pub enum Task {
+    /// Collects the versions before the GC safe point in the regions led by this store, if
+    /// the safe point has moved forward since the last round.
+    Gc,
+}
+
+impl Display for Task {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Task::Gc => write!(f, "gc"),
+        }
+    }
+}
+
+pub struct Runner<T: PdClient, R: RaftStoreRouter> {
+    pd_client: Arc<T>,
+    router: R,
+    storage: Storage,
+    // 0 means no limit.
+    max_regions_per_sec: u64,
+    safe_point: u64,
+    // region id -> (leader peer id, `MvccStats::written`) of the regions which had no
+    // versions after the safe point when they were collected
+    collected: HashMap<u64, (u64, u64)>,
+}
+
+impl<T: PdClient, R: RaftStoreRouter> Runner<T, R> {
+    pub fn new(pd_client: Arc<T>,
+               router: R,
+               storage: Storage,
+               max_regions_per_sec: u64)
+               -> Runner<T, R> {
+        Runner {
+            pd_client: pd_client,
+            router: router,
+            storage: storage,
+            max_regions_per_sec: max_regions_per_sec,
+            safe_point: 0,
+            collected: HashMap::new(),
+        }
+    }
+
+    fn handle_gc(&mut self) {
+        let safe_point = match self.pd_client.get_gc_safe_point() {
+            Ok(safe_point) => safe_point,
+            Err(e) => {
+                error!("failed to get gc safe point: {:?}", e);
+                return;
+            }
+        };
+        if safe_point <= self.safe_point {
+            debug!("safe point {} is not after the last one {}, skip gc",
+                   safe_point,
+                   self.safe_point);
+            return;
+        }
+        let mut regions = match self.leader_regions() {
+            Ok(regions) => regions,
+            Err(e) => {
+                error!("failed to get leader regions, skip gc: {:?}", e);
+                return;
+            }
+        };
+        regions.sort_by(|a, b| a.0.get_start_key().cmp(b.0.get_start_key()));
+
+        info!("gc round with safe point {} starts, {} regions to walk",
+              safe_point,
+              regions.len());
+        let timer = GC_ROUND_HISTOGRAM.start_timer();
+        GC_SAFE_POINT_GAUGE.set(safe_point as f64);
+        GC_ROUND_REGIONS_GAUGE.set(0.0);
+        // Forgets the regions no longer led by this store.
+        let ids: HashSet<u64> = regions.iter().map(|r| r.0.get_id()).collect();
+        self.collected.retain(|id, _| ids.contains(id));
+        for (i, (region, leader, stats)) in regions.into_iter().enumerate() {
+            let region_id = region.get_id();
+            let seen = (leader.get_id(), stats.written);
+            if self.collected.get(&region_id) == Some(&seen) {
+                GC_REGION_COUNTER.with_label_values(&["skipped"]).inc();
+                GC_ROUND_REGIONS_GAUGE.set((i + 1) as f64);
+                continue;
+            }
+            let t = Instant::now();
+            // The stats are got before the GC, so the records written during it get the region
+            // collected again in the next round.
+            if self.gc_region(region, leader, safe_point) == Some(false) {
+                self.collected.insert(region_id, seen);
+            } else {
+                self.collected.remove(&region_id);
+            }
+            GC_ROUND_REGIONS_GAUGE.set((i + 1) as f64);
+            self.throttle(t);
+        }
+        timer.observe_duration();
+        // Regions failed in this round are collected once the safe point moves forward.
+        self.safe_point = safe_point;
+        info!("gc round with safe point {} finishes", safe_point);
+    }
+
+    // Gets the regions led by this store from raftstore.
+    fn leader_regions(&self) -> Result<Vec<(metapb::Region, metapb::Peer, MvccStats)>> {
+        let (tx, rx) = mpsc::channel();
+        box_try!(self.router.send(StoreMsg::LeaderRegions {
+            callback: box move |regions| {
+                // The receiver may have timed out.
+                let _ = tx.send(regions);
+            },
+        }));
+        let regions = box_try!(rx.recv_timeout(Duration::from_secs(LEADER_REGIONS_TIMEOUT_SECS)));
+        Ok(regions)
+    }
+
+    // Returns whether the region has versions after the safe point, or `None` if it fails.
+    fn gc_region(&self,
+                 region: metapb::Region,
+                 leader: metapb::Peer,
+                 safe_point: u64)
+                 -> Option<bool> {
+        let region_id = region.get_id();
+        let mut ctx = Context::new();
+        ctx.set_region_id(region_id);
+        ctx.set_region_epoch(region.get_region_epoch().clone());
+        ctx.set_peer(leader);
+        // Background GC shouldn't slow down user commands.
+        ctx.set_priority(CommandPri::Low);
+        match self.gc_with_ctx(ctx, safe_point) {
+            Ok(has_newer) => {
+                GC_REGION_COUNTER.with_label_values(&["success"]).inc();
+                Some(has_newer)
+            }
+            Err(e) => {
+                // The leader has moved, or the region has changed since the round started.
+                GC_REGION_COUNTER.with_label_values(&["failed"]).inc();
+                warn!("failed to gc region {} with safe point {}: {:?}",
+                      region_id,
+                      safe_point,
+                      e);
+                None
+            }
+        }
+    }
+
+    fn gc_with_ctx(&self, ctx: Context, safe_point: u64) -> storage::Result<bool> {
+        let (tx, rx) = mpsc::channel();
+        try!(self.storage.async_gc(ctx, safe_point, box move |res| tx.send(res).unwrap()));
+        match rx.recv() {
+            Ok(res) => res,
+            Err(e) => Err(box_err!("gc callback dropped: {:?}", e)),
+        }
+    }
+
+    // Sleeps to keep at most `max_regions_per_sec` regions handled per second.
+    fn throttle(&self, region_start: Instant) {
+        if self.max_regions_per_sec == 0 {
+            return;
+        }
+        let min_cost = Duration::from_millis(1000 / self.max_regions_per_sec);
+        let elapsed = region_start.elapsed();
+        if elapsed < min_cost {
+            thread::sleep(min_cost - elapsed);
+        }
+    }
+}
+
+impl<T: PdClient, R: RaftStoreRouter> Runnable<Task> for Runner<T, R> {
+    fn run(&mut self, task: Task) {
+        match task {
+            Task::Gc => self.handle_gc(),
+        }
+    }
+}
+
+/// GC service of a store.
+///
+/// When `gc_interval` is not zero, a round is scheduled every `gc_interval`, which collects
+/// the versions before the GC safe point from pd.
+pub struct GcWorker {
+    worker: Worker<Task>,
+    ticker: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+impl GcWorker {
+    pub fn new() -> GcWorker {
+        GcWorker {
+            worker: Worker::new("gc-worker"),
+            ticker: None,
+        }
+    }
+
+    pub fn start<T, R>(&mut self,
+                       pd_client: Arc<T>,
+                       router: R,
+                       storage: Storage,
+                       cfg: &StorageConfig)
+                       -> Result<()>
+        where T: PdClient + 'static,
+              R: RaftStoreRouter + 'static
+    {
+        let runner = Runner::new(pd_client, router, storage, cfg.gc_max_regions_per_sec);
+        box_try!(self.worker.start(runner));
+        if cfg.gc_interval == 0 {
+            info!("automatic gc is disabled");
+            return Ok(());
+        }
+
+        let interval = Duration::from_secs(cfg.gc_interval);
+        let scheduler = self.worker.scheduler();
+        let (tx, rx) = mpsc::channel();
+        let h = try!(Builder::new()
+            .name(thd_name!("gc-ticker"))
+            .spawn(move || {
+                // Stops when the sender is dropped.
+                while let Err(mpsc::RecvTimeoutError::Timeout) = rx.recv_timeout(interval) {
+                    if let Err(e) = scheduler.schedule(Task::Gc) {
+                        error!("failed to schedule gc: {:?}", e);
+                    }
+                }
+            }));
+        self.ticker = Some((tx, h));
+        Ok(())
+    }
+}
+
+impl Drop for GcWorker {
+    fn drop(&mut self) {
+        if let Some((tx, h)) = self.ticker.take() {
+            drop(tx);
+            if let Err(e) = h.join() {
+                error!("failed to stop gc ticker thread: {:?}!!!", e);
+            }
+        }
+        if let Some(Err(e)) = self.worker.stop().map(|h| h.join()) {
+            error!("failed to stop gc worker thread: {:?}!!!", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    use kvproto::kvrpcpb::Context;
+    use kvproto::{metapb, pdpb};
+    use pd::{PdClient, Result as PdResult};
+    use raftstore::Result as RaftStoreResult;
+    use raftstore::store::Msg as StoreMsg;
+    use server::transport::RaftStoreRouter;
+    use storage::{self, Storage, Mutation, Value, make_key};
+    use storage::config::Config;
+    use storage::mvcc::MvccStats;
+    use util::worker::Runnable;
+    use super::*;
+
+    // Returns an error if the safe point is `None`.
+    struct MockPdClient {
+        safe_point: Mutex<Option<u64>>,
+    }
+
+    impl MockPdClient {
+        fn set_safe_point(&self, safe_point: Option<u64>) {
+            *self.safe_point.lock().unwrap() = safe_point;
+        }
+    }
+
+    impl PdClient for MockPdClient {
+        fn get_cluster_id(&self) -> PdResult<u64> {
+            unimplemented!();
+        }
+        fn bootstrap_cluster(&self, _: metapb::Store, _: metapb::Region) -> PdResult<()> {
+            unimplemented!();
+        }
+        fn is_cluster_bootstrapped(&self) -> PdResult<bool> {
+            unimplemented!();
+        }
+        fn alloc_id(&self) -> PdResult<u64> {
+            unimplemented!();
+        }
+        fn put_store(&self, _: metapb::Store) -> PdResult<()> {
+            unimplemented!();
+        }
+        fn get_store(&self, _: u64) -> PdResult<metapb::Store> {
+            unimplemented!();
+        }
+        fn get_cluster_config(&self) -> PdResult<metapb::Cluster> {
+            unimplemented!();
+        }
+        fn get_region(&self, _: &[u8]) -> PdResult<metapb::Region> {
+            unimplemented!();
+        }
+        fn get_region_by_id(&self, _: u64) -> PdResult<Option<metapb::Region>> {
+            unimplemented!();
+        }
+        fn get_region_leader(&self, _: &[u8]) -> PdResult<Option<metapb::Peer>> {
+            unimplemented!();
+        }
+        fn region_heartbeat(&self,
+                            _: metapb::Region,
+                            _: metapb::Peer,
+                            _: Vec<pdpb::PeerStats>,
+                            _: MvccStats)
+                            -> PdResult<pdpb::RegionHeartbeatResponse> {
+            unimplemented!();
+        }
+        fn ask_split(&self, _: metapb::Region) -> PdResult<pdpb::AskSplitResponse> {
+            unimplemented!();
+        }
+        fn store_heartbeat(&self, _: pdpb::StoreStats) -> PdResult<()> {
+            unimplemented!();
+        }
+        fn report_split(&self, _: metapb::Region, _: metapb::Region) -> PdResult<()> {
+            unimplemented!();
+        }
+        fn get_gc_safe_point(&self) -> PdResult<u64> {
+            self.safe_point.lock().unwrap().ok_or_else(|| box_err!("no safe point"))
+        }
+    }
+
+    // Leads `regions` and counts the requests for them, `written` is reported as the records
+    // written to every region.
+    #[derive(Clone)]
+    struct MockRouter {
+        regions: Vec<(metapb::Region, metapb::Peer)>,
+        requests: Arc<AtomicUsize>,
+        written: Arc<AtomicUsize>,
+    }
+
+    impl RaftStoreRouter for MockRouter {
+        fn send(&self, msg: StoreMsg) -> RaftStoreResult<()> {
+            match msg {
+                StoreMsg::LeaderRegions { callback } => {
+                    self.requests.fetch_add(1, Ordering::SeqCst);
+                    let mut stats = MvccStats::new();
+                    stats.written = self.written.load(Ordering::SeqCst) as u64;
+                    let regions = self.regions
+                        .iter()
+                        .map(|&(ref r, ref p)| (r.clone(), p.clone(), stats.clone()))
+                        .collect();
+                    callback(regions);
+                }
+                _ => unimplemented!(),
+            }
+            Ok(())
+        }
+
+        fn try_send(&self, msg: StoreMsg) -> RaftStoreResult<()> {
+            self.send(msg)
+        }
+    }
+
+    fn new_runner(storage: &Storage,
+                  region_count: u64,
+                  max_regions_per_sec: u64)
+                  -> (Runner<MockPdClient, MockRouter>, Arc<MockPdClient>, MockRouter) {
+        let pd_client = Arc::new(MockPdClient { safe_point: Mutex::new(None) });
+        let mut regions = vec![];
+        for id in 1..region_count + 1 {
+            let mut region = metapb::Region::new();
+            region.set_id(id);
+            region.set_start_key(vec![id as u8]);
+            let mut peer = metapb::Peer::new();
+            peer.set_store_id(1);
+            regions.push((region, peer));
+        }
+        let router = MockRouter {
+            regions: regions,
+            requests: Arc::new(AtomicUsize::new(0)),
+            written: Arc::new(AtomicUsize::new(0)),
+        };
+        let runner =
+            Runner::new(pd_client.clone(), router.clone(), storage.clone(), max_regions_per_sec);
+        (runner, pd_client, router)
+    }
+
+    fn new_storage() -> Storage {
+        let config = Config::new();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        storage
+    }
+
+    fn must_put(storage: &Storage, key: &[u8], value: &[u8], start_ts: u64, commit_ts: u64) {
+        let (tx, rx) = mpsc::channel();
+        let tx1 = tx.clone();
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(key), value.to_vec()))],
+                            key.to_vec(),
+                            start_ts,
+                            0,
+                            box move |res: storage::Result<_>| tx1.send(res.is_ok()).unwrap())
+            .unwrap();
+        assert!(rx.recv().unwrap());
+        storage.async_commit(Context::new(),
+                          vec![make_key(key)],
+                          start_ts,
+                          commit_ts,
+                          box move |res: storage::Result<_>| tx.send(res.is_ok()).unwrap())
+            .unwrap();
+        assert!(rx.recv().unwrap());
+    }
+
+    fn must_get(storage: &Storage, key: &[u8], ts: u64) -> Option<Value> {
+        let (tx, rx) = mpsc::channel();
+        storage.async_get(Context::new(),
+                       make_key(key),
+                       ts,
+                       box move |res: storage::Result<_>| tx.send(res.unwrap()).unwrap())
+            .unwrap();
+        rx.recv().unwrap()
+    }
+
+    #[test]
+    fn test_gc_round() {
+        let mut storage = new_storage();
+        must_put(&storage, b"k", b"v1", 10, 11);
+        must_put(&storage, b"k", b"v2", 20, 21);
+        let (mut runner, pd_client, router) = new_runner(&storage, 1, 0);
+
+        pd_client.set_safe_point(Some(15));
+        runner.run(Task::Gc);
+        assert_eq!(router.requests.load(Ordering::SeqCst), 1);
+        assert_eq!(must_get(&storage, b"k", 15), Some(b"v1".to_vec()));
+
+        // The version before the latest one before the safe point is collected.
+        pd_client.set_safe_point(Some(30));
+        runner.run(Task::Gc);
+        assert_eq!(router.requests.load(Ordering::SeqCst), 2);
+        assert_eq!(must_get(&storage, b"k", 15), None);
+        assert_eq!(must_get(&storage, b"k", 30), Some(b"v2".to_vec()));
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_gc_skip_round() {
+        let mut storage = new_storage();
+        let (mut runner, pd_client, router) = new_runner(&storage, 1, 0);
+
+        // Failed to get the safe point.
+        runner.run(Task::Gc);
+        assert_eq!(router.requests.load(Ordering::SeqCst), 0);
+
+        pd_client.set_safe_point(Some(30));
+        runner.run(Task::Gc);
+        assert_eq!(router.requests.load(Ordering::SeqCst), 1);
+
+        // The safe point doesn't move forward.
+        runner.run(Task::Gc);
+        pd_client.set_safe_point(Some(20));
+        runner.run(Task::Gc);
+        assert_eq!(router.requests.load(Ordering::SeqCst), 1);
+
+        pd_client.set_safe_point(Some(40));
+        runner.run(Task::Gc);
+        assert_eq!(router.requests.load(Ordering::SeqCst), 2);
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_gc_skip_region() {
+        let mut storage = new_storage();
+        must_put(&storage, b"k", b"v1", 10, 11);
+        must_put(&storage, b"k", b"v2", 20, 21);
+        let (mut runner, pd_client, router) = new_runner(&storage, 1, 0);
+        router.written.store(2, Ordering::SeqCst);
+
+        // v2 is after the safe point, so the region is collected again.
+        pd_client.set_safe_point(Some(15));
+        runner.run(Task::Gc);
+        pd_client.set_safe_point(Some(25));
+        runner.run(Task::Gc);
+        assert_eq!(must_get(&storage, b"k", 15), None);
+
+        // Nothing is left after the safe point, the region is skipped until it's written.
+        // The write of v3 isn't reported to tell whether the region is collected.
+        must_put(&storage, b"k", b"v3", 30, 31);
+        pd_client.set_safe_point(Some(40));
+        runner.run(Task::Gc);
+        assert_eq!(router.requests.load(Ordering::SeqCst), 3);
+        assert_eq!(must_get(&storage, b"k", 25), Some(b"v2".to_vec()));
+
+        router.written.store(3, Ordering::SeqCst);
+        pd_client.set_safe_point(Some(50));
+        runner.run(Task::Gc);
+        assert_eq!(must_get(&storage, b"k", 25), None);
+        assert_eq!(must_get(&storage, b"k", 50), Some(b"v3".to_vec()));
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_gc_throttle() {
+        let mut storage = new_storage();
+        let (mut runner, pd_client, _) = new_runner(&storage, 3, 10);
+        pd_client.set_safe_point(Some(30));
+        let t = Instant::now();
+        runner.run(Task::Gc);
+        assert!(t.elapsed() >= Duration::from_millis(300));
+        storage.stop().unwrap();
+    }
+}
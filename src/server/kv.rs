@@ -17,14 +17,23 @@ use protobuf::RepeatedField;
 
 use kvproto::kvrpcpb::{CmdGetResponse, CmdScanResponse, CmdPrewriteResponse, CmdCommitResponse,
                        CmdBatchRollbackResponse, CmdCleanupResponse, CmdBatchGetResponse,
-                       CmdScanLockResponse, CmdResolveLockResponse, CmdGCResponse, Request,
-                       Response, MessageType, KvPair as RpcKvPair, KeyError, LockInfo, Op};
+                       CmdScanLockResponse, CmdResolveLockResponse, CmdGCResponse,
+                       CmdRawGetResponse, CmdRawBatchGetResponse, CmdRawPutResponse,
+                       CmdRawBatchPutResponse, CmdRawDeleteResponse, CmdRawScanResponse,
+                       CmdPessimisticLockResponse, CmdCheckTxnStatusResponse,
+                       CmdTxnHeartBeatResponse, CmdCheckSecondaryLocksResponse,
+                       CmdMvccGetByKeyResponse,
+                       CmdMvccGetByStartTsResponse, Request, Response, MessageType,
+                       KvPair as RpcKvPair, KeyError, LockInfo, Op, MvccInfo as RpcMvccInfo,
+                       MvccLock, MvccWrite, MvccValue, AlreadyExist};
 use kvproto::msgpb;
 use kvproto::errorpb::{Error as RegionError, ServerIsBusy};
-use storage::{Engine, Storage, Key, Value, KvPair, Mutation, Callback, Result as StorageResult};
+use storage::{Engine, Storage, Key, Value, KvPair, Mutation, Callback, ConcurrencyManager,
+              Result as StorageResult};
 use storage::Error as StorageError;
 use storage::txn::Error as TxnError;
-use storage::mvcc::Error as MvccError;
+use storage::mvcc::{Error as MvccError, TxnStatus, SecondaryLocksStatus, MvccInfo, LockType,
+                    WriteType};
 use storage::engine::Error as EngineError;
 use util::escape;
 
@@ -58,12 +67,20 @@ impl StoreHandler {
         let req = msg.take_cmd_scan_req();
         let start_key = req.get_start_key();
         debug!("start_key [{}]", escape(&start_key));
+        let end_key = if req.get_end_key().is_empty() {
+            None
+        } else {
+            Some(Key::from_raw(req.get_end_key()))
+        };
         let cb = self.make_cb(StoreHandler::cmd_scan_done, on_resp);
         self.store
             .async_scan(msg.take_context(),
                         Key::from_raw(start_key),
+                        end_key,
                         req.get_limit() as usize,
+                        req.get_limit_bytes() as usize,
                         req.get_key_only(),
+                        req.get_reverse(),
                         req.get_version(),
                         cb)
             .map_err(Error::Storage)
@@ -74,17 +91,59 @@ impl StoreHandler {
             return Err(box_err!("msg doesn't contain a CmdPrewriteRequest"));
         }
         let mut req = msg.take_cmd_prewrite_req();
-        let mutations = req.take_mutations()
-            .into_iter()
-            .map(|mut x| {
-                match x.get_op() {
-                    Op::Put => Mutation::Put((Key::from_raw(x.get_key()), x.take_value())),
-                    Op::Del => Mutation::Delete(Key::from_raw(x.get_key())),
-                    Op::Lock => Mutation::Lock(Key::from_raw(x.get_key())),
-                }
-            })
-            .collect();
+        let mut mutations = Vec::with_capacity(req.get_mutations().len());
+        for mut x in req.take_mutations().into_iter() {
+            let key = Key::from_raw(x.get_key());
+            mutations.push(match x.get_op() {
+                Op::Put => Mutation::Put((key, x.take_value())),
+                Op::Del => Mutation::Delete(key),
+                Op::Lock => Mutation::Lock(key),
+                Op::Insert => Mutation::Insert((key, x.take_value())),
+                Op::CheckNotExists => Mutation::CheckNotExists(key),
+                op => return Err(box_err!("invalid op {:?} in CmdPrewriteRequest", op)),
+            });
+        }
+        if req.get_one_pc_commit_ts() != 0 && req.get_for_update_ts() == 0 &&
+           !req.get_use_async_commit() {
+            let cb = self.make_cb(StoreHandler::cmd_one_pc_prewrite_done, on_resp);
+            return self.store
+                .async_one_pc_prewrite(msg.take_context(),
+                                       mutations,
+                                       req.get_primary_lock().to_vec(),
+                                       req.get_start_version(),
+                                       req.get_lock_ttl(),
+                                       req.get_one_pc_commit_ts(),
+                                       cb)
+                .map_err(Error::Storage);
+        }
+        if req.get_use_async_commit() && req.get_for_update_ts() == 0 {
+            let cb = self.make_cb(StoreHandler::cmd_async_commit_prewrite_done, on_resp);
+            return self.store
+                .async_async_commit_prewrite(msg.take_context(),
+                                             mutations,
+                                             req.get_primary_lock().to_vec(),
+                                             req.take_secondaries().into_vec(),
+                                             req.get_start_version(),
+                                             req.get_lock_ttl(),
+                                             req.get_min_commit_ts(),
+                                             cb)
+                .map_err(Error::Storage);
+        }
         let cb = self.make_cb(StoreHandler::cmd_prewrite_done, on_resp);
+        // Async commit isn't supported by pessimistic transactions yet, which always commit
+        // in two phases.
+        if req.get_for_update_ts() != 0 {
+            return self.store
+                .async_pessimistic_prewrite(msg.take_context(),
+                                            mutations,
+                                            req.get_primary_lock().to_vec(),
+                                            req.get_start_version(),
+                                            req.get_lock_ttl(),
+                                            req.get_for_update_ts(),
+                                            req.get_is_pessimistic_lock().to_vec(),
+                                            cb)
+                .map_err(Error::Storage);
+        }
         self.store
             .async_prewrite(msg.take_context(),
                             mutations,
@@ -95,6 +154,24 @@ impl StoreHandler {
             .map_err(Error::Storage)
     }
 
+    fn on_pessimistic_lock(&self, mut msg: Request, on_resp: OnResponse) -> Result<()> {
+        if !msg.has_cmd_pessimistic_lock_req() {
+            return Err(box_err!("msg doesn't contain a CmdPessimisticLockRequest"));
+        }
+        let req = msg.take_cmd_pessimistic_lock_req();
+        let keys = req.get_keys().iter().map(|x| Key::from_raw(x)).collect();
+        let cb = self.make_cb(StoreHandler::cmd_pessimistic_lock_done, on_resp);
+        self.store
+            .async_acquire_pessimistic_lock(msg.take_context(),
+                                            keys,
+                                            req.get_primary_lock().to_vec(),
+                                            req.get_start_version(),
+                                            req.get_lock_ttl(),
+                                            req.get_for_update_ts(),
+                                            cb)
+            .map_err(Error::Storage)
+    }
+
     fn on_commit(&self, mut msg: Request, on_resp: OnResponse) -> Result<()> {
         if !msg.has_cmd_commit_req() {
             return Err(box_err!("msg doesn't contain a CmdCommitRequest"));
@@ -140,6 +217,70 @@ impl StoreHandler {
             .map_err(Error::Storage)
     }
 
+    fn on_check_txn_status(&self, mut msg: Request, on_resp: OnResponse) -> Result<()> {
+        if !msg.has_cmd_check_txn_status_req() {
+            return Err(box_err!("msg doesn't contain a CmdCheckTxnStatusRequest"));
+        }
+        let req = msg.take_cmd_check_txn_status_req();
+        let cb = self.make_cb(StoreHandler::cmd_check_txn_status_done, on_resp);
+        self.store
+            .async_check_txn_status(msg.take_context(),
+                                    Key::from_raw(req.get_primary_key()),
+                                    req.get_lock_ts(),
+                                    req.get_current_ts(),
+                                    cb)
+            .map_err(Error::Storage)
+    }
+
+    fn on_check_secondary_locks(&self, mut msg: Request, on_resp: OnResponse) -> Result<()> {
+        if !msg.has_cmd_check_secondary_locks_req() {
+            return Err(box_err!("msg doesn't contain a CmdCheckSecondaryLocksRequest"));
+        }
+        let req = msg.take_cmd_check_secondary_locks_req();
+        let keys = req.get_keys().iter().map(|x| Key::from_raw(x)).collect();
+        let cb = self.make_cb(StoreHandler::cmd_check_secondary_locks_done, on_resp);
+        self.store
+            .async_check_secondary_locks(msg.take_context(), keys, req.get_start_version(), cb)
+            .map_err(Error::Storage)
+    }
+
+    fn on_txn_heart_beat(&self, mut msg: Request, on_resp: OnResponse) -> Result<()> {
+        if !msg.has_cmd_txn_heart_beat_req() {
+            return Err(box_err!("msg doesn't contain a CmdTxnHeartBeatRequest"));
+        }
+        let req = msg.take_cmd_txn_heart_beat_req();
+        let cb = self.make_cb(StoreHandler::cmd_txn_heart_beat_done, on_resp);
+        self.store
+            .async_txn_heart_beat(msg.take_context(),
+                                  Key::from_raw(req.get_primary_lock()),
+                                  req.get_start_version(),
+                                  req.get_advise_lock_ttl(),
+                                  cb)
+            .map_err(Error::Storage)
+    }
+
+    fn on_mvcc_get_by_key(&self, mut msg: Request, on_resp: OnResponse) -> Result<()> {
+        if !msg.has_cmd_mvcc_get_by_key_req() {
+            return Err(box_err!("msg doesn't contain a CmdMvccGetByKeyRequest"));
+        }
+        let req = msg.take_cmd_mvcc_get_by_key_req();
+        let cb = self.make_cb(StoreHandler::cmd_mvcc_get_by_key_done, on_resp);
+        self.store
+            .async_mvcc_by_key(msg.take_context(), Key::from_raw(req.get_key()), cb)
+            .map_err(Error::Storage)
+    }
+
+    fn on_mvcc_get_by_start_ts(&self, mut msg: Request, on_resp: OnResponse) -> Result<()> {
+        if !msg.has_cmd_mvcc_get_by_start_ts_req() {
+            return Err(box_err!("msg doesn't contain a CmdMvccGetByStartTsRequest"));
+        }
+        let req = msg.take_cmd_mvcc_get_by_start_ts_req();
+        let cb = self.make_cb(StoreHandler::cmd_mvcc_get_by_start_ts_done, on_resp);
+        self.store
+            .async_mvcc_by_start_ts(msg.take_context(), req.get_start_ts(), cb)
+            .map_err(Error::Storage)
+    }
+
     fn on_batch_get(&self, mut msg: Request, on_resp: OnResponse) -> Result<()> {
         if !msg.has_cmd_batch_get_req() {
             return Err(box_err!("msg doesn't contain a CmdBatchGetRequest"));
@@ -159,9 +300,24 @@ impl StoreHandler {
             return Err(box_err!("msg doesn't contain a CmdScanLockRequest"));
         }
         let req = msg.take_cmd_scan_lock_req();
+        let start_key = if req.get_start_key().is_empty() {
+            None
+        } else {
+            Some(Key::from_raw(req.get_start_key()))
+        };
+        let end_key = if req.get_end_key().is_empty() {
+            None
+        } else {
+            Some(Key::from_raw(req.get_end_key()))
+        };
         let cb = self.make_cb(StoreHandler::cmd_scan_lock_done, on_resp);
         self.store
-            .async_scan_lock(msg.take_context(), req.get_max_version(), cb)
+            .async_scan_lock(msg.take_context(),
+                             req.get_max_version(),
+                             start_key,
+                             end_key,
+                             req.get_limit() as usize,
+                             cb)
             .map_err(Error::Storage)
     }
 
@@ -190,6 +346,80 @@ impl StoreHandler {
         self.store.async_gc(msg.take_context(), req.get_safe_point(), cb).map_err(Error::Storage)
     }
 
+    fn on_raw_get(&self, mut msg: Request, on_resp: OnResponse) -> Result<()> {
+        if !msg.has_cmd_raw_get_req() {
+            return Err(box_err!("msg doesn't contain a CmdRawGetRequest"));
+        }
+        let mut req = msg.take_cmd_raw_get_req();
+        let cb = self.make_cb(StoreHandler::cmd_raw_get_done, on_resp);
+        self.store
+            .async_raw_get(msg.take_context(), req.take_key(), cb)
+            .map_err(Error::Storage)
+    }
+
+    fn on_raw_batch_get(&self, mut msg: Request, on_resp: OnResponse) -> Result<()> {
+        if !msg.has_cmd_raw_batch_get_req() {
+            return Err(box_err!("msg doesn't contain a CmdRawBatchGetRequest"));
+        }
+        let mut req = msg.take_cmd_raw_batch_get_req();
+        let cb = self.make_cb(StoreHandler::cmd_raw_batch_get_done, on_resp);
+        self.store
+            .async_raw_batch_get(msg.take_context(), req.take_keys().into_vec(), cb)
+            .map_err(Error::Storage)
+    }
+
+    fn on_raw_put(&self, mut msg: Request, on_resp: OnResponse) -> Result<()> {
+        if !msg.has_cmd_raw_put_req() {
+            return Err(box_err!("msg doesn't contain a CmdRawPutRequest"));
+        }
+        let mut req = msg.take_cmd_raw_put_req();
+        let cb = self.make_cb(StoreHandler::cmd_raw_put_done, on_resp);
+        self.store
+            .async_raw_put(msg.take_context(), req.take_key(), req.take_value(), cb)
+            .map_err(Error::Storage)
+    }
+
+    fn on_raw_batch_put(&self, mut msg: Request, on_resp: OnResponse) -> Result<()> {
+        if !msg.has_cmd_raw_batch_put_req() {
+            return Err(box_err!("msg doesn't contain a CmdRawBatchPutRequest"));
+        }
+        let mut req = msg.take_cmd_raw_batch_put_req();
+        let pairs = req.take_pairs()
+            .into_iter()
+            .map(|mut x| (x.take_key(), x.take_value()))
+            .collect();
+        let cb = self.make_cb(StoreHandler::cmd_raw_batch_put_done, on_resp);
+        self.store
+            .async_raw_batch_put(msg.take_context(), pairs, cb)
+            .map_err(Error::Storage)
+    }
+
+    fn on_raw_delete(&self, mut msg: Request, on_resp: OnResponse) -> Result<()> {
+        if !msg.has_cmd_raw_delete_req() {
+            return Err(box_err!("msg doesn't contain a CmdRawDeleteRequest"));
+        }
+        let mut req = msg.take_cmd_raw_delete_req();
+        let cb = self.make_cb(StoreHandler::cmd_raw_delete_done, on_resp);
+        self.store
+            .async_raw_delete(msg.take_context(), req.take_key(), cb)
+            .map_err(Error::Storage)
+    }
+
+    fn on_raw_scan(&self, mut msg: Request, on_resp: OnResponse) -> Result<()> {
+        if !msg.has_cmd_raw_scan_req() {
+            return Err(box_err!("msg doesn't contain a CmdRawScanRequest"));
+        }
+        let mut req = msg.take_cmd_raw_scan_req();
+        let cb = self.make_cb(StoreHandler::cmd_raw_scan_done, on_resp);
+        self.store
+            .async_raw_scan(msg.take_context(),
+                            req.take_start_key(),
+                            req.take_end_key(),
+                            req.get_limit() as usize,
+                            cb)
+            .map_err(Error::Storage)
+    }
+
     fn make_cb<T: 'static>(&self,
                            f: fn(StorageResult<T>, &mut Response),
                            on_resp: OnResponse)
@@ -239,6 +469,38 @@ impl StoreHandler {
         resp.set_cmd_prewrite_resp(prewrite_resp);
     }
 
+    fn cmd_one_pc_prewrite_done(r: StorageResult<(Vec<StorageResult<()>>, u64)>,
+                                resp: &mut Response) {
+        resp.set_field_type(MessageType::CmdPrewrite);
+        let mut prewrite_resp = CmdPrewriteResponse::new();
+        let results = r.map(|(results, commit_ts)| {
+            prewrite_resp.set_one_pc_commit_ts(commit_ts);
+            results
+        });
+        prewrite_resp.set_errors(RepeatedField::from_vec(extract_key_errors(results)));
+        resp.set_cmd_prewrite_resp(prewrite_resp);
+    }
+
+    fn cmd_async_commit_prewrite_done(r: StorageResult<(Vec<StorageResult<()>>, u64)>,
+                                      resp: &mut Response) {
+        resp.set_field_type(MessageType::CmdPrewrite);
+        let mut prewrite_resp = CmdPrewriteResponse::new();
+        let results = r.map(|(results, min_commit_ts)| {
+            prewrite_resp.set_min_commit_ts(min_commit_ts);
+            results
+        });
+        prewrite_resp.set_errors(RepeatedField::from_vec(extract_key_errors(results)));
+        resp.set_cmd_prewrite_resp(prewrite_resp);
+    }
+
+    fn cmd_pessimistic_lock_done(results: StorageResult<Vec<StorageResult<()>>>,
+                                 resp: &mut Response) {
+        resp.set_field_type(MessageType::CmdPessimisticLock);
+        let mut pessimistic_lock_resp = CmdPessimisticLockResponse::new();
+        pessimistic_lock_resp.set_errors(RepeatedField::from_vec(extract_key_errors(results)));
+        resp.set_cmd_pessimistic_lock_resp(pessimistic_lock_resp);
+    }
+
     fn cmd_commit_done(r: StorageResult<()>, resp: &mut Response) {
         resp.set_field_type(MessageType::CmdCommit);
         let mut cmd_commit_resp = CmdCommitResponse::new();
@@ -270,11 +532,95 @@ impl StoreHandler {
         resp.set_cmd_cleanup_resp(cmd_cleanup_resp);
     }
 
-    fn cmd_scan_lock_done(r: StorageResult<Vec<LockInfo>>, resp: &mut Response) {
+    fn cmd_check_txn_status_done(r: StorageResult<TxnStatus>, resp: &mut Response) {
+        resp.set_field_type(MessageType::CmdCheckTxnStatus);
+        let mut check_resp = CmdCheckTxnStatusResponse::new();
+        match r {
+            Ok(TxnStatus::Alive { ttl }) => check_resp.set_lock_ttl(ttl),
+            Ok(TxnStatus::Committed { commit_ts }) => check_resp.set_commit_version(commit_ts),
+            // The client decides the status by checking the secondaries.
+            Ok(TxnStatus::AsyncCommit { min_commit_ts, secondaries }) => {
+                check_resp.set_min_commit_ts(min_commit_ts);
+                check_resp.set_secondaries(RepeatedField::from_vec(secondaries));
+            }
+            // Both zero means rolled back.
+            Ok(TxnStatus::RolledBack) => {}
+            Err(e) => check_resp.set_error(extract_key_error(&e)),
+        }
+        resp.set_cmd_check_txn_status_resp(check_resp);
+    }
+
+    fn cmd_check_secondary_locks_done(r: StorageResult<SecondaryLocksStatus>,
+                                      resp: &mut Response) {
+        resp.set_field_type(MessageType::CmdCheckSecondaryLocks);
+        let mut check_resp = CmdCheckSecondaryLocksResponse::new();
+        match r {
+            Ok(SecondaryLocksStatus::Locked { min_commit_ts }) => {
+                check_resp.set_min_commit_ts(min_commit_ts)
+            }
+            Ok(SecondaryLocksStatus::Committed { commit_ts }) => {
+                check_resp.set_commit_version(commit_ts)
+            }
+            // Both zero means rolled back.
+            Ok(SecondaryLocksStatus::RolledBack) => {}
+            Err(e) => check_resp.set_error(extract_key_error(&e)),
+        }
+        resp.set_cmd_check_secondary_locks_resp(check_resp);
+    }
+
+    fn cmd_txn_heart_beat_done(r: StorageResult<u64>, resp: &mut Response) {
+        resp.set_field_type(MessageType::CmdTxnHeartBeat);
+        let mut heart_beat_resp = CmdTxnHeartBeatResponse::new();
+        match r {
+            Ok(ttl) => heart_beat_resp.set_lock_ttl(ttl),
+            Err(e) => heart_beat_resp.set_error(extract_key_error(&e)),
+        }
+        resp.set_cmd_txn_heart_beat_resp(heart_beat_resp);
+    }
+
+    fn cmd_mvcc_get_by_key_done(r: StorageResult<MvccInfo>, resp: &mut Response) {
+        resp.set_field_type(MessageType::CmdMvccGetByKey);
+        let mut mvcc_resp = CmdMvccGetByKeyResponse::new();
+        match r {
+            Ok(mvcc) => mvcc_resp.set_info(extract_mvcc_info(mvcc)),
+            Err(e) => mvcc_resp.set_error(format!("{}", e)),
+        }
+        resp.set_cmd_mvcc_get_by_key_resp(mvcc_resp);
+    }
+
+    fn cmd_mvcc_get_by_start_ts_done(r: StorageResult<Option<(Key, MvccInfo)>>,
+                                     resp: &mut Response) {
+        resp.set_field_type(MessageType::CmdMvccGetByStartTs);
+        let mut mvcc_resp = CmdMvccGetByStartTsResponse::new();
+        match r {
+            Ok(Some((key, mvcc))) => {
+                match key.raw() {
+                    Ok(key) => {
+                        mvcc_resp.set_key(key);
+                        mvcc_resp.set_info(extract_mvcc_info(mvcc));
+                    }
+                    Err(e) => mvcc_resp.set_error(format!("{}", e)),
+                }
+            }
+            // Leaves the response empty if no key is found.
+            Ok(None) => {}
+            Err(e) => mvcc_resp.set_error(format!("{}", e)),
+        }
+        resp.set_cmd_mvcc_get_by_start_ts_resp(mvcc_resp);
+    }
+
+    fn cmd_scan_lock_done(r: StorageResult<(Vec<LockInfo>, Option<Vec<u8>>)>,
+                          resp: &mut Response) {
         resp.set_field_type(MessageType::CmdScanLock);
         let mut scan_lock = CmdScanLockResponse::new();
         match r {
-            Ok(locks) => scan_lock.set_locks(RepeatedField::from_vec(locks)),
+            Ok((locks, next_start)) => {
+                scan_lock.set_locks(RepeatedField::from_vec(locks));
+                // Empty means all the locks are scanned.
+                if let Some(key) = next_start {
+                    scan_lock.set_next_start_key(key);
+                }
+            }
             Err(e) => scan_lock.set_error(extract_key_error(&e)),
         }
         resp.set_cmd_scan_lock_resp(scan_lock);
@@ -289,7 +635,7 @@ impl StoreHandler {
         resp.set_cmd_resolve_lock_resp(resolve_lock);
     }
 
-    fn cmd_gc_done(r: StorageResult<()>, resp: &mut Response) {
+    fn cmd_gc_done(r: StorageResult<bool>, resp: &mut Response) {
         resp.set_field_type(MessageType::CmdGC);
         let mut gc = CmdGCResponse::new();
         if let Err(e) = r {
@@ -298,18 +644,83 @@ impl StoreHandler {
         resp.set_cmd_gc_resp(gc);
     }
 
+    fn cmd_raw_get_done(r: StorageResult<Option<Value>>, resp: &mut Response) {
+        resp.set_field_type(MessageType::CmdRawGet);
+        let mut raw_get_resp = CmdRawGetResponse::new();
+        match r {
+            Ok(Some(val)) => raw_get_resp.set_value(val),
+            Ok(None) => {}
+            Err(e) => raw_get_resp.set_error(format!("{}", e)),
+        }
+        resp.set_cmd_raw_get_resp(raw_get_resp);
+    }
+
+    fn cmd_raw_batch_get_done(kvs: StorageResult<Vec<StorageResult<KvPair>>>,
+                              resp: &mut Response) {
+        resp.set_field_type(MessageType::CmdRawBatchGet);
+        let mut raw_batch_get_resp = CmdRawBatchGetResponse::new();
+        raw_batch_get_resp.set_pairs(RepeatedField::from_vec(extract_kv_pairs(kvs)));
+        resp.set_cmd_raw_batch_get_resp(raw_batch_get_resp);
+    }
+
+    fn cmd_raw_put_done(r: StorageResult<()>, resp: &mut Response) {
+        resp.set_field_type(MessageType::CmdRawPut);
+        let mut raw_put_resp = CmdRawPutResponse::new();
+        if let Err(e) = r {
+            raw_put_resp.set_error(format!("{}", e));
+        }
+        resp.set_cmd_raw_put_resp(raw_put_resp);
+    }
+
+    fn cmd_raw_batch_put_done(r: StorageResult<()>, resp: &mut Response) {
+        resp.set_field_type(MessageType::CmdRawBatchPut);
+        let mut raw_batch_put_resp = CmdRawBatchPutResponse::new();
+        if let Err(e) = r {
+            raw_batch_put_resp.set_error(format!("{}", e));
+        }
+        resp.set_cmd_raw_batch_put_resp(raw_batch_put_resp);
+    }
+
+    fn cmd_raw_delete_done(r: StorageResult<()>, resp: &mut Response) {
+        resp.set_field_type(MessageType::CmdRawDelete);
+        let mut raw_delete_resp = CmdRawDeleteResponse::new();
+        if let Err(e) = r {
+            raw_delete_resp.set_error(format!("{}", e));
+        }
+        resp.set_cmd_raw_delete_resp(raw_delete_resp);
+    }
+
+    fn cmd_raw_scan_done(kvs: StorageResult<Vec<StorageResult<KvPair>>>, resp: &mut Response) {
+        resp.set_field_type(MessageType::CmdRawScan);
+        let mut raw_scan_resp = CmdRawScanResponse::new();
+        raw_scan_resp.set_pairs(RepeatedField::from_vec(extract_kv_pairs(kvs)));
+        resp.set_cmd_raw_scan_resp(raw_scan_resp);
+    }
+
     pub fn on_request(&self, req: Request, on_resp: OnResponse) -> Result<()> {
         if let Err(e) = match req.get_field_type() {
             MessageType::CmdGet => self.on_get(req, on_resp),
             MessageType::CmdScan => self.on_scan(req, on_resp),
             MessageType::CmdPrewrite => self.on_prewrite(req, on_resp),
+            MessageType::CmdPessimisticLock => self.on_pessimistic_lock(req, on_resp),
             MessageType::CmdCommit => self.on_commit(req, on_resp),
             MessageType::CmdCleanup => self.on_cleanup(req, on_resp),
+            MessageType::CmdCheckTxnStatus => self.on_check_txn_status(req, on_resp),
+            MessageType::CmdCheckSecondaryLocks => self.on_check_secondary_locks(req, on_resp),
+            MessageType::CmdTxnHeartBeat => self.on_txn_heart_beat(req, on_resp),
+            MessageType::CmdMvccGetByKey => self.on_mvcc_get_by_key(req, on_resp),
+            MessageType::CmdMvccGetByStartTs => self.on_mvcc_get_by_start_ts(req, on_resp),
             MessageType::CmdBatchGet => self.on_batch_get(req, on_resp),
             MessageType::CmdBatchRollback => self.on_batch_rollback(req, on_resp),
             MessageType::CmdScanLock => self.on_scan_lock(req, on_resp),
             MessageType::CmdResolveLock => self.on_resolve_lock(req, on_resp),
             MessageType::CmdGC => self.on_gc(req, on_resp),
+            MessageType::CmdRawGet => self.on_raw_get(req, on_resp),
+            MessageType::CmdRawBatchGet => self.on_raw_batch_get(req, on_resp),
+            MessageType::CmdRawPut => self.on_raw_put(req, on_resp),
+            MessageType::CmdRawBatchPut => self.on_raw_batch_put(req, on_resp),
+            MessageType::CmdRawDelete => self.on_raw_delete(req, on_resp),
+            MessageType::CmdRawScan => self.on_raw_scan(req, on_resp),
         } {
             // TODO: should we return an error and tell the client later?
             error!("Some error occur err[{:?}]", e);
@@ -322,6 +733,10 @@ impl StoreHandler {
         self.store.get_engine()
     }
 
+    pub fn concurrency_manager(&self) -> ConcurrencyManager {
+        self.store.get_concurrency_manager()
+    }
+
     pub fn stop(&mut self) -> Result<()> {
         self.store.stop().map_err(From::from)
     }
@@ -335,7 +750,8 @@ fn extract_region_error<T>(res: &StorageResult<T>) -> Option<RegionError> {
         Err(StorageError::Txn(TxnError::Mvcc(MvccError::Engine(EngineError::Request(ref e))))) => {
             Some(e.to_owned())
         }
-        Err(StorageError::SchedTooBusy) => {
+        Err(StorageError::SchedTooBusy) |
+        Err(StorageError::ReadPoolTooBusy) => {
             let mut err = RegionError::new();
             err.set_server_is_busy(ServerIsBusy::new());
             Some(err)
@@ -365,6 +781,11 @@ fn extract_key_error(err: &StorageError) -> KeyError {
             lock_info.set_lock_ttl(ttl);
             key_error.set_locked(lock_info);
         }
+        StorageError::Txn(TxnError::Mvcc(MvccError::AlreadyExist { ref key })) => {
+            let mut already_exist = AlreadyExist::new();
+            already_exist.set_key(key.to_owned());
+            key_error.set_already_exist(already_exist);
+        }
         StorageError::Txn(TxnError::Mvcc(MvccError::WriteConflict)) |
         StorageError::Txn(TxnError::Mvcc(MvccError::TxnLockNotFound)) => {
             debug!("txn conflicts: {}", err);
@@ -405,6 +826,55 @@ fn extract_kv_pairs(res: StorageResult<Vec<StorageResult<KvPair>>>) -> Vec<RpcKv
     pairs
 }
 
+fn extract_mvcc_info(mvcc: MvccInfo) -> RpcMvccInfo {
+    let mut info = RpcMvccInfo::new();
+    if let Some(lock) = mvcc.lock {
+        let mut mvcc_lock = MvccLock::new();
+        mvcc_lock.set_field_type(match lock.lock_type {
+            LockType::Put => Op::Put,
+            LockType::Delete => Op::Del,
+            LockType::Lock => Op::Lock,
+            LockType::Pessimistic => Op::PessimisticLock,
+        });
+        mvcc_lock.set_start_ts(lock.ts);
+        mvcc_lock.set_primary(lock.primary);
+        if let Some(v) = lock.short_value {
+            mvcc_lock.set_short_value(v);
+        }
+        info.set_lock(mvcc_lock);
+    }
+    let writes = mvcc.writes
+        .into_iter()
+        .map(|(commit_ts, write)| {
+            let mut mvcc_write = MvccWrite::new();
+            mvcc_write.set_field_type(match write.write_type {
+                WriteType::Put => Op::Put,
+                WriteType::Delete => Op::Del,
+                WriteType::Lock => Op::Lock,
+                WriteType::Rollback => Op::Rollback,
+            });
+            mvcc_write.set_start_ts(write.start_ts);
+            mvcc_write.set_commit_ts(commit_ts);
+            if let Some(v) = write.short_value {
+                mvcc_write.set_short_value(v);
+            }
+            mvcc_write
+        })
+        .collect();
+    info.set_writes(RepeatedField::from_vec(writes));
+    let values = mvcc.values
+        .into_iter()
+        .map(|(start_ts, value)| {
+            let mut mvcc_value = MvccValue::new();
+            mvcc_value.set_start_ts(start_ts);
+            mvcc_value.set_value(value);
+            mvcc_value
+        })
+        .collect();
+    info.set_values(RepeatedField::from_vec(values));
+    info
+}
+
 fn extract_key_errors(res: StorageResult<Vec<StorageResult<()>>>) -> Vec<KeyError> {
     let mut errs = vec![];
     match res {
@@ -547,6 +1017,31 @@ mod tests {
         assert_eq!(cmd.get_errors().len(), 1);
     }
 
+    #[test]
+    fn test_prewrite_done_already_exist() {
+        let err = Err(mvcc::Error::AlreadyExist { key: b"key".to_vec() })
+            .map_err(txn::Error::from)
+            .map_err(storage::Error::from);
+        let resp = build_resp(Ok(vec![err]), StoreHandler::cmd_prewrite_done);
+        let errors = resp.get_cmd_prewrite_resp().get_errors();
+        assert_eq!(errors[0].get_already_exist().get_key(), b"key");
+    }
+
+    #[test]
+    fn test_scan_lock_done() {
+        let mut lock = LockInfo::new();
+        lock.set_key(b"a".to_vec());
+        let resp = build_resp(Ok((vec![lock.clone()], Some(b"a\x00".to_vec()))),
+                              StoreHandler::cmd_scan_lock_done);
+        assert_eq!(MessageType::CmdScanLock, resp.get_field_type());
+        let cmd = resp.get_cmd_scan_lock_resp();
+        assert_eq!(cmd.get_locks(), &[lock]);
+        assert_eq!(cmd.get_next_start_key(), b"a\x00");
+
+        let resp = build_resp(Ok((vec![], None)), StoreHandler::cmd_scan_lock_done);
+        assert!(resp.get_cmd_scan_lock_resp().get_next_start_key().is_empty());
+    }
+
     #[test]
     fn test_commit_done_ok() {
         let resp = build_resp(Ok(()), StoreHandler::cmd_commit_done);
@@ -580,6 +1075,94 @@ mod tests {
         assert!(cmd.has_error());
     }
 
+    #[test]
+    fn test_check_txn_status_done() {
+        let resp = build_resp(Ok(mvcc::TxnStatus::Alive { ttl: 10 }),
+                              StoreHandler::cmd_check_txn_status_done);
+        assert_eq!(MessageType::CmdCheckTxnStatus, resp.get_field_type());
+        assert_eq!(resp.get_cmd_check_txn_status_resp().get_lock_ttl(), 10);
+
+        let resp = build_resp(Ok(mvcc::TxnStatus::Committed { commit_ts: 20 }),
+                              StoreHandler::cmd_check_txn_status_done);
+        let cmd = resp.get_cmd_check_txn_status_resp();
+        assert_eq!(cmd.get_commit_version(), 20);
+        assert_eq!(cmd.get_lock_ttl(), 0);
+
+        let resp = build_resp(Ok(mvcc::TxnStatus::RolledBack),
+                              StoreHandler::cmd_check_txn_status_done);
+        let cmd = resp.get_cmd_check_txn_status_resp();
+        assert_eq!((cmd.get_commit_version(), cmd.get_lock_ttl()), (0, 0));
+        assert!(!cmd.has_error());
+
+        let resp = build_resp(Ok(mvcc::TxnStatus::AsyncCommit {
+                                  min_commit_ts: 30,
+                                  secondaries: vec![b"k".to_vec()],
+                              }),
+                              StoreHandler::cmd_check_txn_status_done);
+        let cmd = resp.get_cmd_check_txn_status_resp();
+        assert_eq!((cmd.get_commit_version(), cmd.get_min_commit_ts()), (0, 30));
+        assert_eq!(cmd.get_secondaries(), &[b"k".to_vec()]);
+    }
+
+    #[test]
+    fn test_check_secondary_locks_done() {
+        let resp = build_resp(Ok(mvcc::SecondaryLocksStatus::Locked { min_commit_ts: 10 }),
+                              StoreHandler::cmd_check_secondary_locks_done);
+        assert_eq!(MessageType::CmdCheckSecondaryLocks, resp.get_field_type());
+        let cmd = resp.get_cmd_check_secondary_locks_resp();
+        assert_eq!((cmd.get_commit_version(), cmd.get_min_commit_ts()), (0, 10));
+
+        let resp = build_resp(Ok(mvcc::SecondaryLocksStatus::Committed { commit_ts: 20 }),
+                              StoreHandler::cmd_check_secondary_locks_done);
+        let cmd = resp.get_cmd_check_secondary_locks_resp();
+        assert_eq!((cmd.get_commit_version(), cmd.get_min_commit_ts()), (20, 0));
+
+        let resp = build_resp(Ok(mvcc::SecondaryLocksStatus::RolledBack),
+                              StoreHandler::cmd_check_secondary_locks_done);
+        let cmd = resp.get_cmd_check_secondary_locks_resp();
+        assert_eq!((cmd.get_commit_version(), cmd.get_min_commit_ts()), (0, 0));
+        assert!(!cmd.has_error());
+    }
+
+    #[test]
+    fn test_txn_heart_beat_done() {
+        let resp = build_resp(Ok(100), StoreHandler::cmd_txn_heart_beat_done);
+        assert_eq!(MessageType::CmdTxnHeartBeat, resp.get_field_type());
+        assert_eq!(resp.get_cmd_txn_heart_beat_resp().get_lock_ttl(), 100);
+
+        let resp = build_resp(Err(box_err!("heart beat error")),
+                              StoreHandler::cmd_txn_heart_beat_done);
+        assert!(resp.get_cmd_txn_heart_beat_resp().has_error());
+    }
+
+    #[test]
+    fn test_mvcc_get_by_key_done() {
+        let mut lock = mvcc::Lock::new(mvcc::LockType::Put, b"pk".to_vec(), 10, 0);
+        lock.short_value = Some(b"v".to_vec());
+        let info = mvcc::MvccInfo {
+            lock: Some(lock),
+            writes: vec![(8, mvcc::Write::new(mvcc::WriteType::Rollback, 8)),
+                         (7, mvcc::Write::new(mvcc::WriteType::Put, 5))],
+            values: vec![(5, b"value".to_vec())],
+        };
+        let resp = build_resp(Ok(info), StoreHandler::cmd_mvcc_get_by_key_done);
+        assert_eq!(MessageType::CmdMvccGetByKey, resp.get_field_type());
+        let info = resp.get_cmd_mvcc_get_by_key_resp().get_info();
+        assert_eq!(info.get_lock().get_field_type(), Op::Put);
+        assert_eq!(info.get_lock().get_start_ts(), 10);
+        assert_eq!(info.get_lock().get_short_value(), b"v");
+        let writes: Vec<_> = info.get_writes()
+            .iter()
+            .map(|w| (w.get_field_type(), w.get_start_ts(), w.get_commit_ts()))
+            .collect();
+        assert_eq!(writes, vec![(Op::Rollback, 8, 8), (Op::Put, 5, 7)]);
+        assert_eq!(info.get_values()[0].get_value(), b"value");
+
+        let resp = build_resp(Ok(None), StoreHandler::cmd_mvcc_get_by_start_ts_done);
+        assert_eq!(MessageType::CmdMvccGetByStartTs, resp.get_field_type());
+        assert!(resp.get_cmd_mvcc_get_by_start_ts_resp().get_key().is_empty());
+    }
+
     #[test]
     fn test_rollback_done_ok() {
         let resp = build_resp(Ok(()), StoreHandler::cmd_batch_rollback_done);
@@ -597,6 +1180,26 @@ mod tests {
         assert!(cmd.has_error());
     }
 
+    #[test]
+    fn test_raw_get_done_some() {
+        let val = vec![0x0; 0x8];
+        let resp = build_resp(Ok(Some(val.clone())), StoreHandler::cmd_raw_get_done);
+        let mut cmd = CmdRawGetResponse::new();
+        cmd.set_value(val);
+        let mut expect = Response::new();
+        expect.set_field_type(MessageType::CmdRawGet);
+        expect.set_cmd_raw_get_resp(cmd);
+        assert_eq!(expect, resp);
+    }
+
+    #[test]
+    fn test_raw_put_done_err() {
+        let resp = build_resp(Err(box_err!("put error")), StoreHandler::cmd_raw_put_done);
+        assert_eq!(MessageType::CmdRawPut, resp.get_field_type());
+        let cmd = resp.get_cmd_raw_put_resp();
+        assert!(!cmd.get_error().is_empty());
+    }
+
     #[test]
     fn test_get_not_leader() {
         let mut leader_info = NotLeader::new();
@@ -41,6 +41,38 @@ lazy_static! {
             &["type"]
         ).unwrap();
 
+    pub static ref DEADLOCK_DETECT_COUNTER: CounterVec =
+        register_counter_vec!(
+            "tikv_server_deadlock_detect_total",
+            "Total number of deadlock detections",
+            &["type"]
+        ).unwrap();
+
+    pub static ref GC_REGION_COUNTER: CounterVec =
+        register_counter_vec!(
+            "tikv_server_gc_region_total",
+            "Total number of regions walked by gc worker",
+            &["type"]
+        ).unwrap();
+
+    pub static ref GC_ROUND_HISTOGRAM: Histogram =
+        register_histogram!(
+            "tikv_server_gc_round_duration_seconds",
+            "Bucketed histogram of gc round duration"
+        ).unwrap();
+
+    pub static ref GC_ROUND_REGIONS_GAUGE: Gauge =
+        register_gauge!(
+            "tikv_server_gc_round_regions",
+            "Number of regions walked in the current gc round"
+        ).unwrap();
+
+    pub static ref GC_SAFE_POINT_GAUGE: Gauge =
+        register_gauge!(
+            "tikv_server_gc_safe_point",
+            "Safe point of the current gc round"
+        ).unwrap();
+
     pub static ref CONNECTION_GAUGE: Gauge =
         register_gauge!(
             "tikv_server_connection_total",
@@ -33,6 +33,8 @@ pub mod transport;
 pub mod node;
 pub mod resolve;
 pub mod snap;
+pub mod deadlock;
+pub mod gc_worker;
 
 pub use self::config::{Config, DEFAULT_LISTENING_ADDR, DEFAULT_CLUSTER_ID};
 pub use self::errors::{Result, Error};
@@ -100,6 +102,8 @@ impl Display for ConnData {
             }
             MessageType::CopReq => write!(f, "[{}] coprocessor request", self.msg_id),
             MessageType::CopResp => write!(f, "[{}] coprocessor response", self.msg_id),
+            MessageType::DeadlockReq => write!(f, "[{}] deadlock request", self.msg_id),
+            MessageType::DeadlockResp => write!(f, "[{}] deadlock response", self.msg_id),
             MessageType::PdReq => write!(f, "[{}] pd request", self.msg_id),
             MessageType::PdResp => write!(f, "[{}] pd response", self.msg_id),
             MessageType::None => write!(f, "[{}] invalid message", self.msg_id),
@@ -157,6 +157,7 @@ mod tests {
     use kvproto::pdpb;
     use kvproto::metapb;
     use pd::{PdClient, Result};
+    use storage::mvcc::MvccStats;
     use util;
 
     const STORE_ADDRESS_REFRESH_SECONDS: u64 = 60;
@@ -199,10 +200,14 @@ mod tests {
         fn get_region_by_id(&self, _: u64) -> Result<Option<metapb::Region>> {
             unimplemented!();
         }
+        fn get_region_leader(&self, _: &[u8]) -> Result<Option<metapb::Peer>> {
+            unimplemented!();
+        }
         fn region_heartbeat(&self,
                             _: metapb::Region,
                             _: metapb::Peer,
-                            _: Vec<pdpb::PeerStats>)
+                            _: Vec<pdpb::PeerStats>,
+                            _: MvccStats)
                             -> Result<pdpb::RegionHeartbeatResponse> {
             unimplemented!();
         }
@@ -215,6 +220,9 @@ mod tests {
         fn report_split(&self, _: metapb::Region, _: metapb::Region) -> Result<()> {
             unimplemented!();
         }
+        fn get_gc_safe_point(&self) -> Result<u64> {
+            unimplemented!();
+        }
     }
 
     fn new_store(addr: &str, state: metapb::StoreState) -> metapb::Store {
@@ -25,7 +25,7 @@ use kvproto::msgpb::{MessageType, Message};
 use super::{Msg, ConnData};
 use super::conn::Conn;
 use super::{Result, OnResponse, Config};
-use util::worker::{Stopped, Worker};
+use util::worker::{Stopped, Worker, Scheduler};
 use util::transport::SendCh;
 use storage::Storage;
 use raftstore::store::SnapManager;
@@ -34,6 +34,7 @@ use super::coprocessor::{RequestTask, EndPointHost, EndPointTask};
 use super::transport::RaftStoreRouter;
 use super::resolve::StoreAddrResolver;
 use super::snap::{Task as SnapTask, Runner as SnapHandler};
+use super::deadlock::Task as DeadlockTask;
 use raft::SnapshotStatus;
 use util::sockopt::SocketOpt;
 use super::metrics::*;
@@ -88,6 +89,8 @@ pub struct Server<T: RaftStoreRouter + 'static, S: StoreAddrResolver> {
 
     resolver: S,
 
+    deadlock_detector: Option<Scheduler<DeadlockTask>>,
+
     cfg: Config,
 }
 
@@ -128,6 +131,7 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver> Server<T, S> {
             snap_mgr: snap_mgr,
             snap_worker: snap_worker,
             resolver: resolver,
+            deadlock_detector: None,
             cfg: cfg.clone(),
         };
 
@@ -137,7 +141,8 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver> Server<T, S> {
     pub fn run(&mut self, event_loop: &mut EventLoop<Self>) -> Result<()> {
         let end_point = EndPointHost::new(self.store.engine(),
                                           self.end_point_worker.scheduler(),
-                                          self.cfg.end_point_concurrency);
+                                          self.cfg.end_point_concurrency,
+                                          self.store.concurrency_manager());
         box_try!(self.end_point_worker.start_batch(end_point, DEFAULT_COPROCESSOR_BATCH));
 
         let ch = self.get_sendch();
@@ -154,6 +159,11 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver> Server<T, S> {
         self.sendch.clone()
     }
 
+    /// Sets the deadlock detector which deadlock messages from other stores are passed to.
+    pub fn set_deadlock_detector(&mut self, detector: Scheduler<DeadlockTask>) {
+        self.deadlock_detector = Some(detector);
+    }
+
     // Return listening address, this may only be used for outer test
     // to get the real address because we may use "127.0.0.1:0"
     // in test to avoid port conflict.
@@ -269,6 +279,20 @@ impl<T: RaftStoreRouter, S: StoreAddrResolver> Server<T, S> {
                 box_try!(self.end_point_worker.schedule(EndPointTask::Request(req)));
                 Ok(())
             }
+            MessageType::DeadlockReq | MessageType::DeadlockResp => {
+                RECV_MSG_COUNTER.with_label_values(&["deadlock"]).inc();
+                let detector = match self.deadlock_detector {
+                    Some(ref detector) => detector,
+                    None => return Err(box_err!("deadlock detector is not set")),
+                };
+                let task = if msg_type == MessageType::DeadlockReq {
+                    DeadlockTask::Request(msg.take_deadlock_req())
+                } else {
+                    DeadlockTask::Response(msg.take_deadlock_resp())
+                };
+                box_try!(detector.schedule(task));
+                Ok(())
+            }
             _ => {
                 RECV_MSG_COUNTER.with_label_values(&["invalid"]).inc();
                 Err(box_err!("unsupported message {:?} for token {:?} with msg id {}",
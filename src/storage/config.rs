@@ -16,7 +16,19 @@ const DEFAULT_SCHED_CAPACITY: usize = 10240;
 const DEFAULT_SCHED_MSG_PER_TICK: usize = 1024;
 const DEFAULT_SCHED_CONCURRENCY: usize = 10240;
 const DEFAULT_SCHED_WORKER_POOL_SIZE: usize = 4;
+const DEFAULT_SCHED_HIGH_PRIORITY_POOL_SIZE: usize = 2;
+const DEFAULT_SCHED_LOW_PRIORITY_POOL_SIZE: usize = 1;
 const DEFAULT_SCHED_TOO_BUSY_THRESHOLD: usize = 500;
+const DEFAULT_SCHED_PENDING_WRITE_THRESHOLD: usize = 100 * 1024 * 1024;
+// In milliseconds, 0 means commands return `KeyIsLocked` at once instead of waiting.
+const DEFAULT_SCHED_LOCK_WAIT_TIMEOUT: u64 = 0;
+const DEFAULT_READ_POOL_SIZE: usize = 4;
+// Reads are rejected as too busy when so many of them are running in the read pool.
+const DEFAULT_READ_POOL_TOO_BUSY_THRESHOLD: usize = 1000;
+// In seconds, 0 means the gc worker only runs the rounds scheduled explicitly.
+const DEFAULT_GC_INTERVAL: u64 = 0;
+// 0 means no limit.
+const DEFAULT_GC_MAX_REGIONS_PER_SEC: u64 = 10;
 
 #[derive(Clone, Debug)]
 pub struct Config {
@@ -25,7 +37,15 @@ pub struct Config {
     pub sched_msg_per_tick: usize,
     pub sched_concurrency: usize,
     pub sched_worker_pool_size: usize,
+    pub sched_high_priority_pool_size: usize,
+    pub sched_low_priority_pool_size: usize,
     pub sched_too_busy_threshold: usize,
+    pub sched_pending_write_threshold: usize,
+    pub sched_lock_wait_timeout: u64,
+    pub read_pool_size: usize,
+    pub read_pool_too_busy_threshold: usize,
+    pub gc_interval: u64,
+    pub gc_max_regions_per_sec: u64,
 }
 
 impl Default for Config {
@@ -36,7 +56,15 @@ impl Default for Config {
             sched_msg_per_tick: DEFAULT_SCHED_MSG_PER_TICK,
             sched_concurrency: DEFAULT_SCHED_CONCURRENCY,
             sched_worker_pool_size: DEFAULT_SCHED_WORKER_POOL_SIZE,
+            sched_high_priority_pool_size: DEFAULT_SCHED_HIGH_PRIORITY_POOL_SIZE,
+            sched_low_priority_pool_size: DEFAULT_SCHED_LOW_PRIORITY_POOL_SIZE,
             sched_too_busy_threshold: DEFAULT_SCHED_TOO_BUSY_THRESHOLD,
+            sched_pending_write_threshold: DEFAULT_SCHED_PENDING_WRITE_THRESHOLD,
+            sched_lock_wait_timeout: DEFAULT_SCHED_LOCK_WAIT_TIMEOUT,
+            read_pool_size: DEFAULT_READ_POOL_SIZE,
+            read_pool_too_busy_threshold: DEFAULT_READ_POOL_TOO_BUSY_THRESHOLD,
+            gc_interval: DEFAULT_GC_INTERVAL,
+            gc_max_regions_per_sec: DEFAULT_GC_MAX_REGIONS_PER_SEC,
         }
     }
 }
@@ -39,6 +39,9 @@ pub type Callback<T> = Box<FnBox(Result<T>) + Send>;
 pub enum Modify {
     Delete(CfName, Key),
     Put(CfName, Key, Value),
+    // Deletes every key in [start, end) of all the data cfs, it must be the only modify of a
+    // write.
+    DeleteRange(Key, Key),
 }
 
 pub trait Engine: Send + Debug {
@@ -93,6 +96,12 @@ pub trait Snapshot: Send {
                    fill_cache: bool,
                    mode: ScanMode)
                    -> Result<Cursor<'a>>;
+
+    /// Returns whether `key` is in the range served by the snapshot. A snapshot of the whole
+    /// engine contains all the keys.
+    fn contains_key(&self, _: &Key) -> bool {
+        true
+    }
 }
 
 pub trait Iterator {
@@ -398,7 +407,7 @@ mod tests {
     use super::*;
     use super::SEEK_BOUND;
     use tempdir::TempDir;
-    use storage::{CfName, CF_DEFAULT, make_key};
+    use storage::{CfName, CF_DEFAULT, CF_LOCK, CF_WRITE, CF_RAW, ALL_CFS, make_key};
     use util::codec::bytes;
     use util::escape;
     use kvproto::kvrpcpb::Context;
@@ -419,6 +428,35 @@ mod tests {
         test_empty_write(e.as_ref());
     }
 
+    #[test]
+    fn rocksdb_delete_range() {
+        let dir = TempDir::new("rocksdb_test").unwrap();
+        let e = new_local_engine(dir.path().to_str().unwrap(), ALL_CFS).unwrap();
+        for cf in &[CF_DEFAULT, CF_LOCK, CF_WRITE, CF_RAW] {
+            for key in &[b"x", b"y", b"z"] {
+                must_put_cf(e.as_ref(), *cf, *key, b"1");
+            }
+        }
+
+        // Deleted in the same batch as the other modifies, and in order.
+        e.write(&Context::new(),
+                 vec![Modify::DeleteRange(make_key(b"x"), make_key(b"z")),
+                      Modify::Put(CF_DEFAULT, make_key(b"y"), b"2".to_vec())])
+            .unwrap();
+        for cf in &[CF_LOCK, CF_WRITE] {
+            assert_none_cf(e.as_ref(), *cf, b"x");
+            assert_none_cf(e.as_ref(), *cf, b"y");
+            assert_has_cf(e.as_ref(), *cf, b"z", b"1");
+        }
+        assert_none(e.as_ref(), b"x");
+        assert_has(e.as_ref(), b"y", b"2");
+        assert_has(e.as_ref(), b"z", b"1");
+        // Raw keys are not in the mvcc key space.
+        for key in &[b"x", b"y", b"z"] {
+            assert_has_cf(e.as_ref(), CF_RAW, *key, b"1");
+        }
+    }
+
     #[test]
     fn rocksdb_reopen() {
         let dir = TempDir::new("rocksdb_test").unwrap();
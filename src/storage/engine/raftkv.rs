@@ -16,8 +16,9 @@ use server::transport::RaftStoreRouter;
 use raftstore::errors::Error as RaftServerError;
 use raftstore::coprocessor::{RegionSnapshot, RegionIterator};
 use raftstore::store::engine::Peekable;
+use raftstore::store::util;
 use kvproto::raft_cmdpb::{RaftCmdRequest, RaftCmdResponse, RaftRequestHeader, Request, Response,
-                          CmdType, DeleteRequest, PutRequest};
+                          CmdType, DeleteRequest, PutRequest, DeleteRangeRequest};
 use kvproto::errorpb;
 use kvproto::kvrpcpb::Context;
 
@@ -201,6 +202,13 @@ impl<S: RaftStoreRouter> Engine for RaftKv<S> {
                     req.set_cmd_type(CmdType::Put);
                     req.set_put(put);
                 }
+                Modify::DeleteRange(start_key, end_key) => {
+                    let mut delete_range = DeleteRangeRequest::new();
+                    delete_range.set_start_key(start_key.encoded().to_owned());
+                    delete_range.set_end_key(end_key.encoded().to_owned());
+                    req.set_cmd_type(CmdType::DeleteRange);
+                    req.set_delete_range(delete_range);
+                }
             }
             reqs.push(req);
         }
@@ -293,6 +301,10 @@ impl Snapshot for RegionSnapshot {
         Ok(Cursor::new(try!(RegionSnapshot::iter_cf(self, cf, upper_bound, fill_cache)),
                        mode))
     }
+
+    fn contains_key(&self, key: &Key) -> bool {
+        util::check_key_in_region(key.encoded(), self.get_region()).is_ok()
+    }
 }
 
 impl<'a> EngineIterator for RegionIterator<'a> {
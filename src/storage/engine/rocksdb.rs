@@ -15,7 +15,7 @@ use std::fmt::{self, Formatter, Debug, Display};
 use std::sync::{Arc, Mutex};
 use rocksdb::{DB, Writable, SeekKey, WriteBatch, DBIterator};
 use kvproto::kvrpcpb::Context;
-use storage::{Key, Value, CfName, CF_DEFAULT};
+use storage::{Key, Value, CfName, CF_DEFAULT, CF_LOCK, CF_WRITE};
 use raftstore::store::engine::{Snapshot as RocksSnapshot, Peekable, Iterable};
 use util::escape;
 use util::rocksdb;
@@ -99,6 +99,27 @@ impl Debug for EngineRocksdb {
     }
 }
 
+// Deletes the keys in [`start_key`, `end_key`) of `cf` in the write batch, so they are
+// deleted atomically with the other modifies.
+fn delete_in_range_cf(db: &DB,
+                      wb: &WriteBatch,
+                      cf: CfName,
+                      start_key: &[u8],
+                      end_key: &[u8])
+                      -> ::std::result::Result<(), String> {
+    let handle = try!(rocksdb::get_cf_handle(db, cf));
+    let mut it = try!(db.new_iterator_cf(cf, Some(end_key), false)
+        .map_err(|e| format!("{:?}", e)));
+    it.seek(start_key.into());
+    while it.valid() {
+        try!(wb.delete_cf(handle, it.key()));
+        if !it.next() {
+            break;
+        }
+    }
+    Ok(())
+}
+
 fn write_modifies(db: &DB, modifies: Vec<Modify>) -> Result<()> {
     let wb = WriteBatch::new();
     for rev in modifies {
@@ -123,6 +144,20 @@ fn write_modifies(db: &DB, modifies: Vec<Modify>) -> Result<()> {
                     wb.put_cf(handle, k.encoded(), &v)
                 }
             }
+            Modify::DeleteRange(start_key, end_key) => {
+                trace!("EngineRocksdb: delete_range {} {}", start_key, end_key);
+                // Raw keys and raft states are not in the mvcc key space, so only the column
+                // families raftstore drops for DeleteRange are cleared.
+                let (start_key, end_key) = (start_key.encoded(), end_key.encoded());
+                let mut res = Ok(());
+                for cf in &[CF_DEFAULT, CF_LOCK, CF_WRITE] {
+                    res = delete_in_range_cf(db, &wb, *cf, start_key, end_key);
+                    if res.is_err() {
+                        break;
+                    }
+                }
+                res
+            }
         };
         if let Err(msg) = res {
             return Err(Error::RocksDb(msg));
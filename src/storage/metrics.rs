@@ -11,7 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use prometheus::{CounterVec, Gauge, HistogramVec};
+use prometheus::{CounterVec, Gauge, Histogram, HistogramVec};
 
 lazy_static! {
     pub static ref KV_COMMAND_COUNTER_VEC: CounterVec =
@@ -28,12 +28,25 @@ lazy_static! {
             &["type", "stage"]
         ).unwrap();
 
+    pub static ref SCHED_COMMANDS_PRI_COUNTER_VEC: CounterVec =
+        register_counter_vec!(
+            "tikv_scheduler_commands_pri_total",
+            "Total number of commands received of each priority.",
+            &["priority"]
+        ).unwrap();
+
     pub static ref SCHED_CONTEX_GAUGE: Gauge =
         register_gauge!(
             "tikv_scheduler_contex_total",
             "Total number of pending commands."
         ).unwrap();
 
+    pub static ref SCHED_WRITING_BYTES_GAUGE: Gauge =
+        register_gauge!(
+            "tikv_scheduler_writing_bytes",
+            "Total number of bytes of the commands being written."
+        ).unwrap();
+
     pub static ref SCHED_WORKER_COUNTER_VEC: CounterVec =
         register_counter_vec!(
             "tikv_scheduler_worker_command_total",
@@ -54,4 +67,36 @@ lazy_static! {
             "Bucketed histogram of latch wait",
             &["type"]
         ).unwrap();
+
+    pub static ref SCHED_LOCK_WAIT_HISTOGRAM: Histogram =
+        register_histogram!(
+            "tikv_scheduler_lock_wait_duration_seconds",
+            "Bucketed histogram of waiting for the locks of other transactions"
+        ).unwrap();
+
+    pub static ref SCHED_LOCK_WAIT_GAUGE: Gauge =
+        register_gauge!(
+            "tikv_scheduler_lock_wait_total",
+            "Total number of commands waiting for locks."
+        ).unwrap();
+
+    pub static ref READ_POOL_STAGE_COUNTER_VEC: CounterVec =
+        register_counter_vec!(
+            "tikv_storage_read_pool_stage_total",
+            "Total number of reads on each stage of the read pool.",
+            &["type", "stage"]
+        ).unwrap();
+
+    pub static ref READ_POOL_RUNNING_GAUGE: Gauge =
+        register_gauge!(
+            "tikv_storage_read_pool_running_total",
+            "Total number of reads taking snapshots or being processed in the read pool."
+        ).unwrap();
+
+    pub static ref READ_POOL_HISTOGRAM_VEC: HistogramVec =
+        register_histogram_vec!(
+            "tikv_storage_read_pool_duration_seconds",
+            "Bucketed histogram of read processing in the read pool",
+            &["type"]
+        ).unwrap();
 }
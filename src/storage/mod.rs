@@ -20,6 +20,7 @@ use std::io::Error as IoError;
 use kvproto::kvrpcpb::LockInfo;
 use mio::{EventLoop, EventLoopBuilder};
 use self::metrics::*;
+use self::mvcc::{TxnStatus, SecondaryLocksStatus, MvccInfo};
 
 pub mod engine;
 pub mod mvcc;
@@ -32,7 +33,7 @@ pub use self::config::Config;
 pub use self::engine::{Engine, Snapshot, TEMP_DIR, new_local_engine, Modify, Cursor,
                        Error as EngineError, ScanMode};
 pub use self::engine::raftkv::RaftKv;
-pub use self::txn::{SnapshotStore, Scheduler, Msg};
+pub use self::txn::{SnapshotStore, Scheduler, Msg, ReadPool, ConcurrencyManager};
 pub use self::types::{Key, Value, KvPair, make_key};
 pub type Callback<T> = Box<FnBox(Result<T>) + Send>;
 
@@ -41,13 +42,19 @@ pub const CF_DEFAULT: CfName = "default";
 pub const CF_LOCK: CfName = "lock";
 pub const CF_WRITE: CfName = "write";
 pub const CF_RAFT: CfName = "raft";
-pub const ALL_CFS: &'static [CfName] = &[CF_DEFAULT, CF_LOCK, CF_WRITE, CF_RAFT];
+// Raw keys live in their own column family, apart from the transactional data.
+pub const CF_RAW: CfName = "raw";
+pub const ALL_CFS: &'static [CfName] = &[CF_DEFAULT, CF_LOCK, CF_WRITE, CF_RAFT, CF_RAW];
 
 #[derive(Debug, Clone)]
 pub enum Mutation {
     Put((Key, Value)),
     Delete(Key),
     Lock(Key),
+    /// Puts a value, and fails with `AlreadyExist` if the key has a committed value.
+    Insert((Key, Value)),
+    /// Fails with `AlreadyExist` if the key has a committed value, without writing anything.
+    CheckNotExists(Key),
 }
 
 #[allow(match_same_arms)]
@@ -57,6 +64,17 @@ impl Mutation {
             Mutation::Put((ref key, _)) => key,
             Mutation::Delete(ref key) => key,
             Mutation::Lock(ref key) => key,
+            Mutation::Insert((ref key, _)) => key,
+            Mutation::CheckNotExists(ref key) => key,
+        }
+    }
+
+    /// Returns whether the key must not have a committed value.
+    pub fn should_not_exist(&self) -> bool {
+        match *self {
+            Mutation::Insert(_) |
+            Mutation::CheckNotExists(_) => true,
+            _ => false,
         }
     }
 }
@@ -68,7 +86,15 @@ pub enum StorageCb {
     Booleans(Callback<Vec<Result<()>>>),
     SingleValue(Callback<Option<Value>>),
     KvPairs(Callback<Vec<Result<KvPair>>>),
-    Locks(Callback<Vec<LockInfo>>),
+    Locks(Callback<(Vec<LockInfo>, Option<Vec<u8>>)>),
+    OnePc(Callback<(Vec<Result<()>>, u64)>),
+    AsyncCommitPrewrite(Callback<(Vec<Result<()>>, u64)>),
+    TxnStatus(Callback<TxnStatus>),
+    SecondaryLocksStatus(Callback<SecondaryLocksStatus>),
+    LockTtl(Callback<u64>),
+    MvccInfoByKey(Callback<MvccInfo>),
+    MvccInfoByStartTs(Callback<Option<(Key, MvccInfo)>>),
+    Gc(Callback<bool>),
 }
 
 pub enum Command {
@@ -85,8 +111,13 @@ pub enum Command {
     Scan {
         ctx: Context,
         start_key: Key,
+        // A reverse scan goes backward from `start_key` (exclusive) to `end_key` (inclusive).
+        end_key: Option<Key>,
         limit: usize,
+        // 0 means no byte limit.
+        limit_bytes: usize,
         key_only: bool,
+        reverse: bool,
         start_ts: u64,
     },
     Prewrite {
@@ -95,6 +126,24 @@ pub enum Command {
         primary: Vec<u8>,
         start_ts: u64,
         lock_ttl: u64,
+        // Non-zero for pessimistic transactions. `is_pessimistic_lock` tells which mutations
+        // have been locked by `AcquirePessimisticLock`.
+        for_update_ts: u64,
+        is_pessimistic_lock: Vec<bool>,
+        // Set for async commit transactions, the primary lock records all the secondaries.
+        secondaries: Option<Vec<Vec<u8>>>,
+        min_commit_ts: u64,
+        // Non-zero to commit the transaction in one phase if possible, at this ts or after the
+        // reads of the keys served in the store.
+        one_pc_commit_ts: u64,
+    },
+    AcquirePessimisticLock {
+        ctx: Context,
+        keys: Vec<Key>,
+        primary: Vec<u8>,
+        start_ts: u64,
+        lock_ttl: u64,
+        for_update_ts: u64,
     },
     Commit {
         ctx: Context,
@@ -112,7 +161,30 @@ pub enum Command {
         keys: Vec<Key>,
         start_ts: u64,
     },
-    ScanLock { ctx: Context, max_ts: u64 },
+    CheckTxnStatus {
+        ctx: Context,
+        primary_key: Key,
+        lock_ts: u64,
+        current_ts: u64,
+    },
+    CheckSecondaryLocks {
+        ctx: Context,
+        keys: Vec<Key>,
+        start_ts: u64,
+    },
+    TxnHeartBeat {
+        ctx: Context,
+        primary_key: Key,
+        start_ts: u64,
+        advise_ttl: u64,
+    },
+    ScanLock {
+        ctx: Context,
+        max_ts: u64,
+        start_key: Option<Key>,
+        end_key: Option<Key>,
+        limit: usize,
+    },
     ResolveLock {
         ctx: Context,
         start_ts: u64,
@@ -125,7 +197,25 @@ pub enum Command {
         safe_point: u64,
         scan_key: Option<Key>,
         keys: Vec<Key>,
+        // whether the keys collected so far have versions after the safe point
+        has_newer: bool,
+    },
+    DeleteRange {
+        ctx: Context,
+        start_key: Key,
+        end_key: Key,
+        safe_point: u64,
+    },
+    RawGet { ctx: Context, key: Key },
+    RawBatchGet { ctx: Context, keys: Vec<Key> },
+    RawScan {
+        ctx: Context,
+        start_key: Key,
+        end_key: Option<Key>,
+        limit: usize,
     },
+    MvccByKey { ctx: Context, key: Key },
+    MvccByStartTs { ctx: Context, start_ts: u64 },
 }
 
 impl Display for Command {
@@ -141,11 +231,13 @@ impl Display for Command {
                        start_ts,
                        ctx)
             }
-            Command::Scan { ref ctx, ref start_key, limit, start_ts, .. } => {
+            Command::Scan { ref ctx, ref start_key, ref end_key, limit, reverse, start_ts, .. } => {
                 write!(f,
-                       "kv::command::scan {}({}) @ {} | {:?}",
+                       "kv::command::scan {}..{:?}({}) reverse: {} @ {} | {:?}",
                        start_key,
+                       end_key,
                        limit,
+                       reverse,
                        start_ts,
                        ctx)
             }
@@ -156,6 +248,14 @@ impl Display for Command {
                        start_ts,
                        ctx)
             }
+            Command::AcquirePessimisticLock { ref ctx, ref keys, start_ts, for_update_ts, .. } => {
+                write!(f,
+                       "kv::command::acquire_pessimistic_lock keys({}) @ {} {} | {:?}",
+                       keys.len(),
+                       start_ts,
+                       for_update_ts,
+                       ctx)
+            }
             Command::Commit { ref ctx, ref keys, lock_ts, commit_ts, .. } => {
                 write!(f,
                        "kv::command::commit {} {} -> {} | {:?}",
@@ -174,8 +274,37 @@ impl Display for Command {
                        start_ts,
                        ctx)
             }
-            Command::ScanLock { ref ctx, max_ts, .. } => {
-                write!(f, "kv::scan_lock {} | {:?}", max_ts, ctx)
+            Command::CheckTxnStatus { ref ctx, ref primary_key, lock_ts, current_ts } => {
+                write!(f,
+                       "kv::command::check_txn_status {} @ {} curr({}) | {:?}",
+                       primary_key,
+                       lock_ts,
+                       current_ts,
+                       ctx)
+            }
+            Command::CheckSecondaryLocks { ref ctx, ref keys, start_ts } => {
+                write!(f,
+                       "kv::command::check_secondary_locks keys({}) @ {} | {:?}",
+                       keys.len(),
+                       start_ts,
+                       ctx)
+            }
+            Command::TxnHeartBeat { ref ctx, ref primary_key, start_ts, advise_ttl } => {
+                write!(f,
+                       "kv::command::txn_heart_beat {} @ {} ttl {} | {:?}",
+                       primary_key,
+                       start_ts,
+                       advise_ttl,
+                       ctx)
+            }
+            Command::ScanLock { ref ctx, max_ts, ref start_key, ref end_key, limit } => {
+                write!(f,
+                       "kv::scan_lock {:?}..{:?}({}) {} | {:?}",
+                       start_key,
+                       end_key,
+                       limit,
+                       max_ts,
+                       ctx)
             }
             Command::ResolveLock { ref ctx, start_ts, commit_ts, .. } => {
                 write!(f,
@@ -191,6 +320,34 @@ impl Display for Command {
                        safe_point,
                        ctx)
             }
+            Command::DeleteRange { ref ctx, ref start_key, ref end_key, safe_point } => {
+                write!(f,
+                       "kv::command::delete_range [{}, {}) @ {} | {:?}",
+                       start_key,
+                       end_key,
+                       safe_point,
+                       ctx)
+            }
+            Command::RawGet { ref ctx, ref key } => {
+                write!(f, "kv::command::raw_get {} | {:?}", key, ctx)
+            }
+            Command::RawBatchGet { ref ctx, ref keys } => {
+                write!(f, "kv::command::raw_batch_get {} | {:?}", keys.len(), ctx)
+            }
+            Command::RawScan { ref ctx, ref start_key, ref end_key, limit } => {
+                write!(f,
+                       "kv::command::raw_scan [{}, {:?})({}) | {:?}",
+                       start_key,
+                       end_key,
+                       limit,
+                       ctx)
+            }
+            Command::MvccByKey { ref ctx, ref key } => {
+                write!(f, "kv::command::mvccbykey {} | {:?}", key, ctx)
+            }
+            Command::MvccByStartTs { ref ctx, start_ts } => {
+                write!(f, "kv::command::mvccbystartts {} | {:?}", start_ts, ctx)
+            }
         }
     }
 }
@@ -207,7 +364,12 @@ impl Command {
             Command::Get { .. } |
             Command::BatchGet { .. } |
             Command::Scan { .. } |
-            Command::ScanLock { .. } => true,
+            Command::ScanLock { .. } |
+            Command::RawGet { .. } |
+            Command::RawBatchGet { .. } |
+            Command::RawScan { .. } |
+            Command::MvccByKey { .. } |
+            Command::MvccByStartTs { .. } => true,
             Command::ResolveLock { ref keys, .. } |
             Command::Gc { ref keys, .. } => keys.is_empty(),
             _ => false,
@@ -220,27 +382,46 @@ impl Command {
             Command::BatchGet { .. } => "batch_get",
             Command::Scan { .. } => "scan",
             Command::Prewrite { .. } => "prewrite",
+            Command::AcquirePessimisticLock { .. } => "acquire_pessimistic_lock",
             Command::Commit { .. } => "commit",
             Command::Cleanup { .. } => "cleanup",
             Command::Rollback { .. } => "rollback",
+            Command::CheckTxnStatus { .. } => "check_txn_status",
+            Command::CheckSecondaryLocks { .. } => "check_secondary_locks",
+            Command::TxnHeartBeat { .. } => "txn_heart_beat",
             Command::ScanLock { .. } => "scan_lock",
             Command::ResolveLock { .. } => "resolve_lock",
             Command::Gc { .. } => "gc",
+            Command::DeleteRange { .. } => "delete_range",
+            Command::RawGet { .. } => "raw_get",
+            Command::RawBatchGet { .. } => "raw_batch_get",
+            Command::RawScan { .. } => "raw_scan",
+            Command::MvccByKey { .. } => "key_mvcc",
+            Command::MvccByStartTs { .. } => "start_ts_mvcc",
         }
     }
 }
 
 use util::transport::SendCh;
+use util::worker::Scheduler as WorkerScheduler;
+use server::deadlock::Task as DetectTask;
+use pd::PdClient;
 
 struct StorageHandle {
     handle: Option<thread::JoinHandle<()>>,
     event_loop: Option<EventLoop<Scheduler>>,
+    deadlock_detector: Option<WorkerScheduler<DetectTask>>,
+    // where the GC safe point of the cluster is got from
+    pd_client: Option<Arc<PdClient>>,
 }
 
 pub struct Storage {
     engine: Box<Engine>,
     sendch: SendCh<Msg>,
     handle: Arc<Mutex<StorageHandle>>,
+    // Serves `Get`, `BatchGet` and `Scan` without the scheduler.
+    read_pool: ReadPool,
+    concurrency_manager: ConcurrencyManager,
 }
 
 impl Storage {
@@ -250,13 +431,20 @@ impl Storage {
         let sendch = SendCh::new(event_loop.channel(), "kv-storage");
 
         info!("storage {:?} started.", engine);
+        let concurrency_manager = ConcurrencyManager::new();
         Ok(Storage {
             engine: engine,
             sendch: sendch,
             handle: Arc::new(Mutex::new(StorageHandle {
                 handle: None,
                 event_loop: Some(event_loop),
+                deadlock_detector: None,
+                pd_client: None,
             })),
+            read_pool: ReadPool::new(config.read_pool_size,
+                                     config.read_pool_too_busy_threshold,
+                                     concurrency_manager.clone()),
+            concurrency_manager: concurrency_manager,
         })
     }
 
@@ -265,6 +453,18 @@ impl Storage {
         Storage::from_engine(engine, config)
     }
 
+    /// Sets the deadlock detector for the transactions waiting for locks, must be called
+    /// before `start`.
+    pub fn set_deadlock_detector(&mut self, detector: WorkerScheduler<DetectTask>) {
+        self.handle.lock().unwrap().deadlock_detector = Some(detector);
+    }
+
+    /// Sets the pd client which the GC safe point of the cluster is got from, `DeleteRange`
+    /// is rejected until it's set.
+    pub fn set_pd_client(&mut self, pd_client: Arc<PdClient>) {
+        self.handle.lock().unwrap().pd_client = Some(pd_client);
+    }
+
     pub fn start(&mut self, config: &Config) -> Result<()> {
         let mut handle = self.handle.lock().unwrap();
         if handle.handle.is_some() {
@@ -276,14 +476,26 @@ impl Storage {
         let mut el = handle.event_loop.take().unwrap();
         let sched_concurrency = config.sched_concurrency;
         let sched_worker_pool_size = config.sched_worker_pool_size;
+        let sched_high_priority_pool_size = config.sched_high_priority_pool_size;
+        let sched_low_priority_pool_size = config.sched_low_priority_pool_size;
         let sched_too_busy_threshold = config.sched_too_busy_threshold;
+        let sched_pending_write_threshold = config.sched_pending_write_threshold;
+        let sched_lock_wait_timeout = config.sched_lock_wait_timeout;
+        let deadlock_detector = handle.deadlock_detector.take();
+        let concurrency_manager = self.concurrency_manager.clone();
         let ch = self.sendch.clone();
         let h = try!(builder.spawn(move || {
             let mut sched = Scheduler::new(engine,
                                            ch,
                                            sched_concurrency,
                                            sched_worker_pool_size,
-                                           sched_too_busy_threshold);
+                                           sched_high_priority_pool_size,
+                                           sched_low_priority_pool_size,
+                                           sched_too_busy_threshold,
+                                           sched_pending_write_threshold,
+                                           sched_lock_wait_timeout,
+                                           deadlock_detector,
+                                           concurrency_manager);
             if let Err(e) = el.run(&mut sched) {
                 panic!("scheduler run err:{:?}", e);
             }
@@ -318,11 +530,22 @@ impl Storage {
         self.engine.clone()
     }
 
+    /// Returns the concurrency manager shared with the reads served outside of storage, e.g.
+    /// coprocessor requests.
+    pub fn get_concurrency_manager(&self) -> ConcurrencyManager {
+        self.concurrency_manager.clone()
+    }
+
     fn send(&self, cmd: Command, cb: StorageCb) -> Result<()> {
         box_try!(self.sendch.try_send(Msg::RawCmd { cmd: cmd, cb: cb }));
         Ok(())
     }
 
+    fn read(&self, cmd: Command, cb: StorageCb) -> Result<()> {
+        try!(self.read_pool.execute(self.engine.as_ref(), cmd, cb));
+        Ok(())
+    }
+
     pub fn async_get(&self,
                      ctx: Context,
                      key: Key,
@@ -335,7 +558,7 @@ impl Storage {
             start_ts: start_ts,
         };
         let tag = cmd.tag();
-        try!(self.send(cmd, StorageCb::SingleValue(callback)));
+        try!(self.read(cmd, StorageCb::SingleValue(callback)));
         KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
         Ok(())
     }
@@ -352,28 +575,38 @@ impl Storage {
             start_ts: start_ts,
         };
         let tag = cmd.tag();
-        try!(self.send(cmd, StorageCb::KvPairs(callback)));
+        try!(self.read(cmd, StorageCb::KvPairs(callback)));
         KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
         Ok(())
     }
 
+    /// Scans up to `limit` rows from `start_key` until `end_key`, or until the rows take up
+    /// `limit_bytes` bytes if it's not 0. A reverse scan goes backward from `start_key`
+    /// (exclusive) to `end_key` (inclusive).
+    #[allow(too_many_arguments)]
     pub fn async_scan(&self,
                       ctx: Context,
                       start_key: Key,
+                      end_key: Option<Key>,
                       limit: usize,
+                      limit_bytes: usize,
                       key_only: bool,
+                      reverse: bool,
                       start_ts: u64,
                       callback: Callback<Vec<Result<KvPair>>>)
                       -> Result<()> {
         let cmd = Command::Scan {
             ctx: ctx,
             start_key: start_key,
+            end_key: end_key,
             limit: limit,
+            limit_bytes: limit_bytes,
             key_only: key_only,
+            reverse: reverse,
             start_ts: start_ts,
         };
         let tag = cmd.tag();
-        try!(self.send(cmd, StorageCb::KvPairs(callback)));
+        try!(self.read(cmd, StorageCb::KvPairs(callback)));
         KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
         Ok(())
     }
@@ -392,6 +625,143 @@ impl Storage {
             primary: primary,
             start_ts: start_ts,
             lock_ttl: lock_ttl,
+            for_update_ts: 0,
+            is_pessimistic_lock: vec![],
+            secondaries: None,
+            min_commit_ts: 0,
+            one_pc_commit_ts: 0,
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::Booleans(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    /// Prewrites the mutations of a pessimistic transaction, `is_pessimistic_lock` must have the
+    /// same length as `mutations`.
+    #[allow(too_many_arguments)]
+    pub fn async_pessimistic_prewrite(&self,
+                                      ctx: Context,
+                                      mutations: Vec<Mutation>,
+                                      primary: Vec<u8>,
+                                      start_ts: u64,
+                                      lock_ttl: u64,
+                                      for_update_ts: u64,
+                                      is_pessimistic_lock: Vec<bool>,
+                                      callback: Callback<Vec<Result<()>>>)
+                                      -> Result<()> {
+        if mutations.len() != is_pessimistic_lock.len() {
+            return Err(box_err!("mutations and is_pessimistic_lock length mismatch"));
+        }
+        let cmd = Command::Prewrite {
+            ctx: ctx,
+            mutations: mutations,
+            primary: primary,
+            start_ts: start_ts,
+            lock_ttl: lock_ttl,
+            for_update_ts: for_update_ts,
+            is_pessimistic_lock: is_pessimistic_lock,
+            secondaries: None,
+            min_commit_ts: 0,
+            one_pc_commit_ts: 0,
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::Booleans(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    /// Prewrites the mutations of an async commit transaction, `secondaries` are the raw keys
+    /// of all the mutations other than the primary one.
+    ///
+    /// The transaction is committed once all the prewrites succeed, at the largest
+    /// `min_commit_ts` of the locks. The callback receives the `min_commit_ts` of the locks
+    /// written by this prewrite, which is raised above the reads of the keys in the store.
+    #[allow(too_many_arguments)]
+    pub fn async_async_commit_prewrite(&self,
+                                       ctx: Context,
+                                       mutations: Vec<Mutation>,
+                                       primary: Vec<u8>,
+                                       secondaries: Vec<Vec<u8>>,
+                                       start_ts: u64,
+                                       lock_ttl: u64,
+                                       min_commit_ts: u64,
+                                       callback: Callback<(Vec<Result<()>>, u64)>)
+                                       -> Result<()> {
+        let cmd = Command::Prewrite {
+            ctx: ctx,
+            mutations: mutations,
+            primary: primary,
+            start_ts: start_ts,
+            lock_ttl: lock_ttl,
+            for_update_ts: 0,
+            is_pessimistic_lock: vec![],
+            secondaries: Some(secondaries),
+            min_commit_ts: min_commit_ts,
+            one_pc_commit_ts: 0,
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::AsyncCommitPrewrite(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    /// Commits the mutations in one phase without writing any locks, if all of them are in the
+    /// same region. Otherwise the mutations are prewritten as usual.
+    ///
+    /// The commit ts is `commit_ts`, or larger if some read of the store at `commit_ts` or
+    /// later may have missed the transaction. The callback receives the commit ts along with
+    /// the results, or 0 if the transaction falls back to two-phase commit.
+    #[allow(too_many_arguments)]
+    pub fn async_one_pc_prewrite(&self,
+                                 ctx: Context,
+                                 mutations: Vec<Mutation>,
+                                 primary: Vec<u8>,
+                                 start_ts: u64,
+                                 lock_ttl: u64,
+                                 commit_ts: u64,
+                                 callback: Callback<(Vec<Result<()>>, u64)>)
+                                 -> Result<()> {
+        if commit_ts <= start_ts {
+            return Err(box_err!("commit_ts {} should be greater than start_ts {}",
+                                commit_ts,
+                                start_ts));
+        }
+        let cmd = Command::Prewrite {
+            ctx: ctx,
+            mutations: mutations,
+            primary: primary,
+            start_ts: start_ts,
+            lock_ttl: lock_ttl,
+            for_update_ts: 0,
+            is_pessimistic_lock: vec![],
+            secondaries: None,
+            min_commit_ts: 0,
+            one_pc_commit_ts: commit_ts,
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::OnePc(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    #[allow(too_many_arguments)]
+    pub fn async_acquire_pessimistic_lock(&self,
+                                          ctx: Context,
+                                          keys: Vec<Key>,
+                                          primary: Vec<u8>,
+                                          start_ts: u64,
+                                          lock_ttl: u64,
+                                          for_update_ts: u64,
+                                          callback: Callback<Vec<Result<()>>>)
+                                          -> Result<()> {
+        let cmd = Command::AcquirePessimisticLock {
+            ctx: ctx,
+            keys: keys,
+            primary: primary,
+            start_ts: start_ts,
+            lock_ttl: lock_ttl,
+            for_update_ts: for_update_ts,
         };
         let tag = cmd.tag();
         try!(self.send(cmd, StorageCb::Booleans(callback)));
@@ -452,14 +822,88 @@ impl Storage {
         Ok(())
     }
 
+    /// Checks the status of the transaction started at `lock_ts` by its primary key, and rolls
+    /// it back if the primary lock has expired at `current_ts`.
+    pub fn async_check_txn_status(&self,
+                                  ctx: Context,
+                                  primary_key: Key,
+                                  lock_ts: u64,
+                                  current_ts: u64,
+                                  callback: Callback<TxnStatus>)
+                                  -> Result<()> {
+        let cmd = Command::CheckTxnStatus {
+            ctx: ctx,
+            primary_key: primary_key,
+            lock_ts: lock_ts,
+            current_ts: current_ts,
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::TxnStatus(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    /// Checks the secondary `keys` of the async commit transaction started at `start_ts`, all
+    /// of them must be in the region of `ctx`. A missing lock is rolled back so that the
+    /// transaction can't be committed later.
+    ///
+    /// The client checks the secondaries region by region, the transaction is committed if all
+    /// of them are locked, at the largest `min_commit_ts` of the locks and the primary lock.
+    pub fn async_check_secondary_locks(&self,
+                                       ctx: Context,
+                                       keys: Vec<Key>,
+                                       start_ts: u64,
+                                       callback: Callback<SecondaryLocksStatus>)
+                                       -> Result<()> {
+        let cmd = Command::CheckSecondaryLocks {
+            ctx: ctx,
+            keys: keys,
+            start_ts: start_ts,
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::SecondaryLocksStatus(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    /// Extends the TTL of the primary lock of the transaction started at `start_ts` to
+    /// `advise_ttl`, and calls back with the TTL of the lock.
+    pub fn async_txn_heart_beat(&self,
+                                ctx: Context,
+                                primary_key: Key,
+                                start_ts: u64,
+                                advise_ttl: u64,
+                                callback: Callback<u64>)
+                                -> Result<()> {
+        let cmd = Command::TxnHeartBeat {
+            ctx: ctx,
+            primary_key: primary_key,
+            start_ts: start_ts,
+            advise_ttl: advise_ttl,
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::LockTtl(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    /// Scans the locks with ts <= `max_ts` in `[start_key, end_key)`, up to `limit` locks if
+    /// it's not 0. The callback gets the raw key to resume the scan from if the limit is reached.
+    #[allow(too_many_arguments)]
     pub fn async_scan_lock(&self,
                            ctx: Context,
                            max_ts: u64,
-                           callback: Callback<Vec<LockInfo>>)
+                           start_key: Option<Key>,
+                           end_key: Option<Key>,
+                           limit: usize,
+                           callback: Callback<(Vec<LockInfo>, Option<Vec<u8>>)>)
                            -> Result<()> {
         let cmd = Command::ScanLock {
             ctx: ctx,
             max_ts: max_ts,
+            start_key: start_key,
+            end_key: end_key,
+            limit: limit,
         };
         let tag = cmd.tag();
         try!(self.send(cmd, StorageCb::Locks(callback)));
@@ -486,18 +930,200 @@ impl Storage {
         Ok(())
     }
 
-    pub fn async_gc(&self, ctx: Context, safe_point: u64, callback: Callback<()>) -> Result<()> {
+    /// Collects the versions before `safe_point` in the region of `ctx`, `safe_point` must be
+    /// the GC safe point of the cluster.
+    ///
+    /// The callback gets whether the region has versions after `safe_point`, which are left to
+    /// the GC with a later safe point.
+    pub fn async_gc(&self,
+                    ctx: Context,
+                    safe_point: u64,
+                    callback: Callback<bool>)
+                    -> Result<()> {
         let cmd = Command::Gc {
             ctx: ctx,
             safe_point: safe_point,
             scan_key: None,
             keys: vec![],
+            has_newer: false,
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::Gc(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    // Gets the GC safe point of the cluster from pd.
+    fn get_gc_safe_point(&self) -> Result<u64> {
+        let pd_client = match self.handle.lock().unwrap().pd_client {
+            Some(ref pd_client) => pd_client.clone(),
+            None => return Err(box_err!("no pd client to get the gc safe point from")),
+        };
+        pd_client.get_gc_safe_point().map_err(|e| box_err!("failed to get gc safe point: {:?}", e))
+    }
+
+    /// Deletes every version of the keys in [`start_key`, `end_key`) at once, including the
+    /// locks. The range must be in the region of `ctx`.
+    ///
+    /// It's only safe when nothing is read or written in the range after `safe_point`, e.g.
+    /// the range belongs to a table dropped before the GC safe point. It fails if
+    /// `safe_point` is after the GC safe point of the cluster, which is got from pd on every
+    /// call, or there are locks of transactions after `safe_point` in the range.
+    pub fn async_delete_range(&self,
+                              ctx: Context,
+                              start_key: Key,
+                              end_key: Key,
+                              safe_point: u64,
+                              callback: Callback<()>)
+                              -> Result<()> {
+        let gc_safe_point = try!(self.get_gc_safe_point());
+        if safe_point > gc_safe_point {
+            return Err(box_err!("safe point {} is after the gc safe point {}",
+                                safe_point,
+                                gc_safe_point));
+        }
+        let cmd = Command::DeleteRange {
+            ctx: ctx,
+            start_key: start_key,
+            end_key: end_key,
+            safe_point: safe_point,
         };
         let tag = cmd.tag();
         try!(self.send(cmd, StorageCb::Boolean(callback)));
         KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
         Ok(())
     }
+
+    /// Raw commands don't use timestamps and store the keys as is in `CF_RAW`, which is
+    /// never read or written by the transactional commands.
+    pub fn async_raw_get(&self,
+                         ctx: Context,
+                         key: Vec<u8>,
+                         callback: Callback<Option<Value>>)
+                         -> Result<()> {
+        let cmd = Command::RawGet {
+            ctx: ctx,
+            key: Key::from_encoded(key),
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::SingleValue(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    pub fn async_raw_batch_get(&self,
+                               ctx: Context,
+                               keys: Vec<Vec<u8>>,
+                               callback: Callback<Vec<Result<KvPair>>>)
+                               -> Result<()> {
+        let cmd = Command::RawBatchGet {
+            ctx: ctx,
+            keys: keys.into_iter().map(Key::from_encoded).collect(),
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::KvPairs(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    pub fn async_raw_put(&self,
+                         ctx: Context,
+                         key: Vec<u8>,
+                         value: Vec<u8>,
+                         callback: Callback<()>)
+                         -> Result<()> {
+        try!(self.engine.async_write(&ctx,
+                                     vec![Modify::Put(CF_RAW, Key::from_encoded(key), value)],
+                                     box move |res| callback(res.map_err(Error::from))));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&["raw_put"]).inc();
+        Ok(())
+    }
+
+    pub fn async_raw_batch_put(&self,
+                               ctx: Context,
+                               pairs: Vec<KvPair>,
+                               callback: Callback<()>)
+                               -> Result<()> {
+        let modifies = pairs.into_iter()
+            .map(|(k, v)| Modify::Put(CF_RAW, Key::from_encoded(k), v))
+            .collect();
+        try!(self.engine.async_write(&ctx,
+                                     modifies,
+                                     box move |res| callback(res.map_err(Error::from))));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&["raw_batch_put"]).inc();
+        Ok(())
+    }
+
+    pub fn async_raw_delete(&self,
+                            ctx: Context,
+                            key: Vec<u8>,
+                            callback: Callback<()>)
+                            -> Result<()> {
+        try!(self.engine.async_write(&ctx,
+                                     vec![Modify::Delete(CF_RAW, Key::from_encoded(key))],
+                                     box move |res| callback(res.map_err(Error::from))));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&["raw_delete"]).inc();
+        Ok(())
+    }
+
+    /// Scans the raw keys in [`start_key`, `end_key`) up to `limit` rows, an empty `end_key`
+    /// means no upper bound.
+    pub fn async_raw_scan(&self,
+                          ctx: Context,
+                          start_key: Vec<u8>,
+                          end_key: Vec<u8>,
+                          limit: usize,
+                          callback: Callback<Vec<Result<KvPair>>>)
+                          -> Result<()> {
+        let end_key = if end_key.is_empty() {
+            None
+        } else {
+            Some(Key::from_encoded(end_key))
+        };
+        let cmd = Command::RawScan {
+            ctx: ctx,
+            start_key: Key::from_encoded(start_key),
+            end_key: end_key,
+            limit: limit,
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::KvPairs(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    /// Gets the lock, the write records and the values of `key`, for debugging.
+    pub fn async_mvcc_by_key(&self,
+                             ctx: Context,
+                             key: Key,
+                             callback: Callback<MvccInfo>)
+                             -> Result<()> {
+        let cmd = Command::MvccByKey {
+            ctx: ctx,
+            key: key,
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::MvccInfoByKey(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
+
+    /// Finds a key written by the transaction started at `start_ts`, and gets its MVCC records.
+    /// It scans the whole region, for debugging only.
+    pub fn async_mvcc_by_start_ts(&self,
+                                  ctx: Context,
+                                  start_ts: u64,
+                                  callback: Callback<Option<(Key, MvccInfo)>>)
+                                  -> Result<()> {
+        let cmd = Command::MvccByStartTs {
+            ctx: ctx,
+            start_ts: start_ts,
+        };
+        let tag = cmd.tag();
+        try!(self.send(cmd, StorageCb::MvccInfoByStartTs(callback)));
+        KV_COMMAND_COUNTER_VEC.with_label_values(&[tag]).inc();
+        Ok(())
+    }
 }
 
 impl Clone for Storage {
@@ -506,6 +1132,8 @@ impl Clone for Storage {
             engine: self.engine.clone(),
             sendch: self.sendch.clone(),
             handle: self.handle.clone(),
+            read_pool: self.read_pool.clone(),
+            concurrency_manager: self.concurrency_manager.clone(),
         }
     }
 }
@@ -539,6 +1167,9 @@ quick_error! {
         SchedTooBusy {
             description("scheduler is too busy")
         }
+        ReadPoolTooBusy {
+            description("read pool is too busy")
+        }
     }
 }
 
@@ -558,7 +1189,13 @@ pub fn create_event_loop(notify_capacity: usize,
 mod tests {
     use super::*;
     use std::sync::mpsc::{channel, Sender};
-    use kvproto::kvrpcpb::Context;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use std::sync::{Arc, Mutex};
+    use kvproto::kvrpcpb::{Context, CommandPri, IsolationLevel};
+    use kvproto::{metapb, pdpb};
+    use pd::{PdClient, Result as PdResult};
+    use storage::mvcc::MvccStats;
 
     fn expect_get_none(done: Sender<i32>) -> Callback<Option<Value>> {
         Box::new(move |x: Result<Option<Value>>| {
@@ -599,27 +1236,72 @@ mod tests {
         })
     }
 
-    fn expect_scan(done: Sender<i32>, pairs: Vec<Option<KvPair>>) -> Callback<Vec<Result<KvPair>>> {
-        Box::new(move |rlt: Result<Vec<Result<KvPair>>>| {
-            let rlt: Vec<Option<KvPair>> = rlt.unwrap()
-                .into_iter()
-                .map(Result::ok)
-                .collect();
-            assert_eq!(rlt, pairs);
-            done.send(1).unwrap()
+    fn expect_read_pool_too_busy<T>(done: Sender<i32>) -> Callback<T> {
+        Box::new(move |x: Result<T>| {
+            match x {
+                Err(Error::ReadPoolTooBusy) => {}
+                _ => panic!("expect read pool too busy"),
+            }
+            done.send(1).unwrap();
         })
     }
 
-    fn expect_batch_get_vals(done: Sender<i32>,
-                             pairs: Vec<Option<KvPair>>)
-                             -> Callback<Vec<Result<KvPair>>> {
-        Box::new(move |rlt: Result<Vec<Result<KvPair>>>| {
-            let rlt: Vec<Option<KvPair>> = rlt.unwrap()
-                .into_iter()
-                .map(Result::ok)
-                .collect();
-            assert_eq!(rlt, pairs);
-            done.send(1).unwrap()
+    fn expect_multi_ok(done: Sender<i32>) -> Callback<Vec<Result<()>>> {
+        Box::new(move |x: Result<Vec<Result<()>>>| {
+            assert!(x.unwrap().iter().all(|r| r.is_ok()));
+            done.send(1).unwrap();
+        })
+    }
+
+    fn expect_multi_ok_with_ts(done: Sender<i32>, ts: u64) -> Callback<(Vec<Result<()>>, u64)> {
+        Box::new(move |x: Result<(Vec<Result<()>>, u64)>| {
+            let (results, res_ts) = x.unwrap();
+            assert!(results.iter().all(|r| r.is_ok()));
+            assert_eq!(res_ts, ts);
+            done.send(1).unwrap();
+        })
+    }
+
+    fn expect_txn_status(done: Sender<i32>, status: TxnStatus) -> Callback<TxnStatus> {
+        Box::new(move |x: Result<TxnStatus>| {
+            assert_eq!(x.unwrap(), status);
+            done.send(1).unwrap();
+        })
+    }
+
+    fn expect_key_is_locked(done: Sender<i32>) -> Callback<Vec<Result<()>>> {
+        Box::new(move |x: Result<Vec<Result<()>>>| {
+            let res = x.unwrap();
+            assert_eq!(res.len(), 1);
+            match res[0] {
+                Err(Error::Txn(txn::Error::Mvcc(mvcc::Error::KeyIsLocked { .. }))) => {}
+                ref r => panic!("expect key is locked, got {:?}", r),
+            }
+            done.send(1).unwrap();
+        })
+    }
+
+    fn expect_scan(done: Sender<i32>, pairs: Vec<Option<KvPair>>) -> Callback<Vec<Result<KvPair>>> {
+        Box::new(move |rlt: Result<Vec<Result<KvPair>>>| {
+            let rlt: Vec<Option<KvPair>> = rlt.unwrap()
+                .into_iter()
+                .map(Result::ok)
+                .collect();
+            assert_eq!(rlt, pairs);
+            done.send(1).unwrap()
+        })
+    }
+
+    fn expect_batch_get_vals(done: Sender<i32>,
+                             pairs: Vec<Option<KvPair>>)
+                             -> Callback<Vec<Result<KvPair>>> {
+        Box::new(move |rlt: Result<Vec<Result<KvPair>>>| {
+            let rlt: Vec<Option<KvPair>> = rlt.unwrap()
+                .into_iter()
+                .map(Result::ok)
+                .collect();
+            assert_eq!(rlt, pairs);
+            done.send(1).unwrap()
         })
     }
 
@@ -688,6 +1370,143 @@ mod tests {
         storage.stop().unwrap();
     }
 
+    // Only serves the GC safe point.
+    struct SafePointPdClient {
+        safe_point: Mutex<u64>,
+    }
+
+    impl PdClient for SafePointPdClient {
+        fn get_cluster_id(&self) -> PdResult<u64> {
+            unimplemented!();
+        }
+        fn bootstrap_cluster(&self, _: metapb::Store, _: metapb::Region) -> PdResult<()> {
+            unimplemented!();
+        }
+        fn is_cluster_bootstrapped(&self) -> PdResult<bool> {
+            unimplemented!();
+        }
+        fn alloc_id(&self) -> PdResult<u64> {
+            unimplemented!();
+        }
+        fn put_store(&self, _: metapb::Store) -> PdResult<()> {
+            unimplemented!();
+        }
+        fn get_store(&self, _: u64) -> PdResult<metapb::Store> {
+            unimplemented!();
+        }
+        fn get_cluster_config(&self) -> PdResult<metapb::Cluster> {
+            unimplemented!();
+        }
+        fn get_region(&self, _: &[u8]) -> PdResult<metapb::Region> {
+            unimplemented!();
+        }
+        fn get_region_by_id(&self, _: u64) -> PdResult<Option<metapb::Region>> {
+            unimplemented!();
+        }
+        fn get_region_leader(&self, _: &[u8]) -> PdResult<Option<metapb::Peer>> {
+            unimplemented!();
+        }
+        fn region_heartbeat(&self,
+                            _: metapb::Region,
+                            _: metapb::Peer,
+                            _: Vec<pdpb::PeerStats>,
+                            _: MvccStats)
+                            -> PdResult<pdpb::RegionHeartbeatResponse> {
+            unimplemented!();
+        }
+        fn ask_split(&self, _: metapb::Region) -> PdResult<pdpb::AskSplitResponse> {
+            unimplemented!();
+        }
+        fn store_heartbeat(&self, _: pdpb::StoreStats) -> PdResult<()> {
+            unimplemented!();
+        }
+        fn report_split(&self, _: metapb::Region, _: metapb::Region) -> PdResult<()> {
+            unimplemented!();
+        }
+        fn get_gc_safe_point(&self) -> PdResult<u64> {
+            Ok(*self.safe_point.lock().unwrap())
+        }
+    }
+
+    #[test]
+    fn test_delete_range() {
+        let config = Config::new();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        // Without pd the gc safe point is unknown.
+        assert!(storage.async_delete_range(Context::new(),
+                                make_key(b"a"),
+                                make_key(b"c"),
+                                0,
+                                expect_ok(channel().0))
+            .is_err());
+        let pd_client = Arc::new(SafePointPdClient { safe_point: Mutex::new(0) });
+        storage.set_pd_client(pd_client.clone());
+        let (tx, rx) = channel();
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"a"), b"aa".to_vec())),
+                                 Mutation::Put((make_key(b"b"), b"bb".to_vec())),
+                                 Mutation::Put((make_key(b"c"), b"cc".to_vec()))],
+                            b"a".to_vec(),
+                            1,
+                            0,
+                            expect_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_commit(Context::new(),
+                          vec![make_key(b"a"), make_key(b"b"), make_key(b"c")],
+                          1,
+                          2,
+                          expect_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"b"), b"bbb".to_vec()))],
+                            b"b".to_vec(),
+                            10,
+                            0,
+                            expect_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+
+        // The safe point is after the gc safe point.
+        assert!(storage.async_delete_range(Context::new(),
+                                make_key(b"a"),
+                                make_key(b"c"),
+                                5,
+                                expect_ok(tx.clone()))
+            .is_err());
+        *pd_client.safe_point.lock().unwrap() = 10;
+
+        // There is a lock after the safe point.
+        storage.async_delete_range(Context::new(),
+                                make_key(b"a"),
+                                make_key(b"c"),
+                                5,
+                                expect_fail(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_delete_range(Context::new(),
+                                make_key(b"a"),
+                                make_key(b"c"),
+                                10,
+                                expect_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_scan(Context::new(),
+                        make_key(b"\x00"),
+                        None,
+                        1000,
+                        0,
+                        false,
+                        false,
+                        20,
+                        expect_scan(tx.clone(), vec![Some((b"c".to_vec(), b"cc".to_vec()))]))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
     #[test]
     fn test_scan() {
         let config = Config::new();
@@ -715,7 +1534,10 @@ mod tests {
         rx.recv().unwrap();
         storage.async_scan(Context::new(),
                         make_key(b"\x00"),
+                        None,
                         1000,
+                        0,
+                        false,
                         false,
                         5,
                         expect_scan(tx.clone(),
@@ -726,6 +1548,57 @@ mod tests {
             ]))
             .unwrap();
         rx.recv().unwrap();
+        // Bounded by the end key.
+        storage.async_scan(Context::new(),
+                        make_key(b"\x00"),
+                        Some(make_key(b"c")),
+                        1000,
+                        0,
+                        false,
+                        false,
+                        5,
+                        expect_scan(tx.clone(),
+                                    vec![Some((b"a".to_vec(), b"aa".to_vec())),
+                                         Some((b"b".to_vec(), b"bb".to_vec()))]))
+            .unwrap();
+        rx.recv().unwrap();
+        // Bounded by the byte limit.
+        storage.async_scan(Context::new(),
+                        make_key(b"\x00"),
+                        None,
+                        1000,
+                        1,
+                        false,
+                        false,
+                        5,
+                        expect_scan(tx.clone(), vec![Some((b"a".to_vec(), b"aa".to_vec()))]))
+            .unwrap();
+        rx.recv().unwrap();
+        // Reverse scan.
+        storage.async_scan(Context::new(),
+                        make_key(b"\xff"),
+                        Some(make_key(b"b")),
+                        1000,
+                        0,
+                        false,
+                        true,
+                        5,
+                        expect_scan(tx.clone(),
+                                    vec![Some((b"c".to_vec(), b"cc".to_vec())),
+                                         Some((b"b".to_vec(), b"bb".to_vec()))]))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_scan(Context::new(),
+                        make_key(b"c"),
+                        None,
+                        1,
+                        0,
+                        false,
+                        true,
+                        5,
+                        expect_scan(tx.clone(), vec![Some((b"b".to_vec(), b"bb".to_vec()))]))
+            .unwrap();
+        rx.recv().unwrap();
         storage.stop().unwrap();
     }
 
@@ -851,6 +1724,519 @@ mod tests {
         storage.stop().unwrap();
     }
 
+    #[test]
+    fn test_read_pool_too_busy() {
+        let mut config = Config::new();
+        config.read_pool_too_busy_threshold = 0;
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage.async_get(Context::new(),
+                       make_key(b"x"),
+                       100,
+                       expect_read_pool_too_busy(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_scan(Context::new(),
+                        make_key(b"\x00"),
+                        None,
+                        1000,
+                        0,
+                        false,
+                        false,
+                        5,
+                        expect_read_pool_too_busy(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        // Writes don't go through the read pool.
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                            b"x".to_vec(),
+                            100,
+                            0,
+                            expect_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_read_pool_too_busy_priority() {
+        let mut config = Config::new();
+        config.read_pool_too_busy_threshold = 1;
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        let new_ctx = |priority| {
+            let mut ctx = Context::new();
+            ctx.set_priority(priority);
+            ctx
+        };
+        // Low priority reads are rejected first.
+        storage.async_get(new_ctx(CommandPri::Low),
+                       make_key(b"x"),
+                       100,
+                       expect_read_pool_too_busy(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        for priority in vec![CommandPri::Normal, CommandPri::High] {
+            storage.async_get(new_ctx(priority),
+                           make_key(b"x"),
+                           100,
+                           expect_get_none(tx.clone()))
+                .unwrap();
+            rx.recv().unwrap();
+        }
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_sched_pending_write_threshold() {
+        let mut config = Config::new();
+        config.sched_pending_write_threshold = 0;
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                            b"x".to_vec(),
+                            100,
+                            0,
+                            expect_too_busy(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        // Reads are not limited.
+        storage.async_get(Context::new(),
+                       make_key(b"x"),
+                       100,
+                       expect_get_none(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_sched_too_busy_priority() {
+        let mut config = Config::new();
+        config.sched_too_busy_threshold = 1;
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        let new_ctx = |priority| {
+            let mut ctx = Context::new();
+            ctx.set_priority(priority);
+            ctx
+        };
+        // Low priority writes are rejected first.
+        storage.async_prewrite(new_ctx(CommandPri::Low),
+                            vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                            b"x".to_vec(),
+                            100,
+                            0,
+                            expect_too_busy(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        for (i, priority) in vec![CommandPri::Normal, CommandPri::High].into_iter().enumerate() {
+            let key = format!("k{}", i).into_bytes();
+            storage.async_prewrite(new_ctx(priority),
+                                vec![Mutation::Put((make_key(&key), b"100".to_vec()))],
+                                key,
+                                100,
+                                0,
+                                expect_ok(tx.clone()))
+                .unwrap();
+            rx.recv().unwrap();
+        }
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_lock_wait() {
+        let mut config = Config::new();
+        config.sched_lock_wait_timeout = 3000;
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                            b"x".to_vec(),
+                            100,
+                            0,
+                            expect_multi_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        // Waits for the lock of txn 100 instead of returning `KeyIsLocked`.
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"101".to_vec()))],
+                            b"x".to_vec(),
+                            101,
+                            0,
+                            expect_multi_ok(tx.clone()))
+            .unwrap();
+        storage.async_rollback(Context::new(), vec![make_key(b"x")], 100, expect_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        rx.recv().unwrap();
+        storage.async_commit(Context::new(),
+                          vec![make_key(b"x")],
+                          101,
+                          110,
+                          expect_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_get(Context::new(),
+                       make_key(b"x"),
+                       120,
+                       expect_get_val(tx.clone(), b"101".to_vec()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_lock_wait_timeout() {
+        let mut config = Config::new();
+        config.sched_lock_wait_timeout = 100;
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                            b"x".to_vec(),
+                            100,
+                            0,
+                            expect_multi_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"101".to_vec()))],
+                            b"x".to_vec(),
+                            101,
+                            0,
+                            expect_key_is_locked(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_lock_wait_keeps_deadline() {
+        let mut config = Config::new();
+        config.sched_lock_wait_timeout = 1000;
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"100".to_vec())),
+                                 Mutation::Put((make_key(b"y"), b"100".to_vec()))],
+                            b"x".to_vec(),
+                            100,
+                            0,
+                            expect_multi_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        let start = Instant::now();
+        let done = tx.clone();
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"101".to_vec())),
+                                 Mutation::Put((make_key(b"y"), b"101".to_vec()))],
+                            b"x".to_vec(),
+                            101,
+                            0,
+                            box move |x: Result<Vec<Result<()>>>| {
+                                let res = x.unwrap();
+                                assert_eq!(res.len(), 2);
+                                assert!(res[0].is_ok());
+                                match res[1] {
+                                    Err(Error::Txn(txn::Error::Mvcc(mvcc::Error::KeyIsLocked {
+                                        ..
+                                    }))) => {}
+                                    ref r => panic!("expect key is locked, got {:?}", r),
+                                }
+                                done.send(1).unwrap();
+                            })
+            .unwrap();
+        // Releases the lock of x after most of the wait is used up, then the prewrite waits for
+        // the lock of y with the rest of the wait only.
+        thread::sleep(Duration::from_millis(800));
+        storage.async_rollback(Context::new(), vec![make_key(b"x")], 100, expect_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        rx.recv().unwrap();
+        assert!(start.elapsed() < Duration::from_millis(1500));
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_raw() {
+        let config = Config::new();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage.async_raw_get(Context::new(), b"a".to_vec(), expect_get_none(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_raw_put(Context::new(),
+                           b"a".to_vec(),
+                           b"aa".to_vec(),
+                           expect_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_raw_batch_put(Context::new(),
+                                 vec![(b"b".to_vec(), b"bb".to_vec()),
+                                      (b"c".to_vec(), b"cc".to_vec())],
+                                 expect_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_raw_get(Context::new(),
+                           b"a".to_vec(),
+                           expect_get_val(tx.clone(), b"aa".to_vec()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_raw_batch_get(Context::new(),
+                                 vec![b"a".to_vec(), b"b".to_vec(), b"d".to_vec()],
+                                 expect_batch_get_vals(tx.clone(),
+                                                       vec![
+            Some((b"a".to_vec(), b"aa".to_vec())),
+            Some((b"b".to_vec(), b"bb".to_vec())),
+            ]))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_raw_delete(Context::new(), b"b".to_vec(), expect_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_raw_scan(Context::new(),
+                            b"".to_vec(),
+                            vec![],
+                            10,
+                            expect_scan(tx.clone(),
+                                        vec![
+            Some((b"a".to_vec(), b"aa".to_vec())),
+            Some((b"c".to_vec(), b"cc".to_vec())),
+            ]))
+            .unwrap();
+        rx.recv().unwrap();
+        // The end key is exclusive.
+        storage.async_raw_scan(Context::new(),
+                            b"".to_vec(),
+                            b"c".to_vec(),
+                            10,
+                            expect_scan(tx.clone(),
+                                        vec![
+            Some((b"a".to_vec(), b"aa".to_vec())),
+            ]))
+            .unwrap();
+        rx.recv().unwrap();
+        // The raw keys are invisible to the transactional commands.
+        storage.async_get(Context::new(),
+                       make_key(b"a"),
+                       100,
+                       expect_get_none(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_async_commit() {
+        let config = Config::new();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        // The transaction must commit after the read of y at 110.
+        storage.async_get(Context::new(), make_key(b"y"), 110, expect_get_none(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_async_commit_prewrite(Context::new(),
+                                         vec![Mutation::Put((make_key(b"x"), b"100".to_vec())),
+                                              Mutation::Put((make_key(b"y"), b"101".to_vec()))],
+                                         b"x".to_vec(),
+                                         vec![b"y".to_vec()],
+                                         100,
+                                         0,
+                                         105,
+                                         expect_multi_ok_with_ts(tx.clone(), 111))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_check_txn_status(Context::new(),
+                                    make_key(b"x"),
+                                    100,
+                                    120,
+                                    expect_txn_status(tx.clone(),
+                                                      TxnStatus::AsyncCommit {
+                                                          min_commit_ts: 111,
+                                                          secondaries: vec![b"y".to_vec()],
+                                                      }))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_check_secondary_locks(Context::new(),
+                                         vec![make_key(b"y")],
+                                         100,
+                                         box move |x: Result<SecondaryLocksStatus>| {
+                                             assert_eq!(x.unwrap(),
+                                                        SecondaryLocksStatus::Locked {
+                                                            min_commit_ts: 111,
+                                                        });
+                                             tx.send(1).unwrap();
+                                         })
+            .unwrap();
+        rx.recv().unwrap();
+        let (tx, rx) = channel();
+        // All the keys are prewritten, so the transaction is committed at 111.
+        storage.async_resolve_lock(Context::new(), 100, Some(111), expect_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_get(Context::new(),
+                       make_key(b"y"),
+                       110,
+                       expect_get_none(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_get(Context::new(),
+                       make_key(b"x"),
+                       111,
+                       expect_get_val(tx.clone(), b"100".to_vec()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_one_pc() {
+        let config = Config::new();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage.async_one_pc_prewrite(Context::new(),
+                                   vec![Mutation::Put((make_key(b"x"), b"100".to_vec())),
+                                        Mutation::Put((make_key(b"y"), b"101".to_vec()))],
+                                   b"x".to_vec(),
+                                   100,
+                                   0,
+                                   101,
+                                   expect_multi_ok_with_ts(tx.clone(), 101))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_get(Context::new(),
+                       make_key(b"y"),
+                       101,
+                       expect_get_val(tx.clone(), b"101".to_vec()))
+            .unwrap();
+        rx.recv().unwrap();
+
+        // Commits after the read at 150, which must not see the transaction.
+        storage.async_get(Context::new(), make_key(b"z"), 150, expect_get_none(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_one_pc_prewrite(Context::new(),
+                                   vec![Mutation::Put((make_key(b"z"), b"140".to_vec()))],
+                                   b"z".to_vec(),
+                                   140,
+                                   0,
+                                   141,
+                                   expect_multi_ok_with_ts(tx.clone(), 151))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_get(Context::new(), make_key(b"z"), 150, expect_get_none(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_get(Context::new(),
+                       make_key(b"z"),
+                       151,
+                       expect_get_val(tx.clone(), b"140".to_vec()))
+            .unwrap();
+        rx.recv().unwrap();
+
+        // Falls back to two-phase commit on conflicts.
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"110".to_vec()))],
+                            b"x".to_vec(),
+                            110,
+                            0,
+                            expect_multi_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_one_pc_prewrite(Context::new(),
+                                   vec![Mutation::Put((make_key(b"y"), b"120".to_vec())),
+                                        Mutation::Put((make_key(b"x"), b"120".to_vec()))],
+                                   b"y".to_vec(),
+                                   120,
+                                   0,
+                                   121,
+                                   box move |x: Result<(Vec<Result<()>>, u64)>| {
+                                       let (results, ts) = x.unwrap();
+                                       assert_eq!(ts, 0);
+                                       assert!(results[0].is_ok());
+                                       assert!(results[1].is_err());
+                                       tx.send(1).unwrap();
+                                   })
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_insert() {
+        let config = Config::new();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                            b"x".to_vec(),
+                            100,
+                            0,
+                            expect_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_commit(Context::new(),
+                          vec![make_key(b"x")],
+                          100,
+                          110,
+                          expect_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+
+        // Keys that already exist fail alone.
+        let done = tx.clone();
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Insert((make_key(b"x"), b"120".to_vec())),
+                                 Mutation::Insert((make_key(b"y"), b"120".to_vec()))],
+                            b"x".to_vec(),
+                            120,
+                            0,
+                            box move |x: Result<Vec<Result<()>>>| {
+                                let results = x.unwrap();
+                                match results[0] {
+                                    Err(Error::Txn(txn::Error::Mvcc(mvcc::Error::AlreadyExist {
+                                        ref key
+                                    }))) => assert_eq!(key, b"x"),
+                                    ref r => panic!("expect already exist, got {:?}", r),
+                                }
+                                assert!(results[1].is_ok());
+                                done.send(1).unwrap();
+                            })
+            .unwrap();
+        rx.recv().unwrap();
+
+        // Falls back to two-phase commit to report them.
+        storage.async_one_pc_prewrite(Context::new(),
+                                   vec![Mutation::CheckNotExists(make_key(b"x"))],
+                                   b"x".to_vec(),
+                                   130,
+                                   0,
+                                   131,
+                                   box move |x: Result<(Vec<Result<()>>, u64)>| {
+                                       let (results, ts) = x.unwrap();
+                                       assert_eq!(ts, 0);
+                                       assert!(results[0].is_err());
+                                       tx.send(1).unwrap();
+                                   })
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
     #[test]
     fn test_cleanup() {
         let config = Config::new();
@@ -876,4 +2262,153 @@ mod tests {
         rx.recv().unwrap();
         storage.stop().unwrap();
     }
+
+    #[test]
+    fn test_check_txn_status() {
+        let config = Config::new();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        let ts = |physical: u64| physical << mvcc::TSO_PHYSICAL_SHIFT_BITS;
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                            b"x".to_vec(),
+                            ts(100),
+                            10,
+                            expect_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_check_txn_status(Context::new(),
+                                    make_key(b"x"),
+                                    ts(100),
+                                    ts(105),
+                                    expect_txn_status(tx.clone(), TxnStatus::Alive { ttl: 5 }))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_check_txn_status(Context::new(),
+                                    make_key(b"x"),
+                                    ts(100),
+                                    ts(110),
+                                    expect_txn_status(tx.clone(), TxnStatus::RolledBack))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_commit(Context::new(),
+                          vec![make_key(b"x")],
+                          ts(100),
+                          ts(111),
+                          expect_fail(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_txn_heart_beat() {
+        let config = Config::new();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage.async_txn_heart_beat(Context::new(),
+                                  make_key(b"x"),
+                                  100,
+                                  50,
+                                  expect_fail(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                            b"x".to_vec(),
+                            100,
+                            10,
+                            expect_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_txn_heart_beat(Context::new(),
+                                  make_key(b"x"),
+                                  100,
+                                  50,
+                                  box move |x: Result<u64>| {
+                                      assert_eq!(x.unwrap(), 50);
+                                      tx.send(1).unwrap();
+                                  })
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_mvcc_info() {
+        let config = Config::new();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                            b"x".to_vec(),
+                            100,
+                            0,
+                            expect_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_mvcc_by_key(Context::new(),
+                               make_key(b"x"),
+                               box move |x: Result<MvccInfo>| {
+                                   let info = x.unwrap();
+                                   assert_eq!(info.lock.unwrap().ts, 100);
+                                   assert!(info.writes.is_empty());
+                                   tx.send(1).unwrap();
+                               })
+            .unwrap();
+        rx.recv().unwrap();
+        let (tx, rx) = channel();
+        storage.async_mvcc_by_start_ts(Context::new(),
+                                    100,
+                                    box move |x: Result<Option<(Key, MvccInfo)>>| {
+                                        let (key, info) = x.unwrap().unwrap();
+                                        assert_eq!(key, make_key(b"x"));
+                                        assert!(info.lock.is_some());
+                                        tx.send(1).unwrap();
+                                    })
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
+
+    #[test]
+    fn test_isolation_level() {
+        let config = Config::new();
+        let mut storage = Storage::new(&config).unwrap();
+        storage.start(&config).unwrap();
+        let (tx, rx) = channel();
+        storage.async_prewrite(Context::new(),
+                            vec![Mutation::Put((make_key(b"x"), b"100".to_vec()))],
+                            b"x".to_vec(),
+                            100,
+                            0,
+                            expect_ok(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.async_get(Context::new(), make_key(b"x"), 101, expect_fail(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        let mut ctx = Context::new();
+        ctx.set_isolation_level(IsolationLevel::RC);
+        storage.async_get(ctx, make_key(b"x"), 101, expect_get_none(tx.clone()))
+            .unwrap();
+        rx.recv().unwrap();
+        let mut ctx = Context::new();
+        ctx.set_resolved_locks(vec![100]);
+        storage.async_scan(ctx,
+                        make_key(b"x"),
+                        None,
+                        1000,
+                        0,
+                        false,
+                        false,
+                        101,
+                        expect_scan(tx.clone(), vec![]))
+            .unwrap();
+        rx.recv().unwrap();
+        storage.stop().unwrap();
+    }
 }
@@ -12,9 +12,10 @@
 // limitations under the License.
 
 use byteorder::ReadBytesExt;
-use storage::Mutation;
+use storage::{Mutation, Value};
 use util::codec::number::{NumberEncoder, NumberDecoder, MAX_VAR_U64_LEN};
 use util::codec::bytes::{BytesEncoder, CompactBytesDecoder};
+use super::write::{SHORT_VALUE_PREFIX, write_short_value, read_short_value};
 use super::{Error, Result};
 
 #[derive(Debug,Clone,Copy,PartialEq)]
@@ -22,18 +23,28 @@ pub enum LockType {
     Put,
     Delete,
     Lock,
+    Pessimistic,
 }
 
 const FLAG_PUT: u8 = b'P';
 const FLAG_DELETE: u8 = b'D';
 const FLAG_LOCK: u8 = b'L';
+const FLAG_PESSIMISTIC: u8 = b'S';
+
+// Optional fields are appended after ttl, each one starts with a prefix byte, so locks written
+// by older versions can still be parsed.
+const FOR_UPDATE_TS_PREFIX: u8 = b'f';
+const ASYNC_COMMIT_PREFIX: u8 = b'a';
+const MIN_COMMIT_TS_PREFIX: u8 = b'c';
 
 impl LockType {
-    pub fn from_mutation(mutation: &Mutation) -> LockType {
+    /// Returns `None` for mutations which lock nothing.
+    pub fn from_mutation(mutation: &Mutation) -> Option<LockType> {
         match *mutation {
-            Mutation::Put(_) => LockType::Put,
-            Mutation::Delete(_) => LockType::Delete,
-            Mutation::Lock(_) => LockType::Lock,
+            Mutation::Put(_) | Mutation::Insert(_) => Some(LockType::Put),
+            Mutation::Delete(_) => Some(LockType::Delete),
+            Mutation::Lock(_) => Some(LockType::Lock),
+            Mutation::CheckNotExists(_) => None,
         }
     }
 
@@ -42,6 +53,7 @@ impl LockType {
             FLAG_PUT => Some(LockType::Put),
             FLAG_DELETE => Some(LockType::Delete),
             FLAG_LOCK => Some(LockType::Lock),
+            FLAG_PESSIMISTIC => Some(LockType::Pessimistic),
             _ => None,
         }
     }
@@ -51,6 +63,7 @@ impl LockType {
             LockType::Put => FLAG_PUT,
             LockType::Delete => FLAG_DELETE,
             LockType::Lock => FLAG_LOCK,
+            LockType::Pessimistic => FLAG_PESSIMISTIC,
         }
     }
 }
@@ -61,6 +74,16 @@ pub struct Lock {
     pub primary: Vec<u8>,
     pub ts: u64,
     pub ttl: u64,
+    // Only set for locks of pessimistic transactions.
+    pub for_update_ts: u64,
+    // Only set for locks of async commit transactions. `secondaries` is only recorded in the
+    // lock of the primary key.
+    pub use_async_commit: bool,
+    pub secondaries: Vec<Vec<u8>>,
+    pub min_commit_ts: u64,
+    // The value of a put is kept here instead of CF_DEFAULT if it's short, and is moved to the
+    // write record on commit.
+    pub short_value: Option<Value>,
 }
 
 impl Lock {
@@ -70,15 +93,57 @@ impl Lock {
             primary: primary,
             ts: ts,
             ttl: ttl,
+            for_update_ts: 0,
+            use_async_commit: false,
+            secondaries: vec![],
+            min_commit_ts: 0,
+            short_value: None,
         }
     }
 
+    pub fn with_for_update_ts(mut self, for_update_ts: u64) -> Lock {
+        self.for_update_ts = for_update_ts;
+        self
+    }
+
+    /// Marks the lock as a lock of an async commit transaction, the transaction is committed
+    /// once all its keys are prewritten, at a commit ts no less than any `min_commit_ts`.
+    pub fn with_async_commit(mut self, secondaries: Vec<Vec<u8>>, min_commit_ts: u64) -> Lock {
+        self.use_async_commit = true;
+        self.secondaries = secondaries;
+        self.min_commit_ts = min_commit_ts;
+        self
+    }
+
+    pub fn with_short_value(mut self, short_value: Option<Value>) -> Lock {
+        self.short_value = short_value;
+        self
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut b = Vec::with_capacity(1 + MAX_VAR_U64_LEN + self.primary.len() + MAX_VAR_U64_LEN);
         b.push(self.lock_type.to_u8());
         b.encode_compact_bytes(&self.primary).unwrap();
         b.encode_var_u64(self.ts).unwrap();
         b.encode_var_u64(self.ttl).unwrap();
+        if self.for_update_ts > 0 {
+            b.push(FOR_UPDATE_TS_PREFIX);
+            b.encode_u64(self.for_update_ts).unwrap();
+        }
+        if self.use_async_commit {
+            b.push(ASYNC_COMMIT_PREFIX);
+            b.encode_var_u64(self.secondaries.len() as u64).unwrap();
+            for k in &self.secondaries {
+                b.encode_compact_bytes(k).unwrap();
+            }
+        }
+        if self.min_commit_ts > 0 {
+            b.push(MIN_COMMIT_TS_PREFIX);
+            b.encode_u64(self.min_commit_ts).unwrap();
+        }
+        if let Some(ref v) = self.short_value {
+            write_short_value(&mut b, v);
+        }
         b
     }
 
@@ -94,6 +159,67 @@ impl Lock {
         } else {
             try!(b.decode_var_u64())
         };
-        Ok(Lock::new(lock_type, primary, ts, ttl))
+        let mut lock = Lock::new(lock_type, primary, ts, ttl);
+        while !b.is_empty() {
+            match try!(b.read_u8()) {
+                FOR_UPDATE_TS_PREFIX => lock.for_update_ts = try!(b.decode_u64()),
+                ASYNC_COMMIT_PREFIX => {
+                    let n = try!(b.decode_var_u64());
+                    let mut secondaries = Vec::with_capacity(n as usize);
+                    for _ in 0..n {
+                        secondaries.push(try!(b.decode_compact_bytes()));
+                    }
+                    lock.use_async_commit = true;
+                    lock.secondaries = secondaries;
+                }
+                MIN_COMMIT_TS_PREFIX => lock.min_commit_ts = try!(b.decode_u64()),
+                SHORT_VALUE_PREFIX => {
+                    let v = try!(read_short_value(&mut b).ok_or(Error::BadFormatLock));
+                    lock.short_value = Some(v);
+                }
+                _ => return Err(Error::BadFormatLock),
+            }
+        }
+        Ok(lock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::codec::number::NumberEncoder;
+    use util::codec::bytes::BytesEncoder;
+
+    #[test]
+    fn test_lock() {
+        let locks = vec![Lock::new(LockType::Put, b"pk".to_vec(), 1, 10),
+                         Lock::new(LockType::Delete, b"pk".to_vec(), 1, 0),
+                         Lock::new(LockType::Pessimistic, b"pk".to_vec(), 1, 10)
+                             .with_for_update_ts(5),
+                         Lock::new(LockType::Put, b"pk".to_vec(), 1, 10)
+                             .with_async_commit(vec![b"k1".to_vec(), b"k2".to_vec()], 3),
+                         Lock::new(LockType::Lock, b"pk".to_vec(), 1, 10)
+                             .with_async_commit(vec![], 3),
+                         Lock::new(LockType::Put, b"pk".to_vec(), 1, 10)
+                             .with_short_value(Some(b"v".to_vec())),
+                         Lock::new(LockType::Put, b"pk".to_vec(), 1, 10)
+                             .with_async_commit(vec![b"k1".to_vec()], 3)
+                             .with_short_value(Some(vec![]))];
+        for lock in locks {
+            let v = lock.to_bytes();
+            assert_eq!(Lock::parse(&v).unwrap(), lock);
+        }
+
+        // Locks written without the optional fields.
+        let mut v = vec![b'L'];
+        v.encode_compact_bytes(b"pk").unwrap();
+        v.encode_var_u64(1).unwrap();
+        assert_eq!(Lock::parse(&v).unwrap(),
+                   Lock::new(LockType::Lock, b"pk".to_vec(), 1, 0));
+
+        // Unknown optional field.
+        v.encode_var_u64(10).unwrap();
+        v.push(b'?');
+        assert!(Lock::parse(&v).is_err());
     }
 }
@@ -15,13 +15,16 @@ mod reader;
 mod txn;
 mod lock;
 mod write;
+mod stats;
 mod metrics;
 
 use std::io;
-pub use self::txn::{MvccTxn, MAX_TXN_WRITE_SIZE};
-pub use self::reader::MvccReader;
+pub use self::txn::{MvccTxn, TxnStatus, SecondaryLocksStatus, MAX_TXN_WRITE_SIZE,
+                    TSO_PHYSICAL_SHIFT_BITS};
+pub use self::reader::{MvccReader, MvccInfo};
 pub use self::lock::{Lock, LockType};
 pub use self::write::{Write, WriteType};
+pub use self::stats::MvccStats;
 use util::escape;
 
 quick_error! {
@@ -57,7 +60,37 @@ quick_error! {
             display("txn already committed @{}", commit_ts)
         }
         TxnLockNotFound {description("txn lock not found")}
+        AlreadyExist {key: Vec<u8>} {
+            description("key already exists")
+            display("key {} already exists", escape(key))
+        }
         WriteConflict {description("write conflict")}
+        PessimisticLockNotFound {description("pessimistic lock not found")}
+        PessimisticLockRolledBack {description("pessimistic lock already rolled back")}
+        Deadlock {start_ts: u64, lock_ts: u64, key: Vec<u8>} {
+            description("deadlock")
+            display("txn {} waits for txn {} on key {} which causes a deadlock",
+                        start_ts,
+                        lock_ts,
+                        escape(key))
+        }
+        CommitTsExpired {start_ts: u64, commit_ts: u64, min_commit_ts: u64} {
+            description("commit ts is smaller than min_commit_ts")
+            display("txn {} commits @{} but min_commit_ts is {}",
+                        start_ts,
+                        commit_ts,
+                        min_commit_ts)
+        }
+        AsyncCommitUndetermined {start_ts: u64, primary: Vec<u8>} {
+            description("status of async commit txn must be checked from its primary")
+            display("status of async commit txn {} must be checked from primary {}",
+                        start_ts,
+                        escape(primary))
+        }
+        PrimaryMismatch {key: Vec<u8>, primary: Vec<u8>} {
+            description("key is not the primary of its lock")
+            display("key {} is not the primary {} of its lock", escape(key), escape(primary))
+        }
         KeyVersion {description("bad format key(version)")}
     }
 }
@@ -11,12 +11,24 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::u64;
+use kvproto::kvrpcpb::IsolationLevel;
 use storage::engine::{Snapshot, Cursor, ScanMode};
 use storage::{Key, Value, CF_LOCK, CF_WRITE};
 use super::{Error, Result};
-use super::lock::Lock;
+use super::lock::{Lock, LockType};
 use super::write::{Write, WriteType};
 
+/// All the MVCC records of a key, for debugging.
+#[derive(Debug, Default, PartialEq)]
+pub struct MvccInfo {
+    pub lock: Option<Lock>,
+    /// Commit ts and write records, the latest first.
+    pub writes: Vec<(u64, Write)>,
+    /// Start ts and values in CF_DEFAULT, the latest first.
+    pub values: Vec<(u64, Value)>,
+}
+
 pub struct MvccReader<'a> {
     snapshot: &'a Snapshot,
     // cursors are used for speeding up scans.
@@ -28,6 +40,11 @@ pub struct MvccReader<'a> {
     key_only: bool,
 
     fill_cache: bool,
+
+    isolation_level: IsolationLevel,
+    // Locks of these transactions are ignored by `get`, since they are known to be committed
+    // after the read ts.
+    bypass_locks: Vec<u64>,
 }
 
 impl<'a> MvccReader<'a> {
@@ -40,6 +57,8 @@ impl<'a> MvccReader<'a> {
             scan_mode: scan_mode,
             key_only: false,
             fill_cache: fill_cache,
+            isolation_level: IsolationLevel::SI,
+            bypass_locks: vec![],
         }
     }
 
@@ -47,6 +66,37 @@ impl<'a> MvccReader<'a> {
         self.key_only = key_only;
     }
 
+    /// Under `RC`, reads ignore all the locks and get the latest committed value before the
+    /// read ts.
+    pub fn set_isolation_level(&mut self, isolation_level: IsolationLevel) {
+        self.isolation_level = isolation_level;
+    }
+
+    pub fn set_bypass_locks(&mut self, bypass_locks: Vec<u64>) {
+        self.bypass_locks = bypass_locks;
+    }
+
+    // Returns `KeyIsLocked` if the lock on `key` blocks reading at `ts`.
+    fn check_lock(&mut self, key: &Key, ts: u64) -> Result<()> {
+        if self.isolation_level == IsolationLevel::RC {
+            return Ok(());
+        }
+        if let Some(lock) = try!(self.load_lock(key)) {
+            // Pessimistic locks only block writers.
+            if lock.ts <= ts && lock.lock_type != LockType::Pessimistic &&
+               !self.bypass_locks.contains(&lock.ts) {
+                // There is a pending lock. Client should wait or clean it.
+                return Err(Error::KeyIsLocked {
+                    key: try!(key.raw()),
+                    primary: lock.primary,
+                    ts: lock.ts,
+                    ttl: lock.ttl,
+                });
+            }
+        }
+        Ok(())
+    }
+
     pub fn load_data(&mut self, key: &Key, ts: u64) -> Result<Value> {
         if self.key_only {
             return Ok(vec![]);
@@ -143,22 +193,18 @@ impl<'a> MvccReader<'a> {
 
     pub fn get(&mut self, key: &Key, mut ts: u64) -> Result<Option<Value>> {
         // Check for locks that signal concurrent writes.
-        if let Some(lock) = try!(self.load_lock(key)) {
-            if lock.ts <= ts {
-                // There is a pending lock. Client should wait or clean it.
-                return Err(Error::KeyIsLocked {
-                    key: try!(key.raw()),
-                    primary: lock.primary,
-                    ts: lock.ts,
-                    ttl: lock.ttl,
-                });
-            }
-        }
+        try!(self.check_lock(key, ts));
         loop {
             match try!(self.seek_write(key, ts)) {
                 Some((commit_ts, write)) => {
                     match write.write_type {
-                        WriteType::Put => return self.load_data(key, write.start_ts).map(Some),
+                        WriteType::Put => {
+                            // Short values are inlined, no need to read CF_DEFAULT.
+                            return match write.short_value {
+                                Some(v) if !self.key_only => Ok(Some(v)),
+                                _ => self.load_data(key, write.start_ts).map(Some),
+                            };
+                        }
                         WriteType::Delete => return Ok(None),
                         WriteType::Lock | WriteType::Rollback => ts = commit_ts - 1,
                     }
@@ -293,9 +339,12 @@ impl<'a> MvccReader<'a> {
         }
     }
 
+    /// Scans the locks in `[start, end)` which pass `filter`, up to `limit` locks. The key of
+    /// the last lock is returned as well if the limit is reached.
     #[allow(type_complexity)]
     pub fn scan_lock<F>(&mut self,
                         start: Option<Key>,
+                        end: Option<&Key>,
                         filter: F,
                         limit: Option<usize>)
                         -> Result<(Vec<(Key, Lock)>, Option<Key>)>
@@ -312,6 +361,11 @@ impl<'a> MvccReader<'a> {
         }
         let mut locks = vec![];
         while cursor.valid() {
+            if let Some(end) = end {
+                if cursor.key() >= end.encoded().as_slice() {
+                    break;
+                }
+            }
             let key = Key::from_encoded(cursor.key().to_vec());
             let lock = try!(Lock::parse(cursor.value()));
             if filter(&lock) {
@@ -350,4 +404,66 @@ impl<'a> MvccReader<'a> {
             keys.push(key);
         }
     }
+
+    /// Returns all the write records of `key`, the latest first.
+    pub fn scan_writes(&mut self, key: &Key) -> Result<Vec<(u64, Write)>> {
+        let mut cursor = try!(self.snapshot
+            .iter_cf(CF_WRITE, None, self.fill_cache, ScanMode::Forward));
+        let mut writes = vec![];
+        let mut ok = try!(cursor.seek(&key.append_ts(u64::MAX)));
+        while ok {
+            let write_key = Key::from_encoded(cursor.key().to_vec());
+            if try!(write_key.truncate_ts()) != *key {
+                break;
+            }
+            let commit_ts = try!(write_key.decode_ts());
+            writes.push((commit_ts, try!(Write::parse(cursor.value()))));
+            ok = cursor.next();
+        }
+        Ok(writes)
+    }
+
+    /// Returns all the values of `key` in CF_DEFAULT, the latest first.
+    pub fn scan_values(&mut self, key: &Key) -> Result<Vec<(u64, Value)>> {
+        let mut cursor = try!(self.snapshot.iter(None, self.fill_cache, ScanMode::Forward));
+        let mut values = vec![];
+        let mut ok = try!(cursor.seek(&key.append_ts(u64::MAX)));
+        while ok {
+            let data_key = Key::from_encoded(cursor.key().to_vec());
+            if try!(data_key.truncate_ts()) != *key {
+                break;
+            }
+            values.push((try!(data_key.decode_ts()), cursor.value().to_vec()));
+            ok = cursor.next();
+        }
+        Ok(values)
+    }
+
+    pub fn get_mvcc_info(&mut self, key: &Key) -> Result<MvccInfo> {
+        Ok(MvccInfo {
+            lock: try!(self.load_lock(key)),
+            writes: try!(self.scan_writes(key)),
+            values: try!(self.scan_values(key)),
+        })
+    }
+
+    /// Finds a key written by the transaction started at `start_ts`, either locked or
+    /// committed. It scans the whole snapshot, so it's only for debugging.
+    pub fn seek_ts(&mut self, start_ts: u64) -> Result<Option<Key>> {
+        let (mut locks, _) = try!(self.scan_lock(None, None, |lock| lock.ts == start_ts, Some(1)));
+        if let Some((key, _)) = locks.pop() {
+            return Ok(Some(key));
+        }
+        let mut cursor = try!(self.snapshot
+            .iter_cf(CF_WRITE, None, self.fill_cache, ScanMode::Forward));
+        let mut ok = cursor.seek_to_first();
+        while ok {
+            if try!(Write::parse(cursor.value())).start_ts == start_ts {
+                let key = try!(Key::from_encoded(cursor.key().to_vec()).truncate_ts());
+                return Ok(Some(key));
+            }
+            ok = cursor.next();
+        }
+        Ok(None)
+    }
 }
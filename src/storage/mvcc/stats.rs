@@ -0,0 +1,133 @@
+// Copyright 2016 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use util::codec::number::{NumberEncoder, NumberDecoder, MAX_VAR_U64_LEN};
+use super::write::{Write, WriteType};
+use super::Result;
+
+/// Approximate counters of the records in `CF_WRITE` of a region.
+///
+/// They are maintained when the raft log is applied without reading anything, and are reset
+/// whenever they can't be tracked, e.g. after applying a snapshot or a split, so zero versions
+/// means unknown.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MvccStats {
+    /// All the records, including the garbage.
+    pub versions: u64,
+    /// Deletes, rollbacks and lock records, which are all removed by GC once they are older
+    /// than the safe point.
+    pub garbage: u64,
+    /// Records written so far. It never goes down and also goes up on a reset, so nothing is
+    /// written to the region as long as it stays the same.
+    pub written: u64,
+}
+
+impl MvccStats {
+    pub fn new() -> MvccStats {
+        MvccStats::default()
+    }
+
+    /// Counts a record written to `CF_WRITE`.
+    pub fn add(&mut self, write: &Write) {
+        self.versions += 1;
+        self.written += 1;
+        match write.write_type {
+            WriteType::Delete | WriteType::Rollback | WriteType::Lock => self.garbage += 1,
+            WriteType::Put => {}
+        }
+    }
+
+    /// Uncounts a record deleted from `CF_WRITE`.
+    ///
+    /// Records are only deleted by GC, so they are all garbage. Puts overwritten by later
+    /// versions are garbage too but aren't counted when they are written, so `garbage` is
+    /// a lower bound.
+    pub fn sub(&mut self) {
+        self.versions = self.versions.saturating_sub(1);
+        self.garbage = self.garbage.saturating_sub(1);
+    }
+
+    /// Forgets the counted records when they can't be tracked any more.
+    pub fn reset(&mut self) {
+        self.versions = 0;
+        self.garbage = 0;
+        self.written += 1;
+    }
+
+    /// Returns a lower bound of the ratio of the records that only exist for MVCC, or `None`
+    /// if it's unknown.
+    pub fn garbage_ratio(&self) -> Option<f64> {
+        if self.versions == 0 {
+            return None;
+        }
+        Some(self.garbage as f64 / self.versions as f64)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut b = Vec::with_capacity(3 * MAX_VAR_U64_LEN);
+        b.encode_var_u64(self.versions).unwrap();
+        b.encode_var_u64(self.garbage).unwrap();
+        b.encode_var_u64(self.written).unwrap();
+        b
+    }
+
+    pub fn parse(mut b: &[u8]) -> Result<MvccStats> {
+        Ok(MvccStats {
+            versions: try!(b.decode_var_u64()),
+            garbage: try!(b.decode_var_u64()),
+            written: try!(b.decode_var_u64()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MvccStats;
+    use super::super::write::{Write, WriteType};
+
+    #[test]
+    fn test_mvcc_stats() {
+        let mut stats = MvccStats::new();
+        assert_eq!(stats.garbage_ratio(), None);
+
+        stats.add(&Write::new(WriteType::Put, 1));
+        stats.add(&Write::new(WriteType::Put, 2));
+        stats.add(&Write::new(WriteType::Delete, 3));
+        stats.add(&Write::new(WriteType::Rollback, 4));
+        assert_eq!(stats.versions, 4);
+        assert_eq!(stats.garbage, 2);
+        assert_eq!(stats.written, 4);
+        assert_eq!(stats.garbage_ratio(), Some(0.5));
+        assert_eq!(MvccStats::parse(&stats.to_bytes()).unwrap(), stats);
+
+        // GC removes the rollback, the delete and the put overwritten by it.
+        stats.sub();
+        stats.sub();
+        stats.sub();
+        assert_eq!(stats.versions, 1);
+        assert_eq!(stats.garbage_ratio(), Some(0.0));
+        // The counters are approximate, they never go below zero.
+        stats.sub();
+        stats.sub();
+        assert_eq!((stats.versions, stats.garbage), (0, 0));
+        // GC doesn't write anything.
+        assert_eq!(stats.written, 4);
+
+        stats.add(&Write::new(WriteType::Put, 5));
+        stats.reset();
+        assert_eq!(stats.garbage_ratio(), None);
+        assert_eq!(stats.written, 6);
+
+        assert!(MvccStats::parse(b"").is_err());
+    }
+}
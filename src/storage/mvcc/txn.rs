@@ -11,7 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::fmt;
+use std::{cmp, fmt};
 use storage::{Key, Value, Mutation, CF_DEFAULT, CF_LOCK, CF_WRITE};
 use storage::engine::{Snapshot, Modify, ScanMode};
 use super::reader::MvccReader;
@@ -21,12 +21,55 @@ use super::{Error, Result};
 use super::metrics::*;
 
 pub const MAX_TXN_WRITE_SIZE: usize = 32 * 1024;
+// The physical part of a timestamp in milliseconds is in the high bits, the low bits are the
+// logical part.
+pub const TSO_PHYSICAL_SHIFT_BITS: u64 = 18;
+// Values no longer than this are inlined in locks and writes instead of written to CF_DEFAULT.
+// It must fit in the length byte of the encoded records.
+pub const SHORT_VALUE_MAX_LEN: usize = 64;
+
+fn is_short_value(value: &[u8]) -> bool {
+    value.len() <= SHORT_VALUE_MAX_LEN
+}
+
+fn physical(ts: u64) -> u64 {
+    ts >> TSO_PHYSICAL_SHIFT_BITS
+}
+
+/// Status of a transaction worked out from its primary key.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TxnStatus {
+    /// The primary lock expires in `ttl` milliseconds.
+    Alive { ttl: u64 },
+    Committed { commit_ts: u64 },
+    RolledBack,
+    /// The primary lock of an async commit transaction has expired. The transaction is
+    /// committed if all the `secondaries` are prewritten, at the largest `min_commit_ts` of
+    /// them and the primary, otherwise it's rolled back. The secondaries are checked in their
+    /// own regions by `check_secondary_locks`.
+    AsyncCommit {
+        min_commit_ts: u64,
+        secondaries: Vec<Vec<u8>>,
+    },
+}
+
+/// Status of the secondary locks of an async commit transaction in a region.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SecondaryLocksStatus {
+    /// All the keys are prewritten, the largest `min_commit_ts` of them is returned.
+    Locked { min_commit_ts: u64 },
+    Committed { commit_ts: u64 },
+    RolledBack,
+}
 
 pub struct MvccTxn<'a> {
     reader: MvccReader<'a>,
     start_ts: u64,
     writes: Vec<Modify>,
     write_size: usize,
+    // Set for async commit transactions.
+    secondaries: Option<Vec<Vec<u8>>>,
+    min_commit_ts: u64,
 }
 
 impl<'a> fmt::Debug for MvccTxn<'a> {
@@ -43,9 +86,23 @@ impl<'a> MvccTxn<'a> {
             start_ts: start_ts,
             writes: vec![],
             write_size: 0,
+            secondaries: None,
+            min_commit_ts: 0,
         }
     }
 
+    /// Makes the following prewrites write locks of an async commit transaction. The lock of
+    /// the primary key records all the `secondaries`.
+    pub fn set_async_commit(&mut self, secondaries: Vec<Vec<u8>>, min_commit_ts: u64) {
+        self.secondaries = Some(secondaries);
+        self.min_commit_ts = cmp::max(min_commit_ts, self.start_ts + 1);
+    }
+
+    /// The `min_commit_ts` of the async commit locks written by this transaction.
+    pub fn min_commit_ts(&self) -> u64 {
+        self.min_commit_ts
+    }
+
     pub fn modifies(self) -> Vec<Modify> {
         self.writes
     }
@@ -54,8 +111,8 @@ impl<'a> MvccTxn<'a> {
         self.write_size
     }
 
-    fn lock_key(&mut self, key: Key, lock_type: LockType, primary: Vec<u8>, ttl: u64) {
-        let lock = Lock::new(lock_type, primary, self.start_ts, ttl).to_bytes();
+    fn lock_key(&mut self, key: Key, lock: Lock) {
+        let lock = lock.to_bytes();
         self.write_size += CF_LOCK.len() + key.encoded().len() + lock.len();
         self.writes.push(Modify::Put(CF_LOCK, key, lock));
     }
@@ -94,14 +151,187 @@ impl<'a> MvccTxn<'a> {
     }
 
     pub fn prewrite(&mut self, mutation: Mutation, primary: &[u8], lock_ttl: u64) -> Result<()> {
-        let key = mutation.key();
-        if let Some((commit, _)) = try!(self.reader.seek_write(&key, u64::max_value())) {
+        if let Some(lock) = try!(self.check_prewrite_conflict(mutation.key())) {
+            if lock.ts != self.start_ts {
+                return Err(Error::KeyIsLocked {
+                    key: try!(mutation.key().raw()),
+                    primary: lock.primary,
+                    ts: lock.ts,
+                    ttl: lock.ttl,
+                });
+            }
+        }
+        self.prewrite_key_value(mutation, primary, lock_ttl, 0)
+    }
+
+    /// Commits a mutation at `commit_ts` directly without prewriting it, for transactions
+    /// that commit in one phase.
+    ///
+    /// Conflicts are checked the same way as `prewrite`, except that any lock on the key fails
+    /// it, including the one left by an earlier prewrite of the transaction.
+    pub fn one_pc_commit(&mut self, mutation: Mutation, commit_ts: u64) -> Result<()> {
+        if let Some(lock) = try!(self.check_prewrite_conflict(mutation.key())) {
+            return Err(Error::KeyIsLocked {
+                key: try!(mutation.key().raw()),
+                primary: lock.primary,
+                ts: lock.ts,
+                ttl: lock.ttl,
+            });
+        }
+        if mutation.should_not_exist() {
+            try!(self.check_data_not_exist(mutation.key()));
+        }
+        let write_type = match LockType::from_mutation(&mutation) {
+            Some(lock_type) => WriteType::from_lock_type(lock_type).unwrap(),
+            None => return Ok(()),
+        };
+        let (key, value) = match mutation {
+            Mutation::Put((key, value)) |
+            Mutation::Insert((key, value)) => (key, Some(value)),
+            Mutation::Delete(key) |
+            Mutation::Lock(key) |
+            Mutation::CheckNotExists(key) => (key, None),
+        };
+        let mut write = Write::new(write_type, self.start_ts);
+        match value {
+            Some(ref v) if is_short_value(v) => write.short_value = Some(v.clone()),
+            Some(v) => {
+                let ts = self.start_ts;
+                self.put_value(&key, ts, v);
+            }
+            None => {}
+        }
+        self.put_write(&key, commit_ts, write.to_bytes());
+        Ok(())
+    }
+
+    // Returns `WriteConflict` if there is a write after our start timestamp, otherwise returns
+    // the lock on the key if any.
+    fn check_prewrite_conflict(&mut self, key: &Key) -> Result<Option<Lock>> {
+        if let Some((commit, _)) = try!(self.reader.seek_write(key, u64::max_value())) {
             // Abort on writes after our start timestamp ...
             if commit >= self.start_ts {
                 return Err(Error::WriteConflict);
             }
         }
         // ... or locks at any timestamp.
+        self.reader.load_lock(key)
+    }
+
+    // Returns `AlreadyExist` if the latest committed version of the key is a put. Write
+    // conflicts must have been checked, so the version is visible to the transaction.
+    fn check_data_not_exist(&mut self, key: &Key) -> Result<()> {
+        let mut ts = u64::max_value();
+        while let Some((commit_ts, write)) = try!(self.reader.seek_write(key, ts)) {
+            match write.write_type {
+                WriteType::Put => return Err(Error::AlreadyExist { key: try!(key.raw()) }),
+                WriteType::Delete => break,
+                WriteType::Lock | WriteType::Rollback => ts = commit_ts - 1,
+            }
+        }
+        Ok(())
+    }
+
+    /// Prewrites a mutation of a pessimistic transaction.
+    ///
+    /// If `is_pessimistic_lock` is true, the key must have been locked by
+    /// `acquire_pessimistic_lock`, which has already checked write conflicts, and the pessimistic
+    /// lock is turned into a normal one.
+    pub fn pessimistic_prewrite(&mut self,
+                                mutation: Mutation,
+                                primary: &[u8],
+                                is_pessimistic_lock: bool,
+                                lock_ttl: u64,
+                                for_update_ts: u64)
+                                -> Result<()> {
+        if !is_pessimistic_lock {
+            let key = mutation.key().clone();
+            if let Some(lock) = try!(self.reader.load_lock(&key)) {
+                if lock.ts == self.start_ts && lock.lock_type == LockType::Pessimistic {
+                    // The key was locked by the transaction but isn't expected to be.
+                    return Err(Error::PessimisticLockNotFound);
+                }
+            }
+            return self.prewrite(mutation, primary, lock_ttl);
+        }
+
+        match try!(self.reader.load_lock(mutation.key())) {
+            Some(ref lock) if lock.ts == self.start_ts => {
+                if lock.lock_type != LockType::Pessimistic {
+                    // Retried prewrite.
+                    return Ok(());
+                }
+            }
+            Some(lock) => {
+                return Err(Error::KeyIsLocked {
+                    key: try!(mutation.key().raw()),
+                    primary: lock.primary,
+                    ts: lock.ts,
+                    ttl: lock.ttl,
+                });
+            }
+            None => {
+                info!("pessimistic lock not found, key:{}, start_ts:{}",
+                      mutation.key(),
+                      self.start_ts);
+                return Err(Error::PessimisticLockNotFound);
+            }
+        }
+        self.prewrite_key_value(mutation, primary, lock_ttl, for_update_ts)
+    }
+
+    fn prewrite_key_value(&mut self,
+                          mutation: Mutation,
+                          primary: &[u8],
+                          lock_ttl: u64,
+                          for_update_ts: u64)
+                          -> Result<()> {
+        if mutation.should_not_exist() {
+            try!(self.check_data_not_exist(mutation.key()));
+        }
+        let lock_type = match LockType::from_mutation(&mutation) {
+            Some(lock_type) => lock_type,
+            None => return Ok(()),
+        };
+        let (key, value) = match mutation {
+            Mutation::Put((key, value)) |
+            Mutation::Insert((key, value)) => (key, Some(value)),
+            Mutation::Delete(key) |
+            Mutation::Lock(key) |
+            Mutation::CheckNotExists(key) => (key, None),
+        };
+        let mut lock = Lock::new(lock_type, primary.to_vec(), self.start_ts, lock_ttl)
+            .with_for_update_ts(for_update_ts);
+        if let Some(ref secondaries) = self.secondaries {
+            let secondaries = if key == Key::from_raw(primary) {
+                secondaries.clone()
+            } else {
+                vec![]
+            };
+            lock = lock.with_async_commit(secondaries, self.min_commit_ts);
+        }
+        match value {
+            Some(ref v) if is_short_value(v) => lock.short_value = Some(v.clone()),
+            Some(v) => {
+                let ts = self.start_ts;
+                self.put_value(&key, ts, v);
+            }
+            None => {}
+        }
+        self.lock_key(key, lock);
+        Ok(())
+    }
+
+    /// Locks `key` for a pessimistic transaction without writing anything.
+    ///
+    /// Write conflicts are checked against `for_update_ts` instead of `start_ts`, so the
+    /// transaction can lock the key at statement time and read the latest committed version.
+    pub fn acquire_pessimistic_lock(&mut self,
+                                    key: Key,
+                                    primary: &[u8],
+                                    lock_ttl: u64,
+                                    for_update_ts: u64)
+                                    -> Result<()> {
         if let Some(lock) = try!(self.reader.load_lock(&key)) {
             if lock.ts != self.start_ts {
                 return Err(Error::KeyIsLocked {
@@ -111,22 +341,54 @@ impl<'a> MvccTxn<'a> {
                     ttl: lock.ttl,
                 });
             }
+            if lock.lock_type != LockType::Pessimistic || lock.for_update_ts >= for_update_ts {
+                // Already prewritten, or a retried request.
+                return Ok(());
+            }
+            // Relock with the larger `for_update_ts`.
+            let lock = Lock::new(LockType::Pessimistic, primary.to_vec(), self.start_ts, lock_ttl)
+                .with_for_update_ts(for_update_ts);
+            self.lock_key(key, lock);
+            return Ok(());
         }
-        self.lock_key(key.clone(),
-                      LockType::from_mutation(&mutation),
-                      primary.to_vec(),
-                      lock_ttl);
-
-        if let Mutation::Put((_, ref value)) = mutation {
-            let ts = self.start_ts;
-            self.put_value(key, ts, value.clone());
+
+        if let Some((commit, _)) = try!(self.reader.seek_write(&key, u64::max_value())) {
+            if commit > for_update_ts {
+                return Err(Error::WriteConflict);
+            }
         }
+        // Rollback records are written at `start_ts`.
+        if let Some((commit, write)) = try!(self.reader.seek_write(&key, self.start_ts)) {
+            if commit == self.start_ts && write.write_type == WriteType::Rollback {
+                info!("pessimistic lock rolled back, key:{}, start_ts:{}",
+                      key,
+                      self.start_ts);
+                return Err(Error::PessimisticLockRolledBack);
+            }
+        }
+
+        let lock = Lock::new(LockType::Pessimistic, primary.to_vec(), self.start_ts, lock_ttl)
+            .with_for_update_ts(for_update_ts);
+        self.lock_key(key, lock);
         Ok(())
     }
 
     pub fn commit(&mut self, key: &Key, commit_ts: u64) -> Result<()> {
-        let lock_type = match try!(self.reader.load_lock(key)) {
-            Some(ref lock) if lock.ts == self.start_ts => lock.lock_type,
+        let (lock_type, short_value) = match try!(self.reader.load_lock(key)) {
+            Some(ref lock) if lock.ts == self.start_ts => {
+                if commit_ts < lock.min_commit_ts {
+                    info!("txn conflict (commit ts expired), key:{}, start_ts:{}, commit_ts:{}",
+                          key,
+                          self.start_ts,
+                          commit_ts);
+                    return Err(Error::CommitTsExpired {
+                        start_ts: self.start_ts,
+                        commit_ts: commit_ts,
+                        min_commit_ts: lock.min_commit_ts,
+                    });
+                }
+                (lock.lock_type, lock.short_value.clone())
+            }
             _ => {
                 return match try!(self.reader.get_txn_commit_ts(key, self.start_ts)) {
                     // Committed by concurrent transaction.
@@ -142,7 +404,16 @@ impl<'a> MvccTxn<'a> {
                 };
             }
         };
-        let write = Write::new(WriteType::from_lock_type(lock_type), self.start_ts);
+        let write_type = match WriteType::from_lock_type(lock_type) {
+            Some(tp) => tp,
+            None => {
+                info!("txn conflict (pessimistic lock not prewritten), key:{}, start_ts:{}",
+                      key,
+                      self.start_ts);
+                return Err(Error::TxnLockNotFound);
+            }
+        };
+        let write = Write::new(write_type, self.start_ts).with_short_value(short_value);
         self.put_write(key, commit_ts, write.to_bytes());
         self.unlock_key(key.clone());
         Ok(())
@@ -151,7 +422,10 @@ impl<'a> MvccTxn<'a> {
     pub fn rollback(&mut self, key: &Key) -> Result<()> {
         match try!(self.reader.load_lock(key)) {
             Some(ref lock) if lock.ts == self.start_ts => {
-                self.delete_value(key, lock.ts);
+                // Pessimistic locks and short values have no value written.
+                if lock.lock_type != LockType::Pessimistic && lock.short_value.is_none() {
+                    self.delete_value(key, lock.ts);
+                }
             }
             _ => {
                 return match try!(self.reader.get_txn_commit_ts(key, self.start_ts)) {
@@ -175,7 +449,151 @@ impl<'a> MvccTxn<'a> {
         Ok(())
     }
 
-    pub fn gc(&mut self, key: &Key, safe_point: u64) -> Result<()> {
+    /// Rolls back the lock of `key` left by a transaction which may be dead. The locks of an
+    /// async commit transaction are refused, as the transaction may have been committed, the
+    /// caller must check its status by `check_txn_status` on the primary key instead.
+    pub fn cleanup(&mut self, key: &Key) -> Result<()> {
+        if let Some(lock) = try!(self.reader.load_lock(key)) {
+            if lock.ts == self.start_ts && lock.use_async_commit {
+                return Err(Error::AsyncCommitUndetermined {
+                    start_ts: self.start_ts,
+                    primary: lock.primary,
+                });
+            }
+        }
+        self.rollback(key)
+    }
+
+    /// Checks the status of the transaction by its primary lock, and rolls the transaction back
+    /// if the lock has expired at `current_ts`. An expired async commit transaction is not
+    /// rolled back but returned as `AsyncCommit`, unless it has no secondaries to check.
+    ///
+    /// If the primary lock is missing, the transaction is committed, rolled back, or not
+    /// prewritten yet. A rollback record is written in the last case, so the prewrite fails
+    /// when it comes.
+    pub fn check_txn_status(&mut self, primary: &Key, current_ts: u64) -> Result<TxnStatus> {
+        match try!(self.reader.load_lock(primary)) {
+            Some(ref lock) if lock.ts == self.start_ts => {
+                let expire = physical(lock.ts).saturating_add(lock.ttl);
+                let now = physical(current_ts);
+                if now < expire {
+                    return Ok(TxnStatus::Alive { ttl: expire - now });
+                }
+                if lock.use_async_commit {
+                    if lock.secondaries.is_empty() {
+                        return Ok(TxnStatus::Committed { commit_ts: lock.min_commit_ts });
+                    }
+                    return Ok(TxnStatus::AsyncCommit {
+                        min_commit_ts: lock.min_commit_ts,
+                        secondaries: lock.secondaries.clone(),
+                    });
+                }
+                try!(self.rollback(primary));
+                Ok(TxnStatus::RolledBack)
+            }
+            _ => {
+                if let Some(ts) = try!(self.reader.get_txn_commit_ts(primary, self.start_ts)) {
+                    return Ok(TxnStatus::Committed { commit_ts: ts });
+                }
+                try!(self.protect_rollback(primary));
+                Ok(TxnStatus::RolledBack)
+            }
+        }
+    }
+
+    /// Extends the TTL of the primary lock to `advise_ttl` if it's larger, and returns the TTL
+    /// of the lock. Fails if the lock doesn't belong to the transaction anymore, or `primary`
+    /// is a secondary key of it.
+    pub fn txn_heart_beat(&mut self, primary: Key, advise_ttl: u64) -> Result<u64> {
+        match try!(self.reader.load_lock(&primary)) {
+            Some(mut lock) => {
+                if lock.ts != self.start_ts {
+                    return Err(Error::TxnLockNotFound);
+                }
+                if Key::from_raw(&lock.primary) != primary {
+                    return Err(Error::PrimaryMismatch {
+                        key: try!(primary.raw()),
+                        primary: lock.primary,
+                    });
+                }
+                let ttl = lock.ttl;
+                if ttl >= advise_ttl {
+                    return Ok(ttl);
+                }
+                lock.ttl = advise_ttl;
+                self.lock_key(primary, lock);
+                Ok(advise_ttl)
+            }
+            None => Err(Error::TxnLockNotFound),
+        }
+    }
+
+    // Writes a rollback record for `key` which isn't locked by the transaction, so its prewrite
+    // fails when it comes. The write of another transaction committed at start_ts is kept.
+    fn protect_rollback(&mut self, key: &Key) -> Result<()> {
+        match try!(self.reader.reverse_seek_write(key, self.start_ts)) {
+            Some((commit_ts, _)) if commit_ts == self.start_ts => {}
+            _ => {
+                let write = Write::new(WriteType::Rollback, self.start_ts);
+                let ts = self.start_ts;
+                self.put_write(key, ts, write.to_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the secondary locks on `keys` of an async commit transaction, which must all be
+    /// in the region of the snapshot.
+    ///
+    /// If some key is neither locked nor committed, the key gets a rollback record so it can't
+    /// be prewritten afterwards, and the transaction is rolled back. The locks are left for
+    /// the resolver, which resolves them once the status of every region is known.
+    pub fn check_secondary_locks(&mut self, keys: &[Key]) -> Result<SecondaryLocksStatus> {
+        let mut min_commit_ts = 0;
+        let mut missing = vec![];
+        for key in keys {
+            if let Some(lock) = try!(self.reader.load_lock(key)) {
+                if lock.ts == self.start_ts {
+                    min_commit_ts = cmp::max(min_commit_ts, lock.min_commit_ts);
+                    continue;
+                }
+            }
+            match try!(self.reader.get_txn_commit_ts(key, self.start_ts)) {
+                // Some secondary has been committed, so is the transaction.
+                Some(ts) => return Ok(SecondaryLocksStatus::Committed { commit_ts: ts }),
+                None => missing.push(key),
+            }
+        }
+        if missing.is_empty() {
+            return Ok(SecondaryLocksStatus::Locked { min_commit_ts: min_commit_ts });
+        }
+        for key in missing {
+            try!(self.protect_rollback(key));
+        }
+        Ok(SecondaryLocksStatus::RolledBack)
+    }
+
+    /// Commits or rolls back the lock of `key` on behalf of the transaction. Pessimistic locks
+    /// of a committed transaction are just released since their keys were never prewritten.
+    pub fn resolve_lock(&mut self, key: &Key, commit_ts: Option<u64>) -> Result<()> {
+        let commit_ts = match commit_ts {
+            Some(ts) => ts,
+            None => return self.rollback(key),
+        };
+        if let Some(lock) = try!(self.reader.load_lock(key)) {
+            if lock.ts == self.start_ts && lock.lock_type == LockType::Pessimistic {
+                self.unlock_key(key.clone());
+                return Ok(());
+            }
+        }
+        self.commit(key, commit_ts)
+    }
+
+    /// Collects the versions of `key` which are no longer read by any transaction after
+    /// `safe_point`. Returns true if some versions are after `safe_point`, which are left to
+    /// the GC with a later safe point.
+    pub fn gc(&mut self, key: &Key, safe_point: u64) -> Result<bool> {
+        let mut has_newer = false;
         let mut remove_older = false;
         let mut ts: u64 = u64::max_value();
         let mut versions = 0;
@@ -184,6 +602,9 @@ impl<'a> MvccTxn<'a> {
             if self.write_size >= MAX_TXN_WRITE_SIZE {
                 break;
             }
+            if commit > safe_point {
+                has_newer = true;
+            }
             if !remove_older {
                 if commit <= safe_point {
                     // Set `remove_older` after we find the latest value.
@@ -206,7 +627,7 @@ impl<'a> MvccTxn<'a> {
                 }
             } else {
                 self.delete_write(key, commit);
-                if write.write_type == WriteType::Put {
+                if write.write_type == WriteType::Put && write.short_value.is_none() {
                     self.delete_value(key, write.start_ts);
                 }
                 delete_versions += 1;
@@ -218,17 +639,19 @@ impl<'a> MvccTxn<'a> {
         if delete_versions > 0 {
             GC_DELETE_VERSIONS_HISTOGRAM.observe(delete_versions as f64);
         }
-        Ok(())
+        Ok(has_newer)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use kvproto::kvrpcpb::Context;
-    use super::MvccTxn;
-    use super::super::MvccReader;
+    use kvproto::kvrpcpb::{Context, IsolationLevel};
+    use super::{MvccTxn, TxnStatus, SecondaryLocksStatus, SHORT_VALUE_MAX_LEN,
+                TSO_PHYSICAL_SHIFT_BITS};
+    use super::super::{MvccReader, MvccInfo, Error};
     use super::super::write::{Write, WriteType};
-    use storage::{make_key, Mutation, ALL_CFS, CF_WRITE, ScanMode};
+    use super::super::lock::LockType;
+    use storage::{make_key, Key, Mutation, ALL_CFS, CF_WRITE, ScanMode};
     use storage::engine::{self, Engine, TEMP_DIR};
 
     #[test]
@@ -347,6 +770,316 @@ mod tests {
         must_rollback_err(engine.as_ref(), b"x", 5);
     }
 
+    #[test]
+    fn test_pessimistic_lock() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+
+        must_acquire_pessimistic_lock(engine.as_ref(), b"x", b"x", 1, 1);
+        must_pessimistic_locked(engine.as_ref(), b"x", 1, 1);
+        // Pessimistic locks don't block readers.
+        must_get_none(engine.as_ref(), b"x", 2);
+        // Retry.
+        must_acquire_pessimistic_lock(engine.as_ref(), b"x", b"x", 1, 1);
+        // Lock conflict.
+        must_acquire_pessimistic_lock_err(engine.as_ref(), b"x", b"x", 2, 2);
+        must_prewrite_lock_err(engine.as_ref(), b"x", b"x", 2);
+        // Can't commit before prewrite.
+        must_commit_err(engine.as_ref(), b"x", 1, 2);
+        must_pessimistic_prewrite_put(engine.as_ref(), b"x", b"x1", b"x", 1, 1, true);
+        must_locked(engine.as_ref(), b"x", 1);
+        // Retry prewrite.
+        must_pessimistic_prewrite_put(engine.as_ref(), b"x", b"x1", b"x", 1, 1, true);
+        must_commit(engine.as_ref(), b"x", 1, 2);
+        must_unlocked(engine.as_ref(), b"x");
+        must_get(engine.as_ref(), b"x", 3, b"x1");
+
+        // Write conflict.
+        must_acquire_pessimistic_lock_err(engine.as_ref(), b"x", b"x", 1, 1);
+        // Lock with a newer `for_update_ts` after the conflict.
+        must_acquire_pessimistic_lock(engine.as_ref(), b"y", b"x", 3, 3);
+        must_acquire_pessimistic_lock(engine.as_ref(), b"x", b"x", 3, 4);
+        must_pessimistic_locked(engine.as_ref(), b"x", 3, 4);
+        // The lock must exist when `is_pessimistic_lock` is set.
+        must_pessimistic_prewrite_put_err(engine.as_ref(), b"z", b"z3", b"x", 3, 4, true);
+        must_pessimistic_prewrite_put(engine.as_ref(), b"z", b"z3", b"x", 3, 4, false);
+
+        // Rollback.
+        must_rollback(engine.as_ref(), b"x", 3);
+        must_unlocked(engine.as_ref(), b"x");
+        must_written(engine.as_ref(), b"x", 3, 3, WriteType::Rollback);
+        must_acquire_pessimistic_lock_err(engine.as_ref(), b"x", b"x", 3, 5);
+
+        // Resolving a committed transaction releases its pessimistic locks.
+        must_resolve_lock(engine.as_ref(), b"y", 3, Some(5));
+        must_unlocked(engine.as_ref(), b"y");
+        must_get_none(engine.as_ref(), b"y", 6);
+    }
+
+    #[test]
+    fn test_async_commit() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+
+        // s2 isn't prewritten, so the transaction is rolled back.
+        let secondaries = vec![b"s1".to_vec(), b"s2".to_vec()];
+        must_async_commit_prewrite_put(engine.as_ref(),
+                                       b"p",
+                                       b"p1",
+                                       b"p",
+                                       secondaries.clone(),
+                                       10,
+                                       15);
+        must_async_commit_prewrite_put(engine.as_ref(), b"s1", b"s1", b"p", vec![], 10, 15);
+        // Cleanup can't tell the status.
+        must_cleanup_async_commit(engine.as_ref(), b"s1", 10);
+        must_check_txn_status(engine.as_ref(),
+                              b"p",
+                              10,
+                              10,
+                              TxnStatus::AsyncCommit {
+                                  min_commit_ts: 15,
+                                  secondaries: secondaries,
+                              });
+        must_locked(engine.as_ref(), b"p", 10);
+        must_check_secondary_locks(engine.as_ref(),
+                                   &[b"s1", b"s2"],
+                                   10,
+                                   SecondaryLocksStatus::RolledBack);
+        must_locked(engine.as_ref(), b"s1", 10);
+        // s2 can't be prewritten afterwards.
+        must_written(engine.as_ref(), b"s2", 10, 10, WriteType::Rollback);
+        must_prewrite_lock_err(engine.as_ref(), b"s2", b"p", 10);
+        must_resolve_lock(engine.as_ref(), b"p", 10, None);
+        must_resolve_lock(engine.as_ref(), b"s1", 10, None);
+        must_unlocked(engine.as_ref(), b"p");
+        must_unlocked(engine.as_ref(), b"s1");
+        must_check_txn_status(engine.as_ref(), b"p", 10, 10, TxnStatus::RolledBack);
+
+        // All keys are prewritten, so the transaction is committed.
+        let secondaries = vec![b"s1".to_vec()];
+        must_async_commit_prewrite_put(engine.as_ref(),
+                                       b"p",
+                                       b"p2",
+                                       b"p",
+                                       secondaries.clone(),
+                                       20,
+                                       25);
+        must_async_commit_prewrite_put(engine.as_ref(), b"s1", b"s2", b"p", vec![], 20, 26);
+        must_check_txn_status(engine.as_ref(),
+                              b"p",
+                              20,
+                              20,
+                              TxnStatus::AsyncCommit {
+                                  min_commit_ts: 25,
+                                  secondaries: secondaries,
+                              });
+        must_check_secondary_locks(engine.as_ref(),
+                                   &[b"s1"],
+                                   20,
+                                   SecondaryLocksStatus::Locked { min_commit_ts: 26 });
+        // Can't commit before min_commit_ts.
+        must_commit_err(engine.as_ref(), b"s1", 20, 25);
+        must_resolve_lock(engine.as_ref(), b"s1", 20, Some(26));
+        // Committed secondary decides the status.
+        must_check_secondary_locks(engine.as_ref(),
+                                   &[b"s1"],
+                                   20,
+                                   SecondaryLocksStatus::Committed { commit_ts: 26 });
+        must_resolve_lock(engine.as_ref(), b"p", 20, Some(26));
+        must_get(engine.as_ref(), b"p", 30, b"p2");
+        must_get(engine.as_ref(), b"s1", 30, b"s2");
+
+        // A transaction without secondaries is committed once the primary is prewritten.
+        must_async_commit_prewrite_put(engine.as_ref(), b"q", b"q1", b"q", vec![], 40, 45);
+        must_check_txn_status(engine.as_ref(),
+                              b"q",
+                              40,
+                              40,
+                              TxnStatus::Committed { commit_ts: 45 });
+    }
+
+    #[test]
+    fn test_check_txn_status() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let ts = |physical: u64| physical << TSO_PHYSICAL_SHIFT_BITS;
+
+        // Alive until the ttl expires.
+        must_prewrite_put_ttl(engine.as_ref(), b"k", b"v", b"k", ts(10), 100);
+        must_check_txn_status(engine.as_ref(), b"k", ts(10), ts(50), TxnStatus::Alive { ttl: 60 });
+        must_locked(engine.as_ref(), b"k", ts(10));
+        must_check_txn_status(engine.as_ref(), b"k", ts(10), ts(110), TxnStatus::RolledBack);
+        must_unlocked(engine.as_ref(), b"k");
+        must_written(engine.as_ref(), b"k", ts(10), ts(10), WriteType::Rollback);
+        must_check_txn_status(engine.as_ref(), b"k", ts(10), ts(120), TxnStatus::RolledBack);
+
+        // Committed.
+        must_prewrite_put_ttl(engine.as_ref(), b"k", b"v", b"k", ts(20), 100);
+        must_commit(engine.as_ref(), b"k", ts(20), ts(25));
+        must_check_txn_status(engine.as_ref(),
+                              b"k",
+                              ts(20),
+                              ts(200),
+                              TxnStatus::Committed { commit_ts: ts(25) });
+
+        // Not prewritten yet, the prewrite fails afterwards.
+        must_check_txn_status(engine.as_ref(), b"k", ts(30), ts(30), TxnStatus::RolledBack);
+        must_written(engine.as_ref(), b"k", ts(30), ts(30), WriteType::Rollback);
+        must_prewrite_lock_err(engine.as_ref(), b"k", b"k", ts(30));
+
+        // The write of the transaction committed at ts(25) is kept.
+        must_check_txn_status(engine.as_ref(), b"k", ts(25), ts(30), TxnStatus::RolledBack);
+        must_written(engine.as_ref(), b"k", ts(20), ts(25), WriteType::Put);
+        must_get(engine.as_ref(), b"k", ts(30), b"v");
+    }
+
+    #[test]
+    fn test_txn_heart_beat() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+
+        must_txn_heart_beat_err(engine.as_ref(), b"k", 5, 100);
+
+        must_prewrite_put_ttl(engine.as_ref(), b"k", b"v", b"k", 5, 100);
+        must_txn_heart_beat(engine.as_ref(), b"k", 5, 200, 200);
+        // The ttl is never shortened.
+        must_txn_heart_beat(engine.as_ref(), b"k", 5, 150, 200);
+        must_check_txn_status(engine.as_ref(), b"k", 5, 5, TxnStatus::Alive { ttl: 200 });
+        // Locks of other transactions are kept.
+        must_txn_heart_beat_err(engine.as_ref(), b"k", 4, 300);
+
+        // Secondary locks are never heart beaten.
+        must_prewrite_put_ttl(engine.as_ref(), b"k2", b"v", b"k", 5, 100);
+        must_txn_heart_beat_err(engine.as_ref(), b"k2", 5, 300);
+        must_check_txn_status(engine.as_ref(), b"k2", 5, 5, TxnStatus::Alive { ttl: 100 });
+
+        must_commit(engine.as_ref(), b"k", 5, 10);
+        must_txn_heart_beat_err(engine.as_ref(), b"k", 5, 300);
+    }
+
+    #[test]
+    fn test_mvcc_info() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let long_value = vec![b'v'; SHORT_VALUE_MAX_LEN + 1];
+
+        must_prewrite_put(engine.as_ref(), b"k", &long_value, b"k", 5);
+        must_commit(engine.as_ref(), b"k", 5, 10);
+        must_prewrite_put(engine.as_ref(), b"k", b"v", b"k", 15);
+        must_commit(engine.as_ref(), b"k", 15, 20);
+        must_prewrite_delete(engine.as_ref(), b"k", b"k", 25);
+        must_rollback(engine.as_ref(), b"k", 25);
+        must_prewrite_put(engine.as_ref(), b"k", &long_value, b"k", 30);
+
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut reader = MvccReader::new(snapshot.as_ref(), None, true);
+        let info = reader.get_mvcc_info(&make_key(b"k")).unwrap();
+        assert_eq!(info.lock.unwrap().ts, 30);
+        let writes: Vec<_> = info.writes.iter().map(|&(ts, ref w)| (ts, w.write_type)).collect();
+        assert_eq!(writes,
+                   vec![(25, WriteType::Rollback), (20, WriteType::Put), (10, WriteType::Put)]);
+        assert_eq!(info.writes[1].1.short_value, Some(b"v".to_vec()));
+        assert_eq!(info.values, vec![(30, long_value.clone()), (5, long_value)]);
+
+        assert_eq!(reader.seek_ts(30).unwrap(), Some(make_key(b"k")));
+        assert_eq!(reader.seek_ts(15).unwrap(), Some(make_key(b"k")));
+        assert_eq!(reader.seek_ts(12).unwrap(), None);
+        let info = reader.get_mvcc_info(&make_key(b"x")).unwrap();
+        assert_eq!(info, MvccInfo::default());
+    }
+
+    #[test]
+    fn test_insert() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+
+        must_prewrite_insert(engine.as_ref(), b"k", b"v1", b"k", 5);
+        must_commit(engine.as_ref(), b"k", 5, 10);
+        must_get(engine.as_ref(), b"k", 10, b"v1");
+        must_prewrite_insert_err(engine.as_ref(), b"k", b"v2", b"k", 15);
+        must_check_not_exists_err(engine.as_ref(), b"k", 15);
+
+        // Rollbacks and locks are skipped.
+        must_prewrite_lock(engine.as_ref(), b"k", b"k", 15);
+        must_commit(engine.as_ref(), b"k", 15, 20);
+        must_rollback(engine.as_ref(), b"k", 25);
+        must_prewrite_insert_err(engine.as_ref(), b"k", b"v2", b"k", 30);
+
+        must_prewrite_delete(engine.as_ref(), b"k", b"k", 30);
+        must_commit(engine.as_ref(), b"k", 30, 35);
+        must_check_not_exists(engine.as_ref(), b"k", 40);
+        must_unlocked(engine.as_ref(), b"k");
+        must_prewrite_insert(engine.as_ref(), b"k", b"v3", b"k", 40);
+        must_commit(engine.as_ref(), b"k", 40, 45);
+        must_get(engine.as_ref(), b"k", 45, b"v3");
+    }
+
+    #[test]
+    fn test_read_isolation() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+
+        must_prewrite_put(engine.as_ref(), b"k", b"v1", b"k", 5);
+        must_commit(engine.as_ref(), b"k", 5, 10);
+        must_prewrite_put(engine.as_ref(), b"k", b"v2", b"k", 15);
+
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let key = make_key(b"k");
+        let mut reader = MvccReader::new(snapshot.as_ref(), None, true);
+        assert!(reader.get(&key, 20).is_err());
+        assert_eq!(reader.get(&key, 12).unwrap().unwrap(), b"v1");
+
+        reader.set_bypass_locks(vec![10, 15]);
+        assert_eq!(reader.get(&key, 20).unwrap().unwrap(), b"v1");
+        reader.set_bypass_locks(vec![10]);
+        assert!(reader.get(&key, 20).is_err());
+
+        reader.set_isolation_level(IsolationLevel::RC);
+        assert_eq!(reader.get(&key, 20).unwrap().unwrap(), b"v1");
+        assert!(reader.get(&key, 8).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_one_pc() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+
+        must_one_pc_commit_put(engine.as_ref(), b"x", b"x5", 5, 10);
+        must_unlocked(engine.as_ref(), b"x");
+        must_written(engine.as_ref(), b"x", 5, 10, WriteType::Put);
+        must_get(engine.as_ref(), b"x", 10, b"x5");
+        must_get_none(engine.as_ref(), b"x", 9);
+
+        // Write conflict.
+        must_one_pc_commit_put_err(engine.as_ref(), b"x", b"x8", 8, 15);
+        // Locked by another transaction.
+        must_prewrite_put(engine.as_ref(), b"x", b"x15", b"x", 15);
+        must_one_pc_commit_put_err(engine.as_ref(), b"x", b"x20", 20, 25);
+        // Locked by itself.
+        must_one_pc_commit_put_err(engine.as_ref(), b"x", b"x15", 15, 20);
+        must_commit(engine.as_ref(), b"x", 15, 20);
+        must_get(engine.as_ref(), b"x", 20, b"x15");
+    }
+
+    #[test]
+    fn test_short_value() {
+        let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let long_value = vec![b'v'; SHORT_VALUE_MAX_LEN + 1];
+
+        must_prewrite_put(engine.as_ref(), b"x", b"x5", b"x", 5);
+        must_prewrite_put(engine.as_ref(), b"y", &long_value, b"x", 5);
+        must_data_written(engine.as_ref(), b"x", 5, false);
+        must_data_written(engine.as_ref(), b"y", 5, true);
+        must_commit(engine.as_ref(), b"x", 5, 10);
+        must_commit(engine.as_ref(), b"y", 5, 10);
+        must_get(engine.as_ref(), b"x", 10, b"x5");
+        must_get(engine.as_ref(), b"y", 10, &long_value);
+
+        must_prewrite_put(engine.as_ref(), b"x", b"x15", b"x", 15);
+        must_rollback(engine.as_ref(), b"x", 15);
+        must_get(engine.as_ref(), b"x", 20, b"x5");
+
+        must_one_pc_commit_put(engine.as_ref(), b"x", b"x25", 25, 30);
+        must_data_written(engine.as_ref(), b"x", 25, false);
+        must_get(engine.as_ref(), b"x", 30, b"x25");
+        must_gc(engine.as_ref(), b"x", 30);
+        must_get_none(engine.as_ref(), b"x", 10);
+        must_get(engine.as_ref(), b"x", 30, b"x25");
+    }
+
     #[test]
     fn test_gc() {
         let engine = engine::new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
@@ -389,7 +1122,7 @@ mod tests {
         // 10             Commit(PUT,5)
         // 5    x5
 
-        must_gc(engine.as_ref(), b"x", 12);
+        assert!(must_gc(engine.as_ref(), b"x", 12));
         must_get(engine.as_ref(), b"x", 12, b"x5");
 
         must_gc(engine.as_ref(), b"x", 22);
@@ -400,7 +1133,8 @@ mod tests {
         must_get_none(engine.as_ref(), b"x", 22);
         must_get_none(engine.as_ref(), b"x", 35);
 
-        must_gc(engine.as_ref(), b"x", 60);
+        // Nothing is left to the later GC.
+        assert!(!must_gc(engine.as_ref(), b"x", 60));
         must_get(engine.as_ref(), b"x", 62, b"x35");
     }
 
@@ -519,6 +1253,39 @@ mod tests {
         engine.write(&ctx, txn.modifies()).unwrap();
     }
 
+    fn must_prewrite_insert(engine: &Engine, key: &[u8], value: &[u8], pk: &[u8], ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), ts, None);
+        txn.prewrite(Mutation::Insert((make_key(key), value.to_vec())), pk, 0).unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_prewrite_insert_err(engine: &Engine, key: &[u8], value: &[u8], pk: &[u8], ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), ts, None);
+        match txn.prewrite(Mutation::Insert((make_key(key), value.to_vec())), pk, 0) {
+            Err(Error::AlreadyExist { .. }) => {}
+            r => panic!("expect already exist, got {:?}", r),
+        }
+    }
+
+    fn must_check_not_exists(engine: &Engine, key: &[u8], ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), ts, None);
+        txn.prewrite(Mutation::CheckNotExists(make_key(key)), key, 0).unwrap();
+        assert!(txn.modifies().is_empty());
+    }
+
+    fn must_check_not_exists_err(engine: &Engine, key: &[u8], ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), ts, None);
+        assert!(txn.prewrite(Mutation::CheckNotExists(make_key(key)), key, 0).is_err());
+    }
+
     fn must_prewrite_delete(engine: &Engine, key: &[u8], pk: &[u8], ts: u64) {
         let ctx = Context::new();
         let snapshot = engine.snapshot(&ctx).unwrap();
@@ -542,6 +1309,182 @@ mod tests {
         assert!(txn.prewrite(Mutation::Lock(make_key(key)), pk, 0).is_err());
     }
 
+    fn must_one_pc_commit_put(engine: &Engine,
+                              key: &[u8],
+                              value: &[u8],
+                              start_ts: u64,
+                              commit_ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), start_ts, None);
+        txn.one_pc_commit(Mutation::Put((make_key(key), value.to_vec())), commit_ts).unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_one_pc_commit_put_err(engine: &Engine,
+                                  key: &[u8],
+                                  value: &[u8],
+                                  start_ts: u64,
+                                  commit_ts: u64) {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), start_ts, None);
+        assert!(txn.one_pc_commit(Mutation::Put((make_key(key), value.to_vec())), commit_ts)
+            .is_err());
+    }
+
+    fn must_async_commit_prewrite_put(engine: &Engine,
+                                      key: &[u8],
+                                      value: &[u8],
+                                      pk: &[u8],
+                                      secondaries: Vec<Vec<u8>>,
+                                      ts: u64,
+                                      min_commit_ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), ts, None);
+        txn.set_async_commit(secondaries, min_commit_ts);
+        txn.prewrite(Mutation::Put((make_key(key), value.to_vec())), pk, 0).unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_prewrite_put_ttl(engine: &Engine,
+                             key: &[u8],
+                             value: &[u8],
+                             pk: &[u8],
+                             ts: u64,
+                             lock_ttl: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), ts, None);
+        txn.prewrite(Mutation::Put((make_key(key), value.to_vec())), pk, lock_ttl).unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_check_txn_status(engine: &Engine,
+                             key: &[u8],
+                             start_ts: u64,
+                             current_ts: u64,
+                             status: TxnStatus) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), start_ts, None);
+        assert_eq!(txn.check_txn_status(&make_key(key), current_ts).unwrap(), status);
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_txn_heart_beat(engine: &Engine, key: &[u8], start_ts: u64, advise_ttl: u64, ttl: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), start_ts, None);
+        assert_eq!(txn.txn_heart_beat(make_key(key), advise_ttl).unwrap(), ttl);
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_txn_heart_beat_err(engine: &Engine, key: &[u8], start_ts: u64, advise_ttl: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), start_ts, None);
+        assert!(txn.txn_heart_beat(make_key(key), advise_ttl).is_err());
+    }
+
+    fn must_cleanup(engine: &Engine, key: &[u8], start_ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), start_ts, None);
+        txn.cleanup(&make_key(key)).unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_cleanup_async_commit(engine: &Engine, key: &[u8], start_ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), start_ts, None);
+        match txn.cleanup(&make_key(key)) {
+            Err(Error::AsyncCommitUndetermined { .. }) => {}
+            r => panic!("expect async commit undetermined, got {:?}", r),
+        }
+    }
+
+    fn must_check_secondary_locks(engine: &Engine,
+                                  keys: &[&[u8]],
+                                  start_ts: u64,
+                                  status: SecondaryLocksStatus) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), start_ts, None);
+        let keys: Vec<Key> = keys.iter().map(|k| make_key(k)).collect();
+        assert_eq!(txn.check_secondary_locks(&keys).unwrap(), status);
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_acquire_pessimistic_lock(engine: &Engine,
+                                     key: &[u8],
+                                     pk: &[u8],
+                                     start_ts: u64,
+                                     for_update_ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), start_ts, None);
+        txn.acquire_pessimistic_lock(make_key(key), pk, 0, for_update_ts).unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_acquire_pessimistic_lock_err(engine: &Engine,
+                                         key: &[u8],
+                                         pk: &[u8],
+                                         start_ts: u64,
+                                         for_update_ts: u64) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), start_ts, None);
+        assert!(txn.acquire_pessimistic_lock(make_key(key), pk, 0, for_update_ts).is_err());
+    }
+
+    fn must_pessimistic_prewrite_put(engine: &Engine,
+                                     key: &[u8],
+                                     value: &[u8],
+                                     pk: &[u8],
+                                     start_ts: u64,
+                                     for_update_ts: u64,
+                                     is_pessimistic_lock: bool) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), start_ts, None);
+        txn.pessimistic_prewrite(Mutation::Put((make_key(key), value.to_vec())),
+                                  pk,
+                                  is_pessimistic_lock,
+                                  0,
+                                  for_update_ts)
+            .unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
+    fn must_pessimistic_prewrite_put_err(engine: &Engine,
+                                         key: &[u8],
+                                         value: &[u8],
+                                         pk: &[u8],
+                                         start_ts: u64,
+                                         for_update_ts: u64,
+                                         is_pessimistic_lock: bool) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), start_ts, None);
+        assert!(txn.pessimistic_prewrite(Mutation::Put((make_key(key), value.to_vec())),
+                                      pk,
+                                      is_pessimistic_lock,
+                                      0,
+                                      for_update_ts)
+            .is_err());
+    }
+
+    fn must_resolve_lock(engine: &Engine, key: &[u8], start_ts: u64, commit_ts: Option<u64>) {
+        let ctx = Context::new();
+        let snapshot = engine.snapshot(&ctx).unwrap();
+        let mut txn = MvccTxn::new(snapshot.as_ref(), start_ts, None);
+        txn.resolve_lock(&make_key(key), commit_ts).unwrap();
+        engine.write(&ctx, txn.modifies()).unwrap();
+    }
+
     fn must_commit(engine: &Engine, key: &[u8], start_ts: u64, commit_ts: u64) {
         let ctx = Context::new();
         let snapshot = engine.snapshot(&ctx).unwrap();
@@ -572,12 +1515,13 @@ mod tests {
         assert!(txn.rollback(&make_key(key)).is_err());
     }
 
-    fn must_gc(engine: &Engine, key: &[u8], safe_point: u64) {
+    fn must_gc(engine: &Engine, key: &[u8], safe_point: u64) -> bool {
         let ctx = Context::new();
         let snapshot = engine.snapshot(&ctx).unwrap();
         let mut txn = MvccTxn::new(snapshot.as_ref(), 0, None);
-        txn.gc(&make_key(key), safe_point).unwrap();
+        let has_newer = txn.gc(&make_key(key), safe_point).unwrap();
         engine.write(&ctx, txn.modifies()).unwrap();
+        has_newer
     }
 
     fn must_locked(engine: &Engine, key: &[u8], start_ts: u64) {
@@ -587,6 +1531,15 @@ mod tests {
         assert_eq!(lock.ts, start_ts);
     }
 
+    fn must_pessimistic_locked(engine: &Engine, key: &[u8], start_ts: u64, for_update_ts: u64) {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let mut reader = MvccReader::new(snapshot.as_ref(), None, true);
+        let lock = reader.load_lock(&make_key(key)).unwrap().unwrap();
+        assert_eq!(lock.ts, start_ts);
+        assert_eq!(lock.for_update_ts, for_update_ts);
+        assert_eq!(lock.lock_type, LockType::Pessimistic);
+    }
+
     fn must_unlocked(engine: &Engine, key: &[u8]) {
         let snapshot = engine.snapshot(&Context::new()).unwrap();
         let mut reader = MvccReader::new(snapshot.as_ref(), None, true);
@@ -602,6 +1555,12 @@ mod tests {
         assert_eq!(write.write_type, tp);
     }
 
+    fn must_data_written(engine: &Engine, key: &[u8], start_ts: u64, written: bool) {
+        let snapshot = engine.snapshot(&Context::new()).unwrap();
+        let value = snapshot.get(&make_key(key).append_ts(start_ts)).unwrap();
+        assert_eq!(value.is_some(), written);
+    }
+
     fn must_seek_write_none(engine: &Engine, key: &[u8], ts: u64) {
         let snapshot = engine.snapshot(&Context::new()).unwrap();
         let mut reader = MvccReader::new(snapshot.as_ref(), None, true);
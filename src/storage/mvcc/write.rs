@@ -12,6 +12,7 @@
 // limitations under the License.
 
 use byteorder::ReadBytesExt;
+use storage::Value;
 use util::codec::number::{NumberEncoder, NumberDecoder, MAX_VAR_U64_LEN};
 use super::lock::LockType;
 use super::{Error, Result};
@@ -29,12 +30,20 @@ const FLAG_DELETE: u8 = b'D';
 const FLAG_LOCK: u8 = b'L';
 const FLAG_ROLLBACK: u8 = b'R';
 
+// Optional fields are appended after start_ts, each one starts with a prefix byte, so writes
+// without them can still be parsed.
+// Followed by a length byte and the value, also used by locks.
+pub const SHORT_VALUE_PREFIX: u8 = b'v';
+
 impl WriteType {
-    pub fn from_lock_type(tp: LockType) -> WriteType {
+    /// Returns `None` for pessimistic locks, which must be prewritten before they can be
+    /// committed.
+    pub fn from_lock_type(tp: LockType) -> Option<WriteType> {
         match tp {
-            LockType::Put => WriteType::Put,
-            LockType::Delete => WriteType::Delete,
-            LockType::Lock => WriteType::Lock,
+            LockType::Put => Some(WriteType::Put),
+            LockType::Delete => Some(WriteType::Delete),
+            LockType::Lock => Some(WriteType::Lock),
+            LockType::Pessimistic => None,
         }
     }
 
@@ -62,6 +71,8 @@ impl WriteType {
 pub struct Write {
     pub write_type: WriteType,
     pub start_ts: u64,
+    // The value of a put is inlined here instead of written to CF_DEFAULT if it's short.
+    pub short_value: Option<Value>,
 }
 
 impl Write {
@@ -69,13 +80,22 @@ impl Write {
         Write {
             write_type: write_type,
             start_ts: start_ts,
+            short_value: None,
         }
     }
 
+    pub fn with_short_value(mut self, short_value: Option<Value>) -> Write {
+        self.short_value = short_value;
+        self
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut b = Vec::with_capacity(1 + MAX_VAR_U64_LEN);
         b.push(self.write_type.to_u8());
         b.encode_var_u64(self.start_ts).unwrap();
+        if let Some(ref v) = self.short_value {
+            write_short_value(&mut b, v);
+        }
         b
     }
 
@@ -85,6 +105,72 @@ impl Write {
         }
         let write_type = try!(WriteType::from_u8(try!(b.read_u8())).ok_or(Error::BadFormatWrite));
         let start_ts = try!(b.decode_var_u64());
-        Ok(Write::new(write_type, start_ts))
+        let mut write = Write::new(write_type, start_ts);
+        while !b.is_empty() {
+            match try!(b.read_u8()) {
+                SHORT_VALUE_PREFIX => {
+                    let v = try!(read_short_value(&mut b).ok_or(Error::BadFormatWrite));
+                    write.short_value = Some(v);
+                }
+                _ => return Err(Error::BadFormatWrite),
+            }
+        }
+        Ok(write)
+    }
+}
+
+/// Appends `SHORT_VALUE_PREFIX` and the short value `v` prefixed with its length.
+pub fn write_short_value(b: &mut Vec<u8>, v: &[u8]) {
+    b.push(SHORT_VALUE_PREFIX);
+    b.push(v.len() as u8);
+    b.extend_from_slice(v);
+}
+
+/// Reads a short value prefixed with its length, returns `None` if it's truncated.
+pub fn read_short_value(b: &mut &[u8]) -> Option<Value> {
+    if b.is_empty() {
+        return None;
+    }
+    let len = b[0] as usize;
+    if b.len() < len + 1 {
+        return None;
+    }
+    let v = b[1..len + 1].to_vec();
+    *b = &b[len + 1..];
+    Some(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::codec::number::NumberEncoder;
+
+    #[test]
+    fn test_write() {
+        let writes = vec![Write::new(WriteType::Put, 1),
+                          Write::new(WriteType::Put, 1).with_short_value(Some(b"v".to_vec())),
+                          Write::new(WriteType::Put, 1).with_short_value(Some(vec![])),
+                          Write::new(WriteType::Delete, 2),
+                          Write::new(WriteType::Rollback, 3)];
+        for write in writes {
+            let v = write.to_bytes();
+            assert_eq!(Write::parse(&v).unwrap(), write);
+        }
+
+        // Writes written without short values.
+        let mut v = vec![b'P'];
+        v.encode_var_u64(1).unwrap();
+        assert_eq!(Write::parse(&v).unwrap(), Write::new(WriteType::Put, 1));
+
+        // Truncated short value.
+        let mut truncated = v.clone();
+        truncated.extend_from_slice(&[b'v', 2, b'x']);
+        assert!(Write::parse(&truncated).is_err());
+        truncated.truncate(v.len() + 1);
+        assert!(Write::parse(&truncated).is_err());
+
+        // Unknown field.
+        v.push(b'x');
+        assert!(Write::parse(&v).is_err());
     }
 }
@@ -0,0 +1,163 @@
+// Copyright 2016 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Concurrency control between the reads and the transactions which decide their commit ts
+//! in the store, i.e. async commit and one-phase commit transactions.
+//!
+//! Such a transaction must commit after every read which has missed its locks. Reads update
+//! `max_read_ts` and check the memory locks before taking snapshots, while the transactions
+//! add memory locks for their keys before getting `max_read_ts`, and keep them until their
+//! locks or writes are applied. So a read either meets the memory lock, or has its ts counted
+//! in `max_read_ts`.
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use storage::Key;
+use storage::mvcc::{Error as MvccError, Result as MvccResult};
+
+/// Lock of a key being written by a transaction, which isn't in the engine yet.
+#[derive(Debug, Clone)]
+pub struct MemoryLock {
+    pub primary: Vec<u8>,
+    pub ts: u64,
+    pub ttl: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    max_read_ts: u64,
+    // encoded key -> lock
+    locks: BTreeMap<Vec<u8>, MemoryLock>,
+}
+
+#[derive(Clone, Default)]
+pub struct ConcurrencyManager {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl ConcurrencyManager {
+    pub fn new() -> ConcurrencyManager {
+        ConcurrencyManager::default()
+    }
+
+    pub fn max_read_ts(&self) -> u64 {
+        self.inner.lock().unwrap().max_read_ts
+    }
+
+    /// Adds the memory locks of `keys` and returns the current `max_read_ts`, the transaction
+    /// must commit after it.
+    pub fn lock_keys(&self, keys: &[Key], lock: MemoryLock) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        for key in keys {
+            inner.locks.insert(key.encoded().to_owned(), lock.clone());
+        }
+        inner.max_read_ts
+    }
+
+    pub fn unlock_keys(&self, keys: &[Key]) {
+        let mut inner = self.inner.lock().unwrap();
+        for key in keys {
+            inner.locks.remove(key.encoded());
+        }
+    }
+
+    /// Updates `max_read_ts` with a read at `ts` of `keys`, fails if any of them has a memory
+    /// lock not after `ts`. Locks of the transactions in `bypass_locks` are ignored.
+    pub fn read_keys(&self, keys: &[Key], ts: u64, bypass_locks: &[u64]) -> MvccResult<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.max_read_ts = cmp::max(inner.max_read_ts, ts);
+        for key in keys {
+            if let Some(lock) = inner.locks.get(key.encoded()) {
+                try!(check_lock(key.encoded(), lock, ts, bypass_locks));
+            }
+        }
+        Ok(())
+    }
+
+    /// Same as `read_keys` but for a read of the keys in [`start`, `end`), `None` means
+    /// unbounded.
+    pub fn read_range(&self,
+                      start: Option<&Key>,
+                      end: Option<&Key>,
+                      ts: u64,
+                      bypass_locks: &[u64])
+                      -> MvccResult<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.max_read_ts = cmp::max(inner.max_read_ts, ts);
+        for (key, lock) in &inner.locks {
+            if start.map_or(false, |k| key < k.encoded()) {
+                continue;
+            }
+            if end.map_or(false, |k| key >= k.encoded()) {
+                break;
+            }
+            try!(check_lock(key, lock, ts, bypass_locks));
+        }
+        Ok(())
+    }
+}
+
+fn check_lock(key: &[u8], lock: &MemoryLock, ts: u64, bypass_locks: &[u64]) -> MvccResult<()> {
+    if lock.ts > ts || bypass_locks.contains(&lock.ts) {
+        return Ok(());
+    }
+    Err(MvccError::KeyIsLocked {
+        key: try!(Key::from_encoded(key.to_vec()).raw()),
+        primary: lock.primary.clone(),
+        ts: lock.ts,
+        ttl: lock.ttl,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use storage::make_key;
+    use storage::mvcc::Error as MvccError;
+    use super::*;
+
+    fn new_lock(ts: u64) -> MemoryLock {
+        MemoryLock {
+            primary: b"p".to_vec(),
+            ts: ts,
+            ttl: 0,
+        }
+    }
+
+    #[test]
+    fn test_concurrency_manager() {
+        let cm = ConcurrencyManager::new();
+        let (k1, k2, k3) = (make_key(b"k1"), make_key(b"k2"), make_key(b"k3"));
+        cm.read_keys(&[k1.clone()], 10, &[]).unwrap();
+        assert_eq!(cm.lock_keys(&[k2.clone()], new_lock(20)), 10);
+
+        // Reads before the lock ts don't meet the lock.
+        cm.read_keys(&[k2.clone()], 15, &[]).unwrap();
+        cm.read_range(Some(&k1), None, 15, &[]).unwrap();
+        match cm.read_keys(&[k1.clone(), k2.clone()], 25, &[]) {
+            Err(MvccError::KeyIsLocked { ref key, ts: 20, .. }) => assert_eq!(key, b"k2"),
+            r => panic!("expect key is locked, got {:?}", r),
+        }
+        assert!(cm.read_range(Some(&k1), Some(&k3), 25, &[]).is_err());
+        assert!(cm.read_range(None, None, 25, &[]).is_err());
+        cm.read_range(Some(&k3), None, 25, &[]).unwrap();
+        cm.read_range(None, Some(&k2), 25, &[]).unwrap();
+        cm.read_keys(&[k2.clone()], 30, &[20]).unwrap();
+        assert_eq!(cm.max_read_ts(), 30);
+
+        cm.unlock_keys(&[k2.clone()]);
+        cm.read_range(None, None, 40, &[]).unwrap();
+        assert_eq!(cm.lock_keys(&[k3], new_lock(50)), 40);
+    }
+}
@@ -95,6 +95,12 @@ impl Latches {
         Lock::new(slots)
     }
 
+    /// Creates a lock which requires all the latches, for a command whose keys are unknown,
+    /// e.g. one writing a whole range.
+    pub fn gen_lock_all(&self) -> Lock {
+        Lock::new((0..self.size).collect())
+    }
+
     /// Tries to acquire the latches specified by the `lock` for command with ID `who`.
     ///
     /// This method will enqueue the command ID into the waiting queues of the latches. A latch is
@@ -185,6 +191,24 @@ mod tests {
         assert_eq!(acquired_b, true);
     }
 
+    #[test]
+    fn test_lock_all() {
+        let mut latches = Latches::new(4);
+        let mut lock_a = latches.gen_lock(&[b"a"]);
+        let mut lock_all = latches.gen_lock_all();
+        assert_eq!(lock_all.required_slots, vec![0, 1, 2, 3]);
+        assert!(latches.acquire(&mut lock_a, 1));
+        assert!(!latches.acquire(&mut lock_all, 2));
+
+        // Waits for any other lock.
+        assert_eq!(latches.release(&lock_a, 1), vec![2]);
+        assert!(latches.acquire(&mut lock_all, 2));
+        let mut lock_b = latches.gen_lock(&[b"b"]);
+        assert!(!latches.acquire(&mut lock_b, 3));
+        assert_eq!(latches.release(&lock_all, 2), vec![3]);
+        assert!(latches.acquire(&mut lock_b, 3));
+    }
+
     #[test]
     fn test_wakeup_by_multi_cmds() {
         let mut latches = Latches::new(256);
@@ -0,0 +1,148 @@
+// Copyright 2016 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{HashMap, VecDeque};
+
+use storage::Key;
+
+/// Table of the commands waiting for the locks of other transactions to be released.
+///
+/// Waiters are indexed by an ID and queued by the encoded key they wait for. When the lock on a
+/// key is released, all the waiters of the key are woken up in FIFO order.
+pub struct WaitTable<T> {
+    // key -> waiter IDs in FIFO order
+    queues: HashMap<Vec<u8>, VecDeque<u64>>,
+    // waiter ID -> (key, waiter)
+    waiters: HashMap<u64, (Vec<u8>, T)>,
+}
+
+impl<T> WaitTable<T> {
+    pub fn new() -> WaitTable<T> {
+        WaitTable {
+            queues: HashMap::new(),
+            waiters: HashMap::new(),
+        }
+    }
+
+    /// Adds a waiter with ID `id` to the tail of the waiting queue of `key`.
+    pub fn add_waiter(&mut self, id: u64, key: &Key, waiter: T) {
+        let key = key.encoded().to_owned();
+        self.queues.entry(key.clone()).or_insert_with(VecDeque::new).push_back(id);
+        if self.waiters.insert(id, (key, waiter)).is_some() {
+            panic!("waiter {} shouldn't exist", id);
+        }
+    }
+
+    /// Removes the waiter with ID `id`, returns `None` if it has been woken up already.
+    pub fn remove_waiter(&mut self, id: u64) -> Option<T> {
+        let (key, waiter) = match self.waiters.remove(&id) {
+            Some(w) => w,
+            None => return None,
+        };
+        let empty = {
+            let queue = self.queues.get_mut(&key).unwrap();
+            queue.retain(|x| *x != id);
+            queue.is_empty()
+        };
+        if empty {
+            self.queues.remove(&key);
+        }
+        Some(waiter)
+    }
+
+    /// Removes and returns all the waiters of `key` in FIFO order.
+    pub fn wake_up(&mut self, key: &Key) -> Vec<T> {
+        let queue = match self.queues.remove(key.encoded()) {
+            Some(q) => q,
+            None => return vec![],
+        };
+        queue.into_iter().map(|id| self.waiters.remove(&id).unwrap().1).collect()
+    }
+
+    /// Removes and returns all the waiters of the keys in [`start`, `end`), in the order of the
+    /// keys and FIFO order for each key.
+    pub fn wake_up_range(&mut self, start: &Key, end: &Key) -> Vec<T> {
+        let mut keys: Vec<Vec<u8>> = self.queues
+            .keys()
+            .filter(|k| k.as_slice() >= start.encoded().as_slice() &&
+                        k.as_slice() < end.encoded().as_slice())
+            .cloned()
+            .collect();
+        keys.sort();
+        let mut waiters = vec![];
+        for key in keys {
+            for id in self.queues.remove(&key).unwrap() {
+                waiters.push(self.waiters.remove(&id).unwrap().1);
+            }
+        }
+        waiters
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.waiters.contains_key(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.waiters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiters.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WaitTable;
+    use storage::make_key;
+
+    #[test]
+    fn test_wait_table() {
+        let mut table = WaitTable::new();
+        let (k1, k2) = (make_key(b"k1"), make_key(b"k2"));
+        table.add_waiter(1, &k1, "a");
+        table.add_waiter(2, &k2, "b");
+        table.add_waiter(3, &k1, "c");
+        table.add_waiter(4, &k1, "d");
+        assert_eq!(table.len(), 4);
+
+        // Timeout.
+        assert_eq!(table.remove_waiter(3), Some("c"));
+        assert_eq!(table.remove_waiter(3), None);
+
+        // Woken up in FIFO order.
+        assert_eq!(table.wake_up(&k1), vec!["a", "d"]);
+        assert!(table.wake_up(&k1).is_empty());
+        assert_eq!(table.remove_waiter(1), None);
+
+        assert_eq!(table.remove_waiter(2), Some("b"));
+        assert!(table.wake_up(&k2).is_empty());
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn test_wait_table_wake_up_range() {
+        let mut table = WaitTable::new();
+        let (k1, k2, k3) = (make_key(b"k1"), make_key(b"k2"), make_key(b"k3"));
+        table.add_waiter(1, &k3, "a");
+        table.add_waiter(2, &k2, "b");
+        table.add_waiter(3, &k1, "c");
+        table.add_waiter(4, &k2, "d");
+        assert!(table.contains(4));
+
+        assert_eq!(table.wake_up_range(&make_key(b"k2"), &make_key(b"k3")), vec!["b", "d"]);
+        assert!(!table.contains(4));
+        assert_eq!(table.wake_up_range(&make_key(b""), &make_key(b"k4")), vec!["c", "a"]);
+        assert!(table.is_empty());
+    }
+}
@@ -14,12 +14,17 @@
 mod store;
 mod scheduler;
 mod latch;
+mod lock_wait;
+mod read_pool;
+mod concurrency;
 
 use std::error;
 use std::io::Error as IoError;
 
 pub use self::scheduler::{Scheduler, Msg, GC_BATCH_SIZE, RESOLVE_LOCK_BATCH_SIZE};
 pub use self::store::SnapshotStore;
+pub use self::read_pool::ReadPool;
+pub use self::concurrency::{ConcurrencyManager, MemoryLock};
 
 quick_error! {
     #[derive(Debug)]
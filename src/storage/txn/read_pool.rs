@@ -0,0 +1,210 @@
+// Copyright 2016 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Thread pool for the reads which need no latches, i.e. `Get`, `BatchGet` and `Scan`.
+//!
+//! The reads take snapshots from the engine directly instead of going through the scheduler
+//! event loop, and are limited on their own, so read spikes and write spikes don't slow down
+//! each other.
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use threadpool::ThreadPool;
+use kvproto::kvrpcpb::{Context, IsolationLevel, CommandPri};
+use storage::{Engine, Command, Snapshot, StorageCb, ScanMode, Error as StorageError};
+use storage::engine::Result as EngineResult;
+use storage::mvcc::Result as MvccResult;
+use super::Error;
+use super::scheduler::{ProcessResult, execute_callback, extract_ctx};
+use super::store::SnapshotStore;
+use super::concurrency::ConcurrencyManager;
+use super::super::metrics::*;
+
+#[derive(Clone)]
+pub struct ReadPool {
+    pool: Arc<Mutex<ThreadPool>>,
+    // Number of the reads taking snapshots or being processed.
+    running: Arc<AtomicUsize>,
+    too_busy_threshold: usize,
+    concurrency_manager: ConcurrencyManager,
+}
+
+impl ReadPool {
+    pub fn new(pool_size: usize,
+               too_busy_threshold: usize,
+               concurrency_manager: ConcurrencyManager)
+               -> ReadPool {
+        ReadPool {
+            pool: Arc::new(Mutex::new(ThreadPool::new_with_name(thd_name!("storage-read-pool"),
+                                                                pool_size))),
+            running: Arc::new(AtomicUsize::new(0)),
+            too_busy_threshold: too_busy_threshold,
+            concurrency_manager: concurrency_manager,
+        }
+    }
+
+    /// Returns whether the pool is too busy to accept a new read of `priority`, like the
+    /// scheduler does for writes. Low priority reads are rejected at half of the threshold,
+    /// and high priority reads are still accepted until twice of it.
+    fn too_busy(&self, priority: CommandPri) -> bool {
+        let threshold = match priority {
+            CommandPri::High => self.too_busy_threshold.saturating_mul(2),
+            CommandPri::Normal => self.too_busy_threshold,
+            CommandPri::Low => self.too_busy_threshold / 2,
+        };
+        self.running.load(Ordering::SeqCst) >= threshold
+    }
+
+    /// Takes a snapshot from `engine` and executes `cmd` on it in the pool. The callback gets
+    /// `ReadPoolTooBusy` if too many reads are running.
+    pub fn execute(&self, engine: &Engine, cmd: Command, cb: StorageCb) -> EngineResult<()> {
+        let tag = cmd.tag();
+        if self.too_busy(extract_ctx(&cmd).get_priority()) {
+            READ_POOL_STAGE_COUNTER_VEC.with_label_values(&[tag, "too_busy"]).inc();
+            execute_callback(cb, ProcessResult::Failed { err: StorageError::ReadPoolTooBusy });
+            return Ok(());
+        }
+        // Must be done before taking the snapshot.
+        if let Err(e) = read_memory_locks(&self.concurrency_manager, &cmd) {
+            READ_POOL_STAGE_COUNTER_VEC.with_label_values(&[tag, "memory_locked"]).inc();
+            let err = StorageError::from(Error::from(e));
+            execute_callback(cb, ProcessResult::Failed { err: err });
+            return Ok(());
+        }
+        READ_POOL_STAGE_COUNTER_VEC.with_label_values(&[tag, "new"]).inc();
+        READ_POOL_RUNNING_GAUGE.set(self.running.fetch_add(1, Ordering::SeqCst) as f64 + 1.0);
+
+        let ctx = extract_ctx(&cmd).clone();
+        let (pool, running) = (self.pool.clone(), self.running.clone());
+        let on_snapshot = box move |snapshot: EngineResult<Box<Snapshot>>| {
+            pool.lock().unwrap().execute(move || {
+                let pr = match snapshot {
+                    Ok(snapshot) => process_read(cmd, snapshot.as_ref()),
+                    Err(e) => {
+                        READ_POOL_STAGE_COUNTER_VEC.with_label_values(&[tag, "snapshot_err"])
+                            .inc();
+                        ProcessResult::Failed { err: e.into() }
+                    }
+                };
+                execute_callback(cb, pr);
+                READ_POOL_RUNNING_GAUGE.set(running.fetch_sub(1, Ordering::SeqCst) as f64 - 1.0);
+            });
+        };
+        if let Err(e) = engine.async_snapshot(&ctx, on_snapshot) {
+            READ_POOL_RUNNING_GAUGE.set(self.running.fetch_sub(1, Ordering::SeqCst) as f64 - 1.0);
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+/// Updates `max_read_ts` with the read and checks the memory locks of the keys to read, see
+/// `ConcurrencyManager`. Reads at read committed ignore locks, so they are skipped.
+fn read_memory_locks(cm: &ConcurrencyManager, cmd: &Command) -> MvccResult<()> {
+    let ctx = extract_ctx(cmd);
+    if ctx.get_isolation_level() == IsolationLevel::RC {
+        return Ok(());
+    }
+    let bypass_locks = ctx.get_resolved_locks();
+    match *cmd {
+        Command::Get { ref key, start_ts, .. } => {
+            cm.read_keys(&[key.clone()], start_ts, bypass_locks)
+        }
+        Command::BatchGet { ref keys, start_ts, .. } => cm.read_keys(keys, start_ts, bypass_locks),
+        Command::Scan { ref start_key, ref end_key, reverse, start_ts, .. } => {
+            if reverse {
+                cm.read_range(end_key.as_ref(), Some(start_key), start_ts, bypass_locks)
+            } else {
+                cm.read_range(Some(start_key), end_key.as_ref(), start_ts, bypass_locks)
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+fn new_snapshot_store<'a>(snapshot: &'a Snapshot,
+                          start_ts: u64,
+                          ctx: &Context)
+                          -> SnapshotStore<'a> {
+    SnapshotStore::new(snapshot,
+                       start_ts,
+                       ctx.get_isolation_level(),
+                       ctx.get_resolved_locks().to_vec())
+}
+
+fn process_read(cmd: Command, snapshot: &Snapshot) -> ProcessResult {
+    let _timer = READ_POOL_HISTOGRAM_VEC.with_label_values(&[cmd.tag()]).start_timer();
+    match cmd {
+        // Gets from the snapshot.
+        Command::Get { ref ctx, ref key, start_ts, .. } => {
+            let snap_store = new_snapshot_store(snapshot, start_ts, ctx);
+            let res = snap_store.get(key);
+            match res {
+                Ok(val) => ProcessResult::Value { value: val },
+                Err(e) => ProcessResult::Failed { err: StorageError::from(e) },
+            }
+        }
+        // Batch gets from the snapshot.
+        Command::BatchGet { ref ctx, ref keys, start_ts, .. } => {
+            let snap_store = new_snapshot_store(snapshot, start_ts, ctx);
+            match snap_store.batch_get(keys) {
+                Ok(results) => {
+                    let mut res = vec![];
+                    for (k, v) in keys.into_iter().zip(results) {
+                        match v {
+                            Ok(Some(x)) => res.push(Ok((k.raw().unwrap(), x))),
+                            Ok(None) => {}
+                            Err(e) => res.push(Err(StorageError::from(e))),
+                        }
+                    }
+                    ProcessResult::MultiKvpairs { pairs: res }
+                }
+                Err(e) => ProcessResult::Failed { err: StorageError::from(e) },
+            }
+        }
+        // Scans a range starting with `start_key` up to `limit` rows from the snapshot.
+        Command::Scan { ref ctx,
+                        ref start_key,
+                        ref end_key,
+                        limit,
+                        limit_bytes,
+                        key_only,
+                        reverse,
+                        start_ts,
+                        .. } => {
+            let snap_store = new_snapshot_store(snapshot, start_ts, ctx);
+            let mode = if reverse {
+                ScanMode::Backward
+            } else {
+                ScanMode::Forward
+            };
+            let res = snap_store.scanner(mode, key_only)
+                .and_then(|mut scanner| {
+                    let end_key = end_key.as_ref();
+                    if reverse {
+                        scanner.reverse_scan(start_key.clone(), end_key, limit, limit_bytes)
+                    } else {
+                        scanner.scan(start_key.clone(), end_key, limit, limit_bytes)
+                    }
+                })
+                .and_then(|mut results| {
+                    Ok(results.drain(..).map(|x| x.map_err(StorageError::from)).collect())
+                });
+            match res {
+                Ok(pairs) => ProcessResult::MultiKvpairs { pairs: pairs },
+                Err(e) => ProcessResult::Failed { err: e.into() },
+            }
+        }
+        _ => panic!("unsupported command {} in read pool", cmd),
+    }
+}
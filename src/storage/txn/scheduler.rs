@@ -30,24 +30,35 @@
 //! multiple commands, therefore conflicts may happen at transaction level. Transaction semantics
 //! is ensured by the transaction protocol implemented in the client library, which is transparent
 //! to the scheduler.
+//!
+//! If lock waiting is enabled, a prewrite which meets a lock of another transaction is parked in a
+//! wait table instead of returning `KeyIsLocked` at once. It is rescheduled when a commit,
+//! rollback, cleanup or resolve lock command on the key finishes, or when the wait times out.
 
 use std::boxed::Box;
+use std::cmp;
 use std::fmt::{self, Formatter, Debug};
+use std::time::{Duration, Instant};
 use threadpool::ThreadPool;
 use prometheus::HistogramTimer;
 use storage::{Engine, Command, Snapshot, StorageCb, Result as StorageResult,
               Error as StorageError, ScanMode};
-use kvproto::kvrpcpb::{Context, LockInfo};
-use storage::mvcc::{MvccTxn, MvccReader, Error as MvccError, MAX_TXN_WRITE_SIZE};
-use storage::{Key, Value, KvPair};
+use kvproto::kvrpcpb::{Context, LockInfo, CommandPri};
+use storage::mvcc::{MvccTxn, MvccReader, MvccInfo, TxnStatus, SecondaryLocksStatus,
+                    Error as MvccError, MAX_TXN_WRITE_SIZE};
+use storage::{Key, Value, KvPair, Mutation, CF_LOCK, CF_RAW};
 use std::collections::HashMap;
 use mio::{self, EventLoop};
 use util::transport::SendCh;
+use util::escape;
+use util::worker::Scheduler as WorkerScheduler;
+use server::deadlock::Task as DetectTask;
 use storage::engine::{Result as EngineResult, Callback as EngineCallback, Modify};
 use super::Result;
 use super::Error;
-use super::store::SnapshotStore;
 use super::latch::{Latches, Lock};
+use super::lock_wait::WaitTable;
+use super::concurrency::{ConcurrencyManager, MemoryLock};
 use super::super::metrics::*;
 
 // TODO: make it configurable.
@@ -61,7 +72,24 @@ pub enum ProcessResult {
     MultiRes { results: Vec<StorageResult<()>> },
     MultiKvpairs { pairs: Vec<StorageResult<KvPair>> },
     Value { value: Option<Value> },
-    Locks { locks: Vec<LockInfo> },
+    Locks {
+        locks: Vec<LockInfo>,
+        next_start: Option<Vec<u8>>,
+    },
+    OnePcRes {
+        results: Vec<StorageResult<()>>,
+        commit_ts: u64,
+    },
+    AsyncCommitPrewriteRes {
+        results: Vec<StorageResult<()>>,
+        min_commit_ts: u64,
+    },
+    TxnStatus { txn_status: TxnStatus },
+    SecondaryLocksStatus { status: SecondaryLocksStatus },
+    LockTtl { ttl: u64 },
+    MvccKey { mvcc: MvccInfo },
+    MvccStartTs { mvcc: Option<(Key, MvccInfo)> },
+    GcRes { has_newer: bool },
     NextCommand { cmd: Command },
     Failed { err: StorageError },
 }
@@ -80,8 +108,24 @@ pub enum Msg {
         cmd: Command,
         pr: ProcessResult,
         to_be_write: Vec<Modify>,
+        write_size: usize,
     },
     WritePrepareFailed { cid: u64, err: Error },
+    WaitForLock {
+        cid: u64,
+        cmd: Command,
+        start_ts: u64,
+        key: Vec<u8>,
+        lock_ts: u64,
+    },
+    Deadlock { cid: u64 },
+    RecheckLock {
+        cid: u64,
+        key: Key,
+        lock_ts: u64,
+        snapshot: EngineResult<Box<Snapshot>>,
+    },
+    LockReleased { cid: u64 },
     WriteFinished {
         cid: u64,
         pr: ProcessResult,
@@ -103,13 +147,25 @@ impl Debug for Msg {
             Msg::WritePrepareFailed { cid, ref err } => {
                 write!(f, "WritePrepareFailed [cid={}, err={:?}]", cid, err)
             }
+            Msg::WaitForLock { cid, ref key, lock_ts, .. } => {
+                write!(f,
+                       "WaitForLock [cid={}, key={}, lock_ts={}]",
+                       cid,
+                       escape(key),
+                       lock_ts)
+            }
+            Msg::Deadlock { cid } => write!(f, "Deadlock [cid={}]", cid),
+            Msg::RecheckLock { cid, ref key, lock_ts, .. } => {
+                write!(f, "RecheckLock [cid={}, key={}, lock_ts={}]", cid, key, lock_ts)
+            }
+            Msg::LockReleased { cid } => write!(f, "LockReleased [cid={}]", cid),
             Msg::WriteFinished { cid, .. } => write!(f, "WriteFinished [cid={}]", cid),
         }
     }
 }
 
 /// Delivers the process result of a command to the storage callback.
-fn execute_callback(callback: StorageCb, pr: ProcessResult) {
+pub fn execute_callback(callback: StorageCb, pr: ProcessResult) {
     match callback {
         StorageCb::Boolean(cb) => {
             match pr {
@@ -141,7 +197,67 @@ fn execute_callback(callback: StorageCb, pr: ProcessResult) {
         }
         StorageCb::Locks(cb) => {
             match pr {
-                ProcessResult::Locks { locks } => cb(Ok(locks)),
+                ProcessResult::Locks { locks, next_start } => cb(Ok((locks, next_start))),
+                ProcessResult::Failed { err } => cb(Err(err)),
+                _ => panic!("process result mismatch"),
+            }
+        }
+        StorageCb::OnePc(cb) => {
+            match pr {
+                ProcessResult::OnePcRes { results, commit_ts } => cb(Ok((results, commit_ts))),
+                // Fell back to two-phase commit.
+                ProcessResult::MultiRes { results } => cb(Ok((results, 0))),
+                ProcessResult::Failed { err } => cb(Err(err)),
+                _ => panic!("process result mismatch"),
+            }
+        }
+        StorageCb::AsyncCommitPrewrite(cb) => {
+            match pr {
+                ProcessResult::AsyncCommitPrewriteRes { results, min_commit_ts } => {
+                    cb(Ok((results, min_commit_ts)))
+                }
+                ProcessResult::Failed { err } => cb(Err(err)),
+                _ => panic!("process result mismatch"),
+            }
+        }
+        StorageCb::SecondaryLocksStatus(cb) => {
+            match pr {
+                ProcessResult::SecondaryLocksStatus { status } => cb(Ok(status)),
+                ProcessResult::Failed { err } => cb(Err(err)),
+                _ => panic!("process result mismatch"),
+            }
+        }
+        StorageCb::TxnStatus(cb) => {
+            match pr {
+                ProcessResult::TxnStatus { txn_status } => cb(Ok(txn_status)),
+                ProcessResult::Failed { err } => cb(Err(err)),
+                _ => panic!("process result mismatch"),
+            }
+        }
+        StorageCb::LockTtl(cb) => {
+            match pr {
+                ProcessResult::LockTtl { ttl } => cb(Ok(ttl)),
+                ProcessResult::Failed { err } => cb(Err(err)),
+                _ => panic!("process result mismatch"),
+            }
+        }
+        StorageCb::Gc(cb) => {
+            match pr {
+                ProcessResult::GcRes { has_newer } => cb(Ok(has_newer)),
+                ProcessResult::Failed { err } => cb(Err(err)),
+                _ => panic!("process result mismatch"),
+            }
+        }
+        StorageCb::MvccInfoByKey(cb) => {
+            match pr {
+                ProcessResult::MvccKey { mvcc } => cb(Ok(mvcc)),
+                ProcessResult::Failed { err } => cb(Err(err)),
+                _ => panic!("process result mismatch"),
+            }
+        }
+        StorageCb::MvccInfoByStartTs(cb) => {
+            match pr {
+                ProcessResult::MvccStartTs { mvcc } => cb(Ok(mvcc)),
                 ProcessResult::Failed { err } => cb(Err(err)),
                 _ => panic!("process result mismatch"),
             }
@@ -156,13 +272,28 @@ pub struct RunningCtx {
     lock: Lock,
     callback: Option<StorageCb>,
     tag: &'static str,
+    // until when the command can wait for the locks of other transactions, `None` if it can't
+    lock_wait_deadline: Option<Instant>,
+    // keys whose locks are released once the command is written
+    released_keys: Vec<Key>,
+    // range whose locks are all released once the command is written
+    released_range: Option<(Key, Key)>,
+    // keys with memory locks in the concurrency manager, unlocked when the command finishes
+    memory_locked_keys: Vec<Key>,
+    // bytes being written to the engine, counted in the pending write bytes of the scheduler
+    write_bytes: usize,
     latch_timer: Option<HistogramTimer>,
     _timer: HistogramTimer,
 }
 
 impl RunningCtx {
     /// Creates a context for a running command.
-    pub fn new(cid: u64, cmd: Command, lock: Lock, cb: StorageCb) -> RunningCtx {
+    pub fn new(cid: u64,
+               cmd: Command,
+               lock: Lock,
+               cb: StorageCb,
+               lock_wait_deadline: Option<Instant>)
+               -> RunningCtx {
         let tag = cmd.tag();
         RunningCtx {
             cid: cid,
@@ -170,12 +301,31 @@ impl RunningCtx {
             lock: lock,
             callback: Some(cb),
             tag: tag,
+            lock_wait_deadline: lock_wait_deadline,
+            released_keys: vec![],
+            released_range: None,
+            memory_locked_keys: vec![],
+            write_bytes: 0,
             latch_timer: Some(SCHED_LATCH_HISTOGRAM_VEC.with_label_values(&[tag]).start_timer()),
             _timer: SCHED_HISTOGRAM_VEC.with_label_values(&[tag]).start_timer(),
         }
     }
 }
 
+/// A command waiting for the lock of another transaction to be released.
+struct Waiter {
+    cid: u64,
+    cmd: Command,
+    callback: StorageCb,
+    start_ts: u64,
+    // raw key of the lock
+    key: Vec<u8>,
+    lock_ts: u64,
+    // the deadline of the wait, it's carried over when the command is woken up
+    deadline: Instant,
+    _timer: HistogramTimer,
+}
+
 /// Creates a callback to receive async results of write prepare from the storage engine.
 fn make_engine_cb(cid: u64, pr: ProcessResult, ch: SendCh<Msg>) -> EngineCallback<()> {
     Box::new(move |result: EngineResult<()>| {
@@ -207,9 +357,22 @@ pub struct Scheduler {
     latches: Latches,
 
     sched_too_busy_threshold: usize,
+    // total write bytes of the commands being written to the engine, and its limit
+    pending_write_bytes: usize,
+    sched_pending_write_threshold: usize,
 
-    // worker pool
+    // worker pools, one for each priority so low priority commands can't occupy the workers
+    // of the others
     worker_pool: ThreadPool,
+    high_priority_pool: ThreadPool,
+    low_priority_pool: ThreadPool,
+
+    // lock wait timeout in milliseconds, 0 means disabled
+    lock_wait_timeout: u64,
+    // commands waiting for locks, indexed by the cid they had when they started waiting
+    wait_table: WaitTable<Waiter>,
+    deadlock_detector: Option<WorkerScheduler<DetectTask>>,
+    concurrency_manager: ConcurrencyManager,
 }
 
 impl Scheduler {
@@ -218,7 +381,13 @@ impl Scheduler {
                schedch: SendCh<Msg>,
                concurrency: usize,
                worker_pool_size: usize,
-               sched_too_busy_threshold: usize)
+               high_priority_pool_size: usize,
+               low_priority_pool_size: usize,
+               sched_too_busy_threshold: usize,
+               sched_pending_write_threshold: usize,
+               lock_wait_timeout: u64,
+               deadlock_detector: Option<WorkerScheduler<DetectTask>>,
+               concurrency_manager: ConcurrencyManager)
                -> Scheduler {
         Scheduler {
             engine: engine,
@@ -227,8 +396,18 @@ impl Scheduler {
             id_alloc: 0,
             latches: Latches::new(concurrency),
             sched_too_busy_threshold: sched_too_busy_threshold,
+            pending_write_bytes: 0,
+            sched_pending_write_threshold: sched_pending_write_threshold,
             worker_pool: ThreadPool::new_with_name(thd_name!("sched-worker-pool"),
                                                    worker_pool_size),
+            high_priority_pool: ThreadPool::new_with_name(thd_name!("sched-high-pri-pool"),
+                                                          high_priority_pool_size),
+            low_priority_pool: ThreadPool::new_with_name(thd_name!("sched-low-pri-pool"),
+                                                         low_priority_pool_size),
+            lock_wait_timeout: lock_wait_timeout,
+            wait_table: WaitTable::new(),
+            deadlock_detector: deadlock_detector,
+            concurrency_manager: concurrency_manager,
         }
     }
 }
@@ -240,52 +419,16 @@ fn process_read(cid: u64, mut cmd: Command, ch: SendCh<Msg>, snapshot: Box<Snaps
     SCHED_WORKER_COUNTER_VEC.with_label_values(&[cmd.tag(), "read"]).inc();
 
     let pr = match cmd {
-        // Gets from the snapshot.
-        Command::Get { ref key, start_ts, .. } => {
-            let snap_store = SnapshotStore::new(snapshot.as_ref(), start_ts);
-            let res = snap_store.get(key);
-            match res {
-                Ok(val) => ProcessResult::Value { value: val },
-                Err(e) => ProcessResult::Failed { err: StorageError::from(e) },
-            }
-        }
-        // Batch gets from the snapshot.
-        Command::BatchGet { ref keys, start_ts, .. } => {
-            let snap_store = SnapshotStore::new(snapshot.as_ref(), start_ts);
-            match snap_store.batch_get(keys) {
-                Ok(results) => {
-                    let mut res = vec![];
-                    for (k, v) in keys.into_iter().zip(results) {
-                        match v {
-                            Ok(Some(x)) => res.push(Ok((k.raw().unwrap(), x))),
-                            Ok(None) => {}
-                            Err(e) => res.push(Err(StorageError::from(e))),
-                        }
-                    }
-                    ProcessResult::MultiKvpairs { pairs: res }
-                }
-                Err(e) => ProcessResult::Failed { err: StorageError::from(e) },
-            }
-        }
-        // Scans a range starting with `start_key` up to `limit` rows from the snapshot.
-        Command::Scan { ref start_key, limit, key_only, start_ts, .. } => {
-            let snap_store = SnapshotStore::new(snapshot.as_ref(), start_ts);
-            let res = snap_store.scanner(ScanMode::Forward, key_only)
-                .and_then(|mut scanner| scanner.scan(start_key.clone(), limit))
-                .and_then(|mut results| {
-                    Ok(results.drain(..).map(|x| x.map_err(StorageError::from)).collect())
-                });
-            match res {
-                Ok(pairs) => ProcessResult::MultiKvpairs { pairs: pairs },
-                Err(e) => ProcessResult::Failed { err: e.into() },
-            }
-        }
-        // Scans locks with timestamp <= `max_ts`
-        Command::ScanLock { max_ts, .. } => {
+        // Scans locks with timestamp <= `max_ts` in `[start_key, end_key)`, up to `limit` locks.
+        Command::ScanLock { max_ts, ref mut start_key, ref end_key, limit, .. } => {
             let mut reader = MvccReader::new(snapshot.as_ref(), Some(ScanMode::Forward), true);
-            let res = reader.scan_lock(None, |lock| lock.ts <= max_ts, None)
+            let limit = if limit == 0 { None } else { Some(limit) };
+            let res = reader.scan_lock(start_key.take(),
+                           end_key.as_ref(),
+                           |lock| lock.ts <= max_ts,
+                           limit)
                 .map_err(Error::from)
-                .and_then(|(v, _)| {
+                .and_then(|(v, last_key)| {
                     let mut locks = vec![];
                     for (key, lock) in v {
                         let mut lock_info = LockInfo::new();
@@ -294,10 +437,24 @@ fn process_read(cid: u64, mut cmd: Command, ch: SendCh<Msg>, snapshot: Box<Snaps
                         lock_info.set_key(try!(key.raw()));
                         locks.push(lock_info);
                     }
-                    Ok(locks)
+                    // Resumes right after the last lock.
+                    let next_start = match last_key {
+                        Some(key) => {
+                            let mut raw = try!(key.raw());
+                            raw.push(0);
+                            Some(raw)
+                        }
+                        None => None,
+                    };
+                    Ok((locks, next_start))
                 });
             match res {
-                Ok(locks) => ProcessResult::Locks { locks: locks },
+                Ok((locks, next_start)) => {
+                    ProcessResult::Locks {
+                        locks: locks,
+                        next_start: next_start,
+                    }
+                }
                 Err(e) => ProcessResult::Failed { err: e.into() },
             }
         }
@@ -306,6 +463,7 @@ fn process_read(cid: u64, mut cmd: Command, ch: SendCh<Msg>, snapshot: Box<Snaps
         Command::ResolveLock { ref ctx, start_ts, commit_ts, ref mut scan_key, .. } => {
             let mut reader = MvccReader::new(snapshot.as_ref(), Some(ScanMode::Forward), true);
             let res = reader.scan_lock(scan_key.take(),
+                           None,
                            |lock| lock.ts == start_ts,
                            Some(RESOLVE_LOCK_BATCH_SIZE))
                 .map_err(Error::from)
@@ -330,7 +488,7 @@ fn process_read(cid: u64, mut cmd: Command, ch: SendCh<Msg>, snapshot: Box<Snaps
             }
         }
         // Collects garbage.
-        Command::Gc { ref ctx, safe_point, ref mut scan_key, .. } => {
+        Command::Gc { ref ctx, safe_point, ref mut scan_key, has_newer, .. } => {
             let mut reader = MvccReader::new(snapshot.as_ref(), Some(ScanMode::Forward), true);
             let res = reader.scan_keys(scan_key.take(), GC_BATCH_SIZE)
                 .map_err(Error::from)
@@ -343,12 +501,57 @@ fn process_read(cid: u64, mut cmd: Command, ch: SendCh<Msg>, snapshot: Box<Snaps
                             safe_point: safe_point,
                             scan_key: next_start,
                             keys: keys,
+                            has_newer: has_newer,
                         }))
                     }
                 });
             match res {
                 Ok(Some(cmd)) => ProcessResult::NextCommand { cmd: cmd },
-                Ok(None) => ProcessResult::Res,
+                Ok(None) => ProcessResult::GcRes { has_newer: has_newer },
+                Err(e) => ProcessResult::Failed { err: e.into() },
+            }
+        }
+        Command::RawGet { ref key, .. } => {
+            match snapshot.get_cf(CF_RAW, key) {
+                Ok(val) => ProcessResult::Value { value: val },
+                Err(e) => ProcessResult::Failed { err: StorageError::from(e) },
+            }
+        }
+        Command::RawBatchGet { ref keys, .. } => {
+            let mut pairs = vec![];
+            for k in keys {
+                match snapshot.get_cf(CF_RAW, k) {
+                    Ok(Some(v)) => pairs.push(Ok((k.encoded().to_owned(), v))),
+                    Ok(None) => {}
+                    Err(e) => pairs.push(Err(StorageError::from(e))),
+                }
+            }
+            ProcessResult::MultiKvpairs { pairs: pairs }
+        }
+        // Scans a range of raw keys starting with `start_key` up to `limit` rows.
+        Command::RawScan { ref start_key, ref end_key, limit, .. } => {
+            match raw_scan(snapshot.as_ref(), start_key, end_key.as_ref(), limit) {
+                Ok(pairs) => ProcessResult::MultiKvpairs { pairs: pairs },
+                Err(e) => ProcessResult::Failed { err: e.into() },
+            }
+        }
+        Command::MvccByKey { ref key, .. } => {
+            let mut reader = MvccReader::new(snapshot.as_ref(), None, false);
+            match reader.get_mvcc_info(key).map_err(Error::from) {
+                Ok(mvcc) => ProcessResult::MvccKey { mvcc: mvcc },
+                Err(e) => ProcessResult::Failed { err: e.into() },
+            }
+        }
+        Command::MvccByStartTs { start_ts, .. } => {
+            let mut reader = MvccReader::new(snapshot.as_ref(), Some(ScanMode::Forward), false);
+            let res = reader.seek_ts(start_ts)
+                .and_then(|key| match key {
+                    Some(key) => reader.get_mvcc_info(&key).map(|mvcc| Some((key, mvcc))),
+                    None => Ok(None),
+                })
+                .map_err(Error::from);
+            match res {
+                Ok(mvcc) => ProcessResult::MvccStartTs { mvcc: mvcc },
                 Err(e) => ProcessResult::Failed { err: e.into() },
             }
         }
@@ -361,11 +564,37 @@ fn process_read(cid: u64, mut cmd: Command, ch: SendCh<Msg>, snapshot: Box<Snaps
     }
 }
 
+fn raw_scan(snapshot: &Snapshot,
+            start_key: &Key,
+            end_key: Option<&Key>,
+            limit: usize)
+            -> Result<Vec<StorageResult<KvPair>>> {
+    let mut cursor = try!(snapshot.iter_cf(CF_RAW, None, true, ScanMode::Forward));
+    if !try!(cursor.seek(start_key)) {
+        return Ok(vec![]);
+    }
+    let mut pairs = vec![];
+    while cursor.valid() && pairs.len() < limit {
+        if end_key.map_or(false, |k| cursor.key() >= k.encoded().as_slice()) {
+            break;
+        }
+        pairs.push(Ok((cursor.key().to_owned(), cursor.value().to_owned())));
+        cursor.next();
+    }
+    Ok(pairs)
+}
+
 /// Processes a write command within a worker thread, then posts either a `WritePrepareFinished`
-/// message if successful or a `WritePrepareFailed` message back to the event loop.
-fn process_write(cid: u64, cmd: Command, ch: SendCh<Msg>, snapshot: Box<Snapshot>) {
+/// message if successful or a `WritePrepareFailed` message back to the event loop. If `lock_wait`
+/// is true and a prewrite meets the lock of another transaction, a `WaitForLock` message is posted
+/// instead and nothing is written.
+fn process_write(cid: u64,
+                 cmd: Command,
+                 ch: SendCh<Msg>,
+                 snapshot: Box<Snapshot>,
+                 lock_wait: bool) {
     SCHED_WORKER_COUNTER_VEC.with_label_values(&[cmd.tag(), "write"]).inc();
-    if let Err(e) = process_write_impl(cid, cmd, ch.clone(), snapshot.as_ref()) {
+    if let Err(e) = process_write_impl(cid, cmd, ch.clone(), snapshot.as_ref(), lock_wait) {
         if let Err(err) = ch.send(Msg::WritePrepareFailed { cid: cid, err: e }) {
             // Todo: if this happens, lock will hold for ever
             panic!("send WritePrepareFailed message to channel failed. cid={}, err={:?}",
@@ -378,22 +607,100 @@ fn process_write(cid: u64, cmd: Command, ch: SendCh<Msg>, snapshot: Box<Snapshot
 fn process_write_impl(cid: u64,
                       mut cmd: Command,
                       ch: SendCh<Msg>,
-                      snapshot: &Snapshot)
+                      snapshot: &Snapshot,
+                      lock_wait: bool)
                       -> Result<()> {
-    let (pr, modifies) = match cmd {
-        Command::Prewrite { ref mutations, ref primary, start_ts, lock_ttl, .. } => {
+    // (start_ts, key, lock_ts) of the lock to wait for
+    let mut wait_for = None;
+    let (pr, write_size, modifies) = match cmd {
+        Command::Prewrite { ref mutations,
+                            ref primary,
+                            start_ts,
+                            lock_ttl,
+                            for_update_ts,
+                            ref is_pessimistic_lock,
+                            ref secondaries,
+                            min_commit_ts,
+                            one_pc_commit_ts,
+                            .. } => {
+            // Only transactions whose keys are all in one region can commit in one phase,
+            // otherwise the writes can't be applied atomically.
+            let one_pc = if one_pc_commit_ts > 0 && for_update_ts == 0 &&
+                            secondaries.is_none() &&
+                            mutations.iter().all(|m| snapshot.contains_key(m.key())) {
+                try!(one_pc_commit(snapshot, mutations, start_ts, one_pc_commit_ts))
+            } else {
+                None
+            };
+            if let Some((write_size, modifies)) = one_pc {
+                let pr = ProcessResult::OnePcRes {
+                    results: mutations.iter().map(|_| Ok(())).collect(),
+                    commit_ts: one_pc_commit_ts,
+                };
+                (pr, write_size, modifies)
+            } else {
+                let mut txn = MvccTxn::new(snapshot, start_ts, None);
+                if let Some(ref secondaries) = *secondaries {
+                    txn.set_async_commit(secondaries.clone(), min_commit_ts);
+                }
+                let mut results = vec![];
+                for (i, m) in mutations.iter().enumerate() {
+                    let res = if for_update_ts == 0 {
+                        txn.prewrite(m.clone(), primary, lock_ttl)
+                    } else {
+                        txn.pessimistic_prewrite(m.clone(),
+                                                 primary,
+                                                 is_pessimistic_lock[i],
+                                                 lock_ttl,
+                                                 for_update_ts)
+                    };
+                    match res {
+                        Ok(_) => results.push(Ok(())),
+                        Err(MvccError::KeyIsLocked { ref key, ts, .. }) if lock_wait => {
+                            wait_for = Some((start_ts, key.to_owned(), ts));
+                            break;
+                        }
+                        e @ Err(MvccError::KeyIsLocked { .. }) |
+                        e @ Err(MvccError::AlreadyExist { .. }) => {
+                            results.push(e.map_err(Error::from))
+                        }
+                        Err(e) => return Err(Error::from(e)),
+                    }
+                }
+                let res = results.drain(..).map(|x| x.map_err(StorageError::from)).collect();
+                let pr = if secondaries.is_some() {
+                    ProcessResult::AsyncCommitPrewriteRes {
+                        results: res,
+                        min_commit_ts: txn.min_commit_ts(),
+                    }
+                } else {
+                    ProcessResult::MultiRes { results: res }
+                };
+                (pr, txn.write_size(), txn.modifies())
+            }
+        }
+        Command::AcquirePessimisticLock { ref keys,
+                                          ref primary,
+                                          start_ts,
+                                          lock_ttl,
+                                          for_update_ts,
+                                          .. } => {
             let mut txn = MvccTxn::new(snapshot, start_ts, None);
             let mut results = vec![];
-            for m in mutations {
-                match txn.prewrite(m.clone(), primary, lock_ttl) {
+            for k in keys {
+                match txn.acquire_pessimistic_lock(k.clone(), primary, lock_ttl, for_update_ts) {
                     Ok(_) => results.push(Ok(())),
+                    Err(MvccError::KeyIsLocked { ref key, ts, .. }) if lock_wait => {
+                        wait_for = Some((start_ts, key.to_owned(), ts));
+                        break;
+                    }
                     e @ Err(MvccError::KeyIsLocked { .. }) => results.push(e.map_err(Error::from)),
                     Err(e) => return Err(Error::from(e)),
                 }
             }
             let res = results.drain(..).map(|x| x.map_err(StorageError::from)).collect();
             let pr = ProcessResult::MultiRes { results: res };
-            (pr, txn.modifies())
+            (pr, txn.write_size(), txn.modifies())
         }
         Command::Commit { ref keys, lock_ts, commit_ts, .. } => {
             let mut txn = MvccTxn::new(snapshot, lock_ts, None);
@@ -402,14 +709,14 @@ fn process_write_impl(cid: u64,
             }
 
             let pr = ProcessResult::Res;
-            (pr, txn.modifies())
+            (pr, txn.write_size(), txn.modifies())
         }
         Command::Cleanup { ref key, start_ts, .. } => {
             let mut txn = MvccTxn::new(snapshot, start_ts, None);
-            try!(txn.rollback(&key));
+            try!(txn.cleanup(&key));
 
             let pr = ProcessResult::Res;
-            (pr, txn.modifies())
+            (pr, txn.write_size(), txn.modifies())
         }
         Command::Rollback { ref keys, start_ts, .. } => {
             let mut txn = MvccTxn::new(snapshot, start_ts, None);
@@ -418,23 +725,41 @@ fn process_write_impl(cid: u64,
             }
 
             let pr = ProcessResult::Res;
-            (pr, txn.modifies())
+            (pr, txn.write_size(), txn.modifies())
+        }
+        Command::CheckTxnStatus { ref primary_key, lock_ts, current_ts, .. } => {
+            let mut txn = MvccTxn::new(snapshot, lock_ts, None);
+            let txn_status = try!(txn.check_txn_status(primary_key, current_ts));
+
+            let pr = ProcessResult::TxnStatus { txn_status: txn_status };
+            (pr, txn.write_size(), txn.modifies())
+        }
+        Command::CheckSecondaryLocks { ref keys, start_ts, .. } => {
+            let mut txn = MvccTxn::new(snapshot, start_ts, None);
+            let status = try!(txn.check_secondary_locks(keys));
+
+            let pr = ProcessResult::SecondaryLocksStatus { status: status };
+            (pr, txn.write_size(), txn.modifies())
+        }
+        Command::TxnHeartBeat { ref primary_key, start_ts, advise_ttl, .. } => {
+            let mut txn = MvccTxn::new(snapshot, start_ts, None);
+            let ttl = try!(txn.txn_heart_beat(primary_key.clone(), advise_ttl));
+
+            let pr = ProcessResult::LockTtl { ttl: ttl };
+            (pr, txn.write_size(), txn.modifies())
         }
         Command::ResolveLock { ref ctx, start_ts, commit_ts, ref mut scan_key, ref keys } => {
             let mut scan_key = scan_key.take();
             let mut txn = MvccTxn::new(snapshot, start_ts, None);
             for k in keys {
-                match commit_ts {
-                    Some(ts) => try!(txn.commit(&k, ts)),
-                    None => try!(txn.rollback(&k)),
-                }
+                try!(txn.resolve_lock(k, commit_ts));
                 if txn.write_size() >= MAX_TXN_WRITE_SIZE {
                     scan_key = Some(k.to_owned());
                     break;
                 }
             }
             if scan_key.is_none() {
-                (ProcessResult::Res, txn.modifies())
+                (ProcessResult::Res, txn.write_size(), txn.modifies())
             } else {
                 let pr = ProcessResult::NextCommand {
                     cmd: Command::ResolveLock {
@@ -445,21 +770,23 @@ fn process_write_impl(cid: u64,
                         keys: vec![],
                     },
                 };
-                (pr, txn.modifies())
+                (pr, txn.write_size(), txn.modifies())
             }
         }
-        Command::Gc { ref ctx, safe_point, ref mut scan_key, ref keys } => {
+        Command::Gc { ref ctx, safe_point, ref mut scan_key, ref keys, mut has_newer } => {
             let mut scan_key = scan_key.take();
             let mut txn = MvccTxn::new(snapshot, 0, Some(ScanMode::Mixed));
             for k in keys {
-                try!(txn.gc(k, safe_point));
+                if try!(txn.gc(k, safe_point)) {
+                    has_newer = true;
+                }
                 if txn.write_size() >= MAX_TXN_WRITE_SIZE {
                     scan_key = Some(k.to_owned());
                     break;
                 }
             }
             if scan_key.is_none() {
-                (ProcessResult::Res, txn.modifies())
+                (ProcessResult::GcRes { has_newer: has_newer }, txn.write_size(), txn.modifies())
             } else {
                 let pr = ProcessResult::NextCommand {
                     cmd: Command::Gc {
@@ -467,37 +794,129 @@ fn process_write_impl(cid: u64,
                         safe_point: safe_point,
                         scan_key: scan_key.take(),
                         keys: vec![],
+                        has_newer: has_newer,
                     },
                 };
-                (pr, txn.modifies())
+                (pr, txn.write_size(), txn.modifies())
             }
         }
+        Command::DeleteRange { ref start_key, ref end_key, safe_point, .. } => {
+            let mut reader = MvccReader::new(snapshot, Some(ScanMode::Forward), true);
+            // Transactions after the safe point are still writing the range.
+            let (locks, _) = try!(reader.scan_lock(Some(start_key.clone()),
+                                                   Some(end_key),
+                                                   |lock| lock.ts > safe_point,
+                                                   Some(1)));
+            if let Some((key, lock)) = locks.into_iter().next() {
+                return Err(Error::from(MvccError::KeyIsLocked {
+                    key: try!(key.raw()),
+                    primary: lock.primary,
+                    ts: lock.ts,
+                    ttl: lock.ttl,
+                }));
+            }
+            let write_size = start_key.encoded().len() + end_key.encoded().len();
+            let modifies = vec![Modify::DeleteRange(start_key.clone(), end_key.clone())];
+            (ProcessResult::Res, write_size, modifies)
+        }
         _ => panic!("unsupported write command"),
     };
 
+    if let Some((start_ts, key, lock_ts)) = wait_for {
+        box_try!(ch.send(Msg::WaitForLock {
+            cid: cid,
+            cmd: cmd,
+            start_ts: start_ts,
+            key: key,
+            lock_ts: lock_ts,
+        }));
+        return Ok(());
+    }
+
     box_try!(ch.send(Msg::WritePrepareFinished {
         cid: cid,
         cmd: cmd,
         pr: pr,
         to_be_write: modifies,
+        write_size: write_size,
     }));
 
     Ok(())
 }
 
+/// Commits the mutations in one phase at `commit_ts`.
+///
+/// Returns the write size and the modifies, or `None` if any of the mutations conflicts, so
+/// that the transaction can fall back to two-phase commit, which reports the conflicts and
+/// writes locks for the other mutations.
+fn one_pc_commit(snapshot: &Snapshot,
+                 mutations: &[Mutation],
+                 start_ts: u64,
+                 commit_ts: u64)
+                 -> Result<Option<(usize, Vec<Modify>)>> {
+    let mut txn = MvccTxn::new(snapshot, start_ts, None);
+    for m in mutations {
+        match txn.one_pc_commit(m.clone(), commit_ts) {
+            Ok(_) => {}
+            // Prewrites again to report the errors of the keys.
+            Err(MvccError::KeyIsLocked { .. }) |
+            Err(MvccError::AlreadyExist { .. }) |
+            Err(MvccError::WriteConflict) => return Ok(None),
+            Err(e) => return Err(Error::from(e)),
+        }
+    }
+    Ok(Some((txn.write_size(), txn.modifies())))
+}
+
+/// Returns the keys and the range whose locks are released once `modifies` are written.
+///
+/// The locks are collected from the modifies rather than the command, so the locks released as
+/// a side effect, like the primary lock rolled back when resolving an async commit transaction,
+/// are not missed.
+fn released_locks(modifies: &[Modify]) -> (Vec<Key>, Option<(Key, Key)>) {
+    let mut keys = vec![];
+    let mut range = None;
+    for m in modifies {
+        match *m {
+            Modify::Delete(cf, ref key) if cf == CF_LOCK => keys.push(key.clone()),
+            Modify::DeleteRange(ref start, ref end) => range = Some((start.clone(), end.clone())),
+            _ => {}
+        }
+    }
+    (keys, range)
+}
+
 /// Extracts the context of a command.
-fn extract_ctx(cmd: &Command) -> &Context {
+pub fn extract_ctx(cmd: &Command) -> &Context {
     match *cmd {
         Command::Get { ref ctx, .. } |
         Command::BatchGet { ref ctx, .. } |
         Command::Scan { ref ctx, .. } |
         Command::Prewrite { ref ctx, .. } |
+        Command::AcquirePessimisticLock { ref ctx, .. } |
         Command::Commit { ref ctx, .. } |
         Command::Cleanup { ref ctx, .. } |
         Command::Rollback { ref ctx, .. } |
+        Command::CheckTxnStatus { ref ctx, .. } |
+        Command::CheckSecondaryLocks { ref ctx, .. } |
+        Command::TxnHeartBeat { ref ctx, .. } |
         Command::ScanLock { ref ctx, .. } |
         Command::ResolveLock { ref ctx, .. } |
-        Command::Gc { ref ctx, .. } => ctx,
+        Command::Gc { ref ctx, .. } |
+        Command::DeleteRange { ref ctx, .. } |
+        Command::RawGet { ref ctx, .. } |
+        Command::RawBatchGet { ref ctx, .. } |
+        Command::RawScan { ref ctx, .. } |
+        Command::MvccByKey { ref ctx, .. } |
+        Command::MvccByStartTs { ref ctx, .. } => ctx,
+    }
+}
+
+fn priority_tag(priority: CommandPri) -> &'static str {
+    match priority {
+        CommandPri::High => "high",
+        CommandPri::Normal => "normal",
+        CommandPri::Low => "low",
     }
 }
 
@@ -520,6 +939,13 @@ impl Scheduler {
         let ctx = self.cmd_ctxs.remove(&cid).unwrap();
         assert_eq!(ctx.cid, cid);
         SCHED_CONTEX_GAUGE.set(self.cmd_ctxs.len() as f64);
+        if ctx.write_bytes > 0 {
+            self.pending_write_bytes -= ctx.write_bytes;
+            SCHED_WRITING_BYTES_GAUGE.set(self.pending_write_bytes as f64);
+        }
+        if !ctx.memory_locked_keys.is_empty() {
+            self.concurrency_manager.unlock_keys(&ctx.memory_locked_keys);
+        }
         ctx
     }
 
@@ -531,35 +957,85 @@ impl Scheduler {
     /// Generates the lock for a command.
     ///
     /// Basically, read-only commands require no latches, write commands require latches hashed
-    /// by the referenced keys.
+    /// by the referenced keys, or all the latches if the keys are unknown.
     fn gen_lock(&self, cmd: &Command) -> Lock {
         match *cmd {
             Command::Prewrite { ref mutations, .. } => {
                 let keys: Vec<&Key> = mutations.iter().map(|x| x.key()).collect();
                 self.latches.gen_lock(&keys)
             }
+            Command::AcquirePessimisticLock { ref keys, .. } |
             Command::Commit { ref keys, .. } |
-            Command::Rollback { ref keys, .. } => self.latches.gen_lock(keys),
-            Command::Cleanup { ref key, .. } => self.latches.gen_lock(&[key]),
+            Command::Rollback { ref keys, .. } |
+            Command::CheckSecondaryLocks { ref keys, .. } => self.latches.gen_lock(keys),
+            Command::Cleanup { ref key, .. } |
+            Command::CheckTxnStatus { primary_key: ref key, .. } |
+            Command::TxnHeartBeat { primary_key: ref key, .. } => self.latches.gen_lock(&[key]),
+            // The keys in the range can't be latched one by one, so it waits for all the
+            // running writes and blocks the new ones.
+            Command::DeleteRange { .. } => self.latches.gen_lock_all(),
             _ => Lock::new(vec![]),
         }
     }
 
+    fn get_worker_pool(&self, priority: CommandPri) -> &ThreadPool {
+        match priority {
+            CommandPri::High => &self.high_priority_pool,
+            CommandPri::Normal => &self.worker_pool,
+            CommandPri::Low => &self.low_priority_pool,
+        }
+    }
+
     /// Delivers a command to a worker thread for processing.
     fn process_by_worker(&mut self, cid: u64, snapshot: Box<Snapshot>) {
         SCHED_STAGE_COUNTER_VEC.with_label_values(&[self.get_ctx_tag(cid), "process"]).inc();
         debug!("process cmd with snapshot, cid={}", cid);
-        let cmd = {
+        let (mut cmd, lock_wait) = {
             let ctx = &mut self.cmd_ctxs.get_mut(&cid).unwrap();
             assert_eq!(ctx.cid, cid);
-            ctx.cmd.take().unwrap()
+            (ctx.cmd.take().unwrap(), ctx.lock_wait_deadline.is_some())
         };
+        self.lock_memory(cid, &mut cmd);
         let ch = self.schedch.clone();
         let readcmd = cmd.readonly();
+        let worker_pool = self.get_worker_pool(extract_ctx(&cmd).get_priority());
         if readcmd {
-            self.worker_pool.execute(move || process_read(cid, cmd, ch, snapshot));
+            worker_pool.execute(move || process_read(cid, cmd, ch, snapshot));
         } else {
-            self.worker_pool.execute(move || process_write(cid, cmd, ch, snapshot));
+            worker_pool.execute(move || process_write(cid, cmd, ch, snapshot, lock_wait));
+        }
+    }
+
+    /// Adds memory locks for the keys of an async commit or one-phase commit prewrite until
+    /// the command finishes, and raises its `min_commit_ts` or `one_pc_commit_ts` above the
+    /// reads which may have missed the locks, see `ConcurrencyManager`.
+    fn lock_memory(&mut self, cid: u64, cmd: &mut Command) {
+        if let Command::Prewrite { ref mutations,
+                                   ref primary,
+                                   start_ts,
+                                   lock_ttl,
+                                   for_update_ts,
+                                   ref secondaries,
+                                   ref mut min_commit_ts,
+                                   ref mut one_pc_commit_ts,
+                                   .. } = *cmd {
+            let one_pc = *one_pc_commit_ts > 0 && for_update_ts == 0;
+            if secondaries.is_none() && !one_pc {
+                return;
+            }
+            let keys: Vec<Key> = mutations.iter().map(|m| m.key().clone()).collect();
+            let lock = MemoryLock {
+                primary: primary.clone(),
+                ts: start_ts,
+                ttl: lock_ttl,
+            };
+            let max_read_ts = self.concurrency_manager.lock_keys(&keys, lock);
+            if secondaries.is_some() {
+                *min_commit_ts = cmp::max(*min_commit_ts, max_read_ts + 1);
+            } else {
+                *one_pc_commit_ts = cmp::max(*one_pc_commit_ts, max_read_ts + 1);
+            }
+            self.cmd_ctxs.get_mut(&cid).unwrap().memory_locked_keys = keys;
         }
     }
 
@@ -593,27 +1069,57 @@ impl Scheduler {
     /// Note that once a command is ready to execute, the snapshot is always up-to-date during the
     /// execution because 1) all the conflicting commands (if any) must be in the waiting queues;
     /// 2) there may be non-conflicitng commands running concurrently, but it doesn't matter.
-    fn schedule_command(&mut self, cmd: Command, callback: StorageCb) {
+    fn schedule_command(&mut self,
+                        cmd: Command,
+                        callback: StorageCb,
+                        lock_wait_deadline: Option<Instant>) {
         SCHED_STAGE_COUNTER_VEC.with_label_values(&[cmd.tag(), "new"]).inc();
         let cid = self.gen_id();
         debug!("received new command, cid={}, cmd={}", cid, cmd);
         let lock = self.gen_lock(&cmd);
-        let ctx = RunningCtx::new(cid, cmd, lock, callback);
+        let ctx = RunningCtx::new(cid, cmd, lock, callback, lock_wait_deadline);
         self.insert_ctx(ctx);
         self.lock_and_get_snapshot(cid);
     }
 
-    fn too_busy(&self) -> bool {
-        self.cmd_ctxs.len() >= self.sched_too_busy_threshold
+    /// Returns whether there are too many running commands or pending write bytes to accept a
+    /// new write command of `priority`. Low priority commands are rejected at half of the
+    /// thresholds, and high priority commands are still accepted until twice of them.
+    ///
+    /// Commands parked in the wait table are counted as running ones, as they are resumed
+    /// once the locks are released.
+    fn too_busy(&self, priority: CommandPri) -> bool {
+        let (threshold, bytes_threshold) = match priority {
+            CommandPri::High => {
+                (self.sched_too_busy_threshold.saturating_mul(2),
+                 self.sched_pending_write_threshold.saturating_mul(2))
+            }
+            CommandPri::Normal => {
+                (self.sched_too_busy_threshold, self.sched_pending_write_threshold)
+            }
+            CommandPri::Low => {
+                (self.sched_too_busy_threshold / 2, self.sched_pending_write_threshold / 2)
+            }
+        };
+        self.cmd_ctxs.len() + self.wait_table.len() >= threshold ||
+        self.pending_write_bytes >= bytes_threshold
     }
 
     fn on_receive_new_cmd(&mut self, cmd: Command, callback: StorageCb) {
+        let priority = extract_ctx(&cmd).get_priority();
+        SCHED_COMMANDS_PRI_COUNTER_VEC.with_label_values(&[priority_tag(priority)]).inc();
         // write flow control
-        if !cmd.readonly() && self.too_busy() {
+        if !cmd.readonly() && self.too_busy(priority) {
+            SCHED_STAGE_COUNTER_VEC.with_label_values(&[cmd.tag(), "too_busy"]).inc();
             execute_callback(callback,
                              ProcessResult::Failed { err: StorageError::SchedTooBusy });
         } else {
-            self.schedule_command(cmd, callback);
+            let deadline = if self.lock_wait_timeout > 0 {
+                Some(Instant::now() + Duration::from_millis(self.lock_wait_timeout))
+            } else {
+                None
+            };
+            self.schedule_command(cmd, callback, deadline);
         }
     }
 
@@ -681,7 +1187,7 @@ impl Scheduler {
         let cb = ctx.callback.take().unwrap();
         if let ProcessResult::NextCommand { cmd } = pr {
             SCHED_STAGE_COUNTER_VEC.with_label_values(&[ctx.tag, "next_cmd"]).inc();
-            self.schedule_command(cmd, cb);
+            self.schedule_command(cmd, cb, ctx.lock_wait_deadline);
         } else {
             execute_callback(cb, pr);
         }
@@ -708,11 +1214,21 @@ impl Scheduler {
                                  cid: u64,
                                  cmd: Command,
                                  pr: ProcessResult,
-                                 to_be_write: Vec<Modify>) {
+                                 to_be_write: Vec<Modify>,
+                                 write_size: usize) {
         SCHED_STAGE_COUNTER_VEC.with_label_values(&[self.get_ctx_tag(cid), "write"]).inc();
+        if self.lock_wait_timeout > 0 {
+            let (keys, range) = released_locks(&to_be_write);
+            let ctx = self.cmd_ctxs.get_mut(&cid).unwrap();
+            ctx.released_keys = keys;
+            ctx.released_range = range;
+        }
         if to_be_write.is_empty() {
             return self.on_write_finished(cid, pr, Ok(()));
         }
+        self.cmd_ctxs.get_mut(&cid).unwrap().write_bytes = write_size;
+        self.pending_write_bytes += write_size;
+        SCHED_WRITING_BYTES_GAUGE.set(self.pending_write_bytes as f64);
         let engine_cb = make_engine_cb(cid, pr, self.schedch.clone());
         if let Err(e) = self.engine.async_write(extract_ctx(&cmd), to_be_write, engine_cb) {
             SCHED_STAGE_COUNTER_VEC.with_label_values(&[self.get_ctx_tag(cid), "async_write_err"])
@@ -728,12 +1244,15 @@ impl Scheduler {
         let mut ctx = self.remove_ctx(cid);
         let cb = ctx.callback.take().unwrap();
         let pr = match result {
-            Ok(()) => pr,
+            Ok(()) => {
+                self.wake_up_waiters(&ctx.released_keys, ctx.released_range.as_ref());
+                pr
+            }
             Err(e) => ProcessResult::Failed { err: ::storage::Error::from(e) },
         };
         if let ProcessResult::NextCommand { cmd } = pr {
             SCHED_STAGE_COUNTER_VEC.with_label_values(&[ctx.tag, "next_cmd"]).inc();
-            self.schedule_command(cmd, cb);
+            self.schedule_command(cmd, cb, ctx.lock_wait_deadline);
         } else {
             execute_callback(cb, pr);
         }
@@ -741,6 +1260,218 @@ impl Scheduler {
         self.release_lock(&ctx.lock, cid);
     }
 
+    /// Event handler for a command which meets the lock of another transaction.
+    ///
+    /// Parks the command in the wait table until its deadline and releases its latches, so the
+    /// command which releases the lock can proceed. The lock may have been released between the
+    /// command's snapshot and now, so it's checked again with a new snapshot after parking.
+    ///
+    /// If a deadlock detector is set, the wait-for edge is sent to it as well.
+    #[allow(too_many_arguments)]
+    fn on_wait_for_lock(&mut self,
+                        event_loop: &mut EventLoop<Self>,
+                        cid: u64,
+                        cmd: Command,
+                        start_ts: u64,
+                        key: Vec<u8>,
+                        lock_ts: u64) {
+        SCHED_STAGE_COUNTER_VEC.with_label_values(&[self.get_ctx_tag(cid), "lock_wait"]).inc();
+        debug!("command cid={} of txn {} waits for lock {} on key {}",
+               cid,
+               start_ts,
+               lock_ts,
+               escape(&key));
+        let mut ctx = self.remove_ctx(cid);
+        let cb = ctx.callback.take().unwrap();
+        let deadline = ctx.lock_wait_deadline.unwrap();
+        let now = Instant::now();
+        if deadline <= now {
+            // The command has used up its wait, it returns `KeyIsLocked` if the lock is still
+            // there.
+            self.schedule_command(cmd, cb, None);
+        } else if let Err(e) = event_loop.timeout(cid, deadline - now) {
+            error!("register lock wait timeout for cid={} failed: {:?}", cid, e);
+            self.schedule_command(cmd, cb, None);
+        } else {
+            let region_ctx = extract_ctx(&cmd).clone();
+            let waiter = Waiter {
+                cid: cid,
+                cmd: cmd,
+                callback: cb,
+                start_ts: start_ts,
+                key: key.clone(),
+                lock_ts: lock_ts,
+                deadline: deadline,
+                _timer: SCHED_LOCK_WAIT_HISTOGRAM.start_timer(),
+            };
+            let key = Key::from_raw(&key);
+            self.wait_table.add_waiter(cid, &key, waiter);
+            SCHED_LOCK_WAIT_GAUGE.set(self.wait_table.len() as f64);
+            self.detect_deadlock(cid, start_ts, lock_ts);
+            self.recheck_lock(cid, &region_ctx, key, lock_ts);
+        }
+
+        self.release_lock(&ctx.lock, cid);
+    }
+
+    /// Gets a new snapshot to check whether the lock of `key` a waiter waits for has been
+    /// released before the waiter is parked. A `RecheckLock` message is posted back to the event
+    /// loop with the snapshot.
+    fn recheck_lock(&mut self, cid: u64, ctx: &Context, key: Key, lock_ts: u64) {
+        let ch = self.schedch.clone();
+        let cb = box move |snapshot: EngineResult<Box<Snapshot>>| {
+            if let Err(e) = ch.send(Msg::RecheckLock {
+                cid: cid,
+                key: key,
+                lock_ts: lock_ts,
+                snapshot: snapshot,
+            }) {
+                error!("send RecheckLock of cid={} failed: {:?}", cid, e);
+            }
+        };
+        if let Err(e) = self.engine.async_snapshot(ctx, cb) {
+            // The waiter is still woken up when the wait times out.
+            error!("get snapshot to recheck lock for cid={} failed: {:?}", cid, e);
+        }
+    }
+
+    /// Event handler for the snapshot to recheck the lock a waiter waits for.
+    ///
+    /// The lock is loaded by a worker thread, which posts a `LockReleased` message back to the
+    /// event loop if the lock is gone.
+    fn on_recheck_lock(&mut self,
+                       cid: u64,
+                       key: Key,
+                       lock_ts: u64,
+                       snapshot: EngineResult<Box<Snapshot>>) {
+        let snapshot = match snapshot {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                error!("get snapshot to recheck lock for cid={} failed: {:?}", cid, e);
+                return;
+            }
+        };
+        if !self.wait_table.contains(cid) {
+            return;
+        }
+        let ch = self.schedch.clone();
+        self.worker_pool.execute(move || {
+            let mut reader = MvccReader::new(snapshot.as_ref(), None, true);
+            match reader.load_lock(&key) {
+                Ok(Some(ref lock)) if lock.ts == lock_ts => {}
+                Err(e) => error!("recheck lock of cid={} failed: {:?}", cid, e),
+                _ => {
+                    if let Err(e) = ch.send(Msg::LockReleased { cid: cid }) {
+                        error!("send LockReleased of cid={} failed: {:?}", cid, e);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Event handler for a waiter whose lock was released before it was parked, the waiter is
+    /// rescheduled with the rest of its wait.
+    fn on_lock_released(&mut self, cid: u64) {
+        if let Some(Waiter { cmd, callback, start_ts, lock_ts, deadline, .. }) =
+               self.wait_table.remove_waiter(cid) {
+            SCHED_STAGE_COUNTER_VEC.with_label_values(&[cmd.tag(), "lock_wait_recheck"]).inc();
+            SCHED_LOCK_WAIT_GAUGE.set(self.wait_table.len() as f64);
+            self.clean_up_wait_for(cid, start_ts, lock_ts);
+            self.schedule_command(cmd, callback, Some(deadline));
+        }
+    }
+
+    fn detect_deadlock(&self, cid: u64, start_ts: u64, lock_ts: u64) {
+        let detector = match self.deadlock_detector {
+            Some(ref detector) => detector,
+            None => return,
+        };
+        let ch = self.schedch.clone();
+        let task = DetectTask::Detect {
+            cid: cid,
+            txn_ts: start_ts,
+            lock_ts: lock_ts,
+            cb: box move || {
+                if let Err(e) = ch.send(Msg::Deadlock { cid: cid }) {
+                    error!("send deadlock of cid={} failed: {:?}", cid, e);
+                }
+            },
+        };
+        if let Err(e) = detector.schedule(task) {
+            error!("schedule deadlock detection for cid={} failed: {:?}", cid, e);
+        }
+    }
+
+    /// Tells the deadlock detector that command `cid` of txn `start_ts` no longer waits for
+    /// txn `lock_ts`.
+    fn clean_up_wait_for(&self, cid: u64, start_ts: u64, lock_ts: u64) {
+        if let Some(ref detector) = self.deadlock_detector {
+            let task = DetectTask::CleanUpWaitFor {
+                cid: cid,
+                txn_ts: start_ts,
+                lock_ts: lock_ts,
+            };
+            if let Err(e) = detector.schedule(task) {
+                error!("schedule cleaning up txn {} waiting for txn {} failed: {:?}",
+                       start_ts,
+                       lock_ts,
+                       e);
+            }
+        }
+    }
+
+    /// Event handler for a waiter which causes a deadlock, the waiter fails with `Deadlock`.
+    fn on_deadlock(&mut self, id: u64) {
+        if let Some(Waiter { cmd, callback, start_ts, key, lock_ts, .. }) =
+               self.wait_table.remove_waiter(id) {
+            SCHED_STAGE_COUNTER_VEC.with_label_values(&[cmd.tag(), "deadlock"]).inc();
+            SCHED_LOCK_WAIT_GAUGE.set(self.wait_table.len() as f64);
+            let err = MvccError::Deadlock {
+                start_ts: start_ts,
+                lock_ts: lock_ts,
+                key: key,
+            };
+            warn!("{}", err);
+            execute_callback(callback,
+                             ProcessResult::Failed { err: StorageError::from(Error::from(err)) });
+        }
+    }
+
+    /// Reschedules all the commands waiting for the locks of `keys` or the locks in `range` in
+    /// FIFO order. The commands keep the deadlines of their waits.
+    fn wake_up_waiters(&mut self, keys: &[Key], range: Option<&(Key, Key)>) {
+        if self.wait_table.is_empty() {
+            return;
+        }
+        let mut waiters = vec![];
+        for key in keys {
+            waiters.extend(self.wait_table.wake_up(key));
+        }
+        if let Some(&(ref start, ref end)) = range {
+            waiters.extend(self.wait_table.wake_up_range(start, end));
+        }
+        for Waiter { cid, cmd, callback, start_ts, lock_ts, deadline, .. } in waiters {
+            SCHED_STAGE_COUNTER_VEC.with_label_values(&[cmd.tag(), "lock_wait_wakeup"]).inc();
+            self.clean_up_wait_for(cid, start_ts, lock_ts);
+            self.schedule_command(cmd, callback, Some(deadline));
+        }
+        SCHED_LOCK_WAIT_GAUGE.set(self.wait_table.len() as f64);
+    }
+
+    /// Event handler for lock wait timeout.
+    ///
+    /// The command is rescheduled without lock waiting, so it returns `KeyIsLocked` if the lock
+    /// is still there.
+    fn on_lock_wait_timeout(&mut self, id: u64) {
+        if let Some(Waiter { cmd, callback, start_ts, lock_ts, .. }) =
+               self.wait_table.remove_waiter(id) {
+            SCHED_STAGE_COUNTER_VEC.with_label_values(&[cmd.tag(), "lock_wait_timeout"]).inc();
+            SCHED_LOCK_WAIT_GAUGE.set(self.wait_table.len() as f64);
+            self.clean_up_wait_for(id, start_ts, lock_ts);
+            self.schedule_command(cmd, callback, None);
+        }
+    }
+
     /// Releases all the latches held by a command.
     fn release_lock(&mut self, lock: &Lock, cid: u64) {
         let wakeup_list = self.latches.release(lock, cid);
@@ -766,7 +1497,8 @@ impl Scheduler {
 
 /// Handler of the scheduler event loop.
 impl mio::Handler for Scheduler {
-    type Timeout = ();
+    // ID of the lock waiter
+    type Timeout = u64;
     type Message = Msg;
 
     /// Event handler for message events.
@@ -776,14 +1508,27 @@ impl mio::Handler for Scheduler {
             Msg::RawCmd { cmd, cb } => self.on_receive_new_cmd(cmd, cb),
             Msg::SnapshotFinished { cid, snapshot } => self.on_snapshot_finished(cid, snapshot),
             Msg::ReadFinished { cid, pr } => self.on_read_finished(cid, pr),
-            Msg::WritePrepareFinished { cid, cmd, pr, to_be_write } => {
-                self.on_write_prepare_finished(cid, cmd, pr, to_be_write)
+            Msg::WritePrepareFinished { cid, cmd, pr, to_be_write, write_size } => {
+                self.on_write_prepare_finished(cid, cmd, pr, to_be_write, write_size)
             }
             Msg::WritePrepareFailed { cid, err } => self.on_write_prepare_failed(cid, err),
+            Msg::WaitForLock { cid, cmd, start_ts, key, lock_ts } => {
+                self.on_wait_for_lock(event_loop, cid, cmd, start_ts, key, lock_ts)
+            }
+            Msg::Deadlock { cid } => self.on_deadlock(cid),
+            Msg::RecheckLock { cid, key, lock_ts, snapshot } => {
+                self.on_recheck_lock(cid, key, lock_ts, snapshot)
+            }
+            Msg::LockReleased { cid } => self.on_lock_released(cid),
             Msg::WriteFinished { cid, pr, result } => self.on_write_finished(cid, pr, result),
         }
     }
 
+    /// Event handler for timeout events.
+    fn timeout(&mut self, _: &mut EventLoop<Self>, id: u64) {
+        self.on_lock_wait_timeout(id);
+    }
+
     /// Handler for tick events.
     fn tick(&mut self, event_loop: &mut EventLoop<Self>) {
         if !event_loop.is_running() {
@@ -791,3 +1536,39 @@ impl mio::Handler for Scheduler {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use mio::EventLoop;
+    use kvproto::kvrpcpb::CommandPri;
+    use storage::{new_local_engine, TEMP_DIR, ALL_CFS};
+    use util::transport::SendCh;
+    use super::Scheduler;
+    use super::super::concurrency::ConcurrencyManager;
+
+    #[test]
+    fn test_too_busy_pending_write_bytes() {
+        let engine = new_local_engine(TEMP_DIR, ALL_CFS).unwrap();
+        let event_loop: EventLoop<Scheduler> = EventLoop::new().unwrap();
+        let ch = SendCh::new(event_loop.channel(), "test-scheduler");
+        let mut sched =
+            Scheduler::new(engine, ch, 8, 1, 1, 1, 500, 100, 0, None, ConcurrencyManager::new());
+
+        // The budget is 200 bytes for high priority, 100 for normal and 50 for low.
+        let cases = vec![(0, false, false, false),
+                         (49, false, false, false),
+                         (50, false, false, true),
+                         (99, false, false, true),
+                         (100, false, true, true),
+                         (199, false, true, true),
+                         (200, true, true, true)];
+        for (bytes, high, normal, low) in cases {
+            sched.pending_write_bytes = bytes;
+            let busy = (bytes,
+                        sched.too_busy(CommandPri::High),
+                        sched.too_busy(CommandPri::Normal),
+                        sched.too_busy(CommandPri::Low));
+            assert_eq!(busy, (bytes, high, normal, low));
+        }
+    }
+}
@@ -11,6 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use kvproto::kvrpcpb::IsolationLevel;
 use storage::{Key, Value, KvPair, Snapshot, ScanMode};
 use storage::mvcc::{MvccReader, Error as MvccError};
 use super::{Error, Result};
@@ -18,25 +19,42 @@ use super::{Error, Result};
 pub struct SnapshotStore<'a> {
     snapshot: &'a Snapshot,
     start_ts: u64,
+    isolation_level: IsolationLevel,
+    // Start ts of the transactions known to be committed after `start_ts`, whose locks don't
+    // block reads.
+    bypass_locks: Vec<u64>,
 }
 
 impl<'a> SnapshotStore<'a> {
-    pub fn new(snapshot: &'a Snapshot, start_ts: u64) -> SnapshotStore {
+    pub fn new(snapshot: &'a Snapshot,
+               start_ts: u64,
+               isolation_level: IsolationLevel,
+               bypass_locks: Vec<u64>)
+               -> SnapshotStore {
         SnapshotStore {
             snapshot: snapshot,
             start_ts: start_ts,
+            isolation_level: isolation_level,
+            bypass_locks: bypass_locks,
         }
     }
 
+    fn new_reader(&self, scan_mode: Option<ScanMode>) -> MvccReader<'a> {
+        let mut reader = MvccReader::new(self.snapshot, scan_mode, true);
+        reader.set_isolation_level(self.isolation_level);
+        reader.set_bypass_locks(self.bypass_locks.clone());
+        reader
+    }
+
     pub fn get(&self, key: &Key) -> Result<Option<Value>> {
-        let mut reader = MvccReader::new(self.snapshot, None, true);
+        let mut reader = self.new_reader(None);
         let v = try!(reader.get(key, self.start_ts));
         Ok(v)
     }
 
     pub fn batch_get(&self, keys: &[Key]) -> Result<Vec<Result<Option<Value>>>> {
         // TODO: sort the keys and use ScanMode::Forward
-        let mut reader = MvccReader::new(self.snapshot, None, true);
+        let mut reader = self.new_reader(None);
         let mut results = Vec::with_capacity(keys.len());
         for k in keys {
             results.push(reader.get(k, self.start_ts).map_err(Error::from));
@@ -47,7 +65,7 @@ impl<'a> SnapshotStore<'a> {
     /// Create a scanner.
     /// when key_only is true, all the returned value will be empty.
     pub fn scanner(&self, mode: ScanMode, key_only: bool) -> Result<StoreScanner> {
-        let mut reader = MvccReader::new(self.snapshot, Some(mode), true);
+        let mut reader = self.new_reader(Some(mode));
         reader.set_key_only(key_only);
         Ok(StoreScanner {
             reader: reader,
@@ -71,50 +89,79 @@ impl<'a> StoreScanner<'a> {
     }
 
     #[inline]
-    fn handle_mvcc_err(e: MvccError, result: &mut Vec<Result<KvPair>>) -> Result<Key> {
+    fn handle_mvcc_err(e: MvccError) -> Result<(Key, Result<KvPair>)> {
         let key = if let MvccError::KeyIsLocked { key: ref k, .. } = e {
             Some(Key::from_raw(k))
         } else {
             None
         };
         match key {
-            Some(k) => {
-                result.push(Err(e.into()));
-                Ok(k)
-            }
+            Some(k) => Ok((k, Err(e.into()))),
             None => Err(e.into()),
         }
     }
 
-    pub fn scan(&mut self, mut key: Key, limit: usize) -> Result<Vec<Result<KvPair>>> {
-        let mut results = vec![];
-        while results.len() < limit {
-            match self.seek(key) {
-                Ok(Some((k, v))) => {
-                    results.push(Ok((try!(k.raw()), v)));
-                    key = k;
-                }
-                Ok(None) => break,
-                Err(Error::Mvcc(e)) => key = try!(StoreScanner::handle_mvcc_err(e, &mut results)),
-                Err(e) => return Err(e),
-            }
-            key = key.append_ts(0);
-        }
-        Ok(results)
+    /// Scans up to `limit` rows from `key` until `end_key` (exclusive). The scan also stops
+    /// once the rows returned take up `limit_bytes` bytes, 0 means no byte limit.
+    pub fn scan(&mut self,
+                key: Key,
+                end_key: Option<&Key>,
+                limit: usize,
+                limit_bytes: usize)
+                -> Result<Vec<Result<KvPair>>> {
+        self.scan_impl(key, end_key, limit, limit_bytes, false)
     }
 
-    pub fn reverse_scan(&mut self, mut key: Key, limit: usize) -> Result<Vec<Result<KvPair>>> {
+    /// Scans backward from `key` (exclusive) until `end_key` (inclusive). The limits are the
+    /// same as `scan`.
+    pub fn reverse_scan(&mut self,
+                        key: Key,
+                        end_key: Option<&Key>,
+                        limit: usize,
+                        limit_bytes: usize)
+                        -> Result<Vec<Result<KvPair>>> {
+        self.scan_impl(key, end_key, limit, limit_bytes, true)
+    }
+
+    fn scan_impl(&mut self,
+                 mut key: Key,
+                 end_key: Option<&Key>,
+                 limit: usize,
+                 limit_bytes: usize,
+                 reverse: bool)
+                 -> Result<Vec<Result<KvPair>>> {
         let mut results = vec![];
-        while results.len() < limit {
-            match self.reverse_seek(key) {
+        let mut bytes = 0;
+        while results.len() < limit && (limit_bytes == 0 || bytes < limit_bytes) {
+            let res = if reverse {
+                self.reverse_seek(key)
+            } else {
+                self.seek(key)
+            };
+            let (k, res) = match res {
                 Ok(Some((k, v))) => {
-                    results.push(Ok((try!(k.raw()), v)));
-                    key = k;
+                    let pair = (try!(k.raw()), v);
+                    (k, Ok(pair))
                 }
                 Ok(None) => break,
-                Err(Error::Mvcc(e)) => key = try!(StoreScanner::handle_mvcc_err(e, &mut results)),
+                Err(Error::Mvcc(e)) => try!(StoreScanner::handle_mvcc_err(e)),
                 Err(e) => return Err(e),
+            };
+            if let Some(end_key) = end_key {
+                let out_of_range = if reverse {
+                    k.encoded() < end_key.encoded()
+                } else {
+                    k.encoded() >= end_key.encoded()
+                };
+                if out_of_range {
+                    break;
+                }
+            }
+            if let Ok((ref k, ref v)) = res {
+                bytes += k.len() + v.len();
             }
+            results.push(res);
+            key = if reverse { k } else { k.append_ts(0) };
         }
         Ok(results)
     }
@@ -3,7 +3,7 @@ use tikv::server::coprocessor;
 use kvproto::kvrpcpb::Context;
 use tikv::util::codec::{table, Datum, datum};
 use tikv::util::codec::number::*;
-use tikv::storage::{Mutation, Key, ALL_CFS};
+use tikv::storage::{Mutation, Key, ConcurrencyManager, ALL_CFS};
 use tikv::storage::engine::{self, Engine, TEMP_DIR};
 use tikv::util::worker::Worker;
 use kvproto::coprocessor::{Request, KeyRange};
@@ -447,6 +447,10 @@ impl Store {
         self.store.get_engine()
     }
 
+    fn get_concurrency_manager(&self) -> ConcurrencyManager {
+        self.store.get_concurrency_manager()
+    }
+
     fn begin(&mut self) {
         self.current_ts = next_id() as u64;
         self.handles.clear();
@@ -532,7 +536,10 @@ fn init_with_data(tbl: &ProductTable,
     store.commit();
 
     let mut end_point = Worker::new("test select worker");
-    let runner = EndPointHost::new(store.get_engine(), end_point.scheduler(), 8);
+    let runner = EndPointHost::new(store.get_engine(),
+                                   end_point.scheduler(),
+                                   8,
+                                   store.get_concurrency_manager());
     end_point.start_batch(runner, 5).unwrap();
 
     (store, end_point)
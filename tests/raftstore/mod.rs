@@ -33,3 +33,4 @@ mod test_stats;
 mod test_snap;
 mod test_down_peers;
 mod test_stale_peer;
+mod test_lease_read;
@@ -24,6 +24,7 @@ use kvproto::eraftpb;
 use tikv::pd::{PdClient, Result, Error, Key};
 use tikv::raftstore::store::keys::{enc_end_key, enc_start_key, data_key};
 use tikv::raftstore::store::util::check_key_in_region;
+use tikv::storage::mvcc::MvccStats;
 use tikv::util::{HandyRwLock, escape};
 use super::util::*;
 
@@ -53,6 +54,11 @@ struct Cluster {
     split_count: usize,
 
     down_peers: HashMap<u64, pdpb::PeerStats>,
+    // region id -> mvcc stats reported by the leader
+    mvcc_stats: HashMap<u64, MvccStats>,
+
+    // region id -> leader
+    leaders: HashMap<u64, metapb::Peer>,
 }
 
 impl Cluster {
@@ -71,6 +77,8 @@ impl Cluster {
             store_stats: HashMap::new(),
             split_count: 0,
             down_peers: HashMap::new(),
+            mvcc_stats: HashMap::new(),
+            leaders: HashMap::new(),
         }
     }
 
@@ -210,10 +218,15 @@ impl Cluster {
             // 2) pd is (1), TiKV is (1, 2, 3)
             // 3) pd is (1, 2), TiKV is (3)
             // 4) pd id (1), TiKV is (2, 3)
-
-            assert!(region_peer_len != cur_region_peer_len);
-
-            if cur_region_peer_len > region_peer_len {
+            // Unless a learner is promoted, then the peers are the same.
+            // With joint consensus, several peers can be changed at once.
+
+            if !cur_region.get_outgoing_peers().is_empty() ||
+               !region.get_outgoing_peers().is_empty() {
+                // The peers are checked by TiKV when entering joint consensus.
+            } else if cur_region_peer_len == region_peer_len {
+                must_same_peers(&cur_region, &region);
+            } else if cur_region_peer_len > region_peer_len {
                 // must pd is (1, 2), TiKV is (1)
                 assert_eq!(cur_region_peer_len - region_peer_len, 1);
                 let peers = setdiff_peers(&cur_region, &region);
@@ -290,6 +303,7 @@ impl Cluster {
         }
 
         try!(self.handle_heartbeat_version(region.clone()));
+        self.leaders.insert(region.get_id(), leader.clone());
         self.handle_heartbeat_conf_ver(region, leader)
     }
 }
@@ -379,7 +393,7 @@ impl TestPdClient {
             };
 
             if let Some(p) = find_peer(&region, peer.get_store_id()) {
-                if p.get_id() == peer.get_id() {
+                if p.get_id() == peer.get_id() && p.get_is_learner() == peer.get_is_learner() {
                     return;
                 }
             }
@@ -476,6 +490,10 @@ impl TestPdClient {
     pub fn get_down_peers(&self) -> HashMap<u64, pdpb::PeerStats> {
         self.cluster.rl().down_peers.clone()
     }
+
+    pub fn get_mvcc_stats(&self, region_id: u64) -> Option<MvccStats> {
+        self.cluster.rl().mvcc_stats.get(&region_id).cloned()
+    }
 }
 
 impl PdClient for TestPdClient {
@@ -523,6 +541,11 @@ impl PdClient for TestPdClient {
         Err(box_err!("no region contains key {:?}", escape(key)))
     }
 
+    fn get_region_leader(&self, key: &[u8]) -> Result<Option<metapb::Peer>> {
+        let region = try!(self.get_region(key));
+        Ok(self.cluster.rl().leaders.get(&region.get_id()).cloned())
+    }
+
     fn get_region_by_id(&self, region_id: u64) -> Result<Option<metapb::Region>> {
         try!(self.check_bootstrap());
         self.cluster.rl().get_region_by_id(region_id)
@@ -537,10 +560,13 @@ impl PdClient for TestPdClient {
     fn region_heartbeat(&self,
                         region: metapb::Region,
                         leader: metapb::Peer,
-                        down_peers: Vec<pdpb::PeerStats>)
+                        down_peers: Vec<pdpb::PeerStats>,
+                        mvcc_stats: MvccStats)
                         -> Result<pdpb::RegionHeartbeatResponse> {
         try!(self.check_bootstrap());
-        self.cluster.wl().region_heartbeat(region, leader, down_peers)
+        let mut cluster = self.cluster.wl();
+        cluster.mvcc_stats.insert(region.get_id(), mvcc_stats);
+        cluster.region_heartbeat(region, leader, down_peers)
     }
 
     fn ask_split(&self, region: metapb::Region) -> Result<pdpb::AskSplitResponse> {
@@ -577,4 +603,10 @@ impl PdClient for TestPdClient {
         self.cluster.wl().split_count += 1;
         Ok(())
     }
+
+    fn get_gc_safe_point(&self) -> Result<u64> {
+        // Nothing is collected by the test clusters.
+        try!(self.check_bootstrap());
+        Ok(0)
+    }
 }
@@ -20,6 +20,7 @@ use tikv::storage::CF_RAFT;
 use kvproto::raft_cmdpb::RaftResponseHeader;
 use kvproto::raft_serverpb::*;
 use kvproto::metapb;
+use kvproto::eraftpb::ConfChangeType;
 use tikv::pd::PdClient;
 
 use super::cluster::{Cluster, Simulator};
@@ -220,6 +221,78 @@ fn test_pd_conf_change<T: Simulator>(cluster: &mut Cluster<T>) {
     // TODO: add more tests.
 }
 
+fn test_learner_conf_change<T: Simulator>(cluster: &mut Cluster<T>) {
+    let pd_client = cluster.pd_client.clone();
+    // Disable default max peer count check.
+    pd_client.disable_default_rule();
+
+    let r1 = cluster.run_conf_change();
+    cluster.must_put(b"k1", b"v1");
+
+    // add learner (2, 2) to region 1, it receives the log.
+    pd_client.must_add_peer(r1, new_learner_peer(2, 2));
+    cluster.must_put(b"k2", b"v2");
+    let engine_2 = cluster.get_engine(2);
+    must_get_equal(&engine_2, b"k1", b"v1");
+    must_get_equal(&engine_2, b"k2", b"v2");
+
+    // The learner doesn't count toward quorum, so writes go on without it.
+    cluster.stop_node(2);
+    cluster.must_put(b"k3", b"v3");
+    cluster.run_node(2);
+    let engine_2 = cluster.get_engine(2);
+    must_get_equal(&engine_2, b"k3", b"v3");
+
+    // promote learner (2, 2) to voter, it keeps the log.
+    pd_client.must_add_peer(r1, new_peer(2, 2));
+    cluster.must_put(b"k4", b"v4");
+    must_get_equal(&engine_2, b"k4", b"v4");
+
+    // Now the writes need peer 2.
+    cluster.stop_node(2);
+    let mut req = new_request(r1,
+                              cluster.get_region_epoch(r1),
+                              vec![new_put_cmd(b"k5", b"v5")],
+                              false);
+    req.mut_header().set_peer(new_peer(1, 1));
+    let resp = cluster.call_command(req, Duration::from_millis(500));
+    assert!(resp.is_err(), "writes should time out without the quorum, got {:?}", resp);
+}
+
+fn test_joint_conf_change<T: Simulator>(cluster: &mut Cluster<T>) {
+    let pd_client = cluster.pd_client.clone();
+    // Disable default max peer count check.
+    pd_client.disable_default_rule();
+
+    let r1 = cluster.run_conf_change();
+    pd_client.must_add_peer(r1, new_peer(2, 2));
+    pd_client.must_add_peer(r1, new_peer(3, 3));
+    cluster.must_put(b"k1", b"v1");
+    let engine_3 = cluster.get_engine(3);
+    must_get_equal(&engine_3, b"k1", b"v1");
+
+    // Replace peer (3, 3) with (4, 4) and add learner (5, 5) in one request.
+    let changes = vec![(ConfChangeType::AddNode, new_peer(4, 4)),
+                       (ConfChangeType::RemoveNode, new_peer(3, 3)),
+                       (ConfChangeType::AddLearnerNode, new_learner_peer(5, 5))];
+    let req = new_admin_request(r1,
+                                &cluster.get_region_epoch(r1),
+                                new_change_peers_cmd(changes));
+    let resp = cluster.call_command_on_leader(req, Duration::from_secs(3)).unwrap();
+    assert!(!resp.get_header().has_error(), "{:?}", resp);
+
+    // The leader leaves joint consensus by itself, then peer 3 is removed.
+    cluster.must_put(b"k2", b"v2");
+    let (engine_4, engine_5) = (cluster.get_engine(4), cluster.get_engine(5));
+    must_get_equal(&engine_4, b"k1", b"v1");
+    must_get_equal(&engine_4, b"k2", b"v2");
+    must_get_equal(&engine_5, b"k2", b"v2");
+    must_get_none(&engine_3, b"k1");
+    pd_client.must_have_peer(r1, new_peer(4, 4));
+    pd_client.must_have_peer(r1, new_learner_peer(5, 5));
+    pd_client.must_none_peer(r1, new_peer(3, 3));
+}
+
 #[test]
 fn test_node_simple_conf_change() {
     let count = 5;
@@ -234,6 +307,34 @@ fn test_server_simple_conf_change() {
     test_simple_conf_change(&mut cluster);
 }
 
+#[test]
+fn test_node_learner_conf_change() {
+    let count = 5;
+    let mut cluster = new_node_cluster(0, count);
+    test_learner_conf_change(&mut cluster);
+}
+
+#[test]
+fn test_server_learner_conf_change() {
+    let count = 5;
+    let mut cluster = new_server_cluster(0, count);
+    test_learner_conf_change(&mut cluster);
+}
+
+#[test]
+fn test_node_joint_conf_change() {
+    let count = 5;
+    let mut cluster = new_node_cluster(0, count);
+    test_joint_conf_change(&mut cluster);
+}
+
+#[test]
+fn test_server_joint_conf_change() {
+    let count = 5;
+    let mut cluster = new_server_cluster(0, count);
+    test_joint_conf_change(&mut cluster);
+}
+
 #[test]
 fn test_node_pd_conf_change() {
     let count = 5;
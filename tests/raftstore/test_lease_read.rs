@@ -0,0 +1,107 @@
+// Copyright 2016 PingCAP, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use tikv::raftstore::store::*;
+use tikv::storage::CF_RAFT;
+use tikv::util::duration_to_ms;
+use std::time::Duration;
+use kvproto::raft_serverpb::RaftApplyState;
+
+use super::util::*;
+use super::cluster::{Cluster, Simulator};
+use super::node::new_node_cluster;
+use super::server::new_server_cluster;
+
+fn applied_index<T: Simulator>(cluster: &Cluster<T>, store_id: u64) -> u64 {
+    let engine = cluster.get_engine(store_id);
+    let state: RaftApplyState = engine.get_msg_cf(CF_RAFT, &keys::apply_state_key(1))
+        .unwrap()
+        .unwrap();
+    state.get_applied_index()
+}
+
+fn test_renew_lease<T: Simulator>(cluster: &mut Cluster<T>) {
+    // Avoid the compact log entries changing the applied index.
+    cluster.cfg.raft_store.raft_log_gc_threshold = 100;
+    cluster.run();
+
+    let (key, value) = (b"k1", b"v1");
+    cluster.must_put(key, value);
+    let leader = cluster.leader_of_region(1).unwrap();
+
+    // The lease of the put has expired by now, it must have been renewed by the
+    // heartbeats, so the reads are served locally without going through raft.
+    sleep_ms(duration_to_ms(cluster.cfg.raft_store.max_leader_lease()) + 100);
+    let applied = applied_index(cluster, leader.get_store_id());
+    for _ in 0..10 {
+        assert_eq!(cluster.get(key), Some(value.to_vec()));
+    }
+    assert_eq!(applied_index(cluster, leader.get_store_id()), applied);
+}
+
+#[test]
+fn test_node_renew_lease() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_renew_lease(&mut cluster);
+}
+
+#[test]
+fn test_server_renew_lease() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_renew_lease(&mut cluster);
+}
+
+fn test_read_index<T: Simulator>(cluster: &mut Cluster<T>) {
+    // Avoid the compact log entries changing the applied index.
+    cluster.cfg.raft_store.raft_log_gc_threshold = 100;
+    cluster.run();
+
+    let (key, value) = (b"k1", b"v1");
+    cluster.must_put(key, value);
+    let leader = cluster.leader_of_region(1).unwrap();
+    let mut region = cluster.get_region(key);
+
+    // The reads with read quorum are confirmed by the read index instead of the log.
+    let applied = applied_index(cluster, leader.get_store_id());
+    for _ in 0..10 {
+        let req = new_request(region.get_id(),
+                              region.get_region_epoch().clone(),
+                              vec![new_get_cmd(key)],
+                              true);
+        let mut resp = cluster.call_command_on_leader(req, Duration::from_secs(3)).unwrap();
+        assert!(!resp.get_header().has_error(), "{:?}", resp);
+        assert_eq!(resp.mut_responses()[0].mut_get().take_value(), value.to_vec());
+    }
+    assert_eq!(applied_index(cluster, leader.get_store_id()), applied);
+
+    // The reads are answered after the writes before them are applied.
+    cluster.must_put(key, b"v2");
+    let req = new_request(region.get_id(),
+                          region.take_region_epoch(),
+                          vec![new_get_cmd(key)],
+                          true);
+    let mut resp = cluster.call_command_on_leader(req, Duration::from_secs(3)).unwrap();
+    assert_eq!(resp.mut_responses()[0].mut_get().take_value(), b"v2".to_vec());
+}
+
+#[test]
+fn test_node_read_index() {
+    let mut cluster = new_node_cluster(0, 3);
+    test_read_index(&mut cluster);
+}
+
+#[test]
+fn test_server_read_index() {
+    let mut cluster = new_server_cluster(0, 3);
+    test_read_index(&mut cluster);
+}
@@ -12,6 +12,7 @@
 // limitations under the License.
 
 use std::time::Duration;
+use tikv::storage::{CF_LOCK, CF_WRITE};
 use super::cluster::{Cluster, Simulator};
 use super::util::*;
 use super::node::new_node_cluster;
@@ -61,6 +62,32 @@ fn test_delete<T: Simulator>(cluster: &mut Cluster<T>) {
     }
 }
 
+fn test_delete_range<T: Simulator>(cluster: &mut Cluster<T>) {
+    cluster.run();
+
+    for i in 0..10 {
+        let (k, v) = (format!("key{}", i), format!("value{}", i));
+        cluster.must_put(k.as_bytes(), v.as_bytes());
+        cluster.must_put_cf(CF_WRITE, k.as_bytes(), v.as_bytes());
+        cluster.must_put_cf(CF_LOCK, k.as_bytes(), v.as_bytes());
+    }
+
+    cluster.must_delete_range(b"key2", b"key5");
+    for i in 0..10 {
+        let (k, v) = (format!("key{}", i), format!("value{}", i));
+        let key = k.as_bytes();
+        for engine in cluster.engines.values() {
+            for cf in &["default", CF_WRITE, CF_LOCK] {
+                if i >= 2 && i < 5 {
+                    must_get_cf_none(engine, cf, key);
+                } else {
+                    must_get_cf_equal(engine, cf, key, v.as_bytes());
+                }
+            }
+        }
+    }
+}
+
 fn test_wrong_store_id<T: Simulator>(cluster: &mut Cluster<T>) {
     cluster.run();
 
@@ -90,6 +117,12 @@ fn test_node_delete() {
     test_delete(&mut cluster);
 }
 
+#[test]
+fn test_node_delete_range() {
+    let mut cluster = new_node_cluster(0, 1);
+    test_delete_range(&mut cluster);
+}
+
 #[test]
 fn test_node_wrong_store_id() {
     let mut cluster = new_node_cluster(0, 1);
@@ -108,6 +141,12 @@ fn test_server_delete() {
     test_delete(&mut cluster);
 }
 
+#[test]
+fn test_server_delete_range() {
+    let mut cluster = new_server_cluster(0, 1);
+    test_delete_range(&mut cluster);
+}
+
 #[test]
 fn test_server_wrong_store_id() {
     let mut cluster = new_server_cluster(0, 1);
@@ -18,6 +18,8 @@ use super::node::new_node_cluster;
 use super::server::new_server_cluster;
 use super::util::*;
 use tikv::pd::PdClient;
+use tikv::storage::CF_WRITE;
+use tikv::storage::mvcc::{Write, WriteType};
 use super::pd::TestPdClient;
 
 fn check_available<T: Simulator>(cluster: &mut Cluster<T>) {
@@ -101,6 +103,58 @@ fn test_server_simple_store_stats() {
     test_simple_store_stats(&mut cluster);
 }
 
+fn must_mvcc_stats(pd_client: &Arc<TestPdClient>, region_id: u64, versions: u64, garbage: u64) {
+    let expect = Some((versions, garbage));
+    for _ in 0..100 {
+        let stats = pd_client.get_mvcc_stats(region_id);
+        if stats.map(|s| (s.versions, s.garbage)) == expect {
+            return;
+        }
+        sleep_ms(20);
+    }
+    panic!("region {} mvcc stats {:?}, expect {:?}",
+           region_id,
+           pd_client.get_mvcc_stats(region_id),
+           expect);
+}
+
+fn test_region_mvcc_stats<T: Simulator>(cluster: &mut Cluster<T>) {
+    let pd_client = cluster.pd_client.clone();
+    cluster.run();
+
+    let region = pd_client.get_region(b"").unwrap();
+    let writes = vec![(b"k1", WriteType::Put),
+                      (b"k2", WriteType::Rollback),
+                      (b"k3", WriteType::Delete)];
+    for (key, write_type) in writes {
+        cluster.must_put_cf(CF_WRITE, key, &Write::new(write_type, 1).to_bytes());
+    }
+    must_mvcc_stats(&pd_client, region.get_id(), 3, 2);
+
+    // Records are only deleted by GC, so they are uncounted as garbage.
+    cluster.must_delete_cf(CF_WRITE, b"k2");
+    must_mvcc_stats(&pd_client, region.get_id(), 2, 1);
+
+    // Neither half knows its stats after split.
+    cluster.must_split(&region, b"k2");
+    let right = pd_client.get_region(b"k3").unwrap();
+    assert!(right.get_id() != region.get_id());
+    must_mvcc_stats(&pd_client, region.get_id(), 0, 0);
+    must_mvcc_stats(&pd_client, right.get_id(), 0, 0);
+}
+
+#[test]
+fn test_node_region_mvcc_stats() {
+    let mut cluster = new_node_cluster(0, 1);
+    test_region_mvcc_stats(&mut cluster);
+}
+
+#[test]
+fn test_server_region_mvcc_stats() {
+    let mut cluster = new_server_cluster(0, 1);
+    test_region_mvcc_stats(&mut cluster);
+}
+
 #[test]
 fn test_server_store_snap_stats() {
     let mut cluster = new_server_cluster(0, 2);
@@ -22,7 +22,7 @@ use protobuf;
 
 use kvproto::metapb::{self, RegionEpoch};
 use kvproto::raft_cmdpb::{Request, StatusRequest, AdminRequest, RaftCmdRequest, RaftCmdResponse};
-use kvproto::raft_cmdpb::{CmdType, StatusCmdType, AdminCmdType};
+use kvproto::raft_cmdpb::{CmdType, StatusCmdType, AdminCmdType, ChangePeerRequest};
 use kvproto::pdpb::{ChangePeer, RegionHeartbeatResponse, TransferLeader};
 use kvproto::eraftpb::ConfChangeType;
 
@@ -86,6 +86,7 @@ pub fn new_store_cfg() -> Config {
         // In production environment, the value of max_leader_missing_duration
         // should be configured far beyond the election timeout.
         max_leader_missing_duration: Duration::from_secs(3),
+        raft_max_clock_drift: Duration::from_millis(50),
         ..Config::default()
     }
 }
@@ -156,6 +157,14 @@ pub fn new_delete_cmd(cf: &str, key: &[u8]) -> Request {
     cmd
 }
 
+pub fn new_delete_range_cmd(start_key: &[u8], end_key: &[u8]) -> Request {
+    let mut cmd = Request::new();
+    cmd.set_cmd_type(CmdType::DeleteRange);
+    cmd.mut_delete_range().set_start_key(start_key.to_vec());
+    cmd.mut_delete_range().set_end_key(end_key.to_vec());
+    cmd
+}
+
 pub fn new_status_request(region_id: u64,
                           peer: metapb::Peer,
                           request: StatusRequest)
@@ -201,6 +210,18 @@ pub fn new_compact_log_cmd(index: u64) -> AdminRequest {
     cmd
 }
 
+pub fn new_change_peers_cmd(changes: Vec<(ConfChangeType, metapb::Peer)>) -> AdminRequest {
+    let mut cmd = AdminRequest::new();
+    cmd.set_cmd_type(AdminCmdType::ChangePeers);
+    for (change_type, peer) in changes {
+        let mut change = ChangePeerRequest::new();
+        change.set_change_type(change_type);
+        change.set_peer(peer);
+        cmd.mut_change_peers().mut_changes().push(change);
+    }
+    cmd
+}
+
 pub fn new_peer(store_id: u64, peer_id: u64) -> metapb::Peer {
     let mut peer = metapb::Peer::new();
     peer.set_store_id(store_id);
@@ -208,6 +229,12 @@ pub fn new_peer(store_id: u64, peer_id: u64) -> metapb::Peer {
     peer
 }
 
+pub fn new_learner_peer(store_id: u64, peer_id: u64) -> metapb::Peer {
+    let mut peer = new_peer(store_id, peer_id);
+    peer.set_is_learner(true);
+    peer
+}
+
 
 pub fn new_store(store_id: u64, addr: String) -> metapb::Store {
     let mut store = metapb::Store::new();
@@ -242,9 +269,16 @@ pub fn new_pd_add_change_peer(region: &metapb::Region,
                               -> Option<RegionHeartbeatResponse> {
     if let Some(p) = find_peer(region, peer.get_store_id()) {
         assert_eq!(p.get_id(), peer.get_id());
+        if p.get_is_learner() && !peer.get_is_learner() {
+            // Promotes the learner.
+            return Some(new_pd_change_peer(ConfChangeType::AddNode, peer));
+        }
         return None;
     }
 
+    if peer.get_is_learner() {
+        return Some(new_pd_change_peer(ConfChangeType::AddLearnerNode, peer));
+    }
     Some(new_pd_change_peer(ConfChangeType::AddNode, peer))
 }
 
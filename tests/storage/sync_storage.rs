@@ -14,8 +14,9 @@
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use tikv::storage::{Storage, Engine, Key, Value, KvPair, Mutation, Result};
+use tikv::storage::{Storage, Engine, Key, Value, KvPair, Mutation, ConcurrencyManager, Result};
 use tikv::storage::config::Config;
+use tikv::storage::mvcc::{TxnStatus, SecondaryLocksStatus};
 use kvproto::kvrpcpb::{Context, LockInfo};
 
 /// `SyncStorage` wraps `Storage` with sync API, usually used for testing.
@@ -47,6 +48,10 @@ impl SyncStorage {
         self.store.get_engine()
     }
 
+    pub fn get_concurrency_manager(&self) -> ConcurrencyManager {
+        self.store.get_concurrency_manager()
+    }
+
     pub fn get(&self, ctx: Context, key: &Key, start_ts: u64) -> Result<Option<Value>> {
         wait_op!(|cb| self.store.async_get(ctx, key.to_owned(), start_ts, cb).unwrap()).unwrap()
     }
@@ -68,7 +73,25 @@ impl SyncStorage {
                 key_only: bool,
                 start_ts: u64)
                 -> Result<Vec<Result<KvPair>>> {
-        wait_op!(|cb| self.store.async_scan(ctx, key, limit, key_only, start_ts, cb).unwrap())
+        wait_op!(|cb| {
+                self.store
+                    .async_scan(ctx, key, None, limit, 0, key_only, false, start_ts, cb)
+                    .unwrap()
+            })
+            .unwrap()
+    }
+
+    pub fn reverse_scan(&self,
+                        ctx: Context,
+                        key: Key,
+                        limit: usize,
+                        start_ts: u64)
+                        -> Result<Vec<Result<KvPair>>> {
+        wait_op!(|cb| {
+                self.store
+                    .async_scan(ctx, key, None, limit, 0, false, true, start_ts, cb)
+                    .unwrap()
+            })
             .unwrap()
     }
 
@@ -82,6 +105,30 @@ impl SyncStorage {
             .unwrap()
     }
 
+    /// Returns the results and the `min_commit_ts` of the locks.
+    #[allow(dead_code)]
+    pub fn async_commit_prewrite(&self,
+                                 ctx: Context,
+                                 mutations: Vec<Mutation>,
+                                 primary: Vec<u8>,
+                                 secondaries: Vec<Vec<u8>>,
+                                 start_ts: u64)
+                                 -> Result<(Vec<Result<()>>, u64)> {
+        wait_op!(|cb| {
+                self.store
+                    .async_async_commit_prewrite(ctx,
+                                                 mutations,
+                                                 primary,
+                                                 secondaries,
+                                                 start_ts,
+                                                 0,
+                                                 0,
+                                                 cb)
+                    .unwrap()
+            })
+            .unwrap()
+    }
+
     pub fn commit(&self,
                   ctx: Context,
                   keys: Vec<Key>,
@@ -99,15 +146,49 @@ impl SyncStorage {
         wait_op!(|cb| self.store.async_rollback(ctx, keys, start_ts, cb).unwrap()).unwrap()
     }
 
-    pub fn scan_lock(&self, ctx: Context, max_ts: u64) -> Result<Vec<LockInfo>> {
-        wait_op!(|cb| self.store.async_scan_lock(ctx, max_ts, cb).unwrap()).unwrap()
+    #[allow(dead_code)]
+    pub fn check_txn_status(&self,
+                            ctx: Context,
+                            primary_key: Key,
+                            lock_ts: u64,
+                            current_ts: u64)
+                            -> Result<TxnStatus> {
+        wait_op!(|cb| {
+                self.store
+                    .async_check_txn_status(ctx, primary_key, lock_ts, current_ts, cb)
+                    .unwrap()
+            })
+            .unwrap()
+    }
+
+    #[allow(dead_code)]
+    pub fn check_secondary_locks(&self,
+                                 ctx: Context,
+                                 keys: Vec<Key>,
+                                 start_ts: u64)
+                                 -> Result<SecondaryLocksStatus> {
+        wait_op!(|cb| self.store.async_check_secondary_locks(ctx, keys, start_ts, cb).unwrap())
+            .unwrap()
+    }
+
+    pub fn scan_lock(&self,
+                     ctx: Context,
+                     max_ts: u64,
+                     start_key: Option<Key>,
+                     end_key: Option<Key>,
+                     limit: usize)
+                     -> Result<(Vec<LockInfo>, Option<Vec<u8>>)> {
+        wait_op!(|cb| {
+                self.store.async_scan_lock(ctx, max_ts, start_key, end_key, limit, cb).unwrap()
+            })
+            .unwrap()
     }
 
     pub fn resolve_lock(&self, ctx: Context, start_ts: u64, commit_ts: Option<u64>) -> Result<()> {
         wait_op!(|cb| self.store.async_resolve_lock(ctx, start_ts, commit_ts, cb).unwrap()).unwrap()
     }
 
-    pub fn gc(&self, ctx: Context, safe_point: u64) -> Result<()> {
+    pub fn gc(&self, ctx: Context, safe_point: u64) -> Result<bool> {
         wait_op!(|cb| self.store.async_gc(ctx, safe_point, cb).unwrap()).unwrap()
     }
 }
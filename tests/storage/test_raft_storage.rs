@@ -13,7 +13,9 @@
 
 use tikv::util::HandyRwLock;
 use tikv::storage::{Mutation, make_key, ALL_CFS};
+use tikv::storage::mvcc::{TxnStatus, SecondaryLocksStatus};
 use kvproto::kvrpcpb::Context;
+use kvproto::metapb::Region;
 use raftstore::server::new_server_cluster_with_cfs;
 use raftstore::cluster::Cluster;
 use raftstore::server::ServerCluster;
@@ -29,12 +31,15 @@ fn new_raft_storage() -> (Cluster<ServerCluster>, SyncStorage, Context) {
     let leader_id = cluster.leader_of_region(region.get_id()).unwrap();
     let engine = cluster.sim.rl().storages[&leader_id.get_id()].clone();
 
+    (cluster, SyncStorage::from_engine(engine, &Default::default()), new_context(&region))
+}
+
+fn new_context(region: &Region) -> Context {
     let mut ctx = Context::new();
     ctx.set_region_id(region.get_id());
     ctx.set_region_epoch(region.get_region_epoch().clone());
     ctx.set_peer(region.get_peers()[0].clone());
-
-    (cluster, SyncStorage::from_engine(engine, &Default::default()), ctx)
+    ctx
 }
 
 #[test]
@@ -57,5 +62,73 @@ fn test_raft_storage() {
     assert!(storage.get(ctx.clone(), &key, 20).is_err());
     assert!(storage.batch_get(ctx.clone(), &[key.clone()], 20).is_err());
     assert!(storage.scan(ctx.clone(), key.clone(), 1, false, 20).is_err());
-    assert!(storage.scan_lock(ctx.clone(), 20).is_err());
+    assert!(storage.scan_lock(ctx.clone(), 20, None, None, 0).is_err());
+}
+
+#[test]
+fn test_raft_storage_async_commit_across_regions() {
+    let (mut cluster, storage, _) = new_raft_storage();
+    let region = cluster.get_region(b"");
+    cluster.must_split(&region, b"k5");
+    let (ctx1, ctx2) = (new_context(&cluster.get_region(b"k1")),
+                        new_context(&cluster.get_region(b"k5")));
+    assert!(ctx1.get_region_id() != ctx2.get_region_id());
+    let (k1, k2, k5, k6) = (make_key(b"k1"), make_key(b"k2"), make_key(b"k5"), make_key(b"k6"));
+
+    let (results, min_commit_ts) = storage.async_commit_prewrite(ctx1.clone(),
+                               vec![Mutation::Put((k1.clone(), b"v1".to_vec()))],
+                               b"k1".to_vec(),
+                               vec![b"k5".to_vec()],
+                               10)
+        .unwrap();
+    assert!(results.iter().all(|r| r.is_ok()));
+    assert_eq!(min_commit_ts, 11);
+    // A read in the secondary region before its prewrite raises min_commit_ts.
+    assert_eq!(storage.get(ctx2.clone(), &k5, 20).unwrap(), None);
+    let (results, min_commit_ts) = storage.async_commit_prewrite(ctx2.clone(),
+                               vec![Mutation::Put((k5.clone(), b"v5".to_vec()))],
+                               b"k1".to_vec(),
+                               vec![],
+                               10)
+        .unwrap();
+    assert!(results.iter().all(|r| r.is_ok()));
+    assert_eq!(min_commit_ts, 21);
+
+    // The primary region can't decide the status, the secondaries are checked in their own
+    // region.
+    match storage.check_txn_status(ctx1.clone(), k1.clone(), 10, 30).unwrap() {
+        TxnStatus::AsyncCommit { min_commit_ts, secondaries } => {
+            assert_eq!(min_commit_ts, 11);
+            assert_eq!(secondaries, vec![b"k5".to_vec()]);
+        }
+        status => panic!("unexpected status {:?}", status),
+    }
+    assert_eq!(storage.check_secondary_locks(ctx2.clone(), vec![k5.clone()], 10).unwrap(),
+               SecondaryLocksStatus::Locked { min_commit_ts: 21 });
+    // So the transaction is committed at max(11, 21).
+    storage.resolve_lock(ctx1.clone(), 10, Some(21)).unwrap();
+    storage.resolve_lock(ctx2.clone(), 10, Some(21)).unwrap();
+    assert_eq!(storage.get(ctx2.clone(), &k5, 20).unwrap(), None);
+    assert_eq!(storage.get(ctx1.clone(), &k1, 21).unwrap().unwrap(), b"v1".to_vec());
+    assert_eq!(storage.get(ctx2.clone(), &k5, 21).unwrap().unwrap(), b"v5".to_vec());
+
+    // The secondary in the other region isn't prewritten, the check rolls it back so that the
+    // late prewrite fails.
+    storage.async_commit_prewrite(ctx1.clone(),
+                               vec![Mutation::Put((k2.clone(), b"v2".to_vec()))],
+                               b"k2".to_vec(),
+                               vec![b"k6".to_vec()],
+                               40)
+        .unwrap();
+    assert_eq!(storage.check_secondary_locks(ctx2.clone(), vec![k6.clone()], 40).unwrap(),
+               SecondaryLocksStatus::RolledBack);
+    assert!(storage.async_commit_prewrite(ctx2.clone(),
+                               vec![Mutation::Put((k6.clone(), b"v6".to_vec()))],
+                               b"k2".to_vec(),
+                               vec![],
+                               40)
+        .is_err());
+    storage.resolve_lock(ctx1.clone(), 40, None).unwrap();
+    assert_eq!(storage.get(ctx1.clone(), &k2, 50).unwrap(), None);
+    assert_eq!(storage.get(ctx2.clone(), &k6, 50).unwrap(), None);
 }
@@ -106,22 +106,20 @@ impl AssertionStorage {
         assert_eq!(result, expect);
     }
 
-    #[allow(unused_variables)]
     fn reverse_scan_ok(&self,
                        start_key: &[u8],
                        limit: usize,
                        ts: u64,
                        expect: Vec<Option<(&[u8], &[u8])>>) {
-        // TODO: uncomment it after Storage support reverse_scan.
-        // let key_address = make_key(start_key);
-        // let result = self.0.reverse_scan(Context::new(), key_address, limit, ts).unwrap();
-        // let result: Vec<Option<KvPair>> = result.into_iter()
-        //     .map(Result::ok)
-        //     .collect();
-        // let expect: Vec<Option<KvPair>> = expect.into_iter()
-        //     .map(|x| x.map(|(k, v)| (k.to_vec(), v.to_vec())))
-        //     .collect();
-        // assert_eq!(result, expect);
+        let key_address = make_key(start_key);
+        let result = self.0.reverse_scan(Context::new(), key_address, limit, ts).unwrap();
+        let result: Vec<Option<KvPair>> = result.into_iter()
+            .map(Result::ok)
+            .collect();
+        let expect: Vec<Option<KvPair>> = expect.into_iter()
+            .map(|x| x.map(|(k, v)| (k.to_vec(), v.to_vec())))
+            .collect();
+        assert_eq!(result, expect);
     }
 
     fn prewrite_ok(&self, mutations: Vec<Mutation>, primary: &[u8], start_ts: u64) {
@@ -152,7 +150,32 @@ impl AssertionStorage {
     }
 
     fn scan_lock_ok(&self, max_ts: u64, expect: Vec<LockInfo>) {
-        assert_eq!(self.0.scan_lock(Context::new(), max_ts).unwrap(), expect);
+        let (locks, next_start) = self.0.scan_lock(Context::new(), max_ts, None, None, 0).unwrap();
+        assert_eq!(locks, expect);
+        assert!(next_start.is_none());
+    }
+
+    fn scan_lock_page_ok(&self,
+                         max_ts: u64,
+                         start_key: &[u8],
+                         end_key: &[u8],
+                         limit: usize,
+                         expect: Vec<LockInfo>,
+                         expect_next: Option<&[u8]>) {
+        let start_key = if start_key.is_empty() {
+            None
+        } else {
+            Some(make_key(start_key))
+        };
+        let end_key = if end_key.is_empty() {
+            None
+        } else {
+            Some(make_key(end_key))
+        };
+        let (locks, next_start) =
+            self.0.scan_lock(Context::new(), max_ts, start_key, end_key, limit).unwrap();
+        assert_eq!(locks, expect);
+        assert_eq!(next_start, expect_next.map(|k| k.to_vec()));
     }
 
     fn resolve_lock_ok(&self, start_ts: u64, commit_ts: Option<u64>) {
@@ -445,6 +468,30 @@ fn test_txn_store_scan_lock() {
                             lock(b"p2", b"p2", 10),
                             lock(b"s1", b"p1", 5),
                             lock(b"s2", b"p2", 10)]);
+
+    // Pages through the locks.
+    store.scan_lock_page_ok(10,
+                            b"",
+                            b"",
+                            3,
+                            vec![lock(b"p1", b"p1", 5),
+                                 lock(b"p2", b"p2", 10),
+                                 lock(b"s1", b"p1", 5)],
+                            Some(&b"s1\x00"[..]));
+    store.scan_lock_page_ok(10,
+                            b"s1\x00",
+                            b"",
+                            3,
+                            vec![lock(b"s2", b"p2", 10)],
+                            None);
+    store.scan_lock_page_ok(20,
+                            b"p2",
+                            b"s2",
+                            0,
+                            vec![lock(b"p2", b"p2", 10),
+                                 lock(b"p3", b"p3", 20),
+                                 lock(b"s1", b"p1", 5)],
+                            None);
 }
 
 #[test]
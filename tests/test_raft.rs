@@ -86,6 +86,18 @@ pub fn new_test_raft(id: u64,
     Interface::new(Raft::new(&new_test_config(id, peers, election, heartbeat), storage))
 }
 
+pub fn new_test_learner_raft(id: u64,
+                             peers: Vec<u64>,
+                             learners: Vec<u64>,
+                             election: usize,
+                             heartbeat: usize,
+                             storage: MemStorage)
+                             -> Interface {
+    let mut config = new_test_config(id, peers, election, heartbeat);
+    config.learners = learners;
+    Interface::new(Raft::new(&config, storage))
+}
+
 fn read_messages<T: Storage>(raft: &mut Raft<T>) -> Vec<Message> {
     raft.msgs.drain(..).collect()
 }
@@ -149,9 +161,12 @@ impl Interface {
     fn initial(&mut self, id: u64, ids: &[u64]) {
         if self.raft.is_some() {
             self.id = id;
+            let learners = self.learner_nodes();
             self.prs = HashMap::with_capacity(ids.len());
             for id in ids {
-                self.prs.insert(*id, Progress { ..Default::default() });
+                let is_learner = learners.contains(id);
+                self.prs.insert(*id,
+                                Progress { is_learner: is_learner, ..Default::default() });
             }
             self.reset(0);
         }
@@ -248,7 +263,12 @@ impl Network {
     // A nil node will be replaced with a new *stateMachine.
     // A *stateMachine will get its k, id.
     // When using stateMachine, the address list is always [1, n].
-    pub fn new(mut peers: Vec<Option<Interface>>) -> Network {
+    pub fn new(peers: Vec<Option<Interface>>) -> Network {
+        Network::new_with_config(peers, false)
+    }
+
+    // new_with_config is like new but sets pre_vote of the nil nodes.
+    pub fn new_with_config(mut peers: Vec<Option<Interface>>, pre_vote: bool) -> Network {
         let size = peers.len();
         let peer_addrs: Vec<u64> = (1..size as u64 + 1).collect();
         let mut nstorage = HashMap::new();
@@ -257,7 +277,9 @@ impl Network {
             match p {
                 None => {
                     nstorage.insert(id, new_storage());
-                    let r = new_test_raft(id, peer_addrs.clone(), 10, 1, nstorage[&id].clone());
+                    let mut r =
+                        new_test_raft(id, peer_addrs.clone(), 10, 1, nstorage[&id].clone());
+                    r.pre_vote = pre_vote;
                     npeers.insert(id, r);
                 }
                 Some(mut p) => {
@@ -568,6 +590,203 @@ fn test_leader_election() {
     }
 }
 
+#[test]
+fn test_leader_election_with_pre_vote() {
+    // In pre-vote mode, an election that fails to complete leaves the node in
+    // pre-candidate state without advancing the term.
+    let mut tests = vec![
+        (Network::new_with_config(vec![None, None, None], true), StateRole::Leader, 1),
+        (Network::new_with_config(vec![None, None, NOP_STEPPER], true), StateRole::Leader, 1),
+        (Network::new_with_config(vec![None, NOP_STEPPER, NOP_STEPPER], true),
+            StateRole::PreCandidate, 0),
+        (Network::new_with_config(vec![None, NOP_STEPPER, NOP_STEPPER, None], true),
+            StateRole::PreCandidate, 0),
+        (Network::new_with_config(vec![None, NOP_STEPPER, NOP_STEPPER, None, None], true),
+            StateRole::Leader, 1),
+    ];
+
+    for (i, &mut (ref mut network, state, term)) in tests.iter_mut().enumerate() {
+        network.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+        let raft = &network.peers[&1];
+        if raft.state != state {
+            panic!("#{}: state = {:?}, want {:?}", i, raft.state, state);
+        }
+        if raft.term != term {
+            panic!("#{}: term = {}, want {}", i, raft.term, term)
+        }
+    }
+}
+
+// test_leader_cycle_with_pre_vote verifies that each node in a cluster can campaign
+// and be elected in turn. This ensures that elections (including pre-vote) work
+// when not starting from a clean slate (as they do in test_leader_election)
+#[test]
+fn test_leader_cycle_with_pre_vote() {
+    let mut network = Network::new_with_config(vec![None, None, None], true);
+    for campaigner_id in 1..4 {
+        network.send(vec![new_message(campaigner_id, campaigner_id, MessageType::MsgHup, 0)]);
+
+        for sm in network.peers.values() {
+            if sm.id == campaigner_id && sm.state != StateRole::Leader {
+                panic!("campaigning node {} state = {:?}, want Leader",
+                       sm.id,
+                       sm.state);
+            } else if sm.id != campaigner_id && sm.state != StateRole::Follower {
+                panic!("after campaign of node {}, node {} had state = {:?}, want Follower",
+                       campaigner_id,
+                       sm.id,
+                       sm.state);
+            }
+        }
+    }
+}
+
+// test_pre_vote_from_any_state ensures that pre-vote requests are granted in any
+// state when the log of the sender is up to date, without changing the term, the
+// vote or the state of the receiver.
+#[test]
+fn test_pre_vote_from_any_state() {
+    let states = vec![
+        StateRole::Follower,
+        StateRole::PreCandidate,
+        StateRole::Candidate,
+        StateRole::Leader,
+    ];
+    for state in states {
+        let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+        r.term = 1;
+        match state {
+            StateRole::Follower => {
+                let term = r.term;
+                r.become_follower(term, 3);
+            }
+            StateRole::PreCandidate => r.become_pre_candidate(),
+            StateRole::Candidate => r.become_candidate(),
+            StateRole::Leader => {
+                r.become_candidate();
+                r.become_leader();
+            }
+        }
+        // Note that setting our state above may have advanced r.term
+        // past its initial value.
+        let (orig_term, orig_vote) = (r.term, r.vote);
+        let new_term = r.term + 1;
+
+        let mut m = new_message(2, 1, MessageType::MsgRequestPreVote, 0);
+        m.set_term(new_term);
+        m.set_log_term(new_term);
+        m.set_index(42);
+        r.step(m).expect("");
+
+        let msgs = r.read_messages();
+        assert_eq!(msgs.len(), 1, "{:?}: {:?}", state, msgs);
+        let resp = &msgs[0];
+        assert_eq!(resp.get_msg_type(), MessageType::MsgRequestPreVoteResponse);
+        assert!(!resp.get_reject(), "{:?}: unexpected rejection", state);
+        // pre-votes are answered with the term of the request.
+        assert_eq!(resp.get_term(), new_term);
+
+        assert_eq!(r.state, state);
+        assert_eq!(r.term, orig_term);
+        assert_eq!(r.vote, orig_vote);
+    }
+}
+
+// test_pre_vote_with_lower_term ensures that pre-vote requests with a lower term are
+// rejected with the current term instead of being ignored.
+#[test]
+fn test_pre_vote_with_lower_term() {
+    let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+    r.become_follower(3, INVALID_ID);
+
+    let mut m = new_message(2, 1, MessageType::MsgRequestPreVote, 0);
+    m.set_term(2);
+    r.step(m).expect("");
+
+    let msgs = r.read_messages();
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].get_msg_type(), MessageType::MsgRequestPreVoteResponse);
+    assert!(msgs[0].get_reject());
+    assert_eq!(msgs[0].get_term(), 3);
+    assert_eq!(r.term, 3);
+}
+
+// test_pre_candidate_steps_down ensures that a pre-candidate becomes a follower at the
+// higher term of a rejection, but stays in its term on a granted pre-vote.
+#[test]
+fn test_pre_candidate_steps_down() {
+    let mut r = new_test_raft(1, vec![1, 2, 3, 4, 5], 10, 1, new_storage());
+    r.pre_vote = true;
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).expect("");
+    assert_eq!(r.state, StateRole::PreCandidate);
+    assert_eq!(r.term, 0);
+    for m in r.read_messages() {
+        assert_eq!(m.get_msg_type(), MessageType::MsgRequestPreVote);
+        assert_eq!(m.get_term(), 1);
+    }
+
+    let mut m = new_message(2, 1, MessageType::MsgRequestPreVoteResponse, 0);
+    m.set_term(1);
+    r.step(m).expect("");
+    assert_eq!(r.state, StateRole::PreCandidate);
+    assert_eq!(r.term, 0);
+
+    let mut m = new_message(3, 1, MessageType::MsgRequestPreVoteResponse, 0);
+    m.set_term(5);
+    m.set_reject(true);
+    r.step(m).expect("");
+    assert_eq!(r.state, StateRole::Follower);
+    assert_eq!(r.term, 5);
+}
+
+// test_pre_vote_disrupts_less ensures that a partitioned node which rejoins the
+// cluster doesn't disrupt the leader when pre-vote is enabled.
+#[test]
+fn test_pre_vote_disrupts_less() {
+    let mut nt = Network::new_with_config(vec![None, None, None], true);
+    nt.send(vec![new_message(1, 1, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+
+    nt.isolate(3);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+
+    // 3 can't get any pre-votes while partitioned, so its term is not advanced.
+    nt.send(vec![new_message(3, 3, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&3].state, StateRole::PreCandidate);
+    assert_eq!(nt.peers[&3].term, 1);
+
+    // After healing the partition, 3's pre-vote is rejected as its log is behind,
+    // and the leader keeps its leadership.
+    nt.recover();
+    nt.send(vec![new_message(3, 3, MessageType::MsgHup, 0)]);
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    assert_eq!(nt.peers[&1].term, 1);
+    assert_eq!(nt.peers[&3].state, StateRole::Follower);
+    assert_eq!(nt.peers[&3].term, 1);
+
+    // 3 catches up with the leader.
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    assert_eq!(nt.peers[&3].raft_log.committed, 4);
+}
+
+// test_pre_campaign_while_leader ensures that a single-node cluster campaigning
+// with pre-vote becomes the leader, and ignores the later elections.
+#[test]
+fn test_pre_campaign_while_leader() {
+    let mut r = new_test_raft(1, vec![1], 5, 1, new_storage());
+    r.pre_vote = true;
+    assert_eq!(r.state, StateRole::Follower);
+    // We don't call campaign() directly because it comes after the check
+    // for our current state.
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).expect("");
+    assert_eq!(r.state, StateRole::Leader);
+    let term = r.term;
+    r.step(new_message(1, 1, MessageType::MsgHup, 0)).expect("");
+    assert_eq!(r.state, StateRole::Leader);
+    assert_eq!(r.term, term);
+}
+
 #[test]
 fn test_log_replicatioin() {
     let mut tests = vec![
@@ -2548,3 +2767,355 @@ fn check_leader_transfer_state(r: &Raft<MemStorage>, state: StateRole, lead: u64
     }
     assert_eq!(r.lead_transferee, None);
 }
+
+// test_learner_election_timeout verifies that the learner never campaigns.
+#[test]
+fn test_learner_election_timeout() {
+    let mut n1 = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage());
+    let mut n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    n1.become_follower(1, INVALID_ID);
+    n2.become_follower(1, INVALID_ID);
+
+    // n2 is a learner, it shouldn't start an election even after the timeout.
+    let timeout = n2.get_election_timeout();
+    n2.set_randomized_election_timeout(timeout);
+    for _ in 0..timeout {
+        n2.tick();
+    }
+    assert_eq!(n2.state, StateRole::Follower);
+    assert!(n2.read_messages().is_empty());
+}
+
+// test_learner_promotion verifies that the learner can be promoted to a voter and
+// then be elected.
+#[test]
+fn test_learner_promotion() {
+    let mut n1 = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage());
+    let mut n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    n1.become_follower(1, INVALID_ID);
+    n2.become_follower(1, INVALID_ID);
+
+    let mut nt = Network::new(vec![Some(n1), Some(n2)]);
+    assert!(nt.peers[&1].state != StateRole::Leader);
+
+    // n1 should become leader.
+    {
+        let n1 = nt.peers.get_mut(&1).unwrap();
+        let timeout = n1.get_election_timeout();
+        n1.set_randomized_election_timeout(timeout);
+        for _ in 0..timeout {
+            n1.tick();
+        }
+    }
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    assert_eq!(nt.peers[&2].state, StateRole::Follower);
+
+    nt.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+
+    nt.peers.get_mut(&1).unwrap().add_node(2);
+    nt.peers.get_mut(&2).unwrap().add_node(2);
+    assert!(!nt.peers[&2].is_learner());
+    assert_eq!(nt.peers[&1].nodes(), vec![1, 2]);
+    assert!(nt.peers[&1].learner_nodes().is_empty());
+
+    // n2 starts an election, and should become leader.
+    {
+        let n2 = nt.peers.get_mut(&2).unwrap();
+        let timeout = n2.get_election_timeout();
+        n2.set_randomized_election_timeout(timeout);
+        for _ in 0..timeout {
+            n2.tick();
+        }
+    }
+    nt.send(vec![new_message(2, 2, MessageType::MsgBeat, 0)]);
+
+    assert_eq!(nt.peers[&1].state, StateRole::Follower);
+    assert_eq!(nt.peers[&2].state, StateRole::Leader);
+}
+
+// test_learner_cannot_vote checks that a learner can't vote even it receives a valid
+// vote request.
+#[test]
+fn test_learner_cannot_vote() {
+    let mut n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    n2.become_follower(1, INVALID_ID);
+
+    let mut m = new_message(1, 2, MessageType::MsgRequestVote, 0);
+    m.set_term(2);
+    m.set_log_term(11);
+    m.set_index(11);
+    n2.step(m).expect("");
+
+    assert!(n2.read_messages().is_empty());
+}
+
+// test_learner_log_replication tests that a learner can receive entries from the
+// leader, and doesn't count toward the commit.
+#[test]
+fn test_learner_log_replication() {
+    let n1 = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage());
+    let n2 = new_test_learner_raft(2, vec![1], vec![2], 10, 1, new_storage());
+    let mut nt = Network::new(vec![Some(n1), Some(n2)]);
+    nt.peers.get_mut(&1).unwrap().become_follower(1, INVALID_ID);
+    nt.peers.get_mut(&2).unwrap().become_follower(1, INVALID_ID);
+
+    {
+        let n1 = nt.peers.get_mut(&1).unwrap();
+        let timeout = n1.get_election_timeout();
+        n1.set_randomized_election_timeout(timeout);
+        for _ in 0..timeout {
+            n1.tick();
+        }
+    }
+    nt.send(vec![new_message(1, 1, MessageType::MsgBeat, 0)]);
+
+    // n1 is leader and n2 is learner.
+    assert_eq!(nt.peers[&1].state, StateRole::Leader);
+    assert!(nt.peers[&2].is_learner());
+
+    let next_committed = nt.peers[&1].raft_log.committed + 1;
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    assert_eq!(nt.peers[&1].raft_log.committed, next_committed);
+    assert_eq!(nt.peers[&2].raft_log.committed, next_committed);
+    assert_eq!(nt.peers[&1].prs[&2].matched, next_committed);
+
+    // The learner is not needed for the commit.
+    nt.isolate(2);
+    nt.send(vec![new_message(1, 1, MessageType::MsgPropose, 1)]);
+    assert_eq!(nt.peers[&1].raft_log.committed, next_committed + 1);
+}
+
+// test_add_learner tests that add_learner could update pending_conf and nodes
+// correctly.
+#[test]
+fn test_add_learner() {
+    let mut r = new_test_raft(1, vec![1], 10, 1, new_storage());
+    r.pending_conf = true;
+    r.add_learner(2);
+    assert!(!r.pending_conf);
+    assert_eq!(r.nodes(), vec![1]);
+    assert_eq!(r.learner_nodes(), vec![2]);
+    assert!(r.prs[&2].is_learner);
+
+    // A voter can't be changed to a learner.
+    r.add_learner(1);
+    assert_eq!(r.nodes(), vec![1]);
+    assert_eq!(r.learner_nodes(), vec![2]);
+}
+
+// test_remove_learner tests that remove_node could update pending_conf, nodes and
+// learners correctly.
+#[test]
+fn test_remove_learner() {
+    let mut r = new_test_learner_raft(1, vec![1], vec![2], 10, 1, new_storage());
+    r.pending_conf = true;
+    r.remove_node(2);
+    assert!(!r.pending_conf);
+    assert_eq!(r.nodes(), vec![1]);
+    assert!(r.learner_nodes().is_empty());
+
+    // remove all nodes from cluster
+    r.remove_node(1);
+    assert!(r.nodes().is_empty());
+}
+
+// test_restore_with_learner restores a snapshot which contains learners.
+#[test]
+fn test_restore_with_learner() {
+    let mut s = new_snapshot(11, 11, vec![1, 2]);
+    s.mut_metadata().mut_conf_state().set_learners(vec![3]);
+
+    let mut sm = new_test_learner_raft(3, vec![1, 2], vec![3], 10, 1, new_storage());
+    assert!(sm.restore(s));
+    assert_eq!(sm.raft_log.last_index(), 11);
+    assert_eq!(sm.nodes(), vec![1, 2]);
+    assert_eq!(sm.learner_nodes(), vec![3]);
+    assert!(sm.is_learner());
+    assert!(!sm.promotable());
+}
+
+fn new_conf_state(nodes: Vec<u64>, learners: Vec<u64>) -> ConfState {
+    let mut cs = ConfState::new();
+    cs.set_nodes(nodes);
+    cs.set_learners(learners);
+    cs
+}
+
+// test_enter_and_leave_joint ensures that the voters being removed are kept until leaving
+// joint consensus, while the learners being removed are removed at once.
+#[test]
+fn test_enter_and_leave_joint() {
+    let mut r = new_test_learner_raft(1, vec![1, 2, 3], vec![6], 10, 1, new_storage());
+    r.pending_conf = true;
+    r.enter_joint(&new_conf_state(vec![1, 2, 4], vec![5]));
+    assert!(!r.pending_conf);
+    assert!(r.in_joint());
+    assert_eq!(r.nodes(), vec![1, 2, 4]);
+    assert_eq!(r.outgoing_nodes(), vec![1, 2, 3]);
+    assert_eq!(r.learner_nodes(), vec![5]);
+    assert!(r.prs.contains_key(&3));
+    assert!(!r.prs.contains_key(&6));
+
+    // Entering joint consensus again is ignored.
+    r.enter_joint(&new_conf_state(vec![1, 7], vec![]));
+    assert_eq!(r.nodes(), vec![1, 2, 4]);
+    assert_eq!(r.outgoing_nodes(), vec![1, 2, 3]);
+
+    r.pending_conf = true;
+    r.leave_joint();
+    assert!(!r.pending_conf);
+    assert!(!r.in_joint());
+    assert_eq!(r.nodes(), vec![1, 2, 4]);
+    assert!(r.outgoing_nodes().is_empty());
+    assert_eq!(r.learner_nodes(), vec![5]);
+    assert!(!r.prs.contains_key(&3));
+}
+
+// test_enter_joint_with_learner tests that learners can be promoted, but voters can't be
+// changed to learners.
+#[test]
+fn test_enter_joint_with_learner() {
+    let mut r = new_test_learner_raft(1, vec![1, 2], vec![3], 10, 1, new_storage());
+    r.enter_joint(&new_conf_state(vec![1], vec![2]));
+    assert!(!r.in_joint());
+    assert_eq!(r.nodes(), vec![1, 2]);
+
+    r.enter_joint(&new_conf_state(vec![1, 3], vec![]));
+    assert_eq!(r.nodes(), vec![1, 3]);
+    assert_eq!(r.outgoing_nodes(), vec![1, 2]);
+    assert!(r.learner_nodes().is_empty());
+    r.leave_joint();
+    assert_eq!(r.nodes(), vec![1, 3]);
+}
+
+// test_joint_commit tests that an entry is committed only with majorities of both the
+// incoming and the outgoing voters in joint consensus.
+#[test]
+fn test_joint_commit() {
+    let mut tests = vec![
+        // (matches of 1 to 5, committed)
+        (vec![2, 2, 0, 0, 0], 0),
+        (vec![2, 0, 0, 2, 2], 0),
+        (vec![2, 2, 0, 2, 0], 2),
+        (vec![2, 1, 1, 2, 2], 1),
+        (vec![2, 2, 2, 1, 0], 1),
+    ];
+
+    for (i, (matches, w)) in tests.drain(..).enumerate() {
+        let store = MemStorage::new();
+        store.wl().append(&[empty_entry(2, 1), empty_entry(2, 2)]).expect("");
+        let mut hs = HardState::new();
+        hs.set_term(2);
+        store.wl().set_hardstate(hs);
+
+        let mut sm = new_test_raft(1, vec![1, 2, 3], 5, 1, store);
+        sm.enter_joint(&new_conf_state(vec![1, 4, 5], vec![]));
+        for (j, &v) in matches.iter().enumerate() {
+            sm.prs.get_mut(&(j as u64 + 1)).unwrap().matched = v;
+        }
+        sm.maybe_commit();
+        if sm.raft_log.committed != w {
+            panic!("#{}: committed = {}, want {}", i, sm.raft_log.committed, w);
+        }
+    }
+
+    // Only the incoming voters are needed after leaving joint consensus.
+    let store = MemStorage::new();
+    store.wl().append(&[empty_entry(2, 1), empty_entry(2, 2)]).expect("");
+    let mut hs = HardState::new();
+    hs.set_term(2);
+    store.wl().set_hardstate(hs);
+    let mut sm = new_test_raft(1, vec![1, 2, 3], 5, 1, store);
+    sm.enter_joint(&new_conf_state(vec![1, 4, 5], vec![]));
+    sm.prs.get_mut(&1).unwrap().matched = 2;
+    sm.prs.get_mut(&4).unwrap().matched = 2;
+    sm.maybe_commit();
+    assert_eq!(sm.raft_log.committed, 0);
+    sm.leave_joint();
+    assert_eq!(sm.raft_log.committed, 2);
+}
+
+// test_joint_vote tests that a candidate needs votes from majorities of both the incoming
+// and the outgoing voters in joint consensus.
+#[test]
+fn test_joint_vote() {
+    let mut tests = vec![
+        // (votes, state)
+        (vec![(2, true)], StateRole::Candidate),
+        (vec![(4, true), (5, true)], StateRole::Candidate),
+        (vec![(2, true), (4, true)], StateRole::Leader),
+        (vec![(2, false), (3, false)], StateRole::Follower),
+        (vec![(2, true), (4, false), (5, false)], StateRole::Follower),
+    ];
+
+    for (i, (votes, state)) in tests.drain(..).enumerate() {
+        let mut r = new_test_raft(1, vec![1, 2, 3], 10, 1, new_storage());
+        r.enter_joint(&new_conf_state(vec![1, 4, 5], vec![]));
+        r.step(new_message(1, 1, MessageType::MsgHup, 0)).expect("");
+        let mut to: Vec<_> = r.read_messages().iter().map(|m| m.get_to()).collect();
+        to.sort();
+        assert_eq!(to, vec![2, 3, 4, 5], "#{}", i);
+
+        for (id, vote) in votes {
+            let mut m = new_message(id, 1, MessageType::MsgRequestVoteResponse, 0);
+            m.set_term(r.term);
+            m.set_reject(!vote);
+            r.step(m).expect("");
+        }
+        assert_eq!(r.state, state, "#{}", i);
+    }
+}
+
+// test_joint_conf_change_proposal tests that only leaving is allowed in joint consensus,
+// while leaving is dropped otherwise.
+#[test]
+fn test_joint_conf_change_proposal() {
+    let mut r = new_test_raft(1, vec![1, 2], 10, 1, new_storage());
+    r.become_candidate();
+    r.become_leader();
+
+    let propose = |r: &mut Interface, t: ConfChangeType| {
+        let mut cc = ConfChange::new();
+        cc.set_change_type(t);
+        let mut e = Entry::new();
+        e.set_entry_type(EntryType::EntryConfChange);
+        e.set_data(protobuf::Message::write_to_bytes(&cc).unwrap());
+        let mut m = new_message(1, 1, MessageType::MsgPropose, 0);
+        m.set_entries(RepeatedField::from_vec(vec![e]));
+        r.step(m).expect("");
+        let last_index = r.raft_log.last_index();
+        r.raft_log.entries(last_index, NO_LIMIT).unwrap()[0].get_entry_type()
+    };
+
+    assert_eq!(propose(&mut r, ConfChangeType::FinalizeMembershipChange),
+               EntryType::EntryNormal);
+    assert!(!r.pending_conf);
+    assert_eq!(propose(&mut r, ConfChangeType::BeginMembershipChange),
+               EntryType::EntryConfChange);
+    assert!(r.pending_conf);
+
+    r.enter_joint(&new_conf_state(vec![1, 3], vec![]));
+    assert_eq!(propose(&mut r, ConfChangeType::AddNode),
+               EntryType::EntryNormal);
+    assert_eq!(propose(&mut r, ConfChangeType::BeginMembershipChange),
+               EntryType::EntryNormal);
+    assert!(!r.pending_conf);
+    assert_eq!(propose(&mut r, ConfChangeType::FinalizeMembershipChange),
+               EntryType::EntryConfChange);
+    assert!(r.pending_conf);
+}
+
+// test_restore_with_joint restores a snapshot taken in joint consensus.
+#[test]
+fn test_restore_with_joint() {
+    let mut s = new_snapshot(11, 11, vec![1, 2]);
+    s.mut_metadata().mut_conf_state().set_outgoing_nodes(vec![1, 3]);
+
+    let mut sm = new_test_raft(1, vec![1, 2], 10, 1, new_storage());
+    assert!(sm.restore(s));
+    assert!(sm.in_joint());
+    assert_eq!(sm.nodes(), vec![1, 2]);
+    assert_eq!(sm.outgoing_nodes(), vec![1, 3]);
+    assert!(sm.prs.contains_key(&3));
+    assert!(sm.promotable());
+}
@@ -143,6 +143,47 @@ fn test_raw_node_propose_and_conf_change() {
     assert_eq!(entries[1].get_data(), &*ccdata);
 }
 
+// test_raw_node_joint_conf_change ensures that the membership changes enter and leave
+// joint consensus, and that only leaving is allowed in joint consensus.
+#[test]
+fn test_raw_node_joint_conf_change() {
+    let s = new_storage();
+    let mut raw_node = new_raw_node(1, vec![], 10, 1, s.clone(), vec![new_peer(1)]);
+    let rd = raw_node.ready();
+    s.wl().append(&rd.entries).expect("");
+    raw_node.advance(rd);
+    raw_node.campaign().expect("");
+    assert_eq!(raw_node.raft.state, StateRole::Leader);
+
+    let mut cc = conf_change(ConfChangeType::BeginMembershipChange, 0);
+    cc.mut_configuration().set_nodes(vec![1, 2]);
+    raw_node.propose_conf_change(cc.clone()).expect("");
+    assert!(raw_node.raft.pending_conf);
+    let cs = raw_node.apply_conf_change(cc);
+    assert_eq!(cs.get_nodes(), &[1, 2]);
+    assert_eq!(cs.get_outgoing_nodes(), &[1]);
+    assert!(raw_node.raft.in_joint());
+    assert!(!raw_node.raft.pending_conf);
+
+    // Entering joint consensus again is dropped.
+    let last_index = raw_node.raft.raft_log.last_index();
+    let mut cc = conf_change(ConfChangeType::BeginMembershipChange, 0);
+    cc.mut_configuration().set_nodes(vec![1, 3]);
+    raw_node.propose_conf_change(cc).expect("");
+    assert!(!raw_node.raft.pending_conf);
+    let ents = raw_node.raft.raft_log.entries(last_index + 1, NO_LIMIT).unwrap();
+    assert_eq!(ents.len(), 1);
+    assert_eq!(ents[0].get_entry_type(), EntryType::EntryNormal);
+
+    let cc = conf_change(ConfChangeType::FinalizeMembershipChange, 0);
+    raw_node.propose_conf_change(cc.clone()).expect("");
+    assert!(raw_node.raft.pending_conf);
+    let cs = raw_node.apply_conf_change(cc);
+    assert_eq!(cs.get_nodes(), &[1, 2]);
+    assert!(cs.get_outgoing_nodes().is_empty());
+    assert!(!raw_node.raft.in_joint());
+}
+
 // test_raw_node_read_index ensures that RawNode.read_index sends the MsgReadIndex message
 // to the underlying raft. It also ensures that ReadState can be read out.
 #[test]